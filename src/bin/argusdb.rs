@@ -8,22 +8,30 @@ use config::{Config, Environment, File};
 use futures::stream;
 use pgwire::api::Type;
 use pgwire::api::auth::StartupHandler;
+use pgwire::api::portal::{Format, Portal};
 use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
-use pgwire::api::results::{DataRowEncoder, FieldFormat, FieldInfo, QueryResponse, Response, Tag};
+use pgwire::api::results::{
+    DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldFormat, FieldInfo,
+    QueryResponse, Response, Tag,
+};
+use pgwire::api::stmt::{QueryParser, StoredStatement};
 use pgwire::api::{ClientInfo, ErrorHandler, PgWireServerHandlers};
 use pgwire::error::{PgWireError, PgWireResult};
 use pgwire::messages::data::DataRow;
 use pgwire::tokio::process_socket;
 use serde::Deserialize;
+use serde_json::json;
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
 use tracing::{Level, info, span};
 
-use argusdb::Value;
-use argusdb::db::DB;
+use argusdb::db::{DB, DEFAULT_DISTINCT_SPILL_THRESHOLD, DEFAULT_JOIN_SPILL_THRESHOLD};
+use argusdb::explain::explain;
+use argusdb::expression::{Expression, projection_name};
 use argusdb::parser as argus_parser;
-use argusdb::query::{Statement, execute_plan};
+use argusdb::query::{LogicalPlan, Statement, execute_plan};
+use argusdb::{Value, serde_to_jsonb};
 
 /// ArgusDB Server
 #[derive(Parser, Debug)]
@@ -82,6 +90,12 @@ struct Settings {
     index_threshold: u64,
     #[serde(default = "default_no_log")]
     no_log: bool,
+    #[serde(default = "default_join_spill_threshold")]
+    join_spill_threshold: usize,
+    #[serde(default = "default_distinct_spill_threshold")]
+    distinct_spill_threshold: usize,
+    #[serde(default)]
+    scan_parallelism: Option<usize>,
 }
 
 fn default_host() -> String {
@@ -112,16 +126,126 @@ fn default_no_log() -> bool {
     false
 }
 
+fn default_join_spill_threshold() -> usize {
+    DEFAULT_JOIN_SPILL_THRESHOLD
+}
+
+fn default_distinct_spill_threshold() -> usize {
+    DEFAULT_DISTINCT_SPILL_THRESHOLD
+}
+
 pub struct ArgusHandler {
     db: Arc<Mutex<DB>>,
+    query_parser: Arc<ArgusQueryParser>,
 }
 
 impl ArgusHandler {
     fn new(db: Arc<Mutex<DB>>) -> Self {
-        ArgusHandler { db }
+        ArgusHandler {
+            db,
+            query_parser: Arc::new(ArgusQueryParser),
+        }
     }
 }
 
+fn api_error(e: String) -> PgWireError {
+    PgWireError::ApiError(Box::new(std::io::Error::other(e)))
+}
+
+/// What running a statement produced.
+enum Outcome {
+    /// The documents a query returned
+    Documents(Vec<Value>),
+    /// Lines of text in a single column of the given name
+    Lines(&'static str, Vec<String>),
+    /// The command tag of a statement returning no rows
+    Done(String),
+}
+
+/// Runs a statement whose parameters, if any, are bound.
+fn run_statement(db: &mut DB, stmt: Statement) -> Result<Outcome, String> {
+    match stmt {
+        Statement::Insert {
+            collection,
+            documents,
+        } => {
+            let count = documents.len();
+            for doc in documents {
+                db.insert(&collection, doc)?;
+            }
+            Ok(Outcome::Done(format!("INSERT 0 {}", count)))
+        }
+        Statement::InsertSelect { collection, plan } => {
+            let count = db.insert_plan(&collection, plan)?;
+            Ok(Outcome::Done(format!("INSERT 0 {}", count)))
+        }
+        Statement::Select(plan) => {
+            let mut rows = execute_plan(plan, db)?;
+            let documents = rows.by_ref().map(|item| item.get_value()).collect();
+            rows.check()?;
+            Ok(Outcome::Documents(documents))
+        }
+        Statement::CreateCollection { collection } => {
+            db.create_collection(&collection)?;
+            Ok(Outcome::Done("CREATE COLLECTION".to_string()))
+        }
+        Statement::CreateCollectionAs { collection, plan } => {
+            let count = db.create_collection_as(&collection, plan)?;
+            Ok(Outcome::Done(format!("SELECT {}", count)))
+        }
+        Statement::DropCollection { collection } => {
+            db.drop_collection(&collection)?;
+            Ok(Outcome::Done("DROP COLLECTION".to_string()))
+        }
+        Statement::CreateIndex {
+            index,
+            collection,
+            path,
+            kind,
+        } => {
+            db.create_index(&collection, &index, path, kind)?;
+            Ok(Outcome::Done("CREATE INDEX".to_string()))
+        }
+        Statement::DropIndex { index } => {
+            db.drop_index(&index)?;
+            Ok(Outcome::Done("DROP INDEX".to_string()))
+        }
+        Statement::Explain { plan, analyze } => {
+            Ok(Outcome::Lines("QUERY PLAN", explain(plan, db, analyze)?))
+        }
+        Statement::ShowCollections => Ok(Outcome::Lines("Collections", db.show_collections())),
+    }
+}
+
+/// Encodes rows of text values as a query response.
+fn query_response(
+    fields: Arc<Vec<FieldInfo>>,
+    rows: impl IntoIterator<Item = Vec<String>>,
+) -> PgWireResult<Response> {
+    let mut data_rows: Vec<PgWireResult<DataRow>> = Vec::new();
+    for row in rows {
+        let mut encoder = DataRowEncoder::new(fields.clone());
+        for value in row {
+            encoder
+                .encode_field(&value)
+                .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
+        }
+        data_rows.push(Ok(encoder.take_row()));
+    }
+    Ok(Response::Query(QueryResponse::new(
+        fields,
+        stream::iter(data_rows),
+    )))
+}
+
+fn text_field(name: &str, format: FieldFormat) -> FieldInfo {
+    FieldInfo::new(name.into(), None, None, Type::VARCHAR, format)
+}
+
+fn json_field(name: &str, format: FieldFormat) -> FieldInfo {
+    FieldInfo::new(name.into(), None, None, Type::JSON, format)
+}
+
 #[async_trait]
 impl SimpleQueryHandler for ArgusHandler {
     async fn do_query<C>(&self, _client: &mut C, query: &str) -> PgWireResult<Vec<Response>>
@@ -135,103 +259,255 @@ impl SimpleQueryHandler for ArgusHandler {
         let stmt = match argus_parser::parse(query, &arena) {
             Ok(s) => s,
             Err(e) => {
-                return Ok(vec![Response::Error(Box::new(
-                    PgWireError::ApiError(Box::new(std::io::Error::other(e))).into(),
-                ))]);
+                return Ok(vec![Response::Error(Box::new(api_error(e).into()))]);
             }
         };
 
         let mut db = self.db.lock().await;
-
-        match stmt {
-            Statement::Insert {
-                collection,
-                documents,
-            } => {
-                let count = documents.len();
-                for doc in documents {
-                    db.insert(&collection, doc)
-                        .map_err(|e| PgWireError::ApiError(Box::new(std::io::Error::other(e))))?;
+        let response = match run_statement(&mut db, stmt).map_err(api_error)? {
+            Outcome::Documents(docs) => {
+                if docs.is_empty() {
+                    let fields = Arc::new(vec![]);
+                    return Ok(vec![Response::Query(QueryResponse::new(
+                        fields,
+                        stream::iter(vec![]),
+                    ))]);
                 }
-                Ok(vec![Response::Execution(Tag::new(&format!(
-                    "INSERT 0 {}",
-                    count
-                )))])
+                // Columns are the fields of the first document
+                let names: Vec<String> = docs[0].as_object().unwrap().keys().cloned().collect();
+                let fields = names
+                    .iter()
+                    .map(|name| json_field(name, FieldFormat::Text))
+                    .collect();
+                let rows = docs.iter().map(|doc| {
+                    let obj = doc.as_object().unwrap();
+                    names
+                        .iter()
+                        .map(|name| obj.get(name).unwrap_or(&Value::Null).to_string())
+                        .collect()
+                });
+                query_response(Arc::new(fields), rows)?
             }
-            Statement::Select(plan) => {
-                let iter = execute_plan(plan, &db)
-                    .map_err(|e| PgWireError::ApiError(Box::new(std::io::Error::other(e))))?;
+            Outcome::Lines(name, lines) => query_response(
+                Arc::new(vec![text_field(name, FieldFormat::Text)]),
+                lines.into_iter().map(|line| vec![line]),
+            )?,
+            Outcome::Done(tag) => Response::Execution(Tag::new(&tag)),
+        };
+        Ok(vec![response])
+    }
+}
 
-                let mut rows_data = Vec::new();
-                for item in iter {
-                    rows_data.push(item.get_value());
-                }
+/// Name of the column holding whole documents, for queries over the
+/// extended protocol whose columns are not known before they run.
+const DOCUMENT_COLUMN: &str = "document";
+
+/// A statement prepared over the extended query protocol. Parsed statements
+/// borrow their arena, so the SQL is parsed again for each execution.
+#[derive(Debug, Clone)]
+pub struct ArgusStatement {
+    sql: String,
+    parameter_types: Vec<Type>,
+    columns: Vec<(String, Type)>, // empty if the statement returns no rows
+    whole_documents: bool,        // true if a query returns unprojected documents
+}
 
-                if rows_data.is_empty() {
-                    let fields = Arc::new(vec![]);
-                    let schema = Response::Query(QueryResponse::new(fields, stream::iter(vec![])));
-                    return Ok(vec![schema]);
-                }
+impl ArgusStatement {
+    fn fields(&self, format: &Format) -> Vec<FieldInfo> {
+        self.columns
+            .iter()
+            .enumerate()
+            .map(|(i, (name, ty))| {
+                FieldInfo::new(name.clone(), None, None, ty.clone(), format.format_for(i))
+            })
+            .collect()
+    }
+}
 
-                let first = &rows_data[0];
-                let obj = first.as_object().unwrap();
-                let fields: Vec<FieldInfo> = obj
-                    .keys()
-                    .map(|k| FieldInfo::new(k.clone(), None, None, Type::JSON, FieldFormat::Text))
-                    .collect();
-                let fields = Arc::new(fields);
+/// Returns the columns of the rows a statement returns, and whether they
+/// are whole documents. Queries return the fields they project, or whole
+/// documents if they project `*`.
+fn result_columns(stmt: &Statement) -> (Vec<(String, Type)>, bool) {
+    fn select_columns(plan: &LogicalPlan) -> Option<Vec<String>> {
+        match plan {
+            LogicalPlan::Limit { input, .. }
+            | LogicalPlan::Offset { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Distinct { input, .. } => select_columns(input),
+            LogicalPlan::Project { projections, .. }
+                if !projections
+                    .iter()
+                    .any(|p| matches!(p, Expression::Wildcard)) =>
+            {
+                Some(projections.iter().map(projection_name).collect())
+            }
+            _ => None,
+        }
+    }
+    let text = |name: &str| (vec![(name.to_string(), Type::VARCHAR)], false);
+    match stmt {
+        Statement::Select(plan) => match select_columns(plan) {
+            Some(names) => (
+                names.into_iter().map(|name| (name, Type::JSON)).collect(),
+                false,
+            ),
+            None => (vec![(DOCUMENT_COLUMN.to_string(), Type::JSON)], true),
+        },
+        Statement::Explain { .. } => text("QUERY PLAN"),
+        Statement::ShowCollections => text("Collections"),
+        _ => (Vec::new(), false),
+    }
+}
 
-                let mut data_rows: Vec<PgWireResult<DataRow>> = Vec::new();
-                for doc in rows_data {
-                    let mut encoder = DataRowEncoder::new(fields.clone());
-                    let obj = doc.as_object().unwrap();
-                    for field in fields.iter() {
-                        let key = field.name();
-                        let val = obj.get(key).unwrap_or(&Value::Null);
-                        encoder
-                            .encode_field(&val.to_string())
-                            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-                    }
-                    data_rows.push(Ok(encoder.take_row()));
-                }
+/// Parses statements with `$n` parameters for the extended query protocol.
+/// Parameters the client gives no type for are text.
+pub struct ArgusQueryParser;
 
-                let row_stream = stream::iter(data_rows);
-                Ok(vec![Response::Query(QueryResponse::new(
-                    fields, row_stream,
-                ))])
-            }
-            Statement::CreateCollection { collection } => {
-                db.create_collection(&collection)
-                    .map_err(|e| PgWireError::ApiError(Box::new(std::io::Error::other(e))))?;
-                Ok(vec![Response::Execution(Tag::new("CREATE COLLECTION"))])
-            }
-            Statement::DropCollection { collection } => {
-                db.drop_collection(&collection)
-                    .map_err(|e| PgWireError::ApiError(Box::new(std::io::Error::other(e))))?;
-                Ok(vec![Response::Execution(Tag::new("DROP COLLECTION"))])
-            }
-            Statement::ShowCollections => {
-                let collections = db.show_collections();
-                let fields = Arc::new(vec![FieldInfo::new(
-                    "Collections".into(),
-                    None,
-                    None,
-                    Type::VARCHAR,
-                    FieldFormat::Text,
-                )]);
-                let mut data_rows: Vec<PgWireResult<DataRow>> = Vec::new();
-                for col in collections {
-                    let mut encoder = DataRowEncoder::new(fields.clone());
-                    encoder
-                        .encode_field(&col)
-                        .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-                    data_rows.push(Ok(encoder.take_row()));
-                }
-                let row_stream = stream::iter(data_rows);
-                Ok(vec![Response::Query(QueryResponse::new(
-                    fields, row_stream,
-                ))])
+#[async_trait]
+impl QueryParser for ArgusQueryParser {
+    type Statement = ArgusStatement;
+
+    async fn parse_sql<C>(
+        &self,
+        _client: &C,
+        sql: &str,
+        types: &[Option<Type>],
+    ) -> PgWireResult<ArgusStatement>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let arena = Bump::new();
+        let prepared = argus_parser::prepare(sql, &arena).map_err(api_error)?;
+        let parameter_types = (0..prepared.parameter_count())
+            .map(|i| types.get(i).cloned().flatten().unwrap_or(Type::TEXT))
+            .collect();
+        let (columns, whole_documents) = result_columns(prepared.statement());
+        Ok(ArgusStatement {
+            sql: sql.to_string(),
+            parameter_types,
+            columns,
+            whole_documents,
+        })
+    }
+
+    fn get_parameter_types(&self, stmt: &ArgusStatement) -> PgWireResult<Vec<Type>> {
+        Ok(stmt.parameter_types.clone())
+    }
+
+    fn get_result_schema(
+        &self,
+        stmt: &ArgusStatement,
+        column_format: Option<&Format>,
+    ) -> PgWireResult<Vec<FieldInfo>> {
+        Ok(stmt.fields(column_format.unwrap_or(&Format::UnifiedText)))
+    }
+}
+
+/// Decodes a bound parameter as a value of its declared type. Parameters of
+/// other types are strings, and JSON parameters are parsed.
+fn parameter_value(portal: &Portal<ArgusStatement>, i: usize, ty: &Type) -> PgWireResult<Value> {
+    let value = match *ty {
+        Type::INT2 => portal.parameter::<i16>(i, ty)?.map(|n| json!(n)),
+        Type::INT4 => portal.parameter::<i32>(i, ty)?.map(|n| json!(n)),
+        Type::INT8 => portal.parameter::<i64>(i, ty)?.map(|n| json!(n)),
+        Type::FLOAT4 => portal.parameter::<f32>(i, ty)?.map(|n| json!(n)),
+        Type::FLOAT8 => portal.parameter::<f64>(i, ty)?.map(|n| json!(n)),
+        Type::BOOL => portal.parameter::<bool>(i, ty)?.map(|b| json!(b)),
+        Type::JSON => match portal.parameter::<String>(i, ty)? {
+            Some(text) => Some(
+                serde_json::from_str(&text)
+                    .map_err(|e| api_error(format!("Invalid JSON parameter: {}", e)))?,
+            ),
+            None => None,
+        },
+        _ => portal.parameter::<String>(i, ty)?.map(|text| json!(text)),
+    };
+    Ok(serde_to_jsonb(value.unwrap_or(serde_json::Value::Null)))
+}
+
+#[async_trait]
+impl ExtendedQueryHandler for ArgusHandler {
+    type Statement = ArgusStatement;
+    type QueryParser = ArgusQueryParser;
+
+    fn query_parser(&self) -> Arc<Self::QueryParser> {
+        self.query_parser.clone()
+    }
+
+    async fn do_describe_statement<C>(
+        &self,
+        _client: &mut C,
+        target: &StoredStatement<ArgusStatement>,
+    ) -> PgWireResult<DescribeStatementResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let statement = &target.statement;
+        Ok(DescribeStatementResponse::new(
+            statement.parameter_types.clone(),
+            statement.fields(&Format::UnifiedText),
+        ))
+    }
+
+    async fn do_describe_portal<C>(
+        &self,
+        _client: &mut C,
+        target: &Portal<ArgusStatement>,
+    ) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        Ok(DescribePortalResponse::new(
+            target
+                .statement
+                .statement
+                .fields(&target.result_column_format),
+        ))
+    }
+
+    async fn do_query<C>(
+        &self,
+        _client: &mut C,
+        portal: &Portal<ArgusStatement>,
+        _max_rows: usize,
+    ) -> PgWireResult<Response>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let statement = &portal.statement.statement;
+        let span = span!(Level::DEBUG, "query", query = statement.sql);
+        let _enter = span.enter();
+
+        let params = statement
+            .parameter_types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| parameter_value(portal, i, ty))
+            .collect::<PgWireResult<Vec<Value>>>()?;
+        let arena = Bump::new();
+        let prepared = argus_parser::prepare(&statement.sql, &arena).map_err(api_error)?;
+        let stmt = prepared.bind(&params).map_err(api_error)?;
+
+        let mut db = self.db.lock().await;
+        let fields = Arc::new(statement.fields(&portal.result_column_format));
+        match run_statement(&mut db, stmt).map_err(api_error)? {
+            Outcome::Documents(docs) => {
+                let rows = docs.iter().map(|doc| {
+                    statement
+                        .columns
+                        .iter()
+                        .map(|(name, _)| match doc.as_object() {
+                            Some(obj) if !statement.whole_documents => {
+                                obj.get(name).unwrap_or(&Value::Null).to_string()
+                            }
+                            _ => doc.to_string(),
+                        })
+                        .collect()
+                });
+                query_response(fields, rows)
             }
+            Outcome::Lines(_, lines) => query_response(fields, lines.into_iter().map(|l| vec![l])),
+            Outcome::Done(tag) => Ok(Response::Execution(Tag::new(&tag))),
         }
     }
 }
@@ -250,7 +526,7 @@ impl PgWireServerHandlers for ArgusProcessor {
     }
 
     fn extended_query_handler(&self) -> Arc<impl ExtendedQueryHandler> {
-        Arc::new(pgwire::api::NoopHandler)
+        self.handler.clone()
     }
 
     fn error_handler(&self) -> Arc<impl ErrorHandler> {
@@ -308,13 +584,19 @@ async fn main() {
         Some(1024 * 1024)
     };
 
-    let db = Arc::new(Mutex::new(DB::new(
+    let mut db = DB::new(
         &settings.jstable_dir,
         settings.memtable_threshold,
         settings.jstable_threshold,
         settings.index_threshold,
         log_threshold,
-    )));
+    );
+    db.set_join_spill_threshold(settings.join_spill_threshold);
+    db.set_distinct_spill_threshold(settings.distinct_spill_threshold);
+    if let Some(threads) = settings.scan_parallelism {
+        db.set_scan_parallelism(threads);
+    }
+    let db = Arc::new(Mutex::new(db));
     let handler = Arc::new(ArgusHandler::new(db));
     let processor = Arc::new(ArgusProcessor { handler });
 
@@ -31,10 +31,14 @@ async fn execute_query(db: Arc<Mutex<DB>>, query: Query) {
     match stmt {
         Statement::Select(plan) => {
             let db_guard = db.lock().await;
-            if let Ok(iter) = execute_plan(plan, &db_guard) {
-                for _ in iter {} // Consume
-            } else {
-                eprintln!("Error executing {}", query.name);
+            match execute_plan(plan, &db_guard) {
+                Ok(mut rows) => {
+                    rows.by_ref().for_each(drop); // Consume
+                    if rows.check().is_err() {
+                        eprintln!("Error executing {}", query.name);
+                    }
+                }
+                Err(_) => eprintln!("Error executing {}", query.name),
             }
         }
         _ => eprintln!("Unsupported statement in {}", query.name),
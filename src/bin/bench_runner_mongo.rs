@@ -141,77 +141,92 @@ async fn execute_mongo_query(db: Arc<mongodb::Database>, query: Query) {
 
     match stmt {
         Statement::Select(plan) => {
-            let mut current = &plan;
-            let mut limit = None;
-            let mut offset = None;
-            let mut project = None;
-            let mut filter = None;
-            let mut collection_name = String::new();
-
-            loop {
-                match current {
-                    LogicalPlan::Limit { input, limit: l } => {
-                        limit = Some(*l);
-                        current = input;
-                    }
-                    LogicalPlan::Offset { input, offset: o } => {
-                        offset = Some(*o);
-                        current = input;
-                    }
-                    LogicalPlan::Project { input, projections } => {
-                        project = Some(projections);
-                        current = input;
-                    }
-                    LogicalPlan::Filter { input, predicate } => {
-                        filter = Some(predicate);
-                        current = input;
-                    }
-                    LogicalPlan::Scan { collection } => {
-                        collection_name = collection.clone();
-                        break;
-                    }
+            let (collection_name, pipeline) = match plan_to_pipeline(&plan) {
+                Ok(p) => p,
+                Err(e) => {
+                    eprintln!("Error translating {}: {}", query.name, e);
+                    return;
                 }
-            }
-
+            };
             let collection = db.collection::<Document>(&collection_name);
-            let mut pipeline = Vec::new();
-
-            if let Some(expr) = filter {
-                if let Some(match_doc) = expr_to_match(expr) {
-                    pipeline.push(doc! { "$match": match_doc });
-                }
+            match collection.aggregate(pipeline).await {
+                Ok(_) => {}
+                Err(e) => eprintln!("Error executing {}: {}", query.name, e),
             }
+        }
+        _ => eprintln!("Unsupported statement type"),
+    }
+}
 
-            if let Some(projs) = project {
-                let mut project_doc = Document::new();
-                for (i, expr) in projs.iter().enumerate() {
-                    let val = expr_to_project_expr(expr);
-                    let field_name = if let Expression::FieldReference(_, s) = expr {
-                        s.to_string()
-                    } else {
-                        format!("col_{}", i)
-                    };
-                    project_doc.insert(field_name, val);
-                }
-                project_doc.insert("_id", 0);
-                pipeline.push(doc! { "$project": project_doc });
+/// Translates a scan with an optional filter, projection, offset and limit
+/// into the collection to aggregate and its pipeline.
+#[cfg(feature = "mongo")]
+fn plan_to_pipeline(plan: &LogicalPlan) -> Result<(String, Vec<Document>), String> {
+    let mut current = plan;
+    let mut limit = None;
+    let mut offset = None;
+    let mut project = None;
+    let mut filter = None;
+    let collection_name = loop {
+        match current {
+            LogicalPlan::Limit { input, limit: l } => {
+                limit = Some(*l);
+                current = input;
             }
-
-            if let Some(o) = offset {
-                pipeline.push(doc! { "$skip": o as i64 });
+            LogicalPlan::Offset { input, offset: o } => {
+                offset = Some(*o);
+                current = input;
             }
-
-            if let Some(l) = limit {
-                pipeline.push(doc! { "$limit": l as i64 });
+            LogicalPlan::Project { input, projections } => {
+                project = Some(projections);
+                current = input;
             }
-
-            match collection.aggregate(pipeline).await {
-                Ok(_) => {}
-                Err(e) => eprintln!("Error executing {}: {}", query.name, e),
+            LogicalPlan::Filter { input, predicate } => {
+                filter = Some(predicate);
+                current = input;
+            }
+            LogicalPlan::Scan { collection } => break collection.clone(),
+            _ => {
+                return Err(
+                    "Only scans with filters, projections, offsets and limits are supported"
+                        .to_string(),
+                );
             }
         }
-        _ => eprintln!("Unsupported statement type"),
+    };
+
+    let mut pipeline = Vec::new();
+
+    if let Some(expr) = filter
+        && let Some(match_doc) = expr_to_match(expr)
+    {
+        pipeline.push(doc! { "$match": match_doc });
     }
+
+    if let Some(projs) = project {
+        let mut project_doc = Document::new();
+        for (i, expr) in projs.iter().enumerate() {
+            let val = expr_to_project_expr(expr);
+            let field_name = if let Expression::FieldReference(_, s) = expr {
+                s.to_string()
+            } else {
+                format!("col_{}", i)
+            };
+            project_doc.insert(field_name, val);
+        }
+        project_doc.insert("_id", 0);
+        pipeline.push(doc! { "$project": project_doc });
+    }
+
+    if let Some(o) = offset {
+        pipeline.push(doc! { "$skip": o as i64 });
+    }
+
+    if let Some(l) = limit {
+        pipeline.push(doc! { "$limit": l as i64 });
+    }
+
+    Ok((collection_name, pipeline))
 }
 
 #[cfg(feature = "mongo")]
@@ -230,6 +245,7 @@ fn expr_to_match(expr: &Expression) -> Option<Document> {
                     BinaryOperator::Gte => Some(doc! { *f: { "$gte": bson_v } }),
                     BinaryOperator::Lte => Some(doc! { *f: { "$lte": bson_v } }),
                     BinaryOperator::Neq => Some(doc! { *f: { "$ne": bson_v } }),
+                    _ => None,
                 }
             } else {
                 None
@@ -1,24 +1,113 @@
-use crate::expression::{Expression, evaluate_expression};
+use crate::expression::{
+    Expression, FieldSet, evaluate_document, narrow_document, narrow_lazy, project,
+};
+use crate::fulltext::TextStats;
 use crate::jstable;
 use crate::log::{Log, LogEntry, Logger, NullLogger, Operation};
+use crate::query::{LogicalPlan, execute_plan};
+use crate::schema::{self, Schema, SchemaExt};
+use crate::secondary::{
+    self, IndexDefinition, IndexKind, IndexMemTable, IndexRange, SecondaryIndex,
+};
 use crate::storage::MemTable;
+use crate::vector::{self, Metric};
+use crate::zonemap::{TableStats, ZoneMap};
 use crate::{ExecutionResult, Value};
+use bumpalo::Bump;
+use self_cell::self_cell;
 use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::fs;
+use std::io;
 use std::iter::Peekable;
-use std::path::PathBuf;
-use std::sync::{Arc, mpsc};
+use std::ops::{Add, Bound, RangeBounds, Sub};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
 use std::thread;
 use uuid::Uuid;
 use xorf::{BinaryFuse8, Filter};
 
 type SourceIterator<'a> = Peekable<Box<dyn Iterator<Item = ExecutionResult> + 'a>>;
 
+/// A range of document IDs as (lower, upper) bounds.
+pub type IdRange = (Bound<String>, Bound<String>);
+
+/// Default number of build-side rows a hash join keeps in memory before
+/// spilling to disk.
+pub const DEFAULT_JOIN_SPILL_THRESHOLD: usize = 100_000;
+
+/// Number of rows `INSERT ... SELECT` reads from its query before inserting them.
+pub const INSERT_BATCH_SIZE: usize = 1024;
+
+/// Default number of distinct rows `DISTINCT` keeps in memory before spilling
+/// to disk.
+pub const DEFAULT_DISTINCT_SPILL_THRESHOLD: usize = 100_000;
+
+/// Counters of the storage work done by reads, shared by a DB's collections.
+#[derive(Debug, Default)]
+struct IoStats {
+    jstables_opened: AtomicU64,
+    filter_skips: AtomicU64, // JSTables a point lookup skipped thanks to their filter
+    zone_skips: AtomicU64,   // JSTables and blocks a scan skipped thanks to their zone maps
+    schema_skips: AtomicU64, // JSTables a scan skipped thanks to their schema
+    bytes_read: AtomicU64,
+}
+
+impl IoStats {
+    fn counts(&self) -> IoCounts {
+        IoCounts {
+            jstables_opened: self.jstables_opened.load(Ordering::Relaxed),
+            filter_skips: self.filter_skips.load(Ordering::Relaxed),
+            zone_skips: self.zone_skips.load(Ordering::Relaxed),
+            schema_skips: self.schema_skips.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A snapshot of the storage work done by reads since the DB was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct IoCounts {
+    pub jstables_opened: u64,
+    pub filter_skips: u64,
+    pub zone_skips: u64,
+    pub schema_skips: u64,
+    pub bytes_read: u64,
+}
+
+impl Add for IoCounts {
+    type Output = IoCounts;
+    fn add(self, other: IoCounts) -> IoCounts {
+        IoCounts {
+            jstables_opened: self.jstables_opened + other.jstables_opened,
+            filter_skips: self.filter_skips + other.filter_skips,
+            zone_skips: self.zone_skips + other.zone_skips,
+            schema_skips: self.schema_skips + other.schema_skips,
+            bytes_read: self.bytes_read + other.bytes_read,
+        }
+    }
+}
+
+impl Sub for IoCounts {
+    type Output = IoCounts;
+    fn sub(self, other: IoCounts) -> IoCounts {
+        IoCounts {
+            jstables_opened: self.jstables_opened - other.jstables_opened,
+            filter_skips: self.filter_skips - other.filter_skips,
+            zone_skips: self.zone_skips - other.zone_skips,
+            schema_skips: self.schema_skips - other.schema_skips,
+            bytes_read: self.bytes_read - other.bytes_read,
+        }
+    }
+}
+
 struct MergedIterator<'a> {
     sources: Vec<SourceIterator<'a>>,
     predicate: Option<Expression<'a>>,
     projections: Option<Vec<Expression<'a>>>,
+    fields: Option<FieldSet>,
 }
 
 struct HybridIterator<'a> {
@@ -28,8 +117,10 @@ struct HybridIterator<'a> {
     memtable: &'a HashMap<String, Value>,
     frozen_memtable: Option<&'a HashMap<String, Value>>,
     phase: ScanPhase,
+    range: IdRange,
     predicate: Option<Expression<'a>>,
     projections: Option<Vec<Expression<'a>>>,
+    fields: Option<FieldSet>,
 }
 
 enum ScanPhase {
@@ -38,6 +129,16 @@ enum ScanPhase {
     Disk,
 }
 
+impl HybridIterator<'_> {
+    /// Returns a memtable document, narrowed to the scanned fields if given.
+    fn narrow(&self, doc: &Value) -> Value {
+        match &self.fields {
+            Some(fields) => narrow_document(doc, fields),
+            None => doc.clone(),
+        }
+    }
+}
+
 impl<'a> Iterator for HybridIterator<'a> {
     type Item = ExecutionResult;
 
@@ -51,30 +152,24 @@ impl<'a> Iterator for HybridIterator<'a> {
                             continue; // Tombstone
                         }
 
+                        if !self.range.contains(id) {
+                            continue;
+                        }
+
                         if let Some(pred) = &self.predicate
-                            && evaluate_expression(pred, val) != Value::Bool(true)
+                            && evaluate_document(pred, Some(id.as_str()), val) != Value::Bool(true)
                         {
                             continue;
                         }
 
                         if let Some(projs) = &self.projections {
-                            let mut new_doc = BTreeMap::new();
-                            for expr in projs {
-                                let v = evaluate_expression(expr, val);
-                                let key = match expr {
-                                    Expression::FieldReference(_, raw) => raw.to_string(),
-                                    Expression::JsonPath(_, raw) => raw.to_string(),
-                                    _ => "col".to_string(),
-                                };
-                                new_doc.insert(key, v);
-                            }
                             return Some(ExecutionResult::Value(
                                 id.clone(),
-                                Value::Object(new_doc),
+                                project(projs, |e| evaluate_document(e, Some(id.as_str()), val)),
                             ));
                         }
 
-                        return Some(ExecutionResult::Value(id.clone(), val.clone()));
+                        return Some(ExecutionResult::Value(id.clone(), self.narrow(val)));
                     } else {
                         self.phase = ScanPhase::FrozenMemTable;
                     }
@@ -92,30 +187,27 @@ impl<'a> Iterator for HybridIterator<'a> {
                                 continue; // Tombstone
                             }
 
+                            if !self.range.contains(id) {
+                                continue;
+                            }
+
                             if let Some(pred) = &self.predicate
-                                && evaluate_expression(pred, val) != Value::Bool(true)
+                                && evaluate_document(pred, Some(id.as_str()), val)
+                                    != Value::Bool(true)
                             {
                                 continue;
                             }
 
                             if let Some(projs) = &self.projections {
-                                let mut new_doc = BTreeMap::new();
-                                for expr in projs {
-                                    let v = evaluate_expression(expr, val);
-                                    let key = match expr {
-                                        Expression::FieldReference(_, raw) => raw.to_string(),
-                                        Expression::JsonPath(_, raw) => raw.to_string(),
-                                        _ => "col".to_string(),
-                                    };
-                                    new_doc.insert(key, v);
-                                }
                                 return Some(ExecutionResult::Value(
                                     id.clone(),
-                                    Value::Object(new_doc),
+                                    project(projs, |e| {
+                                        evaluate_document(e, Some(id.as_str()), val)
+                                    }),
                                 ));
                             }
 
-                            return Some(ExecutionResult::Value(id.clone(), val.clone()));
+                            return Some(ExecutionResult::Value(id.clone(), self.narrow(val)));
                         } else {
                             self.phase = ScanPhase::Disk;
                         }
@@ -197,25 +289,22 @@ impl<'a> Iterator for MergedIterator<'a> {
                         if !matches!(val, JsonbValue::Null) {
                             // Check predicate if exists
                             if let Some(pred) = &self.predicate
-                                && evaluate_expression(pred, val) != Value::Bool(true)
+                                && evaluate_document(pred, Some(res.id()), val) != Value::Bool(true)
                             {
                                 continue;
                             }
 
                             if let Some(projs) = &self.projections {
-                                let mut new_doc = BTreeMap::new();
-                                for expr in projs {
-                                    let v = evaluate_expression(expr, val);
-                                    let key = match expr {
-                                        Expression::FieldReference(_, raw) => raw.to_string(),
-                                        Expression::JsonPath(_, raw) => raw.to_string(),
-                                        _ => "col".to_string(),
-                                    };
-                                    new_doc.insert(key, v);
-                                }
                                 return Some(ExecutionResult::Value(
                                     res.id().to_string(),
-                                    Value::Object(new_doc),
+                                    project(projs, |e| evaluate_document(e, Some(res.id()), val)),
+                                ));
+                            }
+
+                            if let Some(fields) = &self.fields {
+                                return Some(ExecutionResult::Value(
+                                    res.id().to_string(),
+                                    narrow_document(val, fields),
                                 ));
                             }
 
@@ -232,19 +321,17 @@ impl<'a> Iterator for MergedIterator<'a> {
                             }
 
                             if let Some(projs) = &self.projections {
-                                let mut new_doc = BTreeMap::new();
-                                for expr in projs {
-                                    let v = evaluate_expression_lazy(expr, doc);
-                                    let key = match expr {
-                                        Expression::FieldReference(_, raw) => raw.to_string(),
-                                        Expression::JsonPath(_, raw) => raw.to_string(),
-                                        _ => "col".to_string(),
-                                    };
-                                    new_doc.insert(key, v);
-                                }
                                 return Some(ExecutionResult::Value(
                                     res.id().to_string(),
-                                    Value::Object(new_doc),
+                                    project(projs, |e| evaluate_expression_lazy(e, doc)),
+                                ));
+                            }
+
+                            // Decode only the scanned fields rather than carrying the whole blob
+                            if let Some(fields) = &self.fields {
+                                return Some(ExecutionResult::Value(
+                                    res.id().to_string(),
+                                    narrow_lazy(doc, fields),
                                 ));
                             }
 
@@ -258,6 +345,35 @@ impl<'a> Iterator for MergedIterator<'a> {
     }
 }
 
+/// Returns true if `id` sorts before the lower bound of a range.
+fn before_range(lower: &Bound<String>, id: &str) -> bool {
+    match lower {
+        Bound::Included(l) => id < l.as_str(),
+        Bound::Excluded(l) => id <= l.as_str(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Returns true if `id` sorts after the upper bound of a range.
+fn past_range(upper: &Bound<String>, id: &str) -> bool {
+    match upper {
+        Bound::Included(u) => id > u.as_str(),
+        Bound::Excluded(u) => id >= u.as_str(),
+        Bound::Unbounded => false,
+    }
+}
+
+/// Finds the data file offset to start reading from using the sparse index.
+fn range_start_offset(index: &[(String, u64)], lower: &Bound<String>) -> u64 {
+    match lower {
+        Bound::Included(l) | Bound::Excluded(l) => {
+            let idx = index.partition_point(|(k, _)| k <= l);
+            if idx > 0 { index[idx - 1].1 } else { 0 }
+        }
+        Bound::Unbounded => 0,
+    }
+}
+
 fn sanitize_filename(name: &str) -> String {
     let mut result = String::new();
     for c in name.chars() {
@@ -270,9 +386,504 @@ fn sanitize_filename(name: &str) -> String {
     result
 }
 
+/// Removes a file, succeeding if it is already gone.
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
 pub(crate) struct LoadedTable {
     filter: BinaryFuse8,
     index: Vec<(String, u64)>,
+    schema: Schema,
+    stats: Option<TableStats>, // None for tables written without statistics
+}
+
+impl LoadedTable {
+    fn open(path: &str) -> io::Result<LoadedTable> {
+        Ok(LoadedTable {
+            filter: jstable::read_filter(path)?,
+            index: jstable::read_index(path)?,
+            schema: jstable::read_schema(path)?,
+            stats: jstable::read_stats(path)?,
+        })
+    }
+
+    /// Returns true if the table's schema shows that none of its documents can
+    /// satisfy `predicate`, and skipping it cannot expose older versions of
+    /// them.
+    fn excluded_by_schema(&self, predicate: Option<&Expression>, oldest: bool) -> bool {
+        let shadows = self
+            .stats
+            .as_ref()
+            .is_none_or(|stats| stats.table.overwrites);
+        (oldest || !shadows) && predicate.is_some_and(|p| !schema::may_match(&self.schema, p))
+    }
+
+    /// Returns true if the table's zone map shows that none of its documents
+    /// can satisfy `predicate`, and skipping it cannot expose older versions
+    /// of them.
+    fn excluded_by_zones(&self, predicate: Option<&Expression>, oldest: bool) -> bool {
+        self.block_stats()
+            .is_some_and(|stats| zone_excludes(&stats.table, predicate, oldest))
+    }
+
+    /// Returns the table's statistics if they cover every block of its
+    /// sparse index.
+    fn block_stats(&self) -> Option<&TableStats> {
+        self.stats
+            .as_ref()
+            .filter(|stats| stats.blocks.len() == self.index.len())
+    }
+
+    /// Returns the `[start, end)` data file ranges a scan of `range` has to
+    /// read, and the number of blocks skipped as no document in them can
+    /// satisfy `predicate`. Blocks of tables other than the oldest that hold
+    /// updates or deletes are always read, as they may shadow older versions
+    /// of their documents.
+    fn scan_ranges(
+        &self,
+        range: &IdRange,
+        predicate: Option<&Expression>,
+        oldest: bool,
+    ) -> (Vec<(u64, u64)>, u64) {
+        let start = range_start_offset(&self.index, &range.0);
+        let Some(stats) = self.block_stats() else {
+            return (vec![(start, u64::MAX)], 0);
+        };
+
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        let mut skipped = 0;
+        for (i, (first_id, offset)) in self.index.iter().enumerate() {
+            let end = self.index.get(i + 1).map_or(u64::MAX, |(_, next)| *next);
+            if end <= start {
+                continue;
+            }
+            if past_range(&range.1, first_id) {
+                break;
+            }
+            if zone_excludes(&stats.blocks[i], predicate, oldest) {
+                skipped += 1;
+                continue;
+            }
+            match ranges.last_mut() {
+                Some(last) if last.1 == *offset => last.1 = end,
+                _ => ranges.push((*offset, end)),
+            }
+        }
+        (ranges, skipped)
+    }
+}
+
+/// Returns true if no document of a zone can satisfy `predicate`, and
+/// skipping it cannot expose older versions of them.
+fn zone_excludes(zone: &ZoneMap, predicate: Option<&Expression>, oldest: bool) -> bool {
+    (oldest || !zone.overwrites) && predicate.is_some_and(|p| !zone.may_match(p))
+}
+
+/// Number of sparse index blocks of the largest JSTable in a morsel of a
+/// parallel scan.
+const MORSEL_BLOCKS: usize = 16;
+
+/// Number of morsels per worker a parallel scan reads ahead of the one it is
+/// returning.
+const MORSELS_PER_WORKER: usize = 2;
+
+/// The memtables and JSTables of a collection that a scan reads.
+#[derive(Clone)]
+struct ScanSources<'a> {
+    memtable: &'a HashMap<String, Value>,
+    frozen_memtable: Option<&'a HashMap<String, Value>>,
+    tables: Arc<TableSources>,
+}
+
+/// The JSTables of a collection that a scan reads, as of its start, which
+/// unlike the collection can be shared with the scan pool's workers.
+struct TableSources {
+    dir: PathBuf,
+    tables: Vec<(u64, Option<Arc<LoadedTable>>)>, // newest first, by number
+    io_stats: Arc<IoStats>,
+}
+
+impl<'a> ScanSources<'a> {
+    /// Scans the documents whose IDs fall in `range`, seeking each JSTable
+    /// via its sparse index and stopping once past the upper bound.
+    ///
+    /// Unprojected documents are narrowed to `fields` if given, so that only
+    /// those sub-values are decoded from disk.
+    fn scan_range(
+        self,
+        range: IdRange,
+        predicate: Option<Expression<'a>>,
+        projections: Option<Vec<Expression<'a>>>,
+        fields: Option<FieldSet>,
+    ) -> HybridIterator<'a> {
+        let disk_iter = self.tables.scan_range(
+            &range,
+            predicate.clone(),
+            projections.clone(),
+            fields.clone(),
+        );
+        self.with_memtables(range, disk_iter, predicate, projections, fields)
+    }
+
+    /// Scans the documents of the memtables whose IDs fall in `range`, then
+    /// those of `disk_iter` that the memtables do not shadow.
+    fn with_memtables(
+        self,
+        range: IdRange,
+        disk_iter: MergedIterator<'a>,
+        predicate: Option<Expression<'a>>,
+        projections: Option<Vec<Expression<'a>>>,
+        fields: Option<FieldSet>,
+    ) -> HybridIterator<'a> {
+        HybridIterator {
+            mem_iter: self.memtable.iter(),
+            frozen_iter: self.frozen_memtable.map(|m| m.iter()),
+            disk_iter,
+            memtable: self.memtable,
+            frozen_memtable: self.frozen_memtable,
+            phase: ScanPhase::MemTable,
+            range,
+            predicate,
+            projections,
+            fields,
+        }
+    }
+
+    /// Returns true if the memtables hold a newer version of the document.
+    fn shadows(&self, id: &str) -> bool {
+        self.memtable.contains_key(id) || self.frozen_memtable.is_some_and(|m| m.contains_key(id))
+    }
+}
+
+impl TableSources {
+    /// Scans the documents of the JSTables whose IDs fall in `range`,
+    /// newest version first.
+    fn scan_range<'e>(
+        &self,
+        range: &IdRange,
+        predicate: Option<Expression<'e>>,
+        projections: Option<Vec<Expression<'e>>>,
+        fields: Option<FieldSet>,
+    ) -> MergedIterator<'e> {
+        let mut disk_sources: Vec<SourceIterator> = Vec::new();
+
+        // JSTable Iterators (Newer to Older)
+        for (i, table) in &self.tables {
+            let ranges = match table {
+                Some(table) => {
+                    let (ranges, skipped) = table.scan_ranges(range, predicate.as_ref(), *i == 0);
+                    self.io_stats
+                        .zone_skips
+                        .fetch_add(skipped, Ordering::Relaxed);
+                    ranges
+                }
+                None => vec![(0, u64::MAX)],
+            };
+            if ranges.is_empty() {
+                continue;
+            }
+            let path = self.dir.join(format!("jstable-{}", i));
+            if let Ok(mut iter) = jstable::JSTableLazyIterator::new(path.to_str().unwrap()) {
+                self.io_stats
+                    .jstables_opened
+                    .fetch_add(1, Ordering::Relaxed);
+                iter.restrict(ranges);
+                let io_stats = self.io_stats.clone();
+                let lower = range.0.clone();
+                let upper = range.1.clone();
+                let iter = iter
+                    .map(|r| r.unwrap())
+                    .inspect(move |doc| {
+                        // Records are length-prefixed
+                        let len = 4 + doc.raw.len() as u64;
+                        io_stats.bytes_read.fetch_add(len, Ordering::Relaxed);
+                    })
+                    .skip_while(move |doc| before_range(&lower, &doc.id))
+                    .take_while(move |doc| !past_range(&upper, &doc.id))
+                    .map(ExecutionResult::Lazy);
+                disk_sources
+                    .push((Box::new(iter) as Box<dyn Iterator<Item = ExecutionResult>>).peekable());
+            }
+        }
+
+        MergedIterator {
+            sources: disk_sources,
+            predicate,
+            projections,
+            fields,
+        }
+    }
+
+    /// Splits `range` into morsels at every `MORSEL_BLOCKS`th key of the
+    /// largest JSTable's sparse index, which together cover the range in ID
+    /// order.
+    fn morsels(&self, range: &IdRange) -> Vec<IdRange> {
+        let Some(index) = self
+            .tables
+            .iter()
+            .filter_map(|(_, table)| table.as_ref())
+            .map(|table| &table.index)
+            .max_by_key(|index| index.len())
+        else {
+            return vec![range.clone()];
+        };
+        let mut morsels = Vec::new();
+        let mut lower = range.0.clone();
+        for (id, _) in index.iter().step_by(MORSEL_BLOCKS).skip(1) {
+            if past_range(&range.1, id) {
+                break;
+            }
+            let splits = match &lower {
+                Bound::Included(l) | Bound::Excluded(l) => id > l,
+                Bound::Unbounded => true,
+            };
+            if splits {
+                morsels.push((lower, Bound::Excluded(id.clone())));
+                lower = Bound::Included(id.clone());
+            }
+        }
+        morsels.push((lower, range.1.clone()));
+        morsels
+    }
+}
+
+/// The predicate and projections a scan applies to the documents it reads.
+type ScanExprs<'a> = (Option<Expression<'a>>, Option<Vec<Expression<'a>>>);
+
+self_cell!(
+    /// A copy of the predicate and projections of a parallel scan, in an
+    /// arena of its own rather than the query's, lent to one job at a time.
+    struct OwnedScanExprs {
+        owner: Bump,
+
+        #[not_covariant]
+        dependent: ScanExprs,
+    }
+);
+
+impl OwnedScanExprs {
+    /// Copies `exprs`, or returns `None` if they hold expressions that are
+    /// replaced before execution.
+    fn copy(exprs: &ScanExprs) -> Option<Self> {
+        OwnedScanExprs::try_new::<()>(Bump::new(), |arena| {
+            let predicate = match &exprs.0 {
+                Some(predicate) => Some(predicate.copy_into(arena).ok_or(())?),
+                None => None,
+            };
+            let projections = match &exprs.1 {
+                Some(projections) => Some(
+                    projections
+                        .iter()
+                        .map(|expr| expr.copy_into(arena))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(())?,
+                ),
+                None => None,
+            };
+            Ok((predicate, projections))
+        })
+        .ok()
+    }
+}
+
+/// A job run by a worker of a `ScanPool`.
+type ScanJob = Box<dyn FnOnce() + Send>;
+
+/// Worker threads that scan the morsels of parallel scans, started once for
+/// a DB and shared by its scans.
+struct ScanPool {
+    jobs: Option<mpsc::Sender<ScanJob>>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl ScanPool {
+    fn new(threads: usize) -> Self {
+        let (jobs, queue) = mpsc::channel::<ScanJob>();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    loop {
+                        // The lock is released before the job runs
+                        let job = queue.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            Err(_) => return,
+                        }
+                    }
+                })
+            })
+            .collect();
+        ScanPool {
+            jobs: Some(jobs),
+            workers,
+        }
+    }
+
+    fn submit(&self, job: ScanJob) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("Scan pool stopped");
+        }
+    }
+}
+
+impl Drop for ScanPool {
+    fn drop(&mut self) {
+        // Workers stop once the queue is closed and empty
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// A morsel scanned by a job, or `None` if the job panicked, and the copy of
+/// the expressions it was lent.
+type ScannedMorsel = (usize, Option<Vec<ExecutionResult>>, OwnedScanExprs);
+
+/// Scans the morsels of a collection's JSTables on a `ScanPool`, returning
+/// their documents in morsel order as soon as each is scanned. The memtables
+/// are scanned once on the calling thread, while the first morsels are read.
+struct ParallelScan<'a> {
+    sources: ScanSources<'a>,
+    memtable_docs: HybridIterator<'a>,
+    pool: &'a ScanPool,
+    spare_exprs: Vec<OwnedScanExprs>, // copies not lent to a job
+    fields: Option<FieldSet>,
+    morsels: std::iter::Enumerate<std::vec::IntoIter<IdRange>>,
+    morsel_count: usize,
+    next_morsel: usize,
+    scanned: BTreeMap<usize, Vec<ExecutionResult>>, // finished out of order
+    results: std::vec::IntoIter<ExecutionResult>,
+    sender: mpsc::Sender<ScannedMorsel>,
+    receiver: mpsc::Receiver<ScannedMorsel>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<'a> ParallelScan<'a> {
+    /// Starts scanning `morsels`. Each queued job is lent a copy of `exprs`:
+    /// `copy`, or one of those made for the other morsels queued at once.
+    fn new(
+        sources: ScanSources<'a>,
+        range: IdRange,
+        morsels: Vec<IdRange>,
+        exprs: ScanExprs<'a>,
+        copy: OwnedScanExprs,
+        fields: Option<FieldSet>,
+        pool: &'a ScanPool,
+    ) -> Self {
+        let queued = pool.workers.len() * MORSELS_PER_WORKER;
+        let mut spare_exprs = vec![copy];
+        spare_exprs.extend((1..queued).map_while(|_| OwnedScanExprs::copy(&exprs)));
+        let memtable_docs = sources.clone().with_memtables(
+            range,
+            MergedIterator {
+                sources: Vec::new(),
+                predicate: None,
+                projections: None,
+                fields: None,
+            },
+            exprs.0,
+            exprs.1,
+            fields.clone(),
+        );
+        let (sender, receiver) = mpsc::channel();
+        let mut scan = ParallelScan {
+            sources,
+            memtable_docs,
+            pool,
+            spare_exprs,
+            fields,
+            morsel_count: morsels.len(),
+            morsels: morsels.into_iter().enumerate(),
+            next_morsel: 0,
+            scanned: BTreeMap::new(),
+            results: Vec::new().into_iter(),
+            sender,
+            receiver,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        };
+        for _ in 0..queued {
+            scan.submit_next();
+        }
+        scan
+    }
+
+    /// Queues the next morsel on the pool, if any are left and a copy of the
+    /// expressions is free.
+    fn submit_next(&mut self) {
+        let Some(exprs) = self.spare_exprs.pop() else {
+            return;
+        };
+        let Some((i, range)) = self.morsels.next() else {
+            self.spare_exprs.push(exprs);
+            return;
+        };
+        let tables = self.sources.tables.clone();
+        let fields = self.fields.clone();
+        let sender = self.sender.clone();
+        let cancelled = self.cancelled.clone();
+        self.pool.submit(Box::new(move || {
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let rows = panic::catch_unwind(AssertUnwindSafe(|| {
+                exprs.with_dependent(|_, (predicate, projections)| {
+                    tables
+                        .scan_range(&range, predicate.clone(), projections.clone(), fields)
+                        .collect::<Vec<_>>()
+                })
+            }));
+            // The scan may have been dropped since
+            let _ = sender.send((i, rows.ok(), exprs));
+        }));
+    }
+
+    /// Waits for the next morsel in order to be scanned, and queues another.
+    fn next_morsel(&mut self) -> Option<Vec<ExecutionResult>> {
+        if self.next_morsel == self.morsel_count {
+            return None;
+        }
+        while !self.scanned.contains_key(&self.next_morsel) {
+            let (i, rows, exprs) = self.receiver.recv().expect("Scan pool stopped");
+            self.spare_exprs.push(exprs);
+            self.scanned.insert(i, rows.expect("Scan worker panicked"));
+        }
+        let rows = self.scanned.remove(&self.next_morsel);
+        self.next_morsel += 1;
+        self.submit_next();
+        rows
+    }
+}
+
+impl Iterator for ParallelScan<'_> {
+    type Item = ExecutionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(row) = self.memtable_docs.next() {
+            return Some(row);
+        }
+        loop {
+            for row in self.results.by_ref() {
+                if !self.sources.shadows(row.id()) {
+                    return Some(row);
+                }
+            }
+            self.results = self.next_morsel()?.into_iter();
+        }
+    }
+}
+
+impl Drop for ParallelScan<'_> {
+    fn drop(&mut self) {
+        // Queued morsels of an abandoned scan are skipped
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 }
 
 struct Collection {
@@ -286,7 +897,9 @@ struct Collection {
     memtable_threshold: usize,
     jstable_threshold: u64,
     index_threshold: u64,
-    tables: Vec<LoadedTable>,
+    tables: Vec<Arc<LoadedTable>>,
+    indexes: Vec<SecondaryIndex>,
+    io_stats: Arc<IoStats>,
 }
 
 impl Collection {
@@ -297,6 +910,7 @@ impl Collection {
         jstable_threshold: u64,
         index_threshold: u64,
         log_rotation_threshold: Option<u64>,
+        io_stats: Arc<IoStats>,
     ) -> Self {
         fs::create_dir_all(&dir).unwrap();
         let log_path = dir.join("argus.log");
@@ -315,16 +929,20 @@ impl Collection {
             .exists()
         {
             let path = dir.join(format!("jstable-{}", jstable_count));
-            let path_str = path.to_str().unwrap();
-
-            let filter = jstable::read_filter(path_str).expect("Failed to read filter");
-            let index = jstable::read_index(path_str).expect("Failed to read index");
-
-            tables.push(LoadedTable { filter, index });
+            let table = LoadedTable::open(path.to_str().unwrap()).expect("Failed to read summary");
+            tables.push(Arc::new(table));
             jstable_count += 1;
         }
+        let indexes = match fs::read(dir.join(secondary::DEFINITIONS_FILE)) {
+            Ok(bytes) => serde_json::from_slice::<Vec<IndexDefinition>>(&bytes)
+                .expect("Failed to read index definitions")
+                .into_iter()
+                .map(SecondaryIndex::new)
+                .collect(),
+            Err(_) => Vec::new(),
+        };
 
-        Collection {
+        let collection = Collection {
             name,
             memtable,
             frozen_memtable: None,
@@ -336,7 +954,21 @@ impl Collection {
             jstable_threshold,
             index_threshold,
             tables,
+            indexes,
+            io_stats,
+        };
+        // Rebuild index tables lost after a crash, which would otherwise
+        // hide the documents of their JSTables from index scans
+        for index in &collection.indexes {
+            for i in 0..collection.jstable_count {
+                if !collection.index_table_path(i, &index.name).exists() {
+                    collection
+                        .write_index_table(i, index)
+                        .expect("Failed to rebuild index table");
+                }
+            }
         }
+        collection
     }
 
     #[tracing::instrument]
@@ -358,6 +990,7 @@ impl Collection {
                 doc: doc.clone(),
             })
             .unwrap();
+        self.index_document(&id, &doc);
         self.memtable.insert(id.clone(), doc);
         id
     }
@@ -378,9 +1011,40 @@ impl Collection {
                 doc: doc.clone(),
             })
             .unwrap();
+        self.index_document(id, &doc);
         self.memtable.update(id, doc);
     }
 
+    /// Adds a new version of a document to the index memtables.
+    fn index_document(&mut self, id: &str, doc: &Value) {
+        for index in &mut self.indexes {
+            index.memtable.insert(index.kind, &index.path, id, doc);
+        }
+    }
+
+    /// Adds the documents of the memtable to the index memtables, e.g. once
+    /// it has been replayed from the log.
+    fn index_memtable(&mut self) {
+        for index in &mut self.indexes {
+            for (id, doc) in &self.memtable.documents {
+                index.memtable.insert(index.kind, &index.path, id, doc);
+            }
+        }
+    }
+
+    /// Drops the memtables frozen for a flush once it is over.
+    fn release_frozen_memtables(&mut self) {
+        self.frozen_memtable = None;
+        for index in &mut self.indexes {
+            index.frozen_memtable = None;
+        }
+        self.flush_rx = None;
+    }
+
+    fn index_table_path(&self, table: u64, index: &str) -> PathBuf {
+        secondary::table_path(&self.dir, table, &sanitize_filename(index))
+    }
+
     fn check_flush_status(&mut self, wait: bool) {
         if let Some(rx) = &self.flush_rx {
             let res = if wait {
@@ -392,7 +1056,7 @@ impl Collection {
             if let Ok(result) = res {
                 match result {
                     Ok(table) => {
-                        self.tables.push(table);
+                        self.tables.push(Arc::new(table));
                         self.jstable_count += 1;
                         if self.jstable_count >= self.jstable_threshold {
                             self.compact();
@@ -400,12 +1064,10 @@ impl Collection {
                     }
                     Err(e) => eprintln!("Background flush failed: {}", e),
                 }
-                self.frozen_memtable = None;
-                self.flush_rx = None;
+                self.release_frozen_memtables();
             } else if wait {
                 // If we waited and got error (e.g. channel closed), clear state
-                self.frozen_memtable = None;
-                self.flush_rx = None;
+                self.release_frozen_memtables();
             }
         }
     }
@@ -425,32 +1087,35 @@ impl Collection {
         let frozen = Arc::new(memtable);
         self.frozen_memtable = Some(frozen.clone());
 
+        // Index tables are written alongside the JSTable
+        let mut index_tables = Vec::new();
+        for i in 0..self.indexes.len() {
+            let path = self.index_table_path(self.jstable_count, &self.indexes[i].name);
+            let index = &mut self.indexes[i];
+            let frozen = Arc::new(std::mem::take(&mut index.memtable));
+            index.frozen_memtable = Some(frozen.clone());
+            index_tables.push((path, index.kind, frozen));
+        }
+
         let (tx, rx) = mpsc::channel();
         self.flush_rx = Some(rx);
 
         thread::spawn(move || {
-            match frozen.flush(
-                jstable_path.to_str().unwrap(),
-                collection_name,
-                index_threshold,
-            ) {
+            let flushed = frozen
+                .flush(
+                    jstable_path.to_str().unwrap(),
+                    collection_name,
+                    index_threshold,
+                )
+                .and_then(|_| {
+                    index_tables.iter().try_for_each(|(path, kind, index)| {
+                        index.write(*kind, path, index_threshold)
+                    })
+                });
+            match flushed {
                 Ok(_) => {
-                    let path_str = jstable_path.to_str().unwrap();
-                    let filter = match jstable::read_filter(path_str) {
-                        Ok(f) => f,
-                        Err(e) => {
-                            let _ = tx.send(Err(e.to_string()));
-                            return;
-                        }
-                    };
-                    let index = match jstable::read_index(path_str) {
-                        Ok(i) => i,
-                        Err(e) => {
-                            let _ = tx.send(Err(e.to_string()));
-                            return;
-                        }
-                    };
-                    let _ = tx.send(Ok(LoadedTable { filter, index }));
+                    let table = LoadedTable::open(jstable_path.to_str().unwrap());
+                    let _ = tx.send(table.map_err(|e| e.to_string()));
                 }
                 Err(e) => {
                     let _ = tx.send(Err(e.to_string()));
@@ -481,51 +1146,90 @@ impl Collection {
             .write(new_path.to_str().unwrap(), self.index_threshold)
             .unwrap();
 
+        // Rebuild the index tables from the merged documents, dropping
+        // entries for versions that no longer exist
+        for index in &self.indexes {
+            for i in 0..self.jstable_count {
+                remove_if_exists(&self.index_table_path(i, &index.name)).unwrap();
+            }
+            let mut memtable = IndexMemTable::default();
+            for (id, doc) in &merged_table.documents {
+                memtable.insert(index.kind, &index.path, id, &doc.document());
+            }
+            memtable
+                .write(
+                    index.kind,
+                    &self.index_table_path(0, &index.name),
+                    self.index_threshold,
+                )
+                .unwrap();
+        }
+
         // Reset tables
         self.tables.clear();
-        let path_str = new_path.to_str().unwrap();
-        let filter = jstable::read_filter(path_str).unwrap();
-        let index = jstable::read_index(path_str).unwrap();
-        self.tables.push(LoadedTable { filter, index });
+        self.tables.push(Arc::new(
+            LoadedTable::open(new_path.to_str().unwrap()).unwrap(),
+        ));
 
         self.jstable_count = 1;
     }
 
-    fn scan<'a>(
+    /// Scans the documents whose IDs fall in `range`, split into morsels that
+    /// the workers of a pool of `parallelism` threads filter and project at
+    /// once. Documents are returned morsel by morsel, so those on disk stay in
+    /// ID order as in a scan on the calling thread.
+    fn parallel_scan_range<'a>(
         &'a self,
+        range: IdRange,
         predicate: Option<Expression<'a>>,
         projections: Option<Vec<Expression<'a>>>,
-    ) -> impl Iterator<Item = ExecutionResult> + 'a {
-        let mut disk_sources: Vec<SourceIterator> = Vec::new();
+        fields: Option<FieldSet>,
+        parallelism: usize,
+        pool: &'a OnceLock<ScanPool>,
+    ) -> Box<dyn Iterator<Item = ExecutionResult> + 'a> {
+        let sources = self.sources(predicate.as_ref());
+        let morsels = sources.tables.morsels(&range);
+        let exprs = (predicate, projections);
+        // Workers need their own copies of the expressions
+        if parallelism > 1
+            && morsels.len() > 1
+            && let Some(copy) = OwnedScanExprs::copy(&exprs)
+        {
+            let pool = pool.get_or_init(|| ScanPool::new(parallelism));
+            return Box::new(ParallelScan::new(
+                sources, range, morsels, exprs, copy, fields, pool,
+            ));
+        }
+        Box::new(sources.scan_range(range, exprs.0, exprs.1, fields))
+    }
 
-        // JSTable Iterators (Newer to Older)
+    /// Returns what a scan reads: the memtables, and the JSTables other than
+    /// those whose schema or zone map shows that none of their documents can
+    /// satisfy `predicate`. Skipped tables are counted here, once per scan.
+    fn sources(&self, predicate: Option<&Expression>) -> ScanSources<'_> {
+        let mut tables = Vec::new();
         for i in (0..self.jstable_count).rev() {
-            let path = self.dir.join(format!("jstable-{}", i));
-            if let Ok(iter) = jstable::JSTableLazyIterator::new(path.to_str().unwrap()) {
-                let iter = iter.map(|r| {
-                    let doc = r.unwrap();
-                    ExecutionResult::Lazy(doc)
-                });
-                disk_sources
-                    .push((Box::new(iter) as Box<dyn Iterator<Item = ExecutionResult>>).peekable());
+            let table = self.tables.get(i as usize);
+            if let Some(table) = table {
+                if table.excluded_by_schema(predicate, i == 0) {
+                    self.io_stats.schema_skips.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                if table.excluded_by_zones(predicate, i == 0) {
+                    self.io_stats.zone_skips.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
             }
+            tables.push((i, table.cloned()));
         }
-
-        let disk_iter = MergedIterator {
-            sources: disk_sources,
-            predicate: predicate.clone(),
-            projections: projections.clone(),
-        };
-
-        HybridIterator {
-            mem_iter: self.memtable.documents.iter(),
-            frozen_iter: self.frozen_memtable.as_ref().map(|m| m.documents.iter()),
-            disk_iter,
+        ScanSources {
             memtable: &self.memtable.documents,
-            frozen_memtable: self.frozen_memtable.as_ref().map(|m| &m.documents),
-            phase: ScanPhase::MemTable,
-            predicate,
-            projections,
+            frozen_memtable: self.frozen_memtable.as_deref().map(|m| &m.documents),
+            tables: Arc::new(TableSources {
+                dir: self.dir.clone(),
+                tables,
+                io_stats: self.io_stats.clone(),
+            }),
         }
     }
 
@@ -559,38 +1263,269 @@ impl Collection {
         };
 
         for i in (0..self.jstable_count).rev() {
-            if let Some(table) = self.tables.get(i as usize)
-                && table.filter.contains(&hash)
+            let Some(table) = self.tables.get(i as usize) else {
+                continue;
+            };
+            if !table.filter.contains(&hash) {
+                self.io_stats.filter_skips.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            // Possible match, find offset using index
+            let index = &table.index;
+            // Find first key > id. We want the one before that.
+            let idx = index.partition_point(|(k, _)| k.as_str() <= id);
+            let start_offset = if idx > 0 { index[idx - 1].1 } else { 0 };
+
+            let path = self.dir.join(format!("jstable-{}", i));
+            if let Ok(mut iter) = jstable::JSTableIterator::new(path.to_str().unwrap())
+                && iter.seek(start_offset).is_ok()
             {
-                // Possible match, find offset using index
-                let index = &table.index;
-                // Find first key > id. We want the one before that.
-                let idx = index.partition_point(|(k, _)| k.as_str() <= id);
-                let start_offset = if idx > 0 { index[idx - 1].1 } else { 0 };
-
-                let path = self.dir.join(format!("jstable-{}", i));
-                if let Ok(mut iter) = jstable::JSTableIterator::new(path.to_str().unwrap())
-                    && iter.seek(start_offset).is_ok()
+                self.io_stats
+                    .jstables_opened
+                    .fetch_add(1, Ordering::Relaxed);
+                // Records are sorted, so stop at the first ID not below `id`
+                let found = iter.by_ref().flatten().find(|(rid, _)| rid.as_str() >= id);
+                self.io_stats
+                    .bytes_read
+                    .fetch_add(iter.bytes_read(), Ordering::Relaxed);
+                if let Some((rid, doc)) = found
+                    && rid == id
                 {
-                    for (rid, doc) in iter.flatten() {
-                        if rid == id {
-                            use jsonb_schema::Value as JsonbValue;
-                            if matches!(doc, JsonbValue::Null) {
-                                return None; // Tombstone
-                            }
-                            return Some(doc);
-                        }
-                        if rid.as_str() > id {
-                            // Not found in this table (sorted)
-                            break;
-                        }
+                    use jsonb_schema::Value as JsonbValue;
+                    if matches!(doc, JsonbValue::Null) {
+                        return None; // Tombstone
                     }
+                    return Some(doc);
                 }
             }
         }
 
         None
     }
+
+    /// Creates an index on the field at `path`, indexing the documents
+    /// already in the collection.
+    fn create_index(
+        &mut self,
+        name: &str,
+        path: Vec<String>,
+        kind: IndexKind,
+    ) -> Result<(), String> {
+        self.wait_for_ongoing_flush();
+        let mut index = SecondaryIndex::new(IndexDefinition {
+            name: name.to_string(),
+            path,
+            kind,
+        });
+        for i in 0..self.jstable_count {
+            self.write_index_table(i, &index)?;
+        }
+        for (id, doc) in &self.memtable.documents {
+            index.memtable.insert(index.kind, &index.path, id, doc);
+        }
+        self.indexes.push(index);
+        self.write_index_definitions()
+    }
+
+    /// Writes the index table of `index` for the JSTable numbered `table`.
+    fn write_index_table(&self, table: u64, index: &SecondaryIndex) -> Result<(), String> {
+        let path = self.dir.join(format!("jstable-{}", table));
+        let table_data =
+            jstable::read_jstable(path.to_str().unwrap()).map_err(|e| e.to_string())?;
+        let mut memtable = IndexMemTable::default();
+        for (id, doc) in &table_data.documents {
+            memtable.insert(index.kind, &index.path, id, &doc.document());
+        }
+        memtable
+            .write(
+                index.kind,
+                &self.index_table_path(table, &index.name),
+                self.index_threshold,
+            )
+            .map_err(|e| e.to_string())
+    }
+
+    fn drop_index(&mut self, name: &str) -> Result<(), String> {
+        self.wait_for_ongoing_flush();
+        self.indexes.retain(|index| index.name != name);
+        for i in 0..self.jstable_count {
+            remove_if_exists(&self.index_table_path(i, name)).map_err(|e| e.to_string())?;
+        }
+        self.write_index_definitions()
+    }
+
+    fn write_index_definitions(&self) -> Result<(), String> {
+        let definitions: Vec<IndexDefinition> = self
+            .indexes
+            .iter()
+            .map(|index| index.definition())
+            .collect();
+        let bytes = serde_json::to_vec(&definitions).map_err(|e| e.to_string())?;
+        fs::write(self.dir.join(secondary::DEFINITIONS_FILE), bytes).map_err(|e| e.to_string())
+    }
+
+    /// Returns the schema of the documents of all the collection's tables and
+    /// memtables, or `None` if a table's is not loaded.
+    fn schema(&self) -> Option<Schema> {
+        if self.tables.len() as u64 != self.jstable_count {
+            return None;
+        }
+        let mut schema = self.memtable.schema().clone();
+        if let Some(frozen) = &self.frozen_memtable {
+            schema.merge(frozen.schema().clone());
+        }
+        for table in &self.tables {
+            schema.merge(table.schema.clone());
+        }
+        Some(schema)
+    }
+
+    fn find_index(&self, name: &str) -> Result<&SecondaryIndex, String> {
+        self.indexes
+            .iter()
+            .find(|index| index.name == name)
+            .ok_or_else(|| format!("Index '{}' not found", name))
+    }
+
+    /// Returns the IDs of the entries of an index whose values fall in
+    /// `range`, including replaced versions.
+    fn index_entries(&self, name: &str, range: &IndexRange) -> Result<Vec<String>, String> {
+        let index = self.find_index(name)?;
+        let mut ids: Vec<String> = index.memtable.ids(range).map(str::to_string).collect();
+        if let Some(frozen) = &index.frozen_memtable {
+            ids.extend(frozen.ids(range).map(str::to_string));
+        }
+        for i in 0..self.jstable_count {
+            let table_ids = secondary::read_ids(&self.index_table_path(i, name), range)
+                .map_err(|e| e.to_string())?;
+            ids.extend(table_ids);
+        }
+        Ok(ids)
+    }
+
+    /// Returns the IDs of the documents whose value in an index may fall in
+    /// any of `ranges`, in order and without duplicates. Entries outlive
+    /// updates and deletes, so the documents must be checked again.
+    fn index_ids(&self, name: &str, ranges: &[IndexRange]) -> Result<Vec<String>, String> {
+        let mut ids = Vec::new();
+        for range in ranges {
+            ids.extend(self.index_entries(name, range)?);
+        }
+        ids.sort();
+        ids.dedup();
+        Ok(ids)
+    }
+
+    /// Gathers the statistics of a full-text index for scoring `terms`.
+    fn text_stats(&self, name: &str, terms: &[String]) -> Result<TextStats, String> {
+        let index = self.find_index(name)?;
+        let mut counts = vec![index.memtable.counts()];
+        counts.extend(index.frozen_memtable.iter().map(|frozen| frozen.counts()));
+        for i in 0..self.jstable_count {
+            counts.push(
+                secondary::read_counts(&self.index_table_path(i, name))
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        let mut stats = TextStats {
+            documents: counts.iter().map(|(documents, _)| documents).sum(),
+            tokens: counts.iter().map(|(_, tokens)| tokens).sum(),
+            frequencies: HashMap::new(),
+        };
+        for term in terms {
+            let entries = self.index_entries(name, &IndexRange::term(term))?;
+            stats.frequencies.insert(term.clone(), entries.len() as u64);
+        }
+        Ok(stats)
+    }
+
+    /// Fetches the documents whose value in an index falls in `range`, keeping
+    /// those that satisfy `predicate` and projecting them.
+    fn index_scan<'a>(
+        &'a self,
+        name: &str,
+        ranges: &[IndexRange],
+        predicate: Expression<'a>,
+        projections: Option<Vec<Expression<'a>>>,
+    ) -> Result<impl Iterator<Item = ExecutionResult> + 'a, String> {
+        let ids = self.index_ids(name, ranges)?;
+        Ok(ids.into_iter().filter_map(move |id| {
+            let doc = self.get(&id)?;
+            if evaluate_document(&predicate, Some(id.as_str()), &doc) != Value::Bool(true) {
+                return None;
+            }
+            let doc = match &projections {
+                Some(projs) => project(projs, |e| evaluate_document(e, Some(id.as_str()), &doc)),
+                None => doc,
+            };
+            Some(ExecutionResult::Value(id, doc))
+        }))
+    }
+
+    /// Finds the documents whose vectors in a vector index are about the `k`
+    /// nearest to `query`: the nearest among the memtables' entries and
+    /// those of the probed lists of each index table. The candidates'
+    /// current documents are returned, nearest first, leaving out those
+    /// whose vector was since removed or has another length. While that
+    /// leaves fewer than `k`, twice as many candidates are fetched from each
+    /// source, until none has more.
+    fn vector_scan(
+        &self,
+        name: &str,
+        query: &[f64],
+        metric: Metric,
+        k: usize,
+    ) -> Result<Vec<ExecutionResult>, String> {
+        let index = self.find_index(name)?;
+        let mut fetch = k;
+        loop {
+            let mut sources = vec![
+                vector::nearest(index.memtable.vectors(), query, metric, fetch)
+                    .into_iter()
+                    .map(str::to_string)
+                    .collect::<Vec<_>>(),
+            ];
+            if let Some(frozen) = &index.frozen_memtable {
+                sources.push(
+                    vector::nearest(frozen.vectors(), query, metric, fetch)
+                        .into_iter()
+                        .map(str::to_string)
+                        .collect(),
+                );
+            }
+            for i in 0..self.jstable_count {
+                let table_ids =
+                    vector::read_nearest(&self.index_table_path(i, name), query, metric, fetch)
+                        .map_err(|e| e.to_string())?;
+                sources.push(table_ids);
+            }
+            // A source with fewer than asked for has no more
+            let exhausted = sources.iter().all(|ids| ids.len() < fetch);
+            let mut ids: Vec<String> = sources.into_iter().flatten().collect();
+            ids.sort();
+            ids.dedup();
+
+            // Entries outlive updates, so distances are taken on current documents
+            let mut found: Vec<(f64, String, Value)> = ids
+                .into_iter()
+                .filter_map(|id| {
+                    let doc = self.get(&id)?;
+                    let vector = vector::to_vector(secondary::field(&doc, &index.path)?)?;
+                    Some((metric.distance(query, &vector)?, id, doc))
+                })
+                .collect();
+            if found.len() < k && !exhausted {
+                fetch *= 2;
+                continue;
+            }
+            found.sort_by(|a, b| a.0.total_cmp(&b.0));
+            return Ok(found
+                .into_iter()
+                .take(k)
+                .map(|(_, id, doc)| ExecutionResult::Value(id, doc))
+                .collect());
+        }
+    }
 }
 
 impl Debug for Collection {
@@ -609,6 +1544,11 @@ pub struct DB {
     jstable_threshold: u64,
     index_threshold: u64,
     log_rotation_threshold: Option<u64>,
+    join_spill_threshold: usize,
+    distinct_spill_threshold: usize,
+    scan_parallelism: usize,
+    scan_pool: OnceLock<ScanPool>, // started by the first parallel scan
+    io_stats: Arc<IoStats>,
 }
 
 impl DB {
@@ -621,6 +1561,7 @@ impl DB {
     ) -> Self {
         fs::create_dir_all(root_dir).unwrap();
         let mut collections = HashMap::new();
+        let io_stats = Arc::new(IoStats::default());
 
         if let Ok(entries) = fs::read_dir(root_dir) {
             for entry in entries.flatten() {
@@ -651,6 +1592,7 @@ impl DB {
                             jstable_threshold,
                             index_threshold,
                             log_rotation_threshold,
+                            io_stats.clone(),
                         );
 
                         if log_rotation_threshold.is_some() {
@@ -676,6 +1618,7 @@ impl DB {
                                 }
                             }
                         }
+                        collection.index_memtable();
 
                         collections.insert(name, collection);
                     }
@@ -690,9 +1633,75 @@ impl DB {
             jstable_threshold,
             index_threshold,
             log_rotation_threshold,
+            join_spill_threshold: DEFAULT_JOIN_SPILL_THRESHOLD,
+            distinct_spill_threshold: DEFAULT_DISTINCT_SPILL_THRESHOLD,
+            scan_parallelism: thread::available_parallelism().map_or(1, |n| n.get()),
+            scan_pool: OnceLock::new(),
+            io_stats,
         }
     }
 
+    /// Returns the storage work done by reads so far, across all collections.
+    pub fn io_counts(&self) -> IoCounts {
+        self.io_stats.counts()
+    }
+
+    /// Sets the number of build-side rows a hash join keeps in memory.
+    pub fn set_join_spill_threshold(&mut self, rows: usize) {
+        self.join_spill_threshold = rows;
+    }
+
+    /// Returns the number of build-side rows a hash join keeps in memory.
+    pub fn join_spill_threshold(&self) -> usize {
+        self.join_spill_threshold
+    }
+
+    /// Sets the number of distinct rows `DISTINCT` keeps in memory.
+    pub fn set_distinct_spill_threshold(&mut self, rows: usize) {
+        self.distinct_spill_threshold = rows;
+    }
+
+    /// Returns the number of distinct rows `DISTINCT` keeps in memory.
+    pub fn distinct_spill_threshold(&self) -> usize {
+        self.distinct_spill_threshold
+    }
+
+    /// Sets the number of worker threads a scan filters and projects
+    /// documents on. 1 scans on the calling thread.
+    pub fn set_scan_parallelism(&mut self, threads: usize) {
+        self.scan_parallelism = threads.max(1);
+        // The next parallel scan starts a pool of the new size
+        self.scan_pool = OnceLock::new();
+    }
+
+    /// Returns the number of worker threads a scan filters and projects
+    /// documents on. Defaults to the number of CPU cores.
+    pub fn scan_parallelism(&self) -> usize {
+        self.scan_parallelism
+    }
+
+    /// Returns true if a scan of the whole collection runs on several worker
+    /// threads.
+    pub fn scans_in_parallel(&self, collection: &str) -> bool {
+        let all = (Bound::Unbounded, Bound::Unbounded);
+        self.scan_parallelism > 1
+            && self
+                .collections
+                .get(collection)
+                .is_some_and(|c| c.sources(None).tables.morsels(&all).len() > 1)
+    }
+
+    /// Creates an anonymous temporary file under the data directory, removed
+    /// once closed, for operators that spill to disk.
+    pub fn spill_file(&self) -> Result<fs::File, String> {
+        tempfile::tempfile_in(&self.root_dir).map_err(|e| e.to_string())
+    }
+
+    /// Returns true if the collection exists.
+    pub fn has_collection(&self, name: &str) -> bool {
+        self.collections.contains_key(name)
+    }
+
     fn get_collection_mut(&mut self, name: &str) -> Result<&mut Collection, String> {
         self.collections
             .get_mut(name)
@@ -718,6 +1727,7 @@ impl DB {
             self.jstable_threshold,
             self.index_threshold,
             self.log_rotation_threshold,
+            self.io_stats.clone(),
         );
         self.collections.insert(name.to_string(), collection);
         Ok(())
@@ -731,6 +1741,107 @@ impl DB {
         }
     }
 
+    /// Creates an index named `name` on the field at `path` of a collection.
+    /// Index names are unique across collections.
+    pub fn create_index(
+        &mut self,
+        collection: &str,
+        name: &str,
+        path: Vec<String>,
+        kind: IndexKind,
+    ) -> Result<(), String> {
+        if self.index_collection(name).is_some() {
+            return Err(format!("Index '{}' already exists", name));
+        }
+        self.get_collection_mut(collection)?
+            .create_index(name, path, kind)
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> Result<(), String> {
+        let collection = self
+            .index_collection(name)
+            .ok_or_else(|| format!("Index '{}' not found", name))?
+            .to_string();
+        self.get_collection_mut(&collection)?.drop_index(name)
+    }
+
+    /// Returns the collection an index belongs to.
+    fn index_collection(&self, name: &str) -> Option<&str> {
+        self.collections
+            .values()
+            .find(|c| c.indexes.iter().any(|index| index.name == name))
+            .map(|c| c.name.as_str())
+    }
+
+    /// Returns the name of an index of the given kind on the field at
+    /// `parts` of a collection.
+    pub fn find_index(&self, collection: &str, parts: &[&str], kind: IndexKind) -> Option<&str> {
+        let collection = self.collections.get(collection)?;
+        collection
+            .indexes
+            .iter()
+            .find(|index| index.covers(parts, kind))
+            .map(|index| index.name.as_str())
+    }
+
+    /// Fetches the documents of a collection whose value in an index falls in
+    /// any of `ranges` and that satisfy `predicate`.
+    pub fn index_scan<'a>(
+        &'a self,
+        collection: &str,
+        index: &str,
+        ranges: &[IndexRange],
+        predicate: Expression<'a>,
+        projections: Option<Vec<Expression<'a>>>,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
+        let iter =
+            self.get_collection(collection)?
+                .index_scan(index, ranges, predicate, projections)?;
+        Ok(Box::new(iter))
+    }
+
+    /// Finds the documents of a collection about nearest to `query` in a
+    /// vector index.
+    pub fn vector_scan<'a>(
+        &'a self,
+        collection: &str,
+        index: &str,
+        query: &[f64],
+        metric: Metric,
+        k: usize,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
+        let found = self
+            .get_collection(collection)?
+            .vector_scan(index, query, metric, k)?;
+        Ok(Box::new(found.into_iter()))
+    }
+
+    /// Returns the statistics of the full-text index on the field at `parts`
+    /// of a collection for scoring `terms`, if there is one.
+    pub fn text_stats(
+        &self,
+        collection: &str,
+        parts: &[&str],
+        terms: &[String],
+    ) -> Result<Option<TextStats>, String> {
+        match self.find_index(collection, parts, IndexKind::FullText) {
+            Some(index) => self
+                .get_collection(collection)?
+                .text_stats(index, terms)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns true if the field at `parts` of a collection only holds
+    /// `date-time` strings, as far as its schemas show.
+    pub fn is_date_time(&self, collection: &str, parts: &[&str]) -> bool {
+        self.collections
+            .get(collection)
+            .and_then(|collection| collection.schema())
+            .is_some_and(|schema| schema::is_date_time(&schema, parts))
+    }
+
     pub fn show_collections(&self) -> Vec<String> {
         self.collections.keys().cloned().collect()
     }
@@ -739,6 +1850,72 @@ impl DB {
         self.get_collection_mut(collection).map(|c| c.insert(doc))
     }
 
+    /// Inserts the rows of a query into a collection, returning how many were
+    /// inserted. Rows stream from the query in batches while the collection is
+    /// set aside; a query that reads the collection itself is run to
+    /// completion first, so it does not see its own inserts.
+    pub fn insert_plan(
+        &mut self,
+        collection: &str,
+        mut plan: LogicalPlan,
+    ) -> Result<usize, String> {
+        if plan.reads_collection(collection) {
+            let rows: Vec<Value> = {
+                let mut rows = execute_plan(plan, self)?;
+                let docs = rows.by_ref().map(|item| item.get_value()).collect();
+                rows.check()?;
+                docs
+            };
+            let count = rows.len();
+            let target = self.get_collection_mut(collection)?;
+            for doc in rows {
+                target.insert(doc);
+            }
+            return Ok(count);
+        }
+
+        let mut target = self
+            .collections
+            .remove(collection)
+            .ok_or_else(|| format!("Collection '{}' not found", collection))?;
+        let result = execute_plan(plan, self).and_then(|mut rows| {
+            let mut count = 0;
+            loop {
+                let batch: Vec<Value> = rows
+                    .by_ref()
+                    .take(INSERT_BATCH_SIZE)
+                    .map(|item| item.get_value())
+                    .collect();
+                if batch.is_empty() {
+                    rows.check()?;
+                    break Ok(count);
+                }
+                count += batch.len();
+                for doc in batch {
+                    target.insert(doc);
+                }
+            }
+        });
+        self.collections.insert(collection.to_string(), target);
+        result
+    }
+
+    /// Creates a collection holding the rows of a query, returning how many
+    /// there are. The collection is dropped again if the query fails.
+    pub fn create_collection_as(
+        &mut self,
+        name: &str,
+        mut plan: LogicalPlan,
+    ) -> Result<usize, String> {
+        if plan.reads_collection(name) {
+            return Err(format!("Collection '{}' not found", name));
+        }
+        self.create_collection(name)?;
+        self.insert_plan(name, plan).inspect_err(|_| {
+            let _ = self.drop_collection(name);
+        })
+    }
+
     pub fn delete(&mut self, collection: &str, id: &str) -> Result<(), String> {
         self.get_collection_mut(collection).map(|c| c.delete(id))
     }
@@ -748,15 +1925,43 @@ impl DB {
             .map(|c| c.update(id, doc))
     }
 
+    /// Scans the documents of a collection. Unprojected documents are
+    /// narrowed to `fields` if given.
     pub fn scan<'a>(
         &'a self,
         collection: &str,
         predicate: Option<Expression<'a>>,
         projections: Option<Vec<Expression<'a>>>,
+        fields: Option<FieldSet>,
+    ) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
+        self.scan_range(
+            collection,
+            (Bound::Unbounded, Bound::Unbounded),
+            predicate,
+            projections,
+            fields,
+        )
+    }
+
+    /// Scans the documents of a collection whose IDs fall within `range`,
+    /// on up to `scan_parallelism` worker threads.
+    pub fn scan_range<'a>(
+        &'a self,
+        collection: &str,
+        range: IdRange,
+        predicate: Option<Expression<'a>>,
+        projections: Option<Vec<Expression<'a>>>,
+        fields: Option<FieldSet>,
     ) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
         self.get_collection(collection).map(|c| {
-            Box::new(c.scan(predicate, projections))
-                as Box<dyn Iterator<Item = ExecutionResult> + 'a>
+            c.parallel_scan_range(
+                range,
+                predicate,
+                projections,
+                fields,
+                self.scan_parallelism,
+                &self.scan_pool,
+            )
         })
     }
 
@@ -1014,7 +2219,7 @@ mod tests {
             .unwrap();
 
         let results: HashMap<String, Value> = db
-            .scan("test", None, None)
+            .scan("test", None, None, None)
             .unwrap()
             .map(|r| (r.id().to_string(), r.get_value()))
             .collect();
@@ -1202,7 +2407,7 @@ mod tests {
         let projections = vec![Expression::FieldReference(vec!["b"], "b")];
 
         let results: Vec<Value> = db
-            .scan("test", Some(predicate), Some(projections))
+            .scan("test", Some(predicate), Some(projections), None)
             .unwrap()
             .map(|r| r.get_value())
             .collect();
@@ -1248,7 +2453,7 @@ mod tests {
         db.wait_for_flush("test").unwrap();
 
         let results: HashMap<String, Value> = db
-            .scan("test", None, None)
+            .scan("test", None, None, None)
             .unwrap()
             .map(|r| (r.id().to_string(), r.get_value()))
             .collect();
@@ -1285,7 +2490,7 @@ mod tests {
 
         // 3. Scan and Verify
         let results: HashMap<String, Value> = db
-            .scan("test", None, None)
+            .scan("test", None, None, None)
             .unwrap()
             .map(|r| (r.id().to_string(), r.get_value()))
             .collect();
@@ -1319,7 +2524,7 @@ mod tests {
         db.delete("test", &id).unwrap();
 
         let results: HashMap<String, Value> = db
-            .scan("test", None, None)
+            .scan("test", None, None, None)
             .unwrap()
             .map(|r| (r.id().to_string(), r.get_value()))
             .collect();
@@ -1357,7 +2562,7 @@ mod tests {
         let projections = vec![Expression::FieldReference(vec!["b"], "b")];
 
         let results: Vec<Value> = db
-            .scan("test", Some(predicate), Some(projections))
+            .scan("test", Some(predicate), Some(projections), None)
             .unwrap()
             .map(|r| r.get_value())
             .collect();
@@ -1404,7 +2609,7 @@ mod tests {
                 Some(1024 * 1024),
             );
             let results: HashMap<String, Value> = db
-                .scan("test", None, None)
+                .scan("test", None, None, None)
                 .unwrap()
                 .map(|r| (r.id().to_string(), r.get_value()))
                 .collect();
@@ -1412,6 +2617,240 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_scan_range() {
+        let dir = tempdir().unwrap();
+        let mut db = DB::new(
+            dir.path().to_str().unwrap(),
+            MEMTABLE_THRESHOLD,
+            JSTABLE_THRESHOLD,
+            INDEX_THRESHOLD,
+            Some(1024 * 1024),
+        );
+        db.create_collection("test").unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..(MEMTABLE_THRESHOLD * 2 + 5) {
+            ids.push(
+                db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                    .unwrap(),
+            );
+        }
+        db.wait_for_flush("test").unwrap();
+        ids.sort();
+
+        let range = (
+            Bound::Excluded(ids[3].clone()),
+            Bound::Included(ids[17].clone()),
+        );
+        let mut results: Vec<String> = db
+            .scan_range("test", range, None, None, None)
+            .unwrap()
+            .map(|r| r.id().to_string())
+            .collect();
+        results.sort();
+        assert_eq!(results, ids[4..18].to_vec());
+    }
+
+    #[test]
+    fn test_parallel_scan() {
+        let dir = tempdir().unwrap();
+        // Small sparse index blocks, so that the JSTables split into morsels
+        let mut db = DB::new(
+            dir.path().to_str().unwrap(),
+            100,
+            JSTABLE_THRESHOLD,
+            16,
+            Some(1024 * 1024),
+        );
+        db.create_collection("test").unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..400 {
+            ids.push(
+                db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                    .unwrap(),
+            );
+        }
+        db.wait_for_flush("test").unwrap();
+        let col = db.collections.get("test").unwrap();
+        assert!(
+            col.sources(None)
+                .tables
+                .morsels(&(Bound::Unbounded, Bound::Unbounded))
+                .len()
+                > 4
+        );
+
+        // With the memtables empty, documents come in ID order
+        db.set_scan_parallelism(4);
+        let scanned: Vec<String> = db
+            .scan("test", None, None, None)
+            .unwrap()
+            .map(|r| r.id().to_string())
+            .collect();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(scanned, sorted);
+
+        // Abandoning a scan leaves the pool free for the next
+        assert_eq!(
+            db.scan("test", None, None, None).unwrap().take(3).count(),
+            3
+        );
+        let scanned: Vec<String> = db
+            .scan("test", None, None, None)
+            .unwrap()
+            .map(|r| r.id().to_string())
+            .collect();
+        assert_eq!(scanned, sorted);
+
+        // Updates and deletes in newer tables and the memtable shadow older
+        // versions in every morsel
+        for (i, id) in ids.iter().enumerate().step_by(7) {
+            if i % 2 == 0 {
+                db.delete("test", id).unwrap();
+            } else {
+                db.update("test", id, serde_to_jsonb(json!({ "a": i + 1000 })))
+                    .unwrap();
+            }
+        }
+        let filtered_scan = |db: &DB| {
+            let predicate = Expression::Binary {
+                left: Box::new(Expression::FieldReference(vec!["a"], "a")),
+                op: crate::expression::BinaryOperator::Gt,
+                right: Box::new(Expression::Literal(serde_to_jsonb(json!(100)))),
+            };
+            let mut results: Vec<(String, serde_json::Value)> = db
+                .scan("test", Some(predicate), None, None)
+                .unwrap()
+                .map(|r| (r.id().to_string(), crate::jsonb_to_serde(&r.get_value())))
+                .collect();
+            results.sort_by(|a, b| a.0.cmp(&b.0));
+            results
+        };
+        let parallel = filtered_scan(&db);
+        // Documents in the memtable are returned once, not once per morsel
+        let mut all: Vec<String> = db
+            .scan("test", None, None, None)
+            .unwrap()
+            .map(|r| r.id().to_string())
+            .collect();
+        assert_eq!(all.len(), 400 - ids.iter().step_by(14).count());
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), 400 - ids.iter().step_by(14).count());
+        db.set_scan_parallelism(1);
+        let serial = filtered_scan(&db);
+        assert_eq!(parallel, serial);
+        assert_eq!(parallel.len(), 285);
+    }
+
+    #[test]
+    fn test_morsels() {
+        let dir = tempdir().unwrap();
+        let mut db = DB::new(
+            dir.path().to_str().unwrap(),
+            100,
+            JSTABLE_THRESHOLD,
+            16,
+            None,
+        );
+        db.create_collection("test").unwrap();
+        let mut ids = Vec::new();
+        for i in 0..100 {
+            ids.push(
+                db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                    .unwrap(),
+            );
+        }
+        db.wait_for_flush("test").unwrap();
+        ids.sort();
+
+        // Morsels are contiguous, start and end at the bounds of the range,
+        // and together hold the documents in it in ID order
+        let range = (
+            Bound::Excluded(ids[10].clone()),
+            Bound::Included(ids[90].clone()),
+        );
+        let sources = db.collections.get("test").unwrap().sources(None);
+        let morsels = sources.tables.morsels(&range);
+        assert!(morsels.len() > 1);
+        assert_eq!(morsels[0].0, range.0);
+        assert_eq!(morsels[morsels.len() - 1].1, range.1);
+        for pair in morsels.windows(2) {
+            let (Bound::Excluded(end), Bound::Included(start)) = (&pair[0].1, &pair[1].0) else {
+                panic!("Morsels are not contiguous: {:?}", pair);
+            };
+            assert_eq!(end, start);
+        }
+        let scanned: Vec<String> = morsels
+            .into_iter()
+            .flat_map(|morsel| sources.clone().scan_range(morsel, None, None, None))
+            .map(|r| r.id().to_string())
+            .collect();
+        assert_eq!(scanned, ids[11..91].to_vec());
+    }
+
+    #[test]
+    fn test_scan_fields() {
+        let dir = tempdir().unwrap();
+        let mut db = DB::new(
+            dir.path().to_str().unwrap(),
+            MEMTABLE_THRESHOLD,
+            JSTABLE_THRESHOLD,
+            INDEX_THRESHOLD,
+            Some(1024 * 1024),
+        );
+        db.create_collection("test").unwrap();
+
+        for i in 0..(MEMTABLE_THRESHOLD + 1) {
+            let doc = json!({"a": {"b": i, "c": "wide"}, "d": i, "e": "wide"});
+            db.insert("test", serde_to_jsonb(doc)).unwrap();
+        }
+        db.wait_for_flush("test").unwrap();
+
+        let mut fields = FieldSet::default();
+        fields.insert(&["a", "b"]);
+        fields.insert(&["d"]);
+        fields.insert(&["missing"]);
+        let results: Vec<ExecutionResult> =
+            db.scan("test", None, None, Some(fields)).unwrap().collect();
+
+        // Both memtable and disk documents are narrowed
+        assert_eq!(results.len(), MEMTABLE_THRESHOLD + 1);
+        for result in results {
+            let doc = crate::jsonb_to_serde(&result.get_value());
+            assert_eq!(doc, json!({"a": {"b": doc["d"]}, "d": doc["d"]}));
+        }
+    }
+
+    #[test]
+    fn test_range_start_offset() {
+        let index = vec![
+            ("b".to_string(), 0),
+            ("f".to_string(), 100),
+            ("k".to_string(), 200),
+        ];
+        assert_eq!(range_start_offset(&index, &Bound::Unbounded), 0);
+        assert_eq!(
+            range_start_offset(&index, &Bound::Included("a".to_string())),
+            0
+        );
+        assert_eq!(
+            range_start_offset(&index, &Bound::Included("f".to_string())),
+            100
+        );
+        assert_eq!(
+            range_start_offset(&index, &Bound::Excluded("g".to_string())),
+            100
+        );
+        assert_eq!(
+            range_start_offset(&index, &Bound::Included("z".to_string())),
+            200
+        );
+    }
+
     #[test]
     fn test_sanitize_filename_edge_cases() {
         assert_eq!(sanitize_filename("valid123"), "valid123");
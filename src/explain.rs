@@ -0,0 +1,311 @@
+use crate::ExecutionResult;
+use crate::db::{DB, IoCounts};
+use crate::optimizer::optimize;
+use crate::query::{
+    Batch, ErrorSlot, JoinKind, LogicalPlan, PhysicalPlan, SetOperator, open_rows, plan_physical,
+    resolve_subqueries,
+};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::ops::Bound;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Returns the lines of `EXPLAIN`: the physical plan, one operator per line,
+/// with inputs indented below the operator that consumes them.
+///
+/// With `analyze`, the query is run to completion and each operator is
+/// annotated with the rows it produced, and the time taken and the JSTables
+/// opened, filter skips and bytes read while it and its inputs ran.
+pub fn explain<'a>(
+    mut plan: LogicalPlan<'a>,
+    db: &'a DB,
+    analyze: bool,
+) -> Result<Vec<String>, String> {
+    if analyze {
+        resolve_subqueries(&mut plan, db)?;
+    }
+    let physical = plan_physical(optimize(plan), db);
+    let mut lines = Vec::new();
+    describe(&physical, 0, &mut lines);
+    if !analyze {
+        return Ok(lines);
+    }
+
+    let profiler = Profiler::default();
+    let start = Instant::now();
+    let errors = ErrorSlot::default();
+    open_rows(physical, db, Some(&profiler), &errors)?.for_each(drop);
+    errors.check()?;
+    let elapsed = start.elapsed();
+    let mut lines: Vec<String> = lines
+        .into_iter()
+        .zip(profiler.operators.into_inner())
+        .map(|(line, stats)| format!("{} ({})", line, stats))
+        .collect();
+    lines.push(format!(
+        "Execution time: {:.3} ms",
+        elapsed.as_secs_f64() * 1000.0
+    ));
+    Ok(lines)
+}
+
+fn describe(plan: &PhysicalPlan, depth: usize, out: &mut Vec<String>) {
+    match depth {
+        0 => out.push(plan.to_string()),
+        _ => out.push(format!("{}-> {}", "   ".repeat(depth - 1), plan)),
+    }
+    for input in plan.inputs() {
+        describe(input, depth + 1, out);
+    }
+}
+
+/// Collects the statistics of each operator of a plan as it is opened, in the
+/// same order as `describe` lists them.
+#[derive(Default)]
+pub(crate) struct Profiler {
+    operators: RefCell<Vec<Rc<OperatorStats>>>,
+}
+
+impl Profiler {
+    /// Opens an operator with `open`, wrapping it to count its rows, time and
+    /// storage work. Opening counts too, as some operators consume their
+    /// input there.
+    pub(crate) fn profile<'a, T: RowCount + 'a>(
+        &self,
+        db: &'a DB,
+        open: impl FnOnce() -> Result<Box<dyn Iterator<Item = T> + 'a>, String>,
+    ) -> Result<Box<dyn Iterator<Item = T> + 'a>, String> {
+        let stats = Rc::new(OperatorStats::default());
+        self.operators.borrow_mut().push(stats.clone());
+        let input = stats.measure(db, open)?;
+        Ok(Box::new(ProfiledOperator { input, stats, db }))
+    }
+}
+
+#[derive(Default)]
+struct OperatorStats {
+    rows: Cell<u64>,
+    time: Cell<Duration>,
+    io: Cell<IoCounts>,
+}
+
+impl OperatorStats {
+    /// Runs `f`, adding the time it takes and the storage work it does.
+    fn measure<R>(&self, db: &DB, f: impl FnOnce() -> R) -> R {
+        let (start, io) = (Instant::now(), db.io_counts());
+        let result = f();
+        self.time.set(self.time.get() + start.elapsed());
+        self.io.set(self.io.get() + (db.io_counts() - io));
+        result
+    }
+}
+
+impl fmt::Display for OperatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let io = self.io.get();
+        write!(
+            f,
+            "rows={} time={:.3}ms jstables={} filter_skips={} zone_skips={} schema_skips={} bytes_read={}",
+            self.rows.get(),
+            self.time.get().as_secs_f64() * 1000.0,
+            io.jstables_opened,
+            io.filter_skips,
+            io.zone_skips,
+            io.schema_skips,
+            io.bytes_read
+        )
+    }
+}
+
+/// An item produced by an operator, which counts as some number of rows.
+pub(crate) trait RowCount {
+    fn row_count(&self) -> u64;
+}
+
+impl RowCount for ExecutionResult {
+    fn row_count(&self) -> u64 {
+        1
+    }
+}
+
+impl RowCount for Batch {
+    fn row_count(&self) -> u64 {
+        self.items.len() as u64
+    }
+}
+
+struct ProfiledOperator<'a, T> {
+    input: Box<dyn Iterator<Item = T> + 'a>,
+    stats: Rc<OperatorStats>,
+    db: &'a DB,
+}
+
+impl<T: RowCount> Iterator for ProfiledOperator<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        let item = self.stats.measure(self.db, || self.input.next());
+        if let Some(item) = &item {
+            self.stats
+                .rows
+                .set(self.stats.rows.get() + item.row_count());
+        }
+        item
+    }
+}
+
+impl fmt::Display for PhysicalPlan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PhysicalPlan::Scan {
+                collection,
+                range,
+                predicate,
+                projections,
+                fields,
+            } => {
+                write!(f, "Scan {}", collection)?;
+                if let Some((lower, upper)) = range {
+                    let mut bounds = Vec::new();
+                    match lower {
+                        Bound::Included(id) => bounds.push(format!("_id >= {}", quote(id))),
+                        Bound::Excluded(id) => bounds.push(format!("_id > {}", quote(id))),
+                        Bound::Unbounded => {}
+                    }
+                    match upper {
+                        Bound::Included(id) => bounds.push(format!("_id <= {}", quote(id))),
+                        Bound::Excluded(id) => bounds.push(format!("_id < {}", quote(id))),
+                        Bound::Unbounded => {}
+                    }
+                    write!(f, " [range: {}]", bounds.join(" AND "))?;
+                }
+                if let Some(predicate) = predicate {
+                    write!(f, " [filter: {}]", predicate)?;
+                }
+                if let Some(projections) = projections {
+                    write!(f, " [project: {}]", join(projections))?;
+                }
+                if let Some(fields) = fields {
+                    write!(f, " [fields: {}]", fields)?;
+                }
+                Ok(())
+            }
+            PhysicalPlan::IdLookup { collection, keys } => {
+                let keys: Vec<String> = keys.iter().map(|key| quote(key)).collect();
+                write!(f, "IdLookup {} [_id IN ({})]", collection, keys.join(", "))
+            }
+            PhysicalPlan::IndexScan {
+                collection,
+                index,
+                predicate,
+                projections,
+                ..
+            } => {
+                write!(
+                    f,
+                    "IndexScan {} [index: {}] [filter: {}]",
+                    collection, index, predicate
+                )?;
+                if let Some(projections) = projections {
+                    write!(f, " [project: {}]", join(projections))?;
+                }
+                Ok(())
+            }
+            PhysicalPlan::VectorScan {
+                collection,
+                index,
+                metric,
+                k,
+                ..
+            } => write!(
+                f,
+                "VectorScan {} [index: {}] [metric: {}] [nearest: {}]",
+                collection, index, metric, k
+            ),
+            PhysicalPlan::Filter { predicate, .. } => write!(f, "Filter {}", predicate),
+            PhysicalPlan::Project { projections, .. } => {
+                write!(f, "Project {}", join(projections))
+            }
+            PhysicalPlan::Limit { limit, .. } => write!(f, "Limit {}", limit),
+            PhysicalPlan::Offset { offset, .. } => write!(f, "Offset {}", offset),
+            PhysicalPlan::Sort { keys, .. } => write!(f, "Sort {}", join(keys)),
+            PhysicalPlan::Unnest { expr, alias, .. } => write!(f, "Unnest {} AS {}", expr, alias),
+            PhysicalPlan::HashJoin {
+                kind,
+                keys,
+                residual,
+                ..
+            } => {
+                let keys: Vec<String> =
+                    keys.iter().map(|(l, r)| format!("{} = {}", l, r)).collect();
+                write!(f, "HashJoin {} ON {}", join_kind(*kind), keys.join(" AND "))?;
+                if let Some(residual) = residual {
+                    write!(f, " [filter: {}]", residual)?;
+                }
+                Ok(())
+            }
+            PhysicalPlan::IndexJoin {
+                collection,
+                kind,
+                right_alias,
+                left_key,
+                residual,
+                ..
+            } => {
+                write!(
+                    f,
+                    "IndexJoin {} {} AS {} ON {}._id = {}",
+                    join_kind(*kind),
+                    collection,
+                    right_alias,
+                    right_alias,
+                    left_key
+                )?;
+                if let Some(residual) = residual {
+                    write!(f, " [filter: {}]", residual)?;
+                }
+                Ok(())
+            }
+            PhysicalPlan::Window { windows, .. } => {
+                let calls: Vec<String> = windows.iter().map(|(call, _)| call.to_string()).collect();
+                write!(f, "Window {}", calls.join(", "))
+            }
+            PhysicalPlan::Distinct { on, .. } if on.is_empty() => write!(f, "Distinct"),
+            PhysicalPlan::Distinct { on, .. } => write!(f, "Distinct ON ({})", join(on)),
+            PhysicalPlan::SetOperation { op, all, .. } => {
+                let name = match op {
+                    SetOperator::Union => "Union",
+                    SetOperator::Intersect => "Intersect",
+                    SetOperator::Except => "Except",
+                };
+                write!(f, "{}{}", name, if *all { " All" } else { "" })
+            }
+            PhysicalPlan::Flatten { .. } => write!(f, "Flatten"),
+            PhysicalPlan::BatchScan { batch_size, .. } => {
+                write!(f, "BatchScan [batch size: {}]", batch_size)
+            }
+            PhysicalPlan::BatchFilter { predicate, .. } => write!(f, "BatchFilter {}", predicate),
+            PhysicalPlan::BatchProject { projections, .. } => {
+                write!(f, "BatchProject {}", join(projections))
+            }
+            PhysicalPlan::BatchLimit { limit, .. } => write!(f, "BatchLimit {}", limit),
+            PhysicalPlan::BatchOffset { offset, .. } => write!(f, "BatchOffset {}", offset),
+        }
+    }
+}
+
+fn join<T: fmt::Display>(items: &[T]) -> String {
+    let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+fn join_kind(kind: JoinKind) -> &'static str {
+    match kind {
+        JoinKind::Inner => "Inner",
+        JoinKind::Left => "Left",
+    }
+}
+
+fn quote(id: &str) -> String {
+    format!("'{}'", id.replace('\'', "''"))
+}
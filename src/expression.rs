@@ -1,13 +1,35 @@
-use crate::{LazyDocument, SerdeWrapper, Value, make_static};
+use crate::fulltext::{self, TextStats};
+use crate::geo::{self, BBox, Coord, Geometry};
+use crate::query::{LogicalPlan, SortKey};
+use crate::temporal::{self, DatePart, Interval};
+use crate::vector::{self, Metric};
+use crate::{ExecutionResult, LazyDocument, SerdeWrapper, Value, make_static};
+use bumpalo::Bump;
+use chrono::{DateTime, Utc};
 use jsonb_schema::jsonpath::JsonPath;
 use jsonb_schema::{Number, OwnedJsonb, RawJsonb};
+use regex::Regex;
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Arc;
+
+/// Name of the pseudo-field that refers to the ID of a document.
+pub const ID_FIELD: &str = "_id";
+
+/// Prefix of the fields a `Window` plan node stores its results in. SQL has
+/// no way to write it, and `*` projections leave such fields out.
+pub const WINDOW_FIELD_PREFIX: &str = "\u{0}";
 
 #[derive(Debug, Clone)]
 pub enum Expression<'a> {
     FieldReference(Vec<&'a str>, &'a str), // (split path in arena, raw string in arena)
     JsonPath(Box<JsonPath<'a>>, &'a str),  // (compiled path, raw string in arena)
     Literal(Value),
+    /// A `$n` or `?` placeholder, replaced by a literal when a prepared
+    /// statement is bound. Numbered from 1.
+    Parameter(usize),
     Binary {
         left: Box<Expression<'a>>,
         op: BinaryOperator,
@@ -18,10 +40,262 @@ pub enum Expression<'a> {
         op: LogicalOperator,
         right: Box<Expression<'a>>,
     },
+    Unary {
+        op: UnaryOperator,
+        expr: Box<Expression<'a>>,
+    },
+    InList {
+        expr: Box<Expression<'a>>,
+        list: Vec<Expression<'a>>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expression<'a>>,
+        low: Box<Expression<'a>>,
+        high: Box<Expression<'a>>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expression<'a>>,
+        pattern: Box<Expression<'a>>,
+        negated: bool,
+        case_insensitive: bool, // ILIKE
+    },
+    IsNull {
+        expr: Box<Expression<'a>>,
+        negated: bool,
+    },
+    IsMissing {
+        expr: Box<Expression<'a>>,
+        negated: bool,
+    },
     Function {
         func: ScalarFunction,
         args: Vec<Expression<'a>>,
     },
+    Case {
+        operand: Option<Box<Expression<'a>>>,
+        branches: Vec<(Expression<'a>, Expression<'a>)>, // (WHEN, THEN)
+        else_result: Option<Box<Expression<'a>>>,
+    },
+    Quantified {
+        left: Box<Expression<'a>>,
+        op: BinaryOperator, // a comparison
+        right: Box<Expression<'a>>,
+        all: bool, // ALL rather than ANY
+    },
+    JsonExists {
+        expr: Box<Expression<'a>>,
+        path: Box<JsonPath<'a>>,
+        raw: &'a str, // path text in arena
+    },
+    /// `MATCH(expr, 'query')`: the BM25 score of a text against the terms of
+    /// the query, using the statistics of a full-text index once planned
+    Match {
+        expr: Box<Expression<'a>>,
+        query: &'a str, // query text in arena
+        terms: Vec<String>,
+        stats: Option<Arc<TextStats>>,
+    },
+    // Uncorrelated subqueries, replaced by their results before execution
+    InSubquery {
+        expr: Box<Expression<'a>>,
+        subquery: Box<LogicalPlan<'a>>,
+        negated: bool,
+    },
+    Exists {
+        subquery: Box<LogicalPlan<'a>>,
+        negated: bool,
+    },
+    /// A window function call, replaced by a field of the `Window` plan node's
+    /// output before execution
+    Window(Box<WindowCall<'a>>),
+    Alias(Box<Expression<'a>>, &'a str), // (expression, output name in arena)
+    Wildcard,                            // The whole document (`SELECT *`)
+}
+
+impl<'a> Expression<'a> {
+    /// Returns the direct sub-expressions, for rewriting an expression tree.
+    pub fn children_mut(&mut self) -> Vec<&mut Expression<'a>> {
+        match self {
+            Expression::FieldReference(..)
+            | Expression::JsonPath(..)
+            | Expression::Literal(_)
+            | Expression::Parameter(_)
+            | Expression::Exists { .. }
+            | Expression::Wildcard => Vec::new(),
+            Expression::Binary { left, right, .. }
+            | Expression::Logical { left, right, .. }
+            | Expression::Quantified { left, right, .. } => vec![left.as_mut(), right.as_mut()],
+            Expression::Unary { expr, .. }
+            | Expression::IsNull { expr, .. }
+            | Expression::IsMissing { expr, .. }
+            | Expression::JsonExists { expr, .. }
+            | Expression::Match { expr, .. }
+            | Expression::InSubquery { expr, .. }
+            | Expression::Alias(expr, _) => vec![expr.as_mut()],
+            Expression::InList { expr, list, .. } => {
+                let mut children = vec![expr.as_mut()];
+                children.extend(list.iter_mut());
+                children
+            }
+            Expression::Between {
+                expr, low, high, ..
+            } => vec![expr.as_mut(), low.as_mut(), high.as_mut()],
+            Expression::Like { expr, pattern, .. } => vec![expr.as_mut(), pattern.as_mut()],
+            Expression::Function { args, .. } => args.iter_mut().collect(),
+            Expression::Window(call) => call.expressions_mut(),
+            Expression::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                let mut children: Vec<&mut Expression<'a>> = Vec::new();
+                children.extend(operand.as_deref_mut());
+                for (when, then) in branches {
+                    children.push(when);
+                    children.push(then);
+                }
+                children.extend(else_result.as_deref_mut());
+                children
+            }
+        }
+    }
+
+    /// Copies the expression into `arena`, so that it can outlive the arena
+    /// it was parsed in. JSON paths are parsed again from their text. Returns
+    /// `None` for subqueries and window calls, which are replaced before
+    /// execution.
+    pub fn copy_into<'b>(&self, arena: &'b Bump) -> Option<Expression<'b>> {
+        let text = |s: &str| -> &'b str { arena.alloc_str(s) };
+        let boxed = |expr: &Expression| expr.copy_into(arena).map(Box::new);
+        let copy_all = |exprs: &[Expression]| -> Option<Vec<Expression<'b>>> {
+            exprs.iter().map(|expr| expr.copy_into(arena)).collect()
+        };
+        Some(match self {
+            Expression::FieldReference(parts, raw) => {
+                Expression::FieldReference(parts.iter().map(|part| text(part)).collect(), text(raw))
+            }
+            Expression::JsonPath(_, raw) => {
+                let raw = text(raw);
+                let path = jsonb_schema::jsonpath::parse_json_path(raw.as_bytes()).ok()?;
+                Expression::JsonPath(Box::new(path), raw)
+            }
+            Expression::Literal(value) => Expression::Literal(value.clone()),
+            Expression::Parameter(n) => Expression::Parameter(*n),
+            Expression::Binary { left, op, right } => Expression::Binary {
+                left: boxed(left)?,
+                op: op.clone(),
+                right: boxed(right)?,
+            },
+            Expression::Logical { left, op, right } => Expression::Logical {
+                left: boxed(left)?,
+                op: op.clone(),
+                right: boxed(right)?,
+            },
+            Expression::Unary { op, expr } => Expression::Unary {
+                op: op.clone(),
+                expr: boxed(expr)?,
+            },
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => Expression::InList {
+                expr: boxed(expr)?,
+                list: copy_all(list)?,
+                negated: *negated,
+            },
+            Expression::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expression::Between {
+                expr: boxed(expr)?,
+                low: boxed(low)?,
+                high: boxed(high)?,
+                negated: *negated,
+            },
+            Expression::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+            } => Expression::Like {
+                expr: boxed(expr)?,
+                pattern: boxed(pattern)?,
+                negated: *negated,
+                case_insensitive: *case_insensitive,
+            },
+            Expression::IsNull { expr, negated } => Expression::IsNull {
+                expr: boxed(expr)?,
+                negated: *negated,
+            },
+            Expression::IsMissing { expr, negated } => Expression::IsMissing {
+                expr: boxed(expr)?,
+                negated: *negated,
+            },
+            Expression::Function { func, args } => Expression::Function {
+                func: func.clone(),
+                args: copy_all(args)?,
+            },
+            Expression::Case {
+                operand,
+                branches,
+                else_result,
+            } => Expression::Case {
+                operand: match operand {
+                    Some(operand) => Some(boxed(operand)?),
+                    None => None,
+                },
+                branches: branches
+                    .iter()
+                    .map(|(when, then)| Some((when.copy_into(arena)?, then.copy_into(arena)?)))
+                    .collect::<Option<_>>()?,
+                else_result: match else_result {
+                    Some(else_result) => Some(boxed(else_result)?),
+                    None => None,
+                },
+            },
+            Expression::Quantified {
+                left,
+                op,
+                right,
+                all,
+            } => Expression::Quantified {
+                left: boxed(left)?,
+                op: op.clone(),
+                right: boxed(right)?,
+                all: *all,
+            },
+            Expression::JsonExists { expr, raw, .. } => {
+                let raw = text(raw);
+                let path = jsonb_schema::jsonpath::parse_json_path(raw.as_bytes()).ok()?;
+                Expression::JsonExists {
+                    expr: boxed(expr)?,
+                    path: Box::new(path),
+                    raw,
+                }
+            }
+            Expression::Match {
+                expr,
+                query,
+                terms,
+                stats,
+            } => Expression::Match {
+                expr: boxed(expr)?,
+                query: text(query),
+                terms: terms.clone(),
+                stats: stats.clone(),
+            },
+            Expression::InSubquery { .. } | Expression::Exists { .. } | Expression::Window(_) => {
+                return None;
+            }
+            Expression::Alias(expr, name) => Expression::Alias(boxed(expr)?, text(name)),
+            Expression::Wildcard => Expression::Wildcard,
+        })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -32,6 +306,83 @@ pub enum BinaryOperator {
     Lte,
     Gt,
     Gte,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    Contains,    // @>
+    ContainedBy, // <@
+}
+
+impl BinaryOperator {
+    /// Returns the operator to use when the operands are swapped.
+    pub fn flip(&self) -> Option<BinaryOperator> {
+        match self {
+            BinaryOperator::Eq => Some(BinaryOperator::Eq),
+            BinaryOperator::Neq => Some(BinaryOperator::Neq),
+            BinaryOperator::Lt => Some(BinaryOperator::Gt),
+            BinaryOperator::Lte => Some(BinaryOperator::Gte),
+            BinaryOperator::Gt => Some(BinaryOperator::Lt),
+            BinaryOperator::Gte => Some(BinaryOperator::Lte),
+            BinaryOperator::Plus => Some(BinaryOperator::Plus),
+            BinaryOperator::Multiply => Some(BinaryOperator::Multiply),
+            BinaryOperator::Contains => Some(BinaryOperator::ContainedBy),
+            BinaryOperator::ContainedBy => Some(BinaryOperator::Contains),
+            BinaryOperator::Minus
+            | BinaryOperator::Divide
+            | BinaryOperator::Modulo
+            | BinaryOperator::Concat => None,
+        }
+    }
+
+    /// Returns true for the comparison operators, which evaluate to a boolean.
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Eq
+                | BinaryOperator::Neq
+                | BinaryOperator::Lt
+                | BinaryOperator::Lte
+                | BinaryOperator::Gt
+                | BinaryOperator::Gte
+        )
+    }
+
+    /// Returns true for the numeric operators `+ - * / %`.
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Plus
+                | BinaryOperator::Minus
+                | BinaryOperator::Multiply
+                | BinaryOperator::Divide
+                | BinaryOperator::Modulo
+        )
+    }
+}
+
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Eq => "=",
+            BinaryOperator::Neq => "!=",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::Lte => "<=",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Gte => ">=",
+            BinaryOperator::Plus => "+",
+            BinaryOperator::Minus => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Concat => "||",
+            BinaryOperator::Contains => "@>",
+            BinaryOperator::ContainedBy => "<@",
+        };
+        write!(f, "{}", symbol)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -40,6 +391,30 @@ pub enum LogicalOperator {
     Or,
 }
 
+impl fmt::Display for LogicalOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogicalOperator::And => write!(f, "AND"),
+            LogicalOperator::Or => write!(f, "OR"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+
+impl fmt::Display for UnaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnaryOperator::Neg => write!(f, "-"),
+            UnaryOperator::Not => write!(f, "NOT "),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScalarFunction {
     Abs,
@@ -66,13 +441,599 @@ pub enum ScalarFunction {
     Sqrt,
     Tan,
     Tanh,
+    // String
+    Lower,
+    Upper,
+    Length,
+    Substr,
+    Trim,
+    Ltrim,
+    Rtrim,
+    Replace,
+    Split,
+    RegexpMatch,
+    // JSON
+    JsonTypeof,
+    ArrayLength,
+    Keys,
+    ToJson,
+    ParseJson,
+    // Conditional
+    Coalesce,
+    Nullif,
+    // Date/time
+    Now,
+    ToTimestamp,
+    DateTrunc,
+    DatePart,
+    // Vector
+    VectorDistance,
+    // Spatial
+    StPoint,
+    StDistance,
+    StDwithin,
+    StContains,
+    StWithinBbox,
+}
+
+impl ScalarFunction {
+    /// Returns the SQL name of the function.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScalarFunction::Abs => "ABS",
+            ScalarFunction::Acos => "ACOS",
+            ScalarFunction::Acosh => "ACOSH",
+            ScalarFunction::Asin => "ASIN",
+            ScalarFunction::Atan => "ATAN",
+            ScalarFunction::Atan2 => "ATAN2",
+            ScalarFunction::Ceil => "CEIL",
+            ScalarFunction::Cos => "COS",
+            ScalarFunction::Cosh => "COSH",
+            ScalarFunction::Div => "DIV",
+            ScalarFunction::Exp => "EXP",
+            ScalarFunction::Floor => "FLOOR",
+            ScalarFunction::Ln => "LN",
+            ScalarFunction::Log => "LOG",
+            ScalarFunction::Log10 => "LOG10",
+            ScalarFunction::Pow => "POW",
+            ScalarFunction::Rand => "RAND",
+            ScalarFunction::Round => "ROUND",
+            ScalarFunction::Sign => "SIGN",
+            ScalarFunction::Sin => "SIN",
+            ScalarFunction::Sinh => "SINH",
+            ScalarFunction::Sqrt => "SQRT",
+            ScalarFunction::Tan => "TAN",
+            ScalarFunction::Tanh => "TANH",
+            ScalarFunction::Lower => "LOWER",
+            ScalarFunction::Upper => "UPPER",
+            ScalarFunction::Length => "LENGTH",
+            ScalarFunction::Substr => "SUBSTR",
+            ScalarFunction::Trim => "TRIM",
+            ScalarFunction::Ltrim => "LTRIM",
+            ScalarFunction::Rtrim => "RTRIM",
+            ScalarFunction::Replace => "REPLACE",
+            ScalarFunction::Split => "SPLIT",
+            ScalarFunction::RegexpMatch => "REGEXP_MATCH",
+            ScalarFunction::JsonTypeof => "JSON_TYPEOF",
+            ScalarFunction::ArrayLength => "ARRAY_LENGTH",
+            ScalarFunction::Keys => "KEYS",
+            ScalarFunction::ToJson => "TO_JSON",
+            ScalarFunction::ParseJson => "PARSE_JSON",
+            ScalarFunction::Coalesce => "COALESCE",
+            ScalarFunction::Nullif => "NULLIF",
+            ScalarFunction::Now => "NOW",
+            ScalarFunction::ToTimestamp => "TO_TIMESTAMP",
+            ScalarFunction::DateTrunc => "DATE_TRUNC",
+            ScalarFunction::DatePart => "DATE_PART",
+            ScalarFunction::VectorDistance => "VECTOR_DISTANCE",
+            ScalarFunction::StPoint => "ST_POINT",
+            ScalarFunction::StDistance => "ST_DISTANCE",
+            ScalarFunction::StDwithin => "ST_DWITHIN",
+            ScalarFunction::StContains => "ST_CONTAINS",
+            ScalarFunction::StWithinBbox => "ST_WITHIN_BBOX",
+        }
+    }
+
+    /// Returns the minimum and maximum (`None` if unbounded) number of arguments.
+    pub fn arity(&self) -> (usize, Option<usize>) {
+        match self {
+            ScalarFunction::Rand | ScalarFunction::Now => (0, Some(0)),
+            ScalarFunction::Atan2
+            | ScalarFunction::Div
+            | ScalarFunction::Pow
+            | ScalarFunction::Split
+            | ScalarFunction::Nullif
+            | ScalarFunction::DateTrunc
+            | ScalarFunction::DatePart
+            | ScalarFunction::StPoint
+            | ScalarFunction::StDistance
+            | ScalarFunction::StContains => (2, Some(2)),
+            ScalarFunction::Log
+            | ScalarFunction::Round
+            | ScalarFunction::ToTimestamp
+            | ScalarFunction::Trim
+            | ScalarFunction::Ltrim
+            | ScalarFunction::Rtrim => (1, Some(2)),
+            ScalarFunction::Substr | ScalarFunction::RegexpMatch => (2, Some(3)),
+            ScalarFunction::Replace
+            | ScalarFunction::VectorDistance
+            | ScalarFunction::StDwithin => (3, Some(3)),
+            ScalarFunction::StWithinBbox => (5, Some(5)),
+            ScalarFunction::Coalesce => (1, None),
+            _ => (1, Some(1)),
+        }
+    }
+}
+
+/// A window function call: `func(args) OVER (PARTITION BY .. ORDER BY .. frame)`.
+#[derive(Debug, Clone)]
+pub struct WindowCall<'a> {
+    pub func: WindowFunction,
+    pub args: Vec<Expression<'a>>, // empty for `COUNT(*)`
+    pub partition_by: Vec<Expression<'a>>,
+    pub order_by: Vec<SortKey<'a>>,
+    pub frame: Option<WindowFrame>, // None: the default frame
+}
+
+impl<'a> WindowCall<'a> {
+    /// Returns the arguments, partition keys and sort key expressions.
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression<'a>> {
+        let mut exprs: Vec<&mut Expression<'a>> = self.args.iter_mut().collect();
+        exprs.extend(self.partition_by.iter_mut());
+        exprs.extend(self.order_by.iter_mut().map(|key| &mut key.expr));
+        exprs
+    }
+
+    /// Returns the frame the function is computed over. Without a frame
+    /// clause it ends at the current row's last peer if there is an ORDER BY,
+    /// and is the whole partition otherwise.
+    pub fn effective_frame(&self) -> WindowFrame {
+        self.frame.unwrap_or(WindowFrame {
+            rows: false,
+            start: FrameBound::UnboundedPreceding,
+            end: if self.order_by.is_empty() {
+                FrameBound::UnboundedFollowing
+            } else {
+                FrameBound::CurrentRow
+            },
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl WindowFunction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowFunction::RowNumber => "ROW_NUMBER",
+            WindowFunction::Rank => "RANK",
+            WindowFunction::DenseRank => "DENSE_RANK",
+            WindowFunction::Lag => "LAG",
+            WindowFunction::Lead => "LEAD",
+            WindowFunction::FirstValue => "FIRST_VALUE",
+            WindowFunction::LastValue => "LAST_VALUE",
+            WindowFunction::Count => "COUNT",
+            WindowFunction::Sum => "SUM",
+            WindowFunction::Avg => "AVG",
+            WindowFunction::Min => "MIN",
+            WindowFunction::Max => "MAX",
+        }
+    }
+
+    /// Returns the (minimum, maximum) number of arguments.
+    pub fn arity(&self) -> (usize, usize) {
+        match self {
+            WindowFunction::RowNumber | WindowFunction::Rank | WindowFunction::DenseRank => (0, 0),
+            WindowFunction::Lag | WindowFunction::Lead => (1, 3),
+            WindowFunction::Count => (0, 1),
+            _ => (1, 1),
+        }
+    }
+
+    /// Returns true if the function is computed over the window frame rather
+    /// than the whole partition.
+    pub fn uses_frame(&self) -> bool {
+        !matches!(
+            self,
+            WindowFunction::RowNumber
+                | WindowFunction::Rank
+                | WindowFunction::DenseRank
+                | WindowFunction::Lag
+                | WindowFunction::Lead
+        )
+    }
+}
+
+/// The rows of a partition a window function is computed over, relative to
+/// the current row. `ROWS` frames count rows; `RANGE` frames (`rows: false`)
+/// extend `CURRENT ROW` to the current row's peers under the ORDER BY.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowFrame {
+    pub rows: bool,
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(usize),
+    CurrentRow,
+    Following(usize),
+    UnboundedFollowing,
+}
+
+impl fmt::Display for FrameBound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameBound::UnboundedPreceding => write!(f, "UNBOUNDED PRECEDING"),
+            FrameBound::Preceding(n) => write!(f, "{} PRECEDING", n),
+            FrameBound::CurrentRow => write!(f, "CURRENT ROW"),
+            FrameBound::Following(n) => write!(f, "{} FOLLOWING", n),
+            FrameBound::UnboundedFollowing => write!(f, "UNBOUNDED FOLLOWING"),
+        }
+    }
+}
+
+impl fmt::Display for WindowCall<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.func.name())?;
+        if self.args.is_empty() && self.func == WindowFunction::Count {
+            write!(f, "*")?;
+        }
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", arg)?;
+        }
+        write!(f, ") OVER (")?;
+        let mut clauses = Vec::new();
+        if !self.partition_by.is_empty() {
+            let keys: Vec<String> = self.partition_by.iter().map(|e| e.to_string()).collect();
+            clauses.push(format!("PARTITION BY {}", keys.join(", ")));
+        }
+        if !self.order_by.is_empty() {
+            let keys: Vec<String> = self.order_by.iter().map(|key| key.to_string()).collect();
+            clauses.push(format!("ORDER BY {}", keys.join(", ")));
+        }
+        if let Some(frame) = &self.frame {
+            clauses.push(format!(
+                "{} BETWEEN {} AND {}",
+                if frame.rows { "ROWS" } else { "RANGE" },
+                frame.start,
+                frame.end
+            ));
+        }
+        write!(f, "{})", clauses.join(" "))
+    }
+}
+
+/// Writes an operand, parenthesizing nested operators so the output is unambiguous.
+fn fmt_operand(expr: &Expression, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match expr {
+        Expression::Binary { .. }
+        | Expression::Logical { .. }
+        | Expression::Unary { .. }
+        | Expression::InList { .. }
+        | Expression::Between { .. }
+        | Expression::Like { .. }
+        | Expression::IsNull { .. }
+        | Expression::IsMissing { .. }
+        | Expression::Quantified { .. }
+        | Expression::InSubquery { .. } => write!(f, "({})", expr),
+        _ => write!(f, "{}", expr),
+    }
+}
+
+impl fmt::Display for Expression<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::FieldReference(_, raw) | Expression::JsonPath(_, raw) => {
+                write!(f, "{}", raw)
+            }
+            Expression::Literal(Value::String(s)) => write!(f, "'{}'", s.replace('\'', "''")),
+            Expression::Literal(val) => write!(f, "{}", val),
+            Expression::Binary { left, op, right } => {
+                fmt_operand(left, f)?;
+                write!(f, " {} ", op)?;
+                fmt_operand(right, f)
+            }
+            Expression::Logical { left, op, right } => {
+                fmt_operand(left, f)?;
+                write!(f, " {} ", op)?;
+                fmt_operand(right, f)
+            }
+            Expression::Unary { op, expr } => {
+                write!(f, "{}", op)?;
+                fmt_operand(expr, f)
+            }
+            Expression::Function { func, args } => {
+                write!(f, "{}(", func.name())?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                fmt_operand(expr, f)?;
+                write!(f, "{} IN (", if *negated { " NOT" } else { "" })?;
+                for (i, item) in list.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")
+            }
+            Expression::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                fmt_operand(expr, f)?;
+                write!(f, "{} BETWEEN ", if *negated { " NOT" } else { "" })?;
+                fmt_operand(low, f)?;
+                write!(f, " AND ")?;
+                fmt_operand(high, f)
+            }
+            Expression::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+            } => {
+                fmt_operand(expr, f)?;
+                write!(
+                    f,
+                    "{} {} ",
+                    if *negated { " NOT" } else { "" },
+                    if *case_insensitive { "ILIKE" } else { "LIKE" }
+                )?;
+                fmt_operand(pattern, f)
+            }
+            Expression::IsNull { expr, negated } => {
+                fmt_operand(expr, f)?;
+                write!(f, " IS {}NULL", if *negated { "NOT " } else { "" })
+            }
+            Expression::IsMissing { expr, negated } => {
+                fmt_operand(expr, f)?;
+                write!(f, " IS {}MISSING", if *negated { "NOT " } else { "" })
+            }
+            Expression::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {}", operand)?;
+                }
+                for (when, then) in branches {
+                    write!(f, " WHEN {} THEN {}", when, then)?;
+                }
+                if let Some(else_result) = else_result {
+                    write!(f, " ELSE {}", else_result)?;
+                }
+                write!(f, " END")
+            }
+            Expression::Quantified {
+                left,
+                op,
+                right,
+                all,
+            } => {
+                fmt_operand(left, f)?;
+                write!(f, " {} {}({})", op, if *all { "ALL" } else { "ANY" }, right)
+            }
+            Expression::JsonExists { expr, raw, .. } => {
+                write!(f, "JSON_EXISTS({}, '{}')", expr, raw.replace('\'', "''"))
+            }
+            Expression::Match { expr, query, .. } => {
+                write!(f, "MATCH({}, '{}')", expr, query.replace('\'', "''"))
+            }
+            Expression::InSubquery { expr, negated, .. } => {
+                fmt_operand(expr, f)?;
+                write!(f, "{} IN (subquery)", if *negated { " NOT" } else { "" })
+            }
+            Expression::Window(call) => write!(f, "{}", call),
+            Expression::Exists { negated, .. } => {
+                write!(f, "{}EXISTS (subquery)", if *negated { "NOT " } else { "" })
+            }
+            Expression::Parameter(n) => write!(f, "${}", n),
+            Expression::Alias(expr, name) => write!(f, "{} AS {}", expr, name),
+            Expression::Wildcard => write!(f, "*"),
+        }
+    }
+}
+
+/// Returns the name of the output field produced by a projection.
+///
+/// Field references and JSON paths keep their source text, aliases use the
+/// alias, and other expressions are named after their SQL rendering.
+pub fn projection_name(expr: &Expression) -> String {
+    match expr {
+        Expression::Alias(_, name) => name.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Builds a projected document, evaluating each projection with `eval`.
+///
+/// A wildcard projection copies every field of the evaluated document but
+/// window function results.
+pub fn project<'a, F>(projections: &[Expression<'a>], mut eval: F) -> Value
+where
+    F: FnMut(&Expression<'a>) -> Value,
+{
+    let mut new_doc = BTreeMap::new();
+    for expr in projections {
+        match expr {
+            Expression::Wildcard => {
+                if let Value::Object(obj) = eval(expr) {
+                    new_doc.extend(
+                        obj.into_iter()
+                            .filter(|(name, _)| !name.starts_with(WINDOW_FIELD_PREFIX)),
+                    );
+                }
+            }
+            _ => {
+                new_doc.insert(projection_name(expr), eval(expr));
+            }
+        }
+    }
+    Value::Object(new_doc)
+}
+
+/// A set of document paths read by a query, none of them inside another.
+/// A scan given one returns documents holding only these fields.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FieldSet {
+    paths: Vec<Vec<String>>,
+}
+
+impl FieldSet {
+    pub fn paths(&self) -> &[Vec<String>] {
+        &self.paths
+    }
+
+    /// Adds a path, unless a path containing it is present, replacing any
+    /// paths inside it.
+    pub fn insert(&mut self, path: &[&str]) {
+        let path: Vec<String> = path.iter().map(|part| part.to_string()).collect();
+        if self.paths.iter().any(|p| path.starts_with(p)) {
+            return;
+        }
+        self.paths.retain(|p| !p.starts_with(&path));
+        self.paths.push(path);
+        self.paths.sort();
+    }
+
+    /// Adds the fields `expr` reads, returning false if it reads the whole
+    /// document (a wildcard or JSON path).
+    pub fn add_expression(&mut self, expr: &mut Expression) -> bool {
+        match expr {
+            // The ID is carried beside the document
+            Expression::FieldReference(parts, _) if is_id_reference(parts) => true,
+            Expression::FieldReference(parts, _) => {
+                self.insert(parts);
+                true
+            }
+            Expression::JsonPath(..) | Expression::Wildcard => false,
+            _ => expr
+                .children_mut()
+                .into_iter()
+                .all(|child| self.add_expression(child)),
+        }
+    }
+
+    /// Returns the fields read under `alias`, relative to it, or `None` if
+    /// the whole value of `alias` is read.
+    pub fn under(&self, alias: &str) -> Option<FieldSet> {
+        let mut fields = FieldSet::default();
+        for path in &self.paths {
+            match path.split_first() {
+                Some((first, [])) if first == alias => return None,
+                Some((first, rest)) if first == alias => {
+                    let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+                    if !is_id_reference(&rest) {
+                        fields.insert(&rest);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Some(fields)
+    }
+}
+
+impl fmt::Display for FieldSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths: Vec<String> = self.paths.iter().map(|path| path.join(".")).collect();
+        write!(f, "{}", paths.join(", "))
+    }
+}
+
+/// Returns a copy of `doc` holding only the fields in `fields`.
+pub fn narrow_document(doc: &Value, fields: &FieldSet) -> Value {
+    let mut narrowed = Value::Object(BTreeMap::new());
+    for path in fields.paths() {
+        let parts: Vec<&str> = path.iter().map(String::as_str).collect();
+        if let Some(val) = get_path(doc, &parts) {
+            set_path(&mut narrowed, path, val);
+        }
+    }
+    narrowed
+}
+
+/// Decodes only the fields in `fields` of a lazily decoded document.
+pub fn narrow_lazy(doc: &LazyDocument, fields: &FieldSet) -> Value {
+    let mut narrowed = Value::Object(BTreeMap::new());
+    let raw_root = RawJsonb::new(&doc.raw);
+    if let Ok(Some(doc_owned)) = raw_root.get_by_index(1) {
+        for path in fields.paths() {
+            let Some((first, rest)) = path.split_first() else {
+                continue;
+            };
+            let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+            // Start from the borrowed document so that it is not copied per path
+            if let Ok(Some(field)) = doc_owned.as_raw().get_by_name(first, false)
+                && let Some(field_bytes) = get_path_lazy(field, &rest)
+                && let Ok(val) = jsonb_schema::from_slice(&field_bytes)
+            {
+                set_path(&mut narrowed, path, make_static(&val));
+            }
+        }
+    }
+    narrowed
+}
+
+/// Sets the value at `path` in an object, creating the objects along it.
+fn set_path(doc: &mut Value, path: &[String], val: Value) {
+    let Value::Object(fields) = doc else {
+        return;
+    };
+    match path {
+        [] => {}
+        [field] => {
+            fields.insert(field.clone(), val);
+        }
+        [field, rest @ ..] => {
+            let child = fields
+                .entry(field.clone())
+                .or_insert_with(|| Value::Object(BTreeMap::new()));
+            set_path(child, rest, val);
+        }
+    }
 }
 
 // Lazy Evaluator
 
 pub fn evaluate_to_f64_lazy<'a>(expr: &Expression<'a>, doc: &LazyDocument) -> Option<f64> {
     match expr {
-        Expression::FieldReference(parts, _) => {
+        Expression::FieldReference(parts, _) if !is_id_reference(parts) => {
             let raw_root = RawJsonb::new(&doc.raw);
             if let Ok(Some(doc_owned)) = raw_root.get_by_index(1) {
                 if let Some(field_bytes) = get_path_lazy(doc_owned, parts) {
@@ -104,6 +1065,9 @@ pub fn evaluate_to_f64_lazy<'a>(expr: &Expression<'a>, doc: &LazyDocument) -> Op
 
 pub fn evaluate_expression_lazy<'a>(expr: &Expression<'a>, doc: &LazyDocument) -> Value {
     match expr {
+        Expression::FieldReference(parts, _) if is_id_reference(parts) => {
+            Value::String(doc.id.clone().into())
+        }
         Expression::FieldReference(parts, _) => {
             // Lazy optimization: only extract the requested field using RawJsonb
             // doc.raw is [id, document]
@@ -158,13 +1122,59 @@ pub fn evaluate_expression_lazy<'a>(expr: &Expression<'a>, doc: &LazyDocument) -
         Expression::Binary { left, op, right } => {
             let l_val = evaluate_expression_lazy(left, doc);
             let r_val = evaluate_expression_lazy(right, doc);
-            evaluate_binary(&l_val, op, &r_val)
+            if op.is_comparison() && (is_temporal(left) || is_temporal(right)) {
+                evaluate_temporal_comparison(&l_val, op, &r_val)
+            } else {
+                evaluate_binary(&l_val, op, &r_val)
+            }
         }
         Expression::Logical { left, op, right } => {
             let l_val = evaluate_expression_lazy(left, doc);
             let r_val = evaluate_expression_lazy(right, doc);
             evaluate_logical(&l_val, op, &r_val)
         }
+        Expression::Unary { op, expr } => evaluate_unary(op, &evaluate_expression_lazy(expr, doc)),
+        Expression::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let val = evaluate_expression_lazy(expr, doc);
+            let items: Vec<Value> = list
+                .iter()
+                .map(|item| evaluate_expression_lazy(item, doc))
+                .collect();
+            evaluate_in_list(&val, &items, *negated)
+        }
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => evaluate_between(
+            &evaluate_expression_lazy(expr, doc),
+            &evaluate_expression_lazy(low, doc),
+            &evaluate_expression_lazy(high, doc),
+            *negated,
+            [expr, low, high].into_iter().any(|e| is_temporal(e)),
+        ),
+        Expression::Like {
+            expr,
+            pattern,
+            negated,
+            case_insensitive,
+        } => evaluate_like(
+            &evaluate_expression_lazy(expr, doc),
+            &evaluate_expression_lazy(pattern, doc),
+            *negated,
+            *case_insensitive,
+        ),
+        Expression::IsNull { expr, negated } => {
+            Value::Bool(matches!(evaluate_expression_lazy(expr, doc), Value::Null) != *negated)
+        }
+        Expression::IsMissing { expr, negated } => {
+            Value::Bool(is_missing_lazy(expr, doc) != *negated)
+        }
         Expression::Function { func, args } => {
             let vals: Vec<Value> = args
                 .iter()
@@ -172,6 +1182,49 @@ pub fn evaluate_expression_lazy<'a>(expr: &Expression<'a>, doc: &LazyDocument) -
                 .collect();
             evaluate_function(func, &vals)
         }
+        Expression::Case {
+            operand,
+            branches,
+            else_result,
+        } => evaluate_case(operand.as_deref(), branches, else_result.as_deref(), |e| {
+            evaluate_expression_lazy(e, doc)
+        }),
+        Expression::Quantified {
+            left,
+            op,
+            right,
+            all,
+        } => evaluate_quantified(
+            &evaluate_expression_lazy(left, doc),
+            op,
+            &evaluate_expression_lazy(right, doc),
+            *all,
+        ),
+        Expression::JsonExists { expr, path, .. } => {
+            evaluate_json_exists(&evaluate_expression_lazy(expr, doc), path)
+        }
+        Expression::Match {
+            expr, terms, stats, ..
+        } => evaluate_match(
+            &evaluate_expression_lazy(expr, doc),
+            terms,
+            stats.as_deref(),
+        ),
+        // Subqueries, window functions and parameters are resolved before execution
+        Expression::InSubquery { .. }
+        | Expression::Exists { .. }
+        | Expression::Window(_)
+        | Expression::Parameter(_) => Value::Null,
+        Expression::Alias(expr, _) => evaluate_expression_lazy(expr, doc),
+        Expression::Wildcard => {
+            let raw_root = RawJsonb::new(&doc.raw);
+            if let Ok(Some(doc_owned)) = raw_root.get_by_index(1)
+                && let Ok(val) = jsonb_schema::from_slice(&doc_owned.to_vec())
+            {
+                return make_static(&val);
+            }
+            Value::Null
+        }
     }
 }
 
@@ -188,8 +1241,75 @@ fn get_path_lazy(mut current: OwnedJsonb, parts: &[&str]) -> Option<Vec<u8>> {
     Some(current.to_vec())
 }
 
+/// Returns true if a field or JSON path is absent from a lazily decoded document.
+/// Computed expressions are never missing.
+fn is_missing_lazy(expr: &Expression, doc: &LazyDocument) -> bool {
+    let raw_root = RawJsonb::new(&doc.raw);
+    match expr {
+        Expression::FieldReference(parts, _) if is_id_reference(parts) => false,
+        Expression::FieldReference(parts, _) => match raw_root.get_by_index(1) {
+            Ok(Some(doc_owned)) => get_path_lazy(doc_owned, parts).is_none(),
+            _ => true,
+        },
+        Expression::JsonPath(json_path, _) => match raw_root.get_by_index(1) {
+            Ok(Some(doc_owned)) => doc_owned
+                .as_raw()
+                .select_by_path(json_path)
+                .map(|results| results.is_empty())
+                .unwrap_or(true),
+            _ => true,
+        },
+        Expression::Alias(expr, _) => is_missing_lazy(expr, doc),
+        _ => false,
+    }
+}
+
+/// Returns true if a field or JSON path is absent from a document.
+/// Computed expressions are never missing.
+fn is_missing(expr: &Expression, doc: &Value) -> bool {
+    match expr {
+        Expression::FieldReference(parts, _) if is_id_reference(parts) => false,
+        Expression::FieldReference(parts, _) => get_path(doc, parts).is_none(),
+        Expression::JsonPath(json_path, _) => {
+            match jsonb_schema::to_owned_jsonb(&SerdeWrapper(doc)) {
+                Ok(blob) => blob
+                    .as_raw()
+                    .select_by_path(json_path)
+                    .map(|results| results.is_empty())
+                    .unwrap_or(true),
+                Err(_) => true,
+            }
+        }
+        Expression::Alias(expr, _) => is_missing(expr, doc),
+        _ => false,
+    }
+}
+
+/// Returns true if the path refers to the document ID rather than a stored field.
+pub fn is_id_reference(parts: &[&str]) -> bool {
+    parts.len() == 1 && parts[0] == ID_FIELD
+}
+
+/// Evaluates an expression against a document without a known ID.
 pub fn evaluate_expression<'a>(expr: &Expression<'a>, doc: &Value) -> Value {
+    evaluate_document(expr, None, doc)
+}
+
+/// Evaluates an expression against either kind of execution result.
+pub fn evaluate_result<'a>(expr: &Expression<'a>, item: &ExecutionResult) -> Value {
+    match item {
+        ExecutionResult::Value(id, doc) => evaluate_document(expr, Some(id.as_str()), doc),
+        ExecutionResult::Lazy(doc) => evaluate_expression_lazy(expr, doc),
+    }
+}
+
+/// Evaluates an expression against a document, resolving `_id` to `id` when given.
+pub fn evaluate_document<'a>(expr: &Expression<'a>, id: Option<&str>, doc: &Value) -> Value {
     match expr {
+        Expression::FieldReference(parts, _) if is_id_reference(parts) => match id {
+            Some(id) => Value::String(id.to_string().into()),
+            None => Value::Null,
+        },
         Expression::FieldReference(parts, _) => get_path(doc, parts).unwrap_or(Value::Null),
         Expression::JsonPath(json_path, _) => {
             let wrapper = SerdeWrapper(doc);
@@ -227,22 +1347,97 @@ pub fn evaluate_expression<'a>(expr: &Expression<'a>, doc: &Value) -> Value {
         }
         Expression::Literal(val) => val.clone(),
         Expression::Binary { left, op, right } => {
-            let l_val = evaluate_expression(left, doc);
-            let r_val = evaluate_expression(right, doc);
-            evaluate_binary(&l_val, op, &r_val)
+            let l_val = evaluate_document(left, id, doc);
+            let r_val = evaluate_document(right, id, doc);
+            if op.is_comparison() && (is_temporal(left) || is_temporal(right)) {
+                evaluate_temporal_comparison(&l_val, op, &r_val)
+            } else {
+                evaluate_binary(&l_val, op, &r_val)
+            }
         }
         Expression::Logical { left, op, right } => {
-            let l_val = evaluate_expression(left, doc);
-            let r_val = evaluate_expression(right, doc);
+            let l_val = evaluate_document(left, id, doc);
+            let r_val = evaluate_document(right, id, doc);
             evaluate_logical(&l_val, op, &r_val)
         }
+        Expression::Unary { op, expr } => evaluate_unary(op, &evaluate_document(expr, id, doc)),
+        Expression::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let val = evaluate_document(expr, id, doc);
+            let items: Vec<Value> = list
+                .iter()
+                .map(|item| evaluate_document(item, id, doc))
+                .collect();
+            evaluate_in_list(&val, &items, *negated)
+        }
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => evaluate_between(
+            &evaluate_document(expr, id, doc),
+            &evaluate_document(low, id, doc),
+            &evaluate_document(high, id, doc),
+            *negated,
+            [expr, low, high].into_iter().any(|e| is_temporal(e)),
+        ),
+        Expression::Like {
+            expr,
+            pattern,
+            negated,
+            case_insensitive,
+        } => evaluate_like(
+            &evaluate_document(expr, id, doc),
+            &evaluate_document(pattern, id, doc),
+            *negated,
+            *case_insensitive,
+        ),
+        Expression::IsNull { expr, negated } => {
+            Value::Bool(matches!(evaluate_document(expr, id, doc), Value::Null) != *negated)
+        }
+        Expression::IsMissing { expr, negated } => Value::Bool(is_missing(expr, doc) != *negated),
         Expression::Function { func, args } => {
             let vals: Vec<Value> = args
                 .iter()
-                .map(|arg| evaluate_expression(arg, doc))
+                .map(|arg| evaluate_document(arg, id, doc))
                 .collect();
             evaluate_function(func, &vals)
         }
+        Expression::Case {
+            operand,
+            branches,
+            else_result,
+        } => evaluate_case(operand.as_deref(), branches, else_result.as_deref(), |e| {
+            evaluate_document(e, id, doc)
+        }),
+        Expression::Quantified {
+            left,
+            op,
+            right,
+            all,
+        } => evaluate_quantified(
+            &evaluate_document(left, id, doc),
+            op,
+            &evaluate_document(right, id, doc),
+            *all,
+        ),
+        Expression::JsonExists { expr, path, .. } => {
+            evaluate_json_exists(&evaluate_document(expr, id, doc), path)
+        }
+        Expression::Match {
+            expr, terms, stats, ..
+        } => evaluate_match(&evaluate_document(expr, id, doc), terms, stats.as_deref()),
+        // Subqueries, window functions and parameters are resolved before execution
+        Expression::InSubquery { .. }
+        | Expression::Exists { .. }
+        | Expression::Window(_)
+        | Expression::Parameter(_) => Value::Null,
+        Expression::Alias(expr, _) => evaluate_document(expr, id, doc),
+        Expression::Wildcard => doc.clone(),
     }
 }
 
@@ -255,7 +1450,7 @@ pub fn get_f64_from_number(n: &Number) -> Option<f64> {
     }
 }
 
-fn get_i64_from_number(n: &Number) -> Option<i64> {
+pub fn get_i64_from_number(n: &Number) -> Option<i64> {
     match n {
         Number::Int64(i) => Some(*i),
         Number::UInt64(u) => i64::try_from(*u).ok(),
@@ -263,32 +1458,437 @@ fn get_i64_from_number(n: &Number) -> Option<i64> {
     }
 }
 
-fn evaluate_function(func: &ScalarFunction, vals: &[Value]) -> Value {
-    let get_f64 = |v: &Value| -> Option<f64> {
-        match v {
-            Value::Number(n) => get_f64_from_number(n),
-            _ => None,
-        }
+/// Scores a text for `MATCH`; a value other than a string scores NULL.
+fn evaluate_match(val: &Value, terms: &[String], stats: Option<&TextStats>) -> Value {
+    match val {
+        Value::String(text) => Value::Number(Number::Float64(fulltext::score(text, terms, stats))),
+        _ => Value::Null,
+    }
+}
+
+/// Computes `VECTOR_DISTANCE`: NULL unless both values are arrays of numbers
+/// of the same length and the metric is known.
+fn evaluate_vector_distance(a: &Value, b: &Value, metric: &Value) -> Value {
+    let Value::String(metric) = metric else {
+        return Value::Null;
     };
+    match (
+        vector::to_vector(a),
+        vector::to_vector(b),
+        Metric::parse(metric),
+    ) {
+        (Some(a), Some(b), Some(metric)) => metric
+            .distance(&a, &b)
+            .map_or(Value::Null, |d| Value::Number(Number::Float64(d))),
+        _ => Value::Null,
+    }
+}
 
-    let f1 = if !vals.is_empty() {
-        get_f64(&vals[0])
-    } else {
-        None
+/// Evaluates the `ST_` functions, or returns None if an argument is not of
+/// the type they take. Geometries are GeoJSON objects.
+fn evaluate_spatial_function(func: &ScalarFunction, vals: &[Value]) -> Option<Value> {
+    let number = |i: usize| match vals.get(i)? {
+        Value::Number(n) => get_f64_from_number(n),
+        _ => None,
+    };
+    let geometry = |i: usize| Geometry::from_value(vals.get(i)?);
+    let point = |i: usize| match geometry(i)? {
+        Geometry::Point(point) => Some(point),
+        _ => None,
     };
+    Some(match func {
+        ScalarFunction::StPoint => Geometry::point_value(Coord::new(number(0)?, number(1)?)?),
+        ScalarFunction::StDistance => {
+            Value::Number(Number::Float64(geo::distance(point(0)?, point(1)?)))
+        }
+        ScalarFunction::StDwithin => Value::Bool(geo::distance(point(0)?, point(1)?) <= number(2)?),
+        ScalarFunction::StContains => Value::Bool(geometry(0)?.contains(&geometry(1)?)?),
+        ScalarFunction::StWithinBbox => {
+            let bbox = BBox {
+                min_lon: number(1)?,
+                min_lat: number(2)?,
+                max_lon: number(3)?,
+                max_lat: number(4)?,
+            };
+            Value::Bool(geometry(0)?.within(&bbox))
+        }
+        _ => return None,
+    })
+}
 
-    let result = match func {
-        // Unary
-        ScalarFunction::Abs => f1.map(|f| f.abs()),
-        ScalarFunction::Acos => f1.map(|f| f.acos()),
-        ScalarFunction::Acosh => f1.map(|f| f.acosh()),
-        ScalarFunction::Asin => f1.map(|f| f.asin()),
-        ScalarFunction::Atan => f1.map(|f| f.atan()),
-        ScalarFunction::Ceil => f1.map(|f| f.ceil()),
-        ScalarFunction::Cos => f1.map(|f| f.cos()),
-        ScalarFunction::Cosh => f1.map(|f| f.cosh()),
-        ScalarFunction::Exp => f1.map(|f| f.exp()),
-        ScalarFunction::Floor => f1.map(|f| f.floor()),
+/// Evaluates a CASE expression, only evaluating the branches it needs.
+///
+/// With an operand, a branch is taken when `operand = WHEN` is true; otherwise
+/// when the WHEN condition itself is true.
+fn evaluate_case<'a, F>(
+    operand: Option<&Expression<'a>>,
+    branches: &[(Expression<'a>, Expression<'a>)],
+    else_result: Option<&Expression<'a>>,
+    mut eval: F,
+) -> Value
+where
+    F: FnMut(&Expression<'a>) -> Value,
+{
+    let operand = operand.map(&mut eval);
+    for (when, then) in branches {
+        let condition = match &operand {
+            Some(operand) => evaluate_comparison(operand, &BinaryOperator::Eq, &eval(when)),
+            None => eval(when),
+        };
+        if condition == Value::Bool(true) {
+            return eval(then);
+        }
+    }
+    else_result.map(eval).unwrap_or(Value::Null)
+}
+
+fn evaluate_function(func: &ScalarFunction, vals: &[Value]) -> Value {
+    let arg = |i: usize| vals.get(i).unwrap_or(&Value::Null);
+    match func {
+        ScalarFunction::Lower => map_string(arg(0), |s| Value::String(s.to_lowercase().into())),
+        ScalarFunction::Upper => map_string(arg(0), |s| Value::String(s.to_uppercase().into())),
+        ScalarFunction::Length => map_string(arg(0), |s| {
+            Value::Number(Number::Int64(s.chars().count() as i64))
+        }),
+        ScalarFunction::Substr => evaluate_substr(arg(0), arg(1), vals.get(2)),
+        ScalarFunction::Trim | ScalarFunction::Ltrim | ScalarFunction::Rtrim => {
+            evaluate_trim(func, arg(0), vals.get(1))
+        }
+        ScalarFunction::Replace => match (arg(0), arg(1), arg(2)) {
+            (Value::String(s), Value::String(from), Value::String(to)) => {
+                if from.is_empty() {
+                    Value::String(s.clone())
+                } else {
+                    Value::String(s.replace(from.as_ref(), to).into())
+                }
+            }
+            _ => Value::Null,
+        },
+        ScalarFunction::Split => match (arg(0), arg(1)) {
+            (Value::String(s), Value::String(delim)) => {
+                let parts: Vec<Value> = if delim.is_empty() {
+                    s.chars()
+                        .map(|c| Value::String(c.to_string().into()))
+                        .collect()
+                } else {
+                    s.split(delim.as_ref())
+                        .map(|part| Value::String(part.to_string().into()))
+                        .collect()
+                };
+                Value::Array(parts)
+            }
+            _ => Value::Null,
+        },
+        ScalarFunction::RegexpMatch => evaluate_regexp_match(arg(0), arg(1), vals.get(2)),
+        ScalarFunction::JsonTypeof => Value::String(json_typeof(arg(0)).into()),
+        ScalarFunction::ArrayLength => match arg(0) {
+            Value::Array(items) => Value::Number(Number::Int64(items.len() as i64)),
+            _ => Value::Null,
+        },
+        ScalarFunction::Keys => match arg(0) {
+            Value::Object(obj) => Value::Array(
+                obj.keys()
+                    .map(|k| Value::String(k.clone().into()))
+                    .collect(),
+            ),
+            _ => Value::Null,
+        },
+        ScalarFunction::ToJson => match arg(0) {
+            Value::Null => Value::Null,
+            val => Value::String(crate::jsonb_to_serde(val).to_string().into()),
+        },
+        ScalarFunction::ParseJson => match arg(0) {
+            Value::String(s) => serde_json::from_str(s)
+                .map(crate::serde_to_jsonb)
+                .unwrap_or(Value::Null),
+            _ => Value::Null,
+        },
+        ScalarFunction::Coalesce => vals
+            .iter()
+            .find(|v| !matches!(v, Value::Null))
+            .cloned()
+            .unwrap_or(Value::Null),
+        ScalarFunction::Nullif => {
+            if evaluate_comparison(arg(0), &BinaryOperator::Eq, arg(1)) == Value::Bool(true) {
+                Value::Null
+            } else {
+                arg(0).clone()
+            }
+        }
+        ScalarFunction::Now => timestamp_value(&Utc::now()),
+        ScalarFunction::ToTimestamp => {
+            let dt = match (arg(0), vals.get(1)) {
+                (val, None) => to_timestamp(val),
+                (Value::String(s), Some(Value::String(format))) => {
+                    temporal::parse_timestamp_with_format(s, format)
+                }
+                _ => None,
+            };
+            dt.map(|dt| timestamp_value(&dt)).unwrap_or(Value::Null)
+        }
+        ScalarFunction::DateTrunc => match (arg(0), to_timestamp(arg(1))) {
+            (Value::String(unit), Some(dt)) => temporal::date_trunc(unit, dt)
+                .map(|dt| timestamp_value(&dt))
+                .unwrap_or(Value::Null),
+            _ => Value::Null,
+        },
+        ScalarFunction::DatePart => match (arg(0), to_timestamp(arg(1))) {
+            (Value::String(field), Some(dt)) => match temporal::date_part(field, dt) {
+                Some(DatePart::Int(i)) => Value::Number(Number::Int64(i)),
+                Some(DatePart::Float(f)) => Value::Number(Number::Float64(f)),
+                None => Value::Null,
+            },
+            _ => Value::Null,
+        },
+        ScalarFunction::VectorDistance => evaluate_vector_distance(arg(0), arg(1), arg(2)),
+        ScalarFunction::StPoint
+        | ScalarFunction::StDistance
+        | ScalarFunction::StDwithin
+        | ScalarFunction::StContains
+        | ScalarFunction::StWithinBbox => {
+            evaluate_spatial_function(func, vals).unwrap_or(Value::Null)
+        }
+        _ => evaluate_numeric_function(func, vals),
+    }
+}
+
+/// Returns true if `expr` computes a timestamp or interval whenever it gives
+/// a string: a call to `TO_TIMESTAMP`, `NOW` or `DATE_TRUNC`, or `+` or `-`.
+/// Comparisons with such an operand compare timestamps as instants.
+pub fn is_temporal(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Function {
+            func: ScalarFunction::ToTimestamp | ScalarFunction::Now | ScalarFunction::DateTrunc,
+            ..
+        } | Expression::Binary {
+            op: BinaryOperator::Plus | BinaryOperator::Minus,
+            ..
+        }
+    )
+}
+
+/// Interprets a value as a timestamp: an ISO-8601 string, or a number of
+/// seconds since the Unix epoch.
+fn to_timestamp(val: &Value) -> Option<DateTime<Utc>> {
+    match val {
+        Value::String(s) => temporal::parse_timestamp(s),
+        Value::Number(n) => temporal::timestamp_from_epoch(get_f64_from_number(n)?),
+        _ => None,
+    }
+}
+
+fn timestamp_value(dt: &DateTime<Utc>) -> Value {
+    Value::String(temporal::format_timestamp(dt).into())
+}
+
+/// `+` and `-` over timestamps and intervals, which are both strings:
+/// timestamp ± interval, interval + timestamp, timestamp - timestamp and
+/// interval ± interval. Returns `None` for any other operands.
+fn evaluate_temporal_arithmetic(left: &str, op: &BinaryOperator, right: &str) -> Option<Value> {
+    let subtract = match op {
+        BinaryOperator::Plus => false,
+        BinaryOperator::Minus => true,
+        _ => return None,
+    };
+    let interval_or_neg = |s: &str| {
+        let interval = Interval::parse_iso8601(s)?;
+        if subtract {
+            interval.checked_neg()
+        } else {
+            Some(interval)
+        }
+    };
+    if let Some(dt) = temporal::parse_timestamp(left) {
+        if let Some(interval) = interval_or_neg(right) {
+            return Some(
+                interval
+                    .add_to(dt)
+                    .map(|dt| timestamp_value(&dt))
+                    .unwrap_or(Value::Null),
+            );
+        }
+        let other = temporal::parse_timestamp(right).filter(|_| subtract)?;
+        let interval = Interval::between(other, dt)?;
+        return Some(Value::String(interval.to_string().into()));
+    }
+    let interval = Interval::parse_iso8601(left)?;
+    if let Some(other) = interval_or_neg(right) {
+        let sum = interval.checked_add(&other)?;
+        return Some(Value::String(sum.to_string().into()));
+    }
+    let dt = temporal::parse_timestamp(right).filter(|_| !subtract)?;
+    Some(
+        interval
+            .add_to(dt)
+            .map(|dt| timestamp_value(&dt))
+            .unwrap_or(Value::Null),
+    )
+}
+
+fn map_string(val: &Value, f: impl FnOnce(&str) -> Value) -> Value {
+    match val {
+        Value::String(s) => f(s),
+        _ => Value::Null,
+    }
+}
+
+/// Converts an integral number to `i64`; fractional and non-numeric values give `None`.
+fn to_integer(val: &Value) -> Option<i64> {
+    match val {
+        Value::Number(n) => get_i64_from_number(n).or_else(|| {
+            get_f64_from_number(n)
+                .filter(|f| f.fract() == 0.0)
+                .map(|f| f as i64)
+        }),
+        _ => None,
+    }
+}
+
+/// `SUBSTR(s, start [, len])` with a 1-based `start` counted in characters.
+/// Positions outside the string are clipped, as in PostgreSQL.
+fn evaluate_substr(val: &Value, start: &Value, len: Option<&Value>) -> Value {
+    let (Value::String(s), Some(start)) = (val, to_integer(start)) else {
+        return Value::Null;
+    };
+    let end = match len {
+        Some(len) => match to_integer(len) {
+            Some(len) if len >= 0 => Some(start.saturating_add(len)),
+            _ => return Value::Null,
+        },
+        None => None,
+    };
+    let skip = (start.max(1) - 1) as usize;
+    let take = match end {
+        Some(end) => ((end.max(1) - 1) as usize).saturating_sub(skip),
+        None => usize::MAX,
+    };
+    Value::String(s.chars().skip(skip).take(take).collect::<String>().into())
+}
+
+/// `TRIM`/`LTRIM`/`RTRIM` of whitespace, or of any of the given characters.
+fn evaluate_trim(func: &ScalarFunction, val: &Value, chars: Option<&Value>) -> Value {
+    let Value::String(s) = val else {
+        return Value::Null;
+    };
+    let set: Option<Vec<char>> = match chars {
+        Some(Value::String(c)) => Some(c.chars().collect()),
+        Some(_) => return Value::Null,
+        None => None,
+    };
+    let matches = |c: char| match &set {
+        Some(set) => set.contains(&c),
+        None => c.is_whitespace(),
+    };
+    let trimmed = match func {
+        ScalarFunction::Ltrim => s.trim_start_matches(matches),
+        ScalarFunction::Rtrim => s.trim_end_matches(matches),
+        _ => s.trim_matches(matches),
+    };
+    Value::String(trimmed.to_string().into())
+}
+
+/// Returns the name of a value's JSON type, as reported by `JSON_TYPEOF`.
+pub fn json_typeof(val: &Value) -> &'static str {
+    match val {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+        _ => "unknown",
+    }
+}
+
+/// Compiles a regular expression with optional flags (`i`, `m`, `s`, `x`).
+/// Compiled expressions are cached per thread, since patterns are usually literals.
+pub fn compile_regex(pattern: &str, flags: &str) -> Result<Regex, String> {
+    const CACHE_CAPACITY: usize = 256;
+    thread_local! {
+        static CACHE: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+    }
+
+    if let Some(flag) = flags.chars().find(|c| !"imsx".contains(*c)) {
+        return Err(format!("Invalid regular expression flag: {}", flag));
+    }
+    let full = if flags.is_empty() {
+        pattern.to_string()
+    } else {
+        format!("(?{}){}", flags, pattern)
+    };
+    CACHE.with(|cache| {
+        if let Some(regex) = cache.borrow().get(&full) {
+            return Ok(regex.clone());
+        }
+        let regex = Regex::new(&full).map_err(|e| format!("Invalid regular expression: {}", e))?;
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(full, regex.clone());
+        Ok(regex)
+    })
+}
+
+/// `REGEXP_MATCH(s, pattern [, flags])`: the capture groups of the first match
+/// (or the whole match if there are none) as an array, or `NULL` if no match.
+fn evaluate_regexp_match(val: &Value, pattern: &Value, flags: Option<&Value>) -> Value {
+    let flags = match flags {
+        Some(Value::String(f)) => f.as_ref(),
+        Some(_) => return Value::Null,
+        None => "",
+    };
+    let (Value::String(s), Value::String(pattern)) = (val, pattern) else {
+        return Value::Null;
+    };
+    let Ok(regex) = compile_regex(pattern, flags) else {
+        return Value::Null;
+    };
+    let Some(captures) = regex.captures(s) else {
+        return Value::Null;
+    };
+    let groups: Vec<Value> = if captures.len() > 1 {
+        captures
+            .iter()
+            .skip(1)
+            .map(|m| match m {
+                Some(m) => Value::String(m.as_str().to_string().into()),
+                None => Value::Null,
+            })
+            .collect()
+    } else {
+        vec![Value::String(captures[0].to_string().into())]
+    };
+    Value::Array(groups)
+}
+
+fn evaluate_numeric_function(func: &ScalarFunction, vals: &[Value]) -> Value {
+    let get_f64 = |v: &Value| -> Option<f64> {
+        match v {
+            Value::Number(n) => get_f64_from_number(n),
+            _ => None,
+        }
+    };
+
+    let f1 = if !vals.is_empty() {
+        get_f64(&vals[0])
+    } else {
+        None
+    };
+
+    let result = match func {
+        // Unary
+        ScalarFunction::Abs => f1.map(|f| f.abs()),
+        ScalarFunction::Acos => f1.map(|f| f.acos()),
+        ScalarFunction::Acosh => f1.map(|f| f.acosh()),
+        ScalarFunction::Asin => f1.map(|f| f.asin()),
+        ScalarFunction::Atan => f1.map(|f| f.atan()),
+        ScalarFunction::Ceil => f1.map(|f| f.ceil()),
+        ScalarFunction::Cos => f1.map(|f| f.cos()),
+        ScalarFunction::Cosh => f1.map(|f| f.cosh()),
+        ScalarFunction::Exp => f1.map(|f| f.exp()),
+        ScalarFunction::Floor => f1.map(|f| f.floor()),
         ScalarFunction::Ln => f1.map(|f| f.ln()),
         ScalarFunction::Log10 => f1.map(|f| f.log10()),
         ScalarFunction::Sin => f1.map(|f| f.sin()),
@@ -361,6 +1961,7 @@ fn evaluate_function(func: &ScalarFunction, vals: &[Value]) -> Value {
             }
             None => None,
         },
+        _ => None,
     };
 
     if let Some(res) = result {
@@ -389,47 +1990,436 @@ fn get_path(doc: &Value, parts: &[&str]) -> Option<Value> {
 
 fn evaluate_binary(left: &Value, op: &BinaryOperator, right: &Value) -> Value {
     match op {
-        BinaryOperator::Eq => Value::Bool(left == right),
-        BinaryOperator::Neq => Value::Bool(left != right),
-        BinaryOperator::Lt => compare_values(left, right)
-            .map(|o| Value::Bool(o == Ordering::Less))
-            .unwrap_or(Value::Bool(false)),
-        BinaryOperator::Lte => compare_values(left, right)
-            .map(|o| Value::Bool(o != Ordering::Greater))
-            .unwrap_or(Value::Bool(false)),
-        BinaryOperator::Gt => compare_values(left, right)
-            .map(|o| Value::Bool(o == Ordering::Greater))
-            .unwrap_or(Value::Bool(false)),
-        BinaryOperator::Gte => compare_values(left, right)
-            .map(|o| Value::Bool(o != Ordering::Less))
-            .unwrap_or(Value::Bool(false)),
+        BinaryOperator::Eq
+        | BinaryOperator::Neq
+        | BinaryOperator::Lt
+        | BinaryOperator::Lte
+        | BinaryOperator::Gt
+        | BinaryOperator::Gte => evaluate_comparison(left, op, right),
+        BinaryOperator::Plus
+        | BinaryOperator::Minus
+        | BinaryOperator::Multiply
+        | BinaryOperator::Divide
+        | BinaryOperator::Modulo => evaluate_arithmetic(left, op, right),
+        BinaryOperator::Concat => match (concat_operand(left), concat_operand(right)) {
+            (Some(l), Some(r)) => Value::String(format!("{}{}", l, r).into()),
+            _ => Value::Null,
+        },
+        BinaryOperator::Contains | BinaryOperator::ContainedBy => {
+            if matches!(left, Value::Null) || matches!(right, Value::Null) {
+                Value::Null
+            } else if *op == BinaryOperator::Contains {
+                Value::Bool(json_contains(left, right))
+            } else {
+                Value::Bool(json_contains(right, left))
+            }
+        }
     }
 }
 
-fn evaluate_logical(left: &Value, op: &LogicalOperator, right: &Value) -> Value {
-    let l_bool = left.as_bool().unwrap_or(false);
-    let r_bool = right.as_bool().unwrap_or(false);
-    match op {
-        LogicalOperator::And => Value::Bool(l_bool && r_bool),
-        LogicalOperator::Or => Value::Bool(l_bool || r_bool),
+/// JSON containment, as in PostgreSQL's `@>`: an object contains another if
+/// each of its key/value pairs is contained in the first object's value for
+/// that key, an array contains another if each of its elements is contained
+/// in some element of the first, and an array also contains any scalar it
+/// has as an element. Scalars contain only equal scalars.
+pub fn json_contains(container: &Value, contained: &Value) -> bool {
+    match (container, contained) {
+        (Value::Object(outer), Value::Object(inner)) => inner.iter().all(|(key, value)| {
+            outer
+                .get(key)
+                .is_some_and(|candidate| json_contains(candidate, value))
+        }),
+        (Value::Array(outer), Value::Array(inner)) => inner.iter().all(|value| {
+            outer
+                .iter()
+                .any(|candidate| json_contains(candidate, value))
+        }),
+        (Value::Array(_), Value::Object(_)) => false,
+        (Value::Array(outer), scalar) => outer
+            .iter()
+            .any(|candidate| json_contains(candidate, scalar)),
+        (Value::Object(_), _) | (_, Value::Array(_) | Value::Object(_)) => false,
+        (a, b) => compare_values(a, b) == Some(Ordering::Equal),
     }
 }
 
-fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
-    match (left, right) {
-        (Value::Number(n1), Value::Number(n2)) => {
-            if let (Some(i1), Some(i2)) = (get_i64_from_number(n1), get_i64_from_number(n2)) {
-                i1.partial_cmp(&i2)
+/// `left op ANY(right)` is true if the comparison holds for some element of
+/// `right`, and `ALL` if it holds for every element; like `IN`, the result is
+/// `NULL` rather than false when undecided because of `NULL`s. A non-array
+/// `right` is treated as a single element.
+fn evaluate_quantified(left: &Value, op: &BinaryOperator, right: &Value, all: bool) -> Value {
+    let elements = match right {
+        Value::Null => return Value::Null,
+        Value::Array(elements) => elements.as_slice(),
+        other => std::slice::from_ref(other),
+    };
+    let mut saw_null = false;
+    for element in elements {
+        match evaluate_comparison(left, op, element) {
+            Value::Bool(b) if b != all => return Value::Bool(b),
+            Value::Bool(_) => {}
+            _ => saw_null = true,
+        }
+    }
+    if saw_null {
+        Value::Null
+    } else {
+        Value::Bool(all)
+    }
+}
+
+/// Returns whether a JSON path selects anything in a value; `NULL` for `NULL`.
+fn evaluate_json_exists(val: &Value, path: &JsonPath) -> Value {
+    if matches!(val, Value::Null) {
+        return Value::Null;
+    }
+    match jsonb_schema::to_owned_jsonb(&SerdeWrapper(val)) {
+        Ok(blob) => match blob.as_raw().select_by_path(path) {
+            Ok(results) => Value::Bool(!results.is_empty()),
+            Err(_) => Value::Null,
+        },
+        Err(_) => Value::Null,
+    }
+}
+
+/// Evaluates a comparison with three-valued logic: a `NULL` operand gives
+/// `NULL`, and values of different JSON types are unequal and unordered.
+pub fn evaluate_comparison(left: &Value, op: &BinaryOperator, right: &Value) -> Value {
+    if matches!(left, Value::Null) || matches!(right, Value::Null) {
+        return Value::Null;
+    }
+    let Some(ord) = compare_values(left, right) else {
+        return match op {
+            BinaryOperator::Eq => Value::Bool(false),
+            BinaryOperator::Neq => Value::Bool(true),
+            _ => Value::Null,
+        };
+    };
+    comparison_result(op, ord)
+}
+
+/// Evaluates a comparison with an operand the query computed as a timestamp,
+/// such as `NOW()`: two strings that are both timestamps compare as instants,
+/// and anything else as by `evaluate_comparison`.
+fn evaluate_temporal_comparison(left: &Value, op: &BinaryOperator, right: &Value) -> Value {
+    if let (Value::String(a), Value::String(b)) = (left, right)
+        && let Some(ord) = temporal::compare_timestamps(a, b)
+    {
+        return comparison_result(op, ord);
+    }
+    evaluate_comparison(left, op, right)
+}
+
+/// Applies a comparison operator to the ordering of its operands.
+fn comparison_result(op: &BinaryOperator, ord: Ordering) -> Value {
+    let result = match op {
+        BinaryOperator::Eq => ord == Ordering::Equal,
+        BinaryOperator::Neq => ord != Ordering::Equal,
+        BinaryOperator::Lt => ord == Ordering::Less,
+        BinaryOperator::Lte => ord != Ordering::Greater,
+        BinaryOperator::Gt => ord == Ordering::Greater,
+        BinaryOperator::Gte => ord != Ordering::Less,
+        _ => return Value::Null,
+    };
+    Value::Bool(result)
+}
+
+/// Applies an arithmetic operator to two integers or floats.
+///
+/// Integer operands stay integers unless the result overflows, in which case
+/// it is computed as a float. `/` always produces a float. Division or modulo
+/// by zero, non-numeric operands and non-finite results yield `NULL`.
+fn evaluate_arithmetic(left: &Value, op: &BinaryOperator, right: &Value) -> Value {
+    if let (Value::String(l), Value::String(r)) = (left, right) {
+        return evaluate_temporal_arithmetic(l, op, r).unwrap_or(Value::Null);
+    }
+    let (Value::Number(l), Value::Number(r)) = (left, right) else {
+        return Value::Null;
+    };
+    if let (Some(a), Some(b)) = (get_i64_from_number(l), get_i64_from_number(r)) {
+        let checked = match op {
+            BinaryOperator::Plus => a.checked_add(b),
+            BinaryOperator::Minus => a.checked_sub(b),
+            BinaryOperator::Multiply => a.checked_mul(b),
+            BinaryOperator::Modulo if b == 0 => return Value::Null,
+            BinaryOperator::Modulo => a.checked_rem(b),
+            _ => None,
+        };
+        if let Some(res) = checked {
+            return Value::Number(Number::Int64(res));
+        }
+    }
+    match (get_f64_from_number(l), get_f64_from_number(r)) {
+        (Some(a), Some(b)) => apply_arithmetic_f64(op, a, b)
+            .map(|res| Value::Number(Number::Float64(res)))
+            .unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+/// Applies an arithmetic operator to two floats, returning `None` where the
+/// result would be `NULL`.
+pub fn apply_arithmetic_f64(op: &BinaryOperator, a: f64, b: f64) -> Option<f64> {
+    let res = match op {
+        BinaryOperator::Plus => a + b,
+        BinaryOperator::Minus => a - b,
+        BinaryOperator::Multiply => a * b,
+        BinaryOperator::Divide | BinaryOperator::Modulo if b == 0.0 => return None,
+        BinaryOperator::Divide => a / b,
+        BinaryOperator::Modulo => a % b,
+        _ => return None,
+    };
+    if res.is_finite() { Some(res) } else { None }
+}
+
+/// Renders a scalar as text for `||`; arrays, objects and `NULL` have no text form.
+fn concat_operand(val: &Value) -> Option<String> {
+    match val {
+        Value::String(s) => Some(s.to_string()),
+        Value::Number(_) | Value::Bool(_) => Some(val.to_string()),
+        _ => None,
+    }
+}
+
+fn evaluate_unary(op: &UnaryOperator, val: &Value) -> Value {
+    match (op, val) {
+        (UnaryOperator::Neg, Value::Number(n)) => {
+            if let Some(neg) = get_i64_from_number(n).and_then(i64::checked_neg) {
+                Value::Number(Number::Int64(neg))
+            } else if let Some(f) = get_f64_from_number(n) {
+                Value::Number(Number::Float64(-f))
             } else {
-                let f1: f64 = get_f64_from_number(n1)?;
-                let f2: f64 = get_f64_from_number(n2)?;
-                f1.partial_cmp(&f2)
+                Value::Null
+            }
+        }
+        (UnaryOperator::Neg, Value::String(s)) => Interval::parse_iso8601(s)
+            .and_then(|interval| interval.checked_neg())
+            .map(|interval| Value::String(interval.to_string().into()))
+            .unwrap_or(Value::Null),
+        (UnaryOperator::Not, Value::Bool(b)) => Value::Bool(!b),
+        _ => Value::Null,
+    }
+}
+
+/// `val [NOT] IN (items)`, equivalent to `val = i1 OR val = i2 ...`: true on a
+/// match, otherwise `NULL` if any comparison was `NULL`, otherwise false.
+fn evaluate_in_list(val: &Value, items: &[Value], negated: bool) -> Value {
+    let mut result = Value::Bool(false);
+    for item in items {
+        match evaluate_comparison(val, &BinaryOperator::Eq, item) {
+            Value::Bool(true) => {
+                result = Value::Bool(true);
+                break;
+            }
+            Value::Null => result = Value::Null,
+            _ => {}
+        }
+    }
+    if negated {
+        evaluate_unary(&UnaryOperator::Not, &result)
+    } else {
+        result
+    }
+}
+
+/// `val [NOT] BETWEEN low AND high`, equivalent to `val >= low AND val <= high`.
+/// Timestamps compare as instants if one of the operands is `temporal`.
+fn evaluate_between(
+    val: &Value,
+    low: &Value,
+    high: &Value,
+    negated: bool,
+    temporal: bool,
+) -> Value {
+    let compare = if temporal {
+        evaluate_temporal_comparison
+    } else {
+        evaluate_comparison
+    };
+    let result = evaluate_logical(
+        &compare(val, &BinaryOperator::Gte, low),
+        &LogicalOperator::And,
+        &compare(val, &BinaryOperator::Lte, high),
+    );
+    if negated {
+        evaluate_unary(&UnaryOperator::Not, &result)
+    } else {
+        result
+    }
+}
+
+/// `val [NOT] LIKE pattern`. Non-string operands yield `NULL`.
+fn evaluate_like(val: &Value, pattern: &Value, negated: bool, case_insensitive: bool) -> Value {
+    match (val, pattern) {
+        (Value::String(s), Value::String(p)) => {
+            Value::Bool(like_match(s, p, case_insensitive) != negated)
+        }
+        _ => Value::Null,
+    }
+}
+
+enum LikeToken {
+    AnySequence,
+    AnyChar,
+    Char(char),
+}
+
+/// Matches `text` against a SQL LIKE pattern: `%` matches any sequence, `_` any
+/// single character, and `\` escapes the next character.
+pub fn like_match(text: &str, pattern: &str, case_insensitive: bool) -> bool {
+    let fold = |s: &str| {
+        if case_insensitive {
+            s.to_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+    let text: Vec<char> = fold(text).chars().collect();
+    let pattern = fold(pattern);
+
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '%' => LikeToken::AnySequence,
+            '_' => LikeToken::AnyChar,
+            '\\' => LikeToken::Char(chars.next().unwrap_or('\\')),
+            c => LikeToken::Char(c),
+        });
+    }
+
+    // Greedy matching, backtracking to the last `%` on a mismatch
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match tokens.get(p) {
+            Some(LikeToken::AnySequence) => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+            }
+            Some(LikeToken::AnyChar) => {
+                t += 1;
+                p += 1;
             }
+            Some(LikeToken::Char(c)) if *c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
         }
-        (Value::String(s1), Value::String(s2)) => Some(s1.cmp(s2)),
-        (Value::Bool(b1), Value::Bool(b2)) => Some(b1.cmp(b2)),
+    }
+    tokens[p..]
+        .iter()
+        .all(|token| matches!(token, LikeToken::AnySequence))
+}
+
+/// Kleene logic: non-boolean operands are unknown (`NULL`).
+fn evaluate_logical(left: &Value, op: &LogicalOperator, right: &Value) -> Value {
+    let (l, r) = (left.as_bool(), right.as_bool());
+    match op {
+        LogicalOperator::And => match (l, r) {
+            (Some(false), _) | (_, Some(false)) => Value::Bool(false),
+            (Some(true), Some(true)) => Value::Bool(true),
+            _ => Value::Null,
+        },
+        LogicalOperator::Or => match (l, r) {
+            (Some(true), _) | (_, Some(true)) => Value::Bool(true),
+            (Some(false), Some(false)) => Value::Bool(false),
+            _ => Value::Null,
+        },
+    }
+}
+
+/// Compares two values for the comparison operators. Returns `None` if either
+/// is `NULL` or they are of different JSON types.
+pub(crate) fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    if matches!(left, Value::Null) || type_rank(left) != type_rank(right) {
+        return None;
+    }
+    Some(total_cmp(left, right))
+}
+
+/// Position of a value's JSON type in the total ordering.
+pub(crate) fn type_rank(val: &Value) -> u8 {
+    match val {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+        _ => 6,
+    }
+}
+
+/// Total ordering over JSON values, used for sorting:
+/// `null` < booleans < numbers < strings < arrays < objects.
+///
+/// Numbers compare numerically across integer and float representations,
+/// strings by code point, arrays element-wise and then by length, and objects
+/// by their `(key, value)` pairs in key order.
+pub fn total_cmp(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => compare_numbers(a, b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(x, y)| total_cmp(x, y))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b)
+            .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| total_cmp(va, vb)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ => type_rank(left)
+            .cmp(&type_rank(right))
+            .then_with(|| left.to_string().cmp(&right.to_string())),
+    }
+}
+
+/// Compares two numbers exactly, whatever mix of Int64, UInt64 and Float64 they are.
+pub fn compare_numbers(a: &Number, b: &Number) -> Ordering {
+    let as_int = |n: &Number| match n {
+        Number::Int64(i) => Some(*i as i128),
+        Number::UInt64(u) => Some(*u as i128),
         _ => None,
+    };
+    let as_f64 = |n: &Number| get_f64_from_number(n).unwrap_or(f64::NAN);
+    match (as_int(a), as_int(b)) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(x), None) => cmp_f64_int(as_f64(b), x).reverse(),
+        (None, Some(y)) => cmp_f64_int(as_f64(a), y),
+        (None, None) => cmp_f64(as_f64(a), as_f64(b)),
+    }
+}
+
+/// Orders floats, placing NaN above every number.
+fn cmp_f64(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// Compares a float to an integer without rounding the integer.
+fn cmp_f64_int(f: f64, i: i128) -> Ordering {
+    if f.is_nan() {
+        return Ordering::Greater;
     }
+    // Float to int casts saturate, so huge floats still order correctly.
+    let truncated = f.trunc();
+    (truncated as i128)
+        .cmp(&i)
+        .then_with(|| cmp_f64(f, truncated))
 }
 
 #[cfg(test)]
@@ -786,4 +2776,947 @@ mod tests {
         let f = evaluate_to_f64_lazy(&expr, &lazy);
         assert_eq!(f, Some(10.5));
     }
+
+    #[test]
+    fn test_projection_names() {
+        let expr = Expression::Function {
+            func: ScalarFunction::Abs,
+            args: vec![make_field_ref("a")],
+        };
+        assert_eq!(projection_name(&expr), "ABS(a)");
+
+        let expr = Expression::Binary {
+            left: Box::new(make_field_ref("a.b")),
+            op: BinaryOperator::Gte,
+            right: Box::new(Expression::Literal(Value::String("it's".into()))),
+        };
+        assert_eq!(projection_name(&expr), "a.b >= 'it''s'");
+
+        let expr = Expression::Alias(Box::new(make_field_ref("a")), "x");
+        assert_eq!(projection_name(&expr), "x");
+        assert_eq!(expr.to_string(), "a AS x");
+    }
+
+    #[test]
+    fn test_project_wildcard_and_alias() {
+        let doc = serde_to_jsonb(json!({"a": 1, "b": 2}));
+        let projections = vec![
+            Expression::Wildcard,
+            Expression::Alias(Box::new(make_field_ref("a")), "c"),
+        ];
+        let result = project(&projections, |e| evaluate_expression(e, &doc));
+        assert_eq!(result, serde_to_jsonb(json!({"a": 1, "b": 2, "c": 1})));
+    }
+
+    #[test]
+    fn test_evaluate_arithmetic() {
+        let doc =
+            serde_to_jsonb(json!({"i": 7, "j": 2, "f": 1.5, "z": 0, "s": "x", "big": i64::MAX}));
+        let eval = |l: &str, op: BinaryOperator, r: &str| {
+            let expr = Expression::Binary {
+                left: Box::new(make_field_ref(l)),
+                op,
+                right: Box::new(make_field_ref(r)),
+            };
+            evaluate_expression(&expr, &doc)
+        };
+
+        // Integers stay integers, `/` is always a float
+        assert_eq!(
+            eval("i", BinaryOperator::Plus, "j"),
+            serde_to_jsonb(json!(9))
+        );
+        assert_eq!(
+            eval("i", BinaryOperator::Minus, "j"),
+            serde_to_jsonb(json!(5))
+        );
+        assert_eq!(
+            eval("i", BinaryOperator::Multiply, "j"),
+            serde_to_jsonb(json!(14))
+        );
+        assert_eq!(
+            eval("i", BinaryOperator::Modulo, "j"),
+            serde_to_jsonb(json!(1))
+        );
+        assert_eq!(
+            eval("i", BinaryOperator::Divide, "j"),
+            serde_to_jsonb(json!(3.5))
+        );
+
+        // Mixed operands produce floats
+        assert_eq!(
+            eval("i", BinaryOperator::Plus, "f"),
+            serde_to_jsonb(json!(8.5))
+        );
+
+        // Overflow falls back to a float
+        assert_eq!(
+            eval("big", BinaryOperator::Plus, "big"),
+            serde_to_jsonb(json!(i64::MAX as f64 * 2.0))
+        );
+
+        // Division by zero, missing and mistyped operands are NULL
+        assert_eq!(eval("i", BinaryOperator::Divide, "z"), Value::Null);
+        assert_eq!(eval("i", BinaryOperator::Modulo, "z"), Value::Null);
+        assert_eq!(eval("i", BinaryOperator::Plus, "missing"), Value::Null);
+        assert_eq!(eval("i", BinaryOperator::Plus, "s"), Value::Null);
+
+        // Concatenation
+        assert_eq!(
+            eval("s", BinaryOperator::Concat, "i"),
+            serde_to_jsonb(json!("x7"))
+        );
+        assert_eq!(eval("s", BinaryOperator::Concat, "missing"), Value::Null);
+    }
+
+    #[test]
+    fn test_evaluate_unary() {
+        let doc = serde_to_jsonb(json!({"i": 7, "f": -1.5, "b": true, "s": "x"}));
+        let eval = |op: UnaryOperator, field: &str| {
+            let expr = Expression::Unary {
+                op,
+                expr: Box::new(make_field_ref(field)),
+            };
+            evaluate_expression(&expr, &doc)
+        };
+
+        assert_eq!(eval(UnaryOperator::Neg, "i"), serde_to_jsonb(json!(-7)));
+        assert_eq!(eval(UnaryOperator::Neg, "f"), serde_to_jsonb(json!(1.5)));
+        assert_eq!(eval(UnaryOperator::Neg, "s"), Value::Null);
+        assert_eq!(eval(UnaryOperator::Not, "b"), Value::Bool(false));
+        assert_eq!(eval(UnaryOperator::Not, "missing"), Value::Null);
+    }
+
+    #[test]
+    fn test_evaluate_lazy_arithmetic() {
+        let doc_val = serde_to_jsonb(json!({"a": 10, "b": 4}));
+        let record = ("id".to_string(), SerdeWrapper(&doc_val));
+        let blob = jsonb_schema::to_owned_jsonb(&record).unwrap();
+        let lazy = LazyDocument {
+            id: "id".to_string(),
+            raw: blob.to_vec(),
+        };
+
+        // -(a - b) * 2
+        let expr = Expression::Binary {
+            left: Box::new(Expression::Unary {
+                op: UnaryOperator::Neg,
+                expr: Box::new(Expression::Binary {
+                    left: Box::new(make_field_ref("a")),
+                    op: BinaryOperator::Minus,
+                    right: Box::new(make_field_ref("b")),
+                }),
+            }),
+            op: BinaryOperator::Multiply,
+            right: Box::new(Expression::Literal(serde_to_jsonb(json!(2)))),
+        };
+        assert_eq!(
+            evaluate_expression_lazy(&expr, &lazy),
+            serde_to_jsonb(json!(-12))
+        );
+        assert_eq!(evaluate_to_f64_lazy(&expr, &lazy), Some(-12.0));
+    }
+
+    #[test]
+    fn test_like_match() {
+        assert!(like_match("hello", "h%o", false));
+        assert!(like_match("hello", "%", false));
+        assert!(like_match("", "%", false));
+        assert!(like_match("hello", "_ell_", false));
+        assert!(!like_match("hello", "_ell", false));
+        assert!(like_match("abcabc", "%b%c", false));
+        assert!(!like_match("abcabd", "%b%c", false));
+        assert!(like_match("50%", "50\\%", false));
+        assert!(!like_match("500", "50\\%", false));
+        assert!(!like_match("HELLO", "hel%", false));
+        assert!(like_match("HELLO", "hel%", true));
+        assert!(like_match("héllo", "h_llo", false));
+    }
+
+    #[test]
+    fn test_evaluate_predicates() {
+        let doc = serde_to_jsonb(json!({"a": 5, "s": "Alice", "n": null}));
+        let lazy = {
+            let record = ("id".to_string(), SerdeWrapper(&doc));
+            let blob = jsonb_schema::to_owned_jsonb(&record).unwrap();
+            LazyDocument {
+                id: "id".to_string(),
+                raw: blob.to_vec(),
+            }
+        };
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let check = |expr: Expression, expected: Value| {
+            assert_eq!(evaluate_expression(&expr, &doc), expected, "{}", expr);
+            assert_eq!(evaluate_expression_lazy(&expr, &lazy), expected, "{}", expr);
+        };
+
+        let in_list = |field: &'static str, negated: bool| Expression::InList {
+            expr: Box::new(make_field_ref(field)),
+            list: vec![lit(json!(1)), lit(json!(5)), lit(json!("x"))],
+            negated,
+        };
+        check(in_list("a", false), Value::Bool(true));
+        check(in_list("a", true), Value::Bool(false));
+        check(in_list("s", false), Value::Bool(false));
+        check(in_list("missing", false), Value::Null);
+
+        let between = |low: i64, high: i64, negated: bool| Expression::Between {
+            expr: Box::new(make_field_ref("a")),
+            low: Box::new(lit(json!(low))),
+            high: Box::new(lit(json!(high))),
+            negated,
+        };
+        check(between(1, 5, false), Value::Bool(true));
+        check(between(6, 9, false), Value::Bool(false));
+        check(between(6, 9, true), Value::Bool(true));
+
+        let like = |pattern: &str, case_insensitive: bool| Expression::Like {
+            expr: Box::new(make_field_ref("s")),
+            pattern: Box::new(lit(json!(pattern))),
+            negated: false,
+            case_insensitive,
+        };
+        check(like("Al%", false), Value::Bool(true));
+        check(like("al%", false), Value::Bool(false));
+        check(like("al%", true), Value::Bool(true));
+
+        // IS NULL is true for null and missing fields, IS MISSING only for missing ones
+        let is_null_expr = |field: &'static str| Expression::IsNull {
+            expr: Box::new(make_field_ref(field)),
+            negated: false,
+        };
+        let is_missing_expr = |field: &'static str| Expression::IsMissing {
+            expr: Box::new(make_field_ref(field)),
+            negated: false,
+        };
+        check(is_null_expr("n"), Value::Bool(true));
+        check(is_null_expr("missing"), Value::Bool(true));
+        check(is_null_expr("a"), Value::Bool(false));
+        check(is_missing_expr("n"), Value::Bool(false));
+        check(is_missing_expr("missing"), Value::Bool(true));
+        check(is_missing_expr("_id"), Value::Bool(false));
+    }
+
+    #[test]
+    fn test_three_valued_logic() {
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+        let n = Value::Null;
+        let and = |l: &Value, r: &Value| evaluate_logical(l, &LogicalOperator::And, r);
+        let or = |l: &Value, r: &Value| evaluate_logical(l, &LogicalOperator::Or, r);
+
+        assert_eq!(and(&t, &n), Value::Null);
+        assert_eq!(and(&f, &n), Value::Bool(false));
+        assert_eq!(and(&n, &f), Value::Bool(false));
+        assert_eq!(or(&t, &n), Value::Bool(true));
+        assert_eq!(or(&n, &f), Value::Null);
+        assert_eq!(evaluate_unary(&UnaryOperator::Not, &n), Value::Null);
+
+        // Comparisons with NULL are NULL, including NULL = NULL
+        let one = serde_to_jsonb(json!(1));
+        assert_eq!(evaluate_binary(&n, &BinaryOperator::Eq, &n), Value::Null);
+        assert_eq!(evaluate_binary(&one, &BinaryOperator::Neq, &n), Value::Null);
+
+        // Values of different types are unequal and unordered
+        let s = serde_to_jsonb(json!("1"));
+        assert_eq!(evaluate_binary(&one, &BinaryOperator::Eq, &s), f);
+        assert_eq!(evaluate_binary(&one, &BinaryOperator::Neq, &s), t);
+        assert_eq!(evaluate_binary(&one, &BinaryOperator::Lt, &s), Value::Null);
+
+        // IN and BETWEEN follow their OR / AND expansions
+        let list = vec![serde_to_jsonb(json!(2)), Value::Null];
+        assert_eq!(evaluate_in_list(&one, &list, false), Value::Null);
+        assert_eq!(evaluate_in_list(&one, &list, true), Value::Null);
+        assert_eq!(evaluate_in_list(&one, &[one.clone(), Value::Null], true), f);
+        assert_eq!(
+            evaluate_between(&one, &n, &Value::Bool(true), false),
+            Value::Null
+        );
+        assert_eq!(
+            evaluate_between(&one, &n, &serde_to_jsonb(json!(0)), false),
+            f
+        );
+    }
+
+    #[test]
+    fn test_string_functions() {
+        let doc = serde_to_jsonb(json!({"s": "  Héllo World  ", "csv": "a,b,,c"}));
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let call = |func: ScalarFunction, args: Vec<Expression<'static>>| {
+            evaluate_expression(&Expression::Function { func, args }, &doc)
+        };
+        let s = |v: &str| serde_to_jsonb(json!(v));
+
+        assert_eq!(
+            call(ScalarFunction::Lower, vec![lit(json!("AbC"))]),
+            s("abc")
+        );
+        assert_eq!(
+            call(ScalarFunction::Upper, vec![lit(json!("straße"))]),
+            s("STRASSE")
+        );
+        assert_eq!(
+            call(ScalarFunction::Length, vec![lit(json!("héllo"))]),
+            serde_to_jsonb(json!(5))
+        );
+        assert_eq!(
+            call(ScalarFunction::Length, vec![lit(json!(5))]),
+            Value::Null
+        );
+
+        // SUBSTR is 1-based and clips out-of-range positions
+        let substr = |args: Vec<serde_json::Value>| {
+            call(ScalarFunction::Substr, args.into_iter().map(lit).collect())
+        };
+        assert_eq!(substr(vec![json!("héllo"), json!(2), json!(3)]), s("éll"));
+        assert_eq!(substr(vec![json!("hello"), json!(3)]), s("llo"));
+        assert_eq!(substr(vec![json!("hello"), json!(0), json!(3)]), s("he"));
+        assert_eq!(substr(vec![json!("hello"), json!(-5), json!(3)]), s(""));
+        assert_eq!(substr(vec![json!("hello"), json!(10)]), s(""));
+        assert_eq!(
+            substr(vec![json!("hello"), json!(1), json!(-1)]),
+            Value::Null
+        );
+
+        assert_eq!(
+            call(ScalarFunction::Trim, vec![make_field_ref("s")]),
+            s("Héllo World")
+        );
+        assert_eq!(
+            call(ScalarFunction::Ltrim, vec![make_field_ref("s")]),
+            s("Héllo World  ")
+        );
+        assert_eq!(
+            call(ScalarFunction::Rtrim, vec![make_field_ref("s")]),
+            s("  Héllo World")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Trim,
+                vec![lit(json!("xxhixy")), lit(json!("xy"))]
+            ),
+            s("hi")
+        );
+
+        assert_eq!(
+            call(
+                ScalarFunction::Replace,
+                vec![lit(json!("banana")), lit(json!("an")), lit(json!("AN"))]
+            ),
+            s("bANANa")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Replace,
+                vec![lit(json!("banana")), lit(json!("")), lit(json!("x"))]
+            ),
+            s("banana")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Split,
+                vec![make_field_ref("csv"), lit(json!(","))]
+            ),
+            serde_to_jsonb(json!(["a", "b", "", "c"]))
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Split,
+                vec![lit(json!("ab")), lit(json!(""))]
+            ),
+            serde_to_jsonb(json!(["a", "b"]))
+        );
+
+        let regexp = |args: Vec<serde_json::Value>| {
+            call(
+                ScalarFunction::RegexpMatch,
+                args.into_iter().map(lit).collect(),
+            )
+        };
+        assert_eq!(
+            regexp(vec![json!("foo123bar45"), json!("[0-9]+")]),
+            serde_to_jsonb(json!(["123"]))
+        );
+        assert_eq!(
+            regexp(vec![json!("key=value"), json!("(\\w+)=(\\w+)")]),
+            serde_to_jsonb(json!(["key", "value"]))
+        );
+        assert_eq!(regexp(vec![json!("ABC"), json!("b")]), Value::Null);
+        assert_eq!(
+            regexp(vec![json!("ABC"), json!("b"), json!("i")]),
+            serde_to_jsonb(json!(["B"]))
+        );
+        assert_eq!(regexp(vec![json!("abc"), json!("(")]), Value::Null);
+        assert!(compile_regex("a", "q").is_err());
+    }
+
+    #[test]
+    fn test_json_and_conditional_functions() {
+        let doc = serde_to_jsonb(json!({
+            "obj": {"b": 1, "a": [1, 2]},
+            "arr": [1, "x", null],
+            "n": null,
+            "x": 3
+        }));
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let call = |func: ScalarFunction, args: Vec<Expression<'static>>| {
+            evaluate_expression(&Expression::Function { func, args }, &doc)
+        };
+        let s = |v: &str| serde_to_jsonb(json!(v));
+
+        for (field, expected) in [
+            ("obj", "object"),
+            ("arr", "array"),
+            ("n", "null"),
+            ("x", "number"),
+            ("missing", "null"),
+        ] {
+            assert_eq!(
+                call(ScalarFunction::JsonTypeof, vec![make_field_ref(field)]),
+                s(expected)
+            );
+        }
+        assert_eq!(
+            call(ScalarFunction::ArrayLength, vec![make_field_ref("arr")]),
+            serde_to_jsonb(json!(3))
+        );
+        assert_eq!(
+            call(ScalarFunction::ArrayLength, vec![make_field_ref("obj")]),
+            Value::Null
+        );
+        assert_eq!(
+            call(ScalarFunction::Keys, vec![make_field_ref("obj")]),
+            serde_to_jsonb(json!(["a", "b"]))
+        );
+        assert_eq!(
+            call(ScalarFunction::ToJson, vec![make_field_ref("arr")]),
+            s("[1,\"x\",null]")
+        );
+        assert_eq!(
+            call(ScalarFunction::ToJson, vec![make_field_ref("n")]),
+            Value::Null
+        );
+
+        assert_eq!(
+            call(
+                ScalarFunction::Coalesce,
+                vec![
+                    make_field_ref("missing"),
+                    make_field_ref("n"),
+                    make_field_ref("x")
+                ]
+            ),
+            serde_to_jsonb(json!(3))
+        );
+        assert_eq!(
+            call(ScalarFunction::Coalesce, vec![make_field_ref("n")]),
+            Value::Null
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Nullif,
+                vec![make_field_ref("x"), lit(json!(3.0))]
+            ),
+            Value::Null
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::Nullif,
+                vec![make_field_ref("x"), lit(json!(4))]
+            ),
+            serde_to_jsonb(json!(3))
+        );
+
+        // Searched CASE takes the first true branch; simple CASE compares with `=`
+        let searched = Expression::Case {
+            operand: None,
+            branches: vec![
+                (
+                    Expression::Binary {
+                        left: Box::new(make_field_ref("x")),
+                        op: BinaryOperator::Gt,
+                        right: Box::new(lit(json!(5))),
+                    },
+                    lit(json!("big")),
+                ),
+                (
+                    Expression::Binary {
+                        left: Box::new(make_field_ref("x")),
+                        op: BinaryOperator::Gt,
+                        right: Box::new(lit(json!(1))),
+                    },
+                    lit(json!("medium")),
+                ),
+            ],
+            else_result: Some(Box::new(lit(json!("small")))),
+        };
+        assert_eq!(evaluate_expression(&searched, &doc), s("medium"));
+        assert_eq!(
+            searched.to_string(),
+            "CASE WHEN x > 5 THEN 'big' WHEN x > 1 THEN 'medium' ELSE 'small' END"
+        );
+
+        let simple = |field: &'static str| Expression::Case {
+            operand: Some(Box::new(make_field_ref(field))),
+            branches: vec![(lit(json!(3)), lit(json!("three")))],
+            else_result: None,
+        };
+        assert_eq!(evaluate_expression(&simple("x"), &doc), s("three"));
+        assert_eq!(evaluate_expression(&simple("n"), &doc), Value::Null);
+    }
+
+    #[test]
+    fn test_temporal_functions() {
+        let doc = serde_to_jsonb(json!({
+            "ts": "2024-05-16T13:45:30+02:00",
+            "epoch": 1715867130,
+            "text": "16/05/2024"
+        }));
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let call = |func: ScalarFunction, args: Vec<Expression<'static>>| {
+            evaluate_expression(&Expression::Function { func, args }, &doc)
+        };
+        let s = |v: &str| serde_to_jsonb(json!(v));
+
+        assert_eq!(
+            call(ScalarFunction::ToTimestamp, vec![make_field_ref("ts")]),
+            s("2024-05-16T11:45:30Z")
+        );
+        assert_eq!(
+            call(ScalarFunction::ToTimestamp, vec![make_field_ref("epoch")]),
+            s("2024-05-16T13:45:30Z")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::ToTimestamp,
+                vec![make_field_ref("text"), lit(json!("%d/%m/%Y"))]
+            ),
+            s("2024-05-16T00:00:00Z")
+        );
+        assert_eq!(
+            call(ScalarFunction::ToTimestamp, vec![lit(json!("soon"))]),
+            Value::Null
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::DateTrunc,
+                vec![lit(json!("hour")), make_field_ref("ts")]
+            ),
+            s("2024-05-16T11:00:00Z")
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::DatePart,
+                vec![lit(json!("dow")), make_field_ref("epoch")]
+            ),
+            serde_to_jsonb(json!(4))
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::DatePart,
+                vec![lit(json!("fortnight")), make_field_ref("ts")]
+            ),
+            Value::Null
+        );
+        let Value::String(now) = call(ScalarFunction::Now, vec![]) else {
+            panic!("NOW() should return a string");
+        };
+        assert!(temporal::is_date_time(&now));
+    }
+
+    #[test]
+    fn test_vector_distance() {
+        let doc = serde_to_jsonb(json!({"v": [3, 4], "s": "x"}));
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let distance = |field: &'static str, query: serde_json::Value, metric: &str| {
+            let args = vec![make_field_ref(field), lit(query), lit(json!(metric))];
+            let expr = Expression::Function {
+                func: ScalarFunction::VectorDistance,
+                args,
+            };
+            evaluate_expression(&expr, &doc)
+        };
+        assert_eq!(
+            distance("v", json!([0, 0]), "l2"),
+            serde_to_jsonb(json!(5.0))
+        );
+        assert_eq!(
+            distance("v", json!([1, 1]), "DOT"),
+            serde_to_jsonb(json!(-7.0))
+        );
+        assert_eq!(
+            distance("v", json!([6, 8]), "cosine"),
+            serde_to_jsonb(json!(0.0))
+        );
+        assert_eq!(distance("v", json!([1, 1, 1]), "l2"), Value::Null);
+        assert_eq!(distance("s", json!([1, 1]), "l2"), Value::Null);
+        assert_eq!(distance("v", json!([1, 1]), "hamming"), Value::Null);
+    }
+
+    #[test]
+    fn test_spatial_functions() {
+        let doc = serde_to_jsonb(json!({
+            "paris": {"type": "Point", "coordinates": [2.3522, 48.8566]},
+            "london": {"type": "Point", "coordinates": [-0.1276, 51.5072]},
+            "france": {"type": "Polygon", "coordinates": [[
+                [-5, 42], [8, 42], [8, 51], [-5, 51], [-5, 42]
+            ]]}
+        }));
+        let lit = |v: serde_json::Value| Expression::Literal(serde_to_jsonb(v));
+        let call = |func: ScalarFunction, args: Vec<Expression<'static>>| {
+            evaluate_expression(&Expression::Function { func, args }, &doc)
+        };
+        let field = make_field_ref;
+
+        let Value::Number(meters) = call(
+            ScalarFunction::StDistance,
+            vec![field("paris"), field("london")],
+        ) else {
+            panic!("ST_DISTANCE should return a number");
+        };
+        assert!((get_f64_from_number(&meters).unwrap() - 343_500.0).abs() < 1_000.0);
+        let within = |meters: i64| {
+            call(
+                ScalarFunction::StDwithin,
+                vec![field("london"), field("paris"), lit(json!(meters))],
+            )
+        };
+        assert_eq!(within(350_000), Value::Bool(true));
+        assert_eq!(within(300_000), Value::Bool(false));
+
+        let contains = |point: &'static str| {
+            call(
+                ScalarFunction::StContains,
+                vec![field("france"), field(point)],
+            )
+        };
+        assert_eq!(contains("paris"), Value::Bool(true));
+        assert_eq!(contains("london"), Value::Bool(false));
+        assert_eq!(
+            call(
+                ScalarFunction::StContains,
+                vec![field("paris"), field("paris")]
+            ),
+            Value::Null
+        );
+
+        let in_box = |geometry: &'static str| {
+            let args = [-1, 48, 3, 52].map(|n| lit(json!(n)));
+            let mut all = vec![field(geometry)];
+            all.extend(args);
+            call(ScalarFunction::StWithinBbox, all)
+        };
+        assert_eq!(in_box("paris"), Value::Bool(true));
+        assert_eq!(in_box("france"), Value::Bool(false));
+
+        assert_eq!(
+            call(
+                ScalarFunction::StPoint,
+                vec![lit(json!(2.3522)), lit(json!(48.8566))]
+            ),
+            serde_to_jsonb(json!({"type": "Point", "coordinates": [2.3522, 48.8566]}))
+        );
+        assert_eq!(
+            call(ScalarFunction::StPoint, vec![lit(json!(0)), lit(json!(91))]),
+            Value::Null
+        );
+        assert_eq!(
+            call(
+                ScalarFunction::StDistance,
+                vec![field("paris"), field("missing")]
+            ),
+            Value::Null
+        );
+    }
+
+    #[test]
+    fn test_temporal_arithmetic_and_comparison() {
+        let s = |v: &str| serde_to_jsonb(json!(v));
+        let ts = s("2024-01-31T12:00:00Z");
+        let hour = s("PT1H");
+        let month = s("P1M");
+
+        assert_eq!(
+            evaluate_binary(&ts, &BinaryOperator::Minus, &hour),
+            s("2024-01-31T11:00:00Z")
+        );
+        assert_eq!(
+            evaluate_binary(&month, &BinaryOperator::Plus, &ts),
+            s("2024-02-29T12:00:00Z")
+        );
+        assert_eq!(
+            evaluate_binary(&s("2024-02-01T13:30:00Z"), &BinaryOperator::Minus, &ts),
+            s("P1DT1H30M")
+        );
+        assert_eq!(
+            evaluate_binary(&hour, &BinaryOperator::Minus, &s("PT30M")),
+            s("PT30M")
+        );
+        assert_eq!(evaluate_unary(&UnaryOperator::Neg, &hour), s("PT-1H"));
+        assert_eq!(
+            evaluate_binary(&hour, &BinaryOperator::Minus, &ts),
+            Value::Null
+        );
+        assert_eq!(
+            evaluate_binary(&s("a"), &BinaryOperator::Plus, &s("b")),
+            Value::Null
+        );
+
+        // Timestamps compare as instants, whatever their offset, when the
+        // query computes one of them
+        let plus_one = s("2024-01-31T12:30:00+01:00");
+        let doc = serde_to_jsonb(json!({"a": "2024-01-31T12:30:00+01:00", "b": "10 apples"}));
+        let compare = |left: Expression, op: BinaryOperator, right: Expression| {
+            evaluate_expression(
+                &Expression::Binary {
+                    left: Box::new(left),
+                    op,
+                    right: Box::new(right),
+                },
+                &doc,
+            )
+        };
+        let to_timestamp = |val: &Value| Expression::Function {
+            func: ScalarFunction::ToTimestamp,
+            args: vec![Expression::Literal(val.clone())],
+        };
+        assert_eq!(
+            compare(make_field_ref("a"), BinaryOperator::Lt, to_timestamp(&ts)),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            compare(
+                to_timestamp(&s("2024-01-31")),
+                BinaryOperator::Eq,
+                Expression::Literal(s("2024-01-31T00:00:00+00:00"))
+            ),
+            Value::Bool(true)
+        );
+        // Other strings compare as text, even with a timestamp
+        assert_eq!(
+            compare(make_field_ref("b"), BinaryOperator::Lt, to_timestamp(&ts)),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            evaluate_binary(&plus_one, &BinaryOperator::Lt, &ts),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            evaluate_binary(&s("2024-01-01"), &BinaryOperator::Lt, &s("10 apples")),
+            Value::Bool(false)
+        );
+        assert_eq!(
+            evaluate_binary(
+                &s("2024-01-31"),
+                &BinaryOperator::Eq,
+                &s("2024-01-31T00:00:00Z")
+            ),
+            Value::Bool(false)
+        );
+        let mut sorted = vec![
+            s("b"),
+            ts.clone(),
+            s("10 apples"),
+            plus_one.clone(),
+            s("2024"),
+        ];
+        sorted.sort_by(total_cmp);
+        assert_eq!(
+            sorted,
+            vec![s("10 apples"), s("2024"), ts, plus_one, s("b")]
+        );
+    }
+
+    #[test]
+    fn test_compare_numbers() {
+        use std::cmp::Ordering::*;
+
+        let cmp = |a: Number, b: Number| compare_numbers(&a, &b);
+        assert_eq!(cmp(Number::Int64(1), Number::Float64(1.0)), Equal);
+        assert_eq!(cmp(Number::Int64(-1), Number::UInt64(u64::MAX)), Less);
+        assert_eq!(
+            cmp(Number::UInt64(u64::MAX), Number::Int64(i64::MAX)),
+            Greater
+        );
+        assert_eq!(cmp(Number::Float64(1.5), Number::Int64(1)), Greater);
+        assert_eq!(cmp(Number::Float64(-1.5), Number::Int64(-1)), Less);
+        assert_eq!(
+            cmp(Number::Float64(1e300), Number::UInt64(u64::MAX)),
+            Greater
+        );
+        // 2^53 + 1 is not representable as f64 but still compares exactly
+        assert_eq!(
+            cmp(
+                Number::Int64(9_007_199_254_740_993),
+                Number::Float64(9_007_199_254_740_992.0)
+            ),
+            Greater
+        );
+    }
+
+    #[test]
+    fn test_total_cmp() {
+        let values: Vec<Value> = [
+            json!(null),
+            json!(false),
+            json!(true),
+            json!(-1.5),
+            json!(2),
+            json!(""),
+            json!("a"),
+            json!([]),
+            json!([1, 2]),
+            json!([1, 3]),
+            json!({}),
+            json!({"a": 1}),
+            json!({"b": 0}),
+        ]
+        .into_iter()
+        .map(serde_to_jsonb)
+        .collect();
+        for (i, a) in values.iter().enumerate() {
+            for (j, b) in values.iter().enumerate() {
+                assert_eq!(total_cmp(a, b), i.cmp(&j), "{} vs {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_quantified_and_containment() {
+        let doc = serde_to_jsonb(json!({
+            "tags": ["a", "b"],
+            "scores": [3, 5, null],
+            "empty": [],
+            "name": "a",
+            "doc": {"a": [1, 5], "b": {"c": 1}}
+        }));
+        let lit = |v: serde_json::Value| Box::new(Expression::Literal(serde_to_jsonb(v)));
+        let quantified = |left, op, field: &'static str, all| {
+            evaluate_expression(
+                &Expression::Quantified {
+                    left,
+                    op,
+                    right: Box::new(make_field_ref(field)),
+                    all,
+                },
+                &doc,
+            )
+        };
+        let t = Value::Bool(true);
+        let f = Value::Bool(false);
+
+        assert_eq!(
+            quantified(lit(json!("b")), BinaryOperator::Eq, "tags", false),
+            t
+        );
+        assert_eq!(
+            quantified(lit(json!("c")), BinaryOperator::Eq, "tags", false),
+            f
+        );
+        // A scalar behaves like a one-element array
+        assert_eq!(
+            quantified(lit(json!("a")), BinaryOperator::Eq, "name", false),
+            t
+        );
+        // NULL elements make an otherwise false ANY (or true ALL) unknown
+        assert_eq!(
+            quantified(lit(json!(4)), BinaryOperator::Lt, "scores", false),
+            t
+        );
+        assert_eq!(
+            quantified(lit(json!(9)), BinaryOperator::Lt, "scores", false),
+            Value::Null
+        );
+        assert_eq!(
+            quantified(lit(json!(9)), BinaryOperator::Gt, "scores", true),
+            Value::Null
+        );
+        assert_eq!(
+            quantified(lit(json!(4)), BinaryOperator::Gt, "scores", true),
+            f
+        );
+        assert_eq!(
+            quantified(lit(json!(1)), BinaryOperator::Eq, "empty", false),
+            f
+        );
+        assert_eq!(
+            quantified(lit(json!(1)), BinaryOperator::Eq, "empty", true),
+            t
+        );
+        assert_eq!(
+            quantified(lit(json!(1)), BinaryOperator::Eq, "missing", false),
+            Value::Null
+        );
+
+        let contains = |a: serde_json::Value, b: serde_json::Value| {
+            json_contains(&serde_to_jsonb(a), &serde_to_jsonb(b))
+        };
+        assert!(contains(
+            json!({"a": [1, 5], "b": {"c": 1}}),
+            json!({"a": [5]})
+        ));
+        assert!(contains(
+            json!({"a": [1, 5], "b": {"c": 1}}),
+            json!({"b": {}})
+        ));
+        assert!(!contains(json!({"a": [1, 5]}), json!({"a": 1})));
+        assert!(contains(json!([1, [2, 3]]), json!([[3], 1])));
+        assert!(contains(json!(["a", "b"]), json!("a")));
+        assert!(!contains(json!("a"), json!(["a"])));
+        assert!(!contains(json!([{"a": 1}]), json!({"a": 1})));
+        assert!(contains(json!(1), json!(1.0)));
+        assert!(contains(json!([]), json!([])));
+
+        let binary = |op| Expression::Binary {
+            left: Box::new(make_field_ref("doc")),
+            op,
+            right: lit(json!({"b": {"c": 1}})),
+        };
+        assert_eq!(
+            evaluate_expression(&binary(BinaryOperator::Contains), &doc),
+            t
+        );
+        assert_eq!(
+            evaluate_expression(&binary(BinaryOperator::ContainedBy), &doc),
+            f
+        );
+        let all = Expression::Quantified {
+            left: lit(json!("x")),
+            op: BinaryOperator::Neq,
+            right: Box::new(make_field_ref("tags")),
+            all: true,
+        };
+        assert_eq!(all.to_string(), "'x' != ALL(tags)");
+
+        let raw = "$.a ? (@ > 3)";
+        let exists = Expression::JsonExists {
+            expr: Box::new(make_field_ref("doc")),
+            path: Box::new(jsonb_schema::jsonpath::parse_json_path(raw.as_bytes()).unwrap()),
+            raw,
+        };
+        assert_eq!(evaluate_expression(&exists, &doc), t);
+        assert_eq!(exists.to_string(), "JSON_EXISTS(doc, '$.a ? (@ > 3)')");
+        let raw = "$.a ? (@ > 9)";
+        let missing = Expression::JsonExists {
+            expr: Box::new(make_field_ref("doc")),
+            path: Box::new(jsonb_schema::jsonpath::parse_json_path(raw.as_bytes()).unwrap()),
+            raw,
+        };
+        assert_eq!(evaluate_expression(&missing, &doc), f);
+
+        assert_eq!(
+            evaluate_function(
+                &ScalarFunction::ParseJson,
+                &[serde_to_jsonb(json!("[1, {\"a\": 2}]"))]
+            ),
+            serde_to_jsonb(json!([1, {"a": 2}]))
+        );
+        assert_eq!(
+            evaluate_function(
+                &ScalarFunction::ParseJson,
+                &[serde_to_jsonb(json!("{oops"))]
+            ),
+            Value::Null
+        );
+    }
 }
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+/// BM25 term frequency saturation.
+const K1: f64 = 1.2;
+/// BM25 document length normalization.
+const B: f64 = 0.75;
+
+/// Splits text into terms on anything other than letters and digits,
+/// lowercasing and stemming each word.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| stem(&word.to_lowercase()))
+        .collect()
+}
+
+/// Returns the distinct terms of a query, in order of appearance.
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in tokenize(query) {
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+/// Strips common English plural and verb endings from a lowercase word, so
+/// that e.g. "indexes", "indexed" and "indexing" all become "index". Words
+/// are only shortened to stems of three or more characters.
+fn stem(word: &str) -> String {
+    let mut stem = word;
+    if let Some(base) = stem.strip_suffix("ies").filter(|base| base.len() >= 2) {
+        return format!("{}y", base);
+    }
+    if let Some(base) = stem.strip_suffix("sses") {
+        return format!("{}ss", base);
+    }
+    if let Some(base) = ["xes", "ches", "shes", "zes"]
+        .iter()
+        .find(|suffix| stem.ends_with(*suffix))
+        .map(|_| &stem[..stem.len() - 2])
+    {
+        stem = base;
+    } else if let Some(base) = stem.strip_suffix('s')
+        && !base.ends_with(['s', 'u', 'i'])
+        && base.len() >= 3
+    {
+        stem = base;
+    }
+    if let Some(base) = stem
+        .strip_suffix("ing")
+        .or_else(|| stem.strip_suffix("ed"))
+        .filter(|base| base.len() >= 3 && base.contains(['a', 'e', 'i', 'o', 'u', 'y']))
+    {
+        stem = base;
+        // "running" -> "run", but "falling" -> "fall"
+        let bytes = stem.as_bytes();
+        let last = bytes[bytes.len() - 1];
+        if last.is_ascii_alphabetic()
+            && bytes[bytes.len() - 2] == last
+            && !b"aeioulsz".contains(&last)
+        {
+            stem = &stem[..stem.len() - 1];
+        }
+    }
+    stem.to_string()
+}
+
+/// Collection-wide statistics of a full-text index, for BM25 scoring.
+///
+/// Like the index itself, they count every indexed version of a document
+/// until compaction drops the replaced ones.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TextStats {
+    /// Number of indexed texts.
+    pub documents: u64,
+    /// Total number of terms in the indexed texts.
+    pub tokens: u64,
+    /// Number of indexed texts containing each query term.
+    pub frequencies: HashMap<String, u64>,
+}
+
+impl TextStats {
+    fn idf(&self, term: &str) -> f64 {
+        let n = self.documents as f64;
+        let df = self.frequencies.get(term).copied().unwrap_or(0) as f64;
+        (1.0 + (n - df + 0.5).max(0.0) / (df + 0.5)).ln()
+    }
+
+    fn average_length(&self) -> f64 {
+        (self.tokens as f64 / self.documents as f64).max(1.0)
+    }
+}
+
+/// Scores `text` against query terms with BM25, returning 0 if none of them
+/// occurs in it. Without statistics, every term has the same weight and the
+/// text is taken to be of average length.
+pub fn score(text: &str, terms: &[String], stats: Option<&TextStats>) -> f64 {
+    let tokens = tokenize(text);
+    let stats = stats.filter(|stats| stats.documents > 0);
+    let mut total = 0.0;
+    for term in terms {
+        let tf = tokens.iter().filter(|token| *token == term).count() as f64;
+        if tf == 0.0 {
+            continue;
+        }
+        let (idf, norm) = match stats {
+            Some(stats) => (
+                stats.idf(term),
+                1.0 - B + B * tokens.len() as f64 / stats.average_length(),
+            ),
+            None => (1.0, 1.0),
+        };
+        total += idf * tf * (K1 + 1.0) / (tf + K1 * norm);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("Indexing the indexes, INDEXED!"),
+            vec!["index", "the", "index", "index"]
+        );
+        assert_eq!(
+            tokenize("Running queries; stopped caches-falls"),
+            vec!["run", "query", "stop", "cach", "fall"]
+        );
+        assert_eq!(
+            tokenize("bus is as string"),
+            vec!["bus", "is", "as", "string"]
+        );
+        assert_eq!(query_terms("fox FOX foxes dog"), vec!["fox", "dog"]);
+    }
+
+    #[test]
+    fn test_score() {
+        let terms = query_terms("quick fox");
+        assert_eq!(score("a lazy dog", &terms, None), 0.0);
+        let one = score("the quick dog", &terms, None);
+        let both = score("the quick fox", &terms, None);
+        assert!(one > 0.0 && both > one);
+
+        // Rare terms weigh more, and shorter texts score higher
+        let stats = TextStats {
+            documents: 10,
+            tokens: 40,
+            frequencies: HashMap::from([("quick".to_string(), 8), ("fox".to_string(), 1)]),
+        };
+        let quick = score("the quick dog", &terms, Some(&stats));
+        let fox = score("the lazy fox", &terms, Some(&stats));
+        assert!(fox > quick);
+        assert!(score("fox", &terms, Some(&stats)) > fox);
+    }
+}
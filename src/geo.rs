@@ -0,0 +1,418 @@
+use crate::Value;
+use crate::expression::get_f64_from_number;
+use std::collections::BTreeMap;
+
+/// Mean radius of the Earth in meters, for great-circle distances.
+const EARTH_RADIUS: f64 = 6_371_008.8;
+/// Length of the geohashes of points in a spatial index, locating them to
+/// within a few centimeters.
+pub const GEOHASH_PRECISION: usize = 12;
+/// Most geohash cells a bounding box is covered with for an index lookup.
+const MAX_CELLS: usize = 16;
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// A longitude and latitude in degrees, in GeoJSON order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coord {
+    pub lon: f64,
+    pub lat: f64,
+}
+
+impl Coord {
+    /// Returns the point, if its coordinates are in range.
+    pub fn new(lon: f64, lat: f64) -> Option<Self> {
+        ((-180.0..=180.0).contains(&lon) && (-90.0..=90.0).contains(&lat))
+            .then_some(Coord { lon, lat })
+    }
+}
+
+/// A GeoJSON geometry with WGS 84 coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Coord),
+    MultiPoint(Vec<Coord>),
+    LineString(Vec<Coord>),
+    /// An outer ring followed by its holes.
+    Polygon(Vec<Vec<Coord>>),
+    MultiPolygon(Vec<Vec<Vec<Coord>>>),
+}
+
+impl Geometry {
+    /// Parses a GeoJSON geometry object, such as
+    /// `{"type": "Point", "coordinates": [lon, lat]}`. Coordinates must be in
+    /// range, and polygon rings closed.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let Value::Object(fields) = value else {
+            return None;
+        };
+        let coordinates = fields.get("coordinates")?;
+        match fields.get("type")? {
+            Value::String(kind) => match kind.as_ref() {
+                "Point" => Some(Geometry::Point(coord(coordinates)?)),
+                "MultiPoint" => Some(Geometry::MultiPoint(coords(coordinates)?)),
+                "LineString" => Some(Geometry::LineString(coords(coordinates)?)),
+                "Polygon" => Some(Geometry::Polygon(rings(coordinates)?)),
+                "MultiPolygon" => Some(Geometry::MultiPolygon(
+                    array(coordinates)?
+                        .iter()
+                        .map(rings)
+                        .collect::<Option<_>>()?,
+                )),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the GeoJSON object of a point.
+    pub fn point_value(point: Coord) -> Value {
+        let number = |n| Value::Number(jsonb_schema::Number::Float64(n));
+        Value::Object(BTreeMap::from([
+            ("type".to_string(), Value::String("Point".into())),
+            (
+                "coordinates".to_string(),
+                Value::Array(vec![number(point.lon), number(point.lat)]),
+            ),
+        ]))
+    }
+
+    fn vertices(&self) -> Vec<Coord> {
+        match self {
+            Geometry::Point(point) => vec![*point],
+            Geometry::MultiPoint(points) | Geometry::LineString(points) => points.clone(),
+            Geometry::Polygon(rings) => rings.concat(),
+            Geometry::MultiPolygon(polygons) => polygons.concat().concat(),
+        }
+    }
+
+    /// Returns the smallest box holding the geometry.
+    pub fn bbox(&self) -> BBox {
+        let vertices = self.vertices();
+        let fold = |f: fn(f64, f64) -> f64, start: f64, get: fn(&Coord) -> f64| {
+            vertices.iter().map(get).fold(start, f)
+        };
+        BBox {
+            min_lon: fold(f64::min, f64::INFINITY, |c| c.lon),
+            min_lat: fold(f64::min, f64::INFINITY, |c| c.lat),
+            max_lon: fold(f64::max, f64::NEG_INFINITY, |c| c.lon),
+            max_lat: fold(f64::max, f64::NEG_INFINITY, |c| c.lat),
+        }
+    }
+
+    /// Returns whether the geometry lies within a box.
+    pub fn within(&self, bbox: &BBox) -> bool {
+        self.vertices().iter().all(|point| bbox.contains(point))
+    }
+
+    /// Returns whether `other` lies within the geometry, which must be a
+    /// polygon or multipolygon, or None otherwise. Coordinates are treated as
+    /// planar, and `other` is within if all its vertices are, so a line
+    /// between two vertices of a concave polygon may cross its boundary.
+    pub fn contains(&self, other: &Geometry) -> Option<bool> {
+        let polygons = match self {
+            Geometry::Polygon(rings) => std::slice::from_ref(rings),
+            Geometry::MultiPolygon(polygons) => polygons.as_slice(),
+            _ => return None,
+        };
+        Some(other.vertices().iter().all(|point| {
+            polygons.iter().any(|rings| {
+                let (outer, holes) = rings.split_first().unwrap();
+                in_ring(point, outer) && !holes.iter().any(|hole| in_ring(point, hole))
+            })
+        }))
+    }
+}
+
+fn array(value: &Value) -> Option<&Vec<Value>> {
+    match value {
+        Value::Array(items) => Some(items),
+        _ => None,
+    }
+}
+
+fn coord(value: &Value) -> Option<Coord> {
+    let number = |value: &Value| match value {
+        Value::Number(n) => get_f64_from_number(n),
+        _ => None,
+    };
+    match array(value)?.as_slice() {
+        [lon, lat, ..] => Coord::new(number(lon)?, number(lat)?),
+        _ => None,
+    }
+}
+
+fn coords(value: &Value) -> Option<Vec<Coord>> {
+    let points: Vec<Coord> = array(value)?.iter().map(coord).collect::<Option<_>>()?;
+    (!points.is_empty()).then_some(points)
+}
+
+fn rings(value: &Value) -> Option<Vec<Vec<Coord>>> {
+    let rings: Vec<Vec<Coord>> = array(value)?.iter().map(coords).collect::<Option<_>>()?;
+    let closed = |ring: &Vec<Coord>| ring.len() >= 4 && ring.first() == ring.last();
+    (!rings.is_empty() && rings.iter().all(closed)).then_some(rings)
+}
+
+/// Returns whether a point lies inside a closed ring, by counting the edges
+/// a ray from it crosses.
+fn in_ring(point: &Coord, ring: &[Coord]) -> bool {
+    let mut inside = false;
+    for edge in ring.windows(2) {
+        let (a, b) = (edge[0], edge[1]);
+        if (a.lat > point.lat) != (b.lat > point.lat)
+            && point.lon < a.lon + (point.lat - a.lat) / (b.lat - a.lat) * (b.lon - a.lon)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Returns the great-circle distance between two points in meters.
+pub fn distance(a: Coord, b: Coord) -> f64 {
+    let (lat_a, lat_b) = (a.lat.to_radians(), b.lat.to_radians());
+    let d_lat = lat_b - lat_a;
+    let d_lon = (b.lon - a.lon).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat_a.cos() * lat_b.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * h.sqrt().min(1.0).asin()
+}
+
+/// A range of longitudes and latitudes, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BBox {
+    pub min_lon: f64,
+    pub min_lat: f64,
+    pub max_lon: f64,
+    pub max_lat: f64,
+}
+
+impl BBox {
+    /// Returns a box holding every point within `meters` of `center`. Near
+    /// the poles, or across the antimeridian, it spans all longitudes.
+    pub fn around(center: Coord, meters: f64) -> Self {
+        let d_lat = (meters / EARTH_RADIUS).to_degrees();
+        let min_lat = (center.lat - d_lat).max(-90.0);
+        let max_lat = (center.lat + d_lat).min(90.0);
+        let widest = min_lat.abs().max(max_lat.abs()).to_radians().cos();
+        let d_lon = d_lat / widest;
+        if max_lat >= 90.0 || min_lat <= -90.0 || !d_lon.is_finite() {
+            return BBox {
+                min_lon: -180.0,
+                min_lat,
+                max_lon: 180.0,
+                max_lat,
+            };
+        }
+        let (min_lon, max_lon) = if center.lon - d_lon < -180.0 || center.lon + d_lon > 180.0 {
+            (-180.0, 180.0)
+        } else {
+            (center.lon - d_lon, center.lon + d_lon)
+        };
+        BBox {
+            min_lon,
+            min_lat,
+            max_lon,
+            max_lat,
+        }
+    }
+
+    pub fn contains(&self, point: &Coord) -> bool {
+        (self.min_lon..=self.max_lon).contains(&point.lon)
+            && (self.min_lat..=self.max_lat).contains(&point.lat)
+    }
+}
+
+/// Returns the geohash of a point: its cell in a grid that each character
+/// divides into 32, alternating between longitude and latitude bits.
+pub fn geohash(point: Coord, precision: usize) -> String {
+    let (mut lon, mut lat) = ((-180.0, 180.0), (-90.0, 90.0));
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = if even {
+                (&mut lon, point.lon)
+            } else {
+                (&mut lat, point.lat)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(GEOHASH_ALPHABET[index] as char);
+    }
+    hash
+}
+
+/// Returns the geohash of the smallest cell holding a geometry, the common
+/// prefix of the geohashes of its bounding box's corners. A point's cell is
+/// its geohash, and a geometry across the largest cells' edges has the
+/// empty geohash of the whole world.
+pub fn cell(geometry: &Geometry) -> String {
+    let bbox = geometry.bbox();
+    let low = geohash(
+        Coord {
+            lon: bbox.min_lon,
+            lat: bbox.min_lat,
+        },
+        GEOHASH_PRECISION,
+    );
+    let high = geohash(
+        Coord {
+            lon: bbox.max_lon,
+            lat: bbox.max_lat,
+        },
+        GEOHASH_PRECISION,
+    );
+    let common = low
+        .bytes()
+        .zip(high.bytes())
+        .take_while(|(a, b)| a == b)
+        .count();
+    low[..common].to_string()
+}
+
+/// Returns the geohash prefixes of the cells covering a box, at the finest
+/// precision that needs few of them, or None if even the coarsest cells are
+/// too many.
+pub fn cover(bbox: &BBox) -> Option<Vec<String>> {
+    let mut best = None;
+    for precision in 1..=GEOHASH_PRECISION {
+        let lon_bits = (5 * precision).div_ceil(2) as i32;
+        let lat_bits = (5 * precision / 2) as i32;
+        let (width, height) = (360.0 / 2f64.powi(lon_bits), 180.0 / 2f64.powi(lat_bits));
+        let step = |value: f64, min: f64, size: f64, bits: i32| {
+            (((value - min) / size).floor() as i64).clamp(0, (1 << bits) - 1)
+        };
+        let columns = step(bbox.min_lon, -180.0, width, lon_bits)
+            ..=step(bbox.max_lon, -180.0, width, lon_bits);
+        let rows = step(bbox.min_lat, -90.0, height, lat_bits)
+            ..=step(bbox.max_lat, -90.0, height, lat_bits);
+        let count = columns.clone().count() * rows.clone().count();
+        if count > MAX_CELLS {
+            break;
+        }
+        let mut cells = Vec::with_capacity(count);
+        for column in columns {
+            for row in rows.clone() {
+                let center = Coord {
+                    lon: -180.0 + (column as f64 + 0.5) * width,
+                    lat: -90.0 + (row as f64 + 0.5) * height,
+                };
+                cells.push(geohash(center, precision));
+            }
+        }
+        best = Some(cells);
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_to_jsonb;
+    use serde_json::json;
+
+    fn point(lon: f64, lat: f64) -> Coord {
+        Coord { lon, lat }
+    }
+
+    #[test]
+    fn test_parse_and_contains() {
+        let geometry = |v: serde_json::Value| Geometry::from_value(&serde_to_jsonb(v));
+        assert_eq!(
+            geometry(json!({"type": "Point", "coordinates": [2.35, 48.85]})),
+            Some(Geometry::Point(point(2.35, 48.85)))
+        );
+        assert_eq!(
+            geometry(json!({"type": "Point", "coordinates": [48.85, 200]})),
+            None
+        );
+        assert_eq!(
+            geometry(json!({"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [1, 1]]]})),
+            None
+        );
+        assert_eq!(
+            geometry(json!({"type": "Circle", "coordinates": [0, 0]})),
+            None
+        );
+        assert_eq!(
+            Geometry::from_value(&Geometry::point_value(point(1.5, -2.0))),
+            Some(Geometry::Point(point(1.5, -2.0)))
+        );
+
+        // A square with a hole in its middle
+        let square = geometry(json!({"type": "Polygon", "coordinates": [
+            [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+            [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]]
+        ]}))
+        .unwrap();
+        let contains = |lon, lat| square.contains(&Geometry::Point(point(lon, lat)));
+        assert_eq!(contains(1.0, 1.0), Some(true));
+        assert_eq!(contains(5.0, 5.0), Some(false));
+        assert_eq!(contains(11.0, 5.0), Some(false));
+        let line = Geometry::LineString(vec![point(1.0, 1.0), point(9.0, 2.0)]);
+        assert_eq!(square.contains(&line), Some(true));
+        assert_eq!(Geometry::Point(point(1.0, 1.0)).contains(&line), None);
+        assert_eq!(
+            square.bbox(),
+            BBox {
+                min_lon: 0.0,
+                min_lat: 0.0,
+                max_lon: 10.0,
+                max_lat: 10.0
+            }
+        );
+    }
+
+    #[test]
+    fn test_distance() {
+        let (paris, london) = (point(2.3522, 48.8566), point(-0.1276, 51.5072));
+        assert!((distance(paris, london) - 343_500.0).abs() < 1_000.0);
+        assert_eq!(distance(paris, paris), 0.0);
+
+        let bbox = BBox::around(paris, 100_000.0);
+        assert!(bbox.contains(&point(2.3522, 49.7)));
+        assert!(!bbox.contains(&london));
+        assert!(Geometry::Point(paris).within(&bbox));
+        assert_eq!(Coord::new(181.0, 0.0), None);
+        assert_eq!(BBox::around(point(179.9, 0.0), 100_000.0).max_lon, 180.0);
+    }
+
+    #[test]
+    fn test_geohash_cover() {
+        assert_eq!(geohash(point(10.40744, 57.64911), 11), "u4pruydqqvj");
+
+        let paris = point(2.3522, 48.8566);
+        let cells = cover(&BBox::around(paris, 1_000.0)).unwrap();
+        assert!(cells.len() <= MAX_CELLS);
+        let hash = geohash(paris, GEOHASH_PRECISION);
+        assert!(cells.iter().any(|cell| hash.starts_with(cell.as_str())));
+        assert!(cells.iter().all(|cell| cell.len() == cells[0].len()));
+
+        assert_eq!(cell(&Geometry::Point(paris)), hash);
+        let square = Geometry::Polygon(vec![vec![
+            point(2.35, 48.85),
+            point(2.351, 48.85),
+            point(2.351, 48.851),
+            point(2.35, 48.85),
+        ]]);
+        assert_eq!(cell(&square), "u09tv");
+        let across = Geometry::LineString(vec![point(-1.0, 1.0), point(1.0, 1.0)]);
+        assert_eq!(cell(&across), "");
+
+        let world = BBox {
+            min_lon: -180.0,
+            min_lat: -90.0,
+            max_lon: 180.0,
+            max_lat: 90.0,
+        };
+        assert_eq!(cover(&world), None);
+    }
+}
@@ -1,8 +1,10 @@
 use crate::schema::{InstanceType, Schema, SchemaExt};
+use crate::zonemap::{TableStats, ZoneMap};
 use crate::{LazyDocument, SerdeWrapper, Value, make_static};
 use jsonb_schema::RawJsonb;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
 use xorf::BinaryFuse8;
@@ -13,11 +15,23 @@ pub enum StoredValue {
     Lazy(LazyDocument),
 }
 
+impl StoredValue {
+    /// Returns the document, decoding it if it is lazy.
+    pub fn document(&self) -> Cow<'_, Value> {
+        match self {
+            StoredValue::Static(doc) => Cow::Borrowed(doc),
+            StoredValue::Lazy(doc) => Cow::Owned(doc.document()),
+        }
+    }
+}
+
 pub struct JSTable {
     pub timestamp: u64,
     pub collection: String,
     pub schema: Schema,
     pub documents: BTreeMap<String, StoredValue>,
+    /// IDs of documents updated or deleted, which may also be in older tables.
+    pub overwrites: HashSet<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,6 +53,7 @@ impl JSTable {
             collection,
             schema,
             documents,
+            overwrites: HashSet::new(),
         }
     }
 
@@ -84,8 +99,9 @@ impl JSTable {
         summary_file.write_all(&filter_len.to_le_bytes())?;
         summary_file.write_all(&filter_bytes)?;
 
-        // Write Documents to data and build index
+        // Write Documents to data and build index, with a zone map per index block
         let mut index: Vec<(String, u64)> = Vec::new();
+        let mut stats = TableStats::default();
         let mut current_offset: u64 = 0;
         let mut bytes_since_last_index: u64 = 0;
         let mut first = true;
@@ -94,10 +110,17 @@ impl JSTable {
             // Add index entry if needed
             if first || bytes_since_last_index >= index_threshold {
                 index.push((id.clone(), current_offset));
+                stats.blocks.push(ZoneMap::default());
                 bytes_since_last_index = 0;
                 first = false;
             }
 
+            let zone = stats.blocks.last_mut().unwrap();
+            zone.add_document(&val.document());
+            if self.overwrites.contains(id) {
+                zone.overwrites = true;
+            }
+
             let record_bytes = match val {
                 StoredValue::Static(doc) => {
                     // Use SerdeWrapper to serialize jsonb Value via serde infrastructure
@@ -129,12 +152,26 @@ impl JSTable {
         summary_file.write_all(&index_len.to_le_bytes())?;
         summary_file.write_all(&index_bytes)?;
 
+        // Write Stats to summary
+        for block in &stats.blocks {
+            stats.table.merge(block);
+        }
+        let stats_bytes = serde_json::to_vec(&stats)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let stats_len = stats_bytes.len() as u32;
+        summary_file.write_all(&stats_len.to_le_bytes())?;
+        summary_file.write_all(&stats_bytes)?;
+
         Ok(())
     }
 }
 
 pub struct JSTableLazyIterator {
     reader: BufReader<File>,
+    bytes_read: u64,
+    offset: u64,
+    end: u64,                     // offset to stop reading at
+    ranges: VecDeque<(u64, u64)>, // further [start, end) ranges to read
     pub timestamp: u64,
     pub collection: String,
     pub schema: Schema,
@@ -146,23 +183,7 @@ impl JSTableLazyIterator {
         let data_path = format!("{}.data", path);
 
         let summary_file = File::open(summary_path)?;
-        let mut summary_reader = BufReader::new(summary_file);
-
-        // Read Header Length from summary
-        let mut len_buf = [0u8; 4];
-        summary_reader.read_exact(&mut len_buf)?;
-        let header_len = u32::from_le_bytes(len_buf) as usize;
-
-        // Read Header Blob from summary
-        let mut header_blob = vec![0u8; header_len];
-        summary_reader.read_exact(&mut header_blob)?;
-
-        let header_val = jsonb_schema::from_slice(&header_blob)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
-        // Convert jsonb_schema::Value -> String -> T
-        let header_str = header_val.to_string();
-        let header: JSTableHeader = serde_json::from_str(&header_str)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let header = read_header(&mut BufReader::new(summary_file))?;
 
         // We don't need to read the filter or index here
 
@@ -171,6 +192,10 @@ impl JSTableLazyIterator {
 
         Ok(Self {
             reader: data_reader,
+            bytes_read: 0,
+            offset: 0,
+            end: u64::MAX,
+            ranges: VecDeque::new(),
             timestamp: header.timestamp,
             collection: header.collection,
             schema: header.schema,
@@ -179,14 +204,34 @@ impl JSTableLazyIterator {
 
     pub fn seek(&mut self, offset: u64) -> io::Result<()> {
         self.reader.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
         Ok(())
     }
+
+    /// Reads only the records in the given `[start, end)` data file ranges,
+    /// in order. Each range must start at a record, e.g. at an index entry.
+    pub fn restrict(&mut self, ranges: Vec<(u64, u64)>) {
+        self.ranges = ranges.into();
+        self.end = 0; // Seek to the first range on the next read
+    }
+
+    /// Returns the number of data file bytes read by the records returned so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
 }
 
 impl Iterator for JSTableLazyIterator {
     type Item = io::Result<LazyDocument>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        while self.offset >= self.end {
+            let (start, end) = self.ranges.pop_front()?;
+            if let Err(e) = self.seek(start) {
+                return Some(Err(e));
+            }
+            self.end = end;
+        }
         let mut len_buf = [0u8; 4];
         match self.reader.read_exact(&mut len_buf) {
             Ok(_) => {
@@ -195,6 +240,8 @@ impl Iterator for JSTableLazyIterator {
                 if let Err(e) = self.reader.read_exact(&mut record_blob) {
                     return Some(Err(e));
                 }
+                self.bytes_read += 4 + record_len as u64;
+                self.offset += 4 + record_len as u64;
 
                 // Extract ID eagerly using RawJsonb to avoid full deserialization
                 // record_blob is [id, doc]
@@ -250,6 +297,10 @@ impl JSTableIterator {
         self.inner.seek(offset)
     }
 
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.bytes_read()
+    }
+
     // Accessors delegated to inner
     pub fn timestamp(&self) -> u64 {
         self.inner.timestamp
@@ -295,6 +346,30 @@ impl Iterator for JSTableIterator {
     }
 }
 
+fn read_header(summary_reader: &mut impl Read) -> io::Result<JSTableHeader> {
+    // Read Header Length from summary
+    let mut len_buf = [0u8; 4];
+    summary_reader.read_exact(&mut len_buf)?;
+    let header_len = u32::from_le_bytes(len_buf) as usize;
+
+    // Read Header Blob from summary
+    let mut header_blob = vec![0u8; header_len];
+    summary_reader.read_exact(&mut header_blob)?;
+
+    let header_val = jsonb_schema::from_slice(&header_blob)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    // Convert jsonb_schema::Value -> String -> T
+    let header_str = header_val.to_string();
+    serde_json::from_str(&header_str).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Reads the schema of the documents in a JSTable.
+pub fn read_schema(path: &str) -> io::Result<Schema> {
+    let summary_path = format!("{}.summary", path);
+    let file = File::open(summary_path)?;
+    Ok(read_header(&mut BufReader::new(file))?.schema)
+}
+
 pub fn read_jstable(path: &str) -> io::Result<JSTable> {
     let iterator = JSTableLazyIterator::new(path)?;
     let timestamp = iterator.timestamp;
@@ -307,12 +382,7 @@ pub fn read_jstable(path: &str) -> io::Result<JSTable> {
         documents.insert(lazy_doc.id.clone(), StoredValue::Lazy(lazy_doc));
     }
 
-    Ok(JSTable {
-        timestamp,
-        collection,
-        schema,
-        documents,
-    })
+    Ok(JSTable::new(timestamp, collection, schema, documents))
 }
 
 pub fn read_filter(path: &str) -> io::Result<BinaryFuse8> {
@@ -390,6 +460,40 @@ pub fn read_index(path: &str) -> io::Result<Vec<(String, u64)>> {
     Ok(index)
 }
 
+/// Reads the value statistics of a JSTable, or `None` if it was written
+/// without them.
+pub fn read_stats(path: &str) -> io::Result<Option<TableStats>> {
+    let summary_path = format!("{}.summary", path);
+    let file = File::open(summary_path)?;
+    let mut reader = BufReader::new(file);
+
+    // Skip Header, Filter and Index
+    for _ in 0..3 {
+        let mut len_buf = [0u8; 4];
+        reader.read_exact(&mut len_buf)?;
+        let len = u32::from_le_bytes(len_buf) as u64;
+        io::copy(&mut reader.by_ref().take(len), &mut io::sink())?;
+    }
+
+    // Read Stats Length
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let stats_len = u32::from_le_bytes(len_buf) as usize;
+
+    // Read Stats Blob
+    let mut stats_blob = vec![0u8; stats_len];
+    reader.read_exact(&mut stats_blob)?;
+
+    let stats: TableStats = serde_json::from_slice(&stats_blob)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+    Ok(Some(stats))
+}
+
 pub fn merge_jstables(mut tables: Vec<JSTable>) -> JSTable {
     tables.sort_by_key(|t| t.timestamp);
 
@@ -707,4 +811,45 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_read_stats() -> Result<(), Box<dyn std::error::Error>> {
+        let documents = (0..20)
+            .map(|i| {
+                let doc = json!({ "a": i, "pad": "x".repeat(100) });
+                (
+                    format!("{:02}", i),
+                    StoredValue::Static(serde_to_jsonb(doc)),
+                )
+            })
+            .collect();
+        let jstable = JSTable::new(
+            123,
+            "stats_test".to_string(),
+            Schema::new(InstanceType::Object),
+            documents,
+        );
+        let dir = tempdir()?;
+        let path = dir.path().join("stats_table");
+        jstable.write(path.to_str().unwrap(), 512)?;
+
+        let index = read_index(path.to_str().unwrap())?;
+        let stats = read_stats(path.to_str().unwrap())?.unwrap();
+        assert!(index.len() > 1);
+        assert_eq!(stats.blocks.len(), index.len());
+        assert_eq!(stats.table.rows, 20);
+        assert_eq!(stats.blocks.iter().map(|b| b.rows).sum::<u64>(), 20);
+        assert!(!stats.table.overwrites);
+
+        let a = &stats.table.fields["a"];
+        assert_eq!(a.count, 20);
+        assert_eq!(a.ranges.len(), 1);
+        assert_eq!(a.ranges[0].min, serde_to_jsonb(json!(0)));
+        assert_eq!(a.ranges[0].max, serde_to_jsonb(json!(19)));
+        assert_eq!(
+            stats.blocks[0].fields["a"].ranges[0].min,
+            serde_to_jsonb(json!(0))
+        );
+        Ok(())
+    }
 }
@@ -1,12 +1,20 @@
 pub mod bench_utils;
 pub mod db;
+pub mod explain;
 pub mod expression;
+pub mod fulltext;
+pub mod geo;
 pub mod jstable;
 pub mod log;
+pub mod optimizer;
 pub mod parser;
 pub mod query;
 pub mod schema;
+pub mod secondary;
 pub mod storage;
+pub mod temporal;
+pub mod vector;
+pub mod zonemap;
 
 pub use expression::*;
 
@@ -22,6 +30,20 @@ pub struct LazyDocument {
 }
 
 impl LazyDocument {
+    /// Decodes the whole document.
+    pub fn document(&self) -> Value {
+        // Decode the raw blob [id, document]
+        if let Ok(val) = jsonb_schema::from_slice(&self.raw) {
+            let static_val = make_static(&val);
+            if let JsonbValue::Array(mut arr) = static_val
+                && arr.len() == 2
+            {
+                return arr.pop().unwrap(); // doc
+            }
+        }
+        JsonbValue::Null
+    }
+
     pub fn is_tombstone(&self) -> bool {
         let raw = RawJsonb::new(&self.raw);
         if let Ok(Some(doc)) = raw.get_by_index(1) {
@@ -49,18 +71,7 @@ impl ExecutionResult {
     pub fn get_value(&self) -> Value {
         match self {
             ExecutionResult::Value(_, v) => v.clone(),
-            ExecutionResult::Lazy(doc) => {
-                // Decode the raw blob [id, document]
-                if let Ok(val) = jsonb_schema::from_slice(&doc.raw) {
-                    let static_val = make_static(&val);
-                    if let JsonbValue::Array(mut arr) = static_val
-                        && arr.len() == 2
-                    {
-                        return arr.pop().unwrap(); // doc
-                    }
-                }
-                JsonbValue::Null
-            }
+            ExecutionResult::Lazy(doc) => doc.document(),
         }
     }
 }
@@ -0,0 +1,733 @@
+use crate::Value;
+use crate::expression::{
+    Expression, LogicalOperator, ScalarFunction, evaluate_expression, is_id_reference, is_temporal,
+    projection_name,
+};
+use crate::query::{
+    JoinKind, LogicalPlan, SetOperator, is_alias_id_reference, join_conjuncts, split_conjuncts,
+};
+use std::cmp::min;
+
+/// Rewrites a plan into an equivalent one that is cheaper to execute.
+///
+/// Constants are folded, filters are split into conjuncts and pushed as close
+/// to the scans as they can go, filters that are always true are removed and
+/// ones that are never true empty their input, projections are pushed below
+/// limits and merged, and limits are pushed into their inputs.
+pub fn optimize(mut plan: LogicalPlan<'_>) -> LogicalPlan<'_> {
+    fold_plan_constants(&mut plan);
+    let plan = push_down_predicates(plan, Vec::new());
+    let plan = push_down_projections(plan);
+    push_down_limits(plan)
+}
+
+/// Rebuilds `plan` with each of its inputs replaced by `f(input)`.
+fn map_inputs<'a>(
+    mut plan: LogicalPlan<'a>,
+    mut f: impl FnMut(LogicalPlan<'a>) -> LogicalPlan<'a>,
+) -> LogicalPlan<'a> {
+    for input in plan.inputs_mut() {
+        let placeholder = LogicalPlan::Scan {
+            collection: String::new(),
+        };
+        *input = f(std::mem::replace(input, placeholder));
+    }
+    plan
+}
+
+// Constant folding
+
+fn fold_plan_constants(plan: &mut LogicalPlan) {
+    match plan {
+        LogicalPlan::Scan { .. }
+        | LogicalPlan::Limit { .. }
+        | LogicalPlan::Offset { .. }
+        | LogicalPlan::SetOperation { .. } => {}
+        LogicalPlan::Filter { predicate, .. } => fold_constants(predicate),
+        // Unaliased projections are named after their text, which folding would change
+        LogicalPlan::Project { projections, .. } => {
+            for projection in projections {
+                if let Expression::Alias(expr, _) = projection {
+                    fold_constants(expr);
+                }
+            }
+        }
+        LogicalPlan::Sort { keys, .. } => {
+            for key in keys {
+                fold_constants(&mut key.expr);
+            }
+        }
+        LogicalPlan::Unnest { expr, .. } => fold_constants(expr),
+        LogicalPlan::Join { keys, residual, .. } => {
+            for (left_key, right_key) in keys {
+                fold_constants(left_key);
+                fold_constants(right_key);
+            }
+            if let Some(predicate) = residual {
+                fold_constants(predicate);
+            }
+        }
+        LogicalPlan::Window { windows, .. } => {
+            for (call, _) in windows {
+                call.expressions_mut().into_iter().for_each(fold_constants);
+            }
+        }
+        LogicalPlan::Distinct { on, .. } => on.iter_mut().for_each(fold_constants),
+    }
+    for input in plan.inputs_mut() {
+        fold_plan_constants(input);
+    }
+}
+
+/// Replaces each sub-expression that does not depend on the row by its value.
+fn fold_constants(expr: &mut Expression) {
+    for child in expr.children_mut() {
+        fold_constants(child);
+    }
+    // One operand can decide the result whatever the other is
+    if let Expression::Logical { left, op, right } = expr {
+        let decisive = Value::Bool(*op == LogicalOperator::Or);
+        if [&**left, &**right]
+            .iter()
+            .any(|operand| matches!(operand, Expression::Literal(val) if *val == decisive))
+        {
+            *expr = Expression::Literal(decisive);
+            return;
+        }
+    }
+    if is_constant(expr) {
+        let val = evaluate_expression(expr, &Value::Null);
+        // A timestamp stays a call, so that comparisons with it know it is one
+        if !(is_temporal(expr) && matches!(val, Value::String(_))) {
+            *expr = Expression::Literal(val);
+        }
+    }
+}
+
+/// Returns true if `expr` can be evaluated without a row, given that its
+/// children have been folded.
+fn is_constant(expr: &mut Expression) -> bool {
+    // Literals are folded already, and the rest depend on the row or query
+    let foldable = !matches!(
+        expr,
+        Expression::Literal(_)
+            | Expression::FieldReference(..)
+            | Expression::JsonPath(..)
+            | Expression::Parameter(_)
+            | Expression::IsMissing { .. }
+            | Expression::InSubquery { .. }
+            | Expression::Exists { .. }
+            | Expression::Window(_)
+            | Expression::Alias(..)
+            | Expression::Wildcard
+    );
+    foldable
+        && !is_volatile(expr)
+        && expr
+            .children_mut()
+            .into_iter()
+            .all(|child| matches!(child, Expression::Literal(_)))
+}
+
+/// Returns true for calls that may return a different value each time.
+fn is_volatile(expr: &Expression) -> bool {
+    matches!(
+        expr,
+        Expression::Function {
+            func: ScalarFunction::Rand | ScalarFunction::Now,
+            ..
+        }
+    )
+}
+
+fn contains(expr: &mut Expression, predicate: fn(&Expression) -> bool) -> bool {
+    predicate(expr)
+        || expr
+            .children_mut()
+            .into_iter()
+            .any(|child| contains(child, predicate))
+}
+
+// Predicate pushdown
+
+/// Applies `conjuncts` (and any filters in `plan`) to `plan`, each as close
+/// to the scans as it can go.
+fn push_down_predicates<'a>(
+    plan: LogicalPlan<'a>,
+    mut conjuncts: Vec<Expression<'a>>,
+) -> LogicalPlan<'a> {
+    match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            split_conjuncts(predicate, &mut conjuncts);
+            push_down_predicates(*input, conjuncts)
+        }
+        LogicalPlan::Project { input, projections } => {
+            let (pushed, kept) = partition_conjuncts(conjuncts, |conjunct| {
+                unproject(conjunct, &projections, true)
+            });
+            let input = Box::new(push_down_predicates(*input, pushed));
+            filter(LogicalPlan::Project { input, projections }, kept)
+        }
+        LogicalPlan::Sort { input, keys } => LogicalPlan::Sort {
+            input: Box::new(push_down_predicates(*input, conjuncts)),
+            keys,
+        },
+        // Duplicates are removed by value, keeping the ID of one of them, so
+        // only conditions on the value can be checked first
+        LogicalPlan::Distinct { input, on } if on.is_empty() => {
+            let (pushed, kept) = partition_conjuncts(conjuncts, without_id);
+            let input = Box::new(push_down_predicates(*input, pushed));
+            filter(LogicalPlan::Distinct { input, on }, kept)
+        }
+        LogicalPlan::Unnest { input, expr, alias } => {
+            let (pushed, kept) = partition_conjuncts(conjuncts, |conjunct| {
+                rewrite_references(conjunct, |parts, raw| {
+                    (parts[0] != alias).then(|| Expression::FieldReference(parts.to_vec(), raw))
+                })
+            });
+            let input = Box::new(push_down_predicates(*input, pushed));
+            filter(LogicalPlan::Unnest { input, expr, alias }, kept)
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            kind,
+            left_alias,
+            right_alias,
+            keys,
+            residual,
+        } => {
+            let (left_conjuncts, rest) =
+                partition_conjuncts(conjuncts, |conjunct| match left_alias {
+                    Some(alias) => strip_alias(conjunct, alias),
+                    // The left input is a join, whose rows are keyed by alias already
+                    None => rewrite_references(conjunct, |parts, raw| {
+                        (parts[0] != right_alias)
+                            .then(|| Expression::FieldReference(parts.to_vec(), raw))
+                    }),
+                });
+            // A left join keeps left rows without a match, so only an inner
+            // join can filter its right input first. A filtered collection
+            // can no longer be joined by `_id` lookups, so that is kept too.
+            let index_join = matches!(*right, LogicalPlan::Scan { .. })
+                && keys
+                    .iter()
+                    .any(|(_, right_key)| is_alias_id_reference(right_key, right_alias));
+            let (right_conjuncts, kept) = match kind {
+                JoinKind::Inner if !index_join => {
+                    partition_conjuncts(rest, |conjunct| strip_alias(conjunct, right_alias))
+                }
+                _ => (Vec::new(), rest),
+            };
+            let join = LogicalPlan::Join {
+                left: Box::new(push_down_predicates(*left, left_conjuncts)),
+                right: Box::new(push_down_predicates(*right, right_conjuncts)),
+                kind,
+                left_alias,
+                right_alias,
+                keys,
+                residual,
+            };
+            filter(join, kept)
+        }
+        // Rows of a set operation are compared by value, so conditions on
+        // the value can filter both inputs instead
+        LogicalPlan::SetOperation {
+            left,
+            right,
+            op,
+            all,
+        } => {
+            let (pushed, kept) = partition_conjuncts(conjuncts, without_id);
+            let set_operation = LogicalPlan::SetOperation {
+                left: Box::new(push_down_predicates(*left, pushed.clone())),
+                right: Box::new(push_down_predicates(*right, pushed)),
+                op,
+                all,
+            };
+            filter(set_operation, kept)
+        }
+        // Scans, and operators whose output depends on which rows they see
+        other => filter(
+            map_inputs(other, |input| push_down_predicates(input, Vec::new())),
+            conjuncts,
+        ),
+    }
+}
+
+/// Puts a filter with `conjuncts` above `input`. Conjuncts that are always
+/// true are dropped, and one that is never true empties the input.
+fn filter<'a>(input: LogicalPlan<'a>, conjuncts: Vec<Expression<'a>>) -> LogicalPlan<'a> {
+    let mut kept = Vec::new();
+    for conjunct in conjuncts {
+        match conjunct {
+            Expression::Literal(Value::Bool(true)) => {}
+            Expression::Literal(_) => {
+                return LogicalPlan::Limit {
+                    input: Box::new(input),
+                    limit: 0,
+                };
+            }
+            other => kept.push(other),
+        }
+    }
+    match join_conjuncts(kept) {
+        Some(predicate) => LogicalPlan::Filter {
+            input: Box::new(input),
+            predicate,
+        },
+        None => input,
+    }
+}
+
+/// Splits `conjuncts` into those that `rewrite` can move into an input,
+/// rewritten for it, and the others.
+fn partition_conjuncts<'a>(
+    conjuncts: Vec<Expression<'a>>,
+    mut rewrite: impl FnMut(&Expression<'a>) -> Option<Expression<'a>>,
+) -> (Vec<Expression<'a>>, Vec<Expression<'a>>) {
+    let mut pushed = Vec::new();
+    let mut kept = Vec::new();
+    for conjunct in conjuncts {
+        match rewrite(&conjunct) {
+            Some(rewritten) => pushed.push(rewritten),
+            None => kept.push(conjunct),
+        }
+    }
+    (pushed, kept)
+}
+
+/// Returns a copy of `expr` with each field reference replaced by
+/// `rewrite(parts, raw)`, or `None` if a reference cannot be rewritten or the
+/// expression reads the whole row.
+fn rewrite_references<'a>(
+    expr: &Expression<'a>,
+    mut rewrite: impl FnMut(&[&'a str], &'a str) -> Option<Expression<'a>>,
+) -> Option<Expression<'a>> {
+    fn visit<'a>(
+        expr: &mut Expression<'a>,
+        rewrite: &mut impl FnMut(&[&'a str], &'a str) -> Option<Expression<'a>>,
+    ) -> bool {
+        match expr {
+            Expression::FieldReference(parts, raw) => match rewrite(parts.as_slice(), *raw) {
+                Some(rewritten) => {
+                    *expr = rewritten;
+                    true
+                }
+                None => false,
+            },
+            Expression::JsonPath(..) | Expression::Wildcard => false,
+            _ => expr
+                .children_mut()
+                .into_iter()
+                .all(|child| visit(child, rewrite)),
+        }
+    }
+    let mut expr = expr.clone();
+    visit(&mut expr, &mut rewrite).then_some(expr)
+}
+
+/// Returns a copy of `expr` if it does not read the row's ID.
+fn without_id<'a>(expr: &Expression<'a>) -> Option<Expression<'a>> {
+    rewrite_references(expr, |parts, raw| {
+        (!is_id_reference(parts)).then(|| Expression::FieldReference(parts.to_vec(), raw))
+    })
+}
+
+/// Rewrites an expression over join rows that only reads the document under
+/// `alias` to one over that document.
+fn strip_alias<'a>(expr: &Expression<'a>, alias: &str) -> Option<Expression<'a>> {
+    rewrite_references(expr, |parts, raw| {
+        let path = raw.strip_prefix(alias)?.strip_prefix('.')?;
+        (parts.len() > 1 && parts[0] == alias)
+            .then(|| Expression::FieldReference(parts[1..].to_vec(), path))
+    })
+}
+
+/// Rewrites an expression over the output of `projections` to one over their
+/// input, or returns `None` if it cannot be. Without `rename`, only fields
+/// passed through unchanged may be read, so the expression keeps its text.
+fn unproject<'a>(
+    expr: &Expression<'a>,
+    projections: &[Expression<'a>],
+    rename: bool,
+) -> Option<Expression<'a>> {
+    // A projected field is never missing, even if its source is
+    if contains(&mut expr.clone(), |e| {
+        matches!(e, Expression::IsMissing { .. })
+    }) {
+        return None;
+    }
+    rewrite_references(expr, |parts, raw| {
+        let unchanged = Expression::FieldReference(parts.to_vec(), raw);
+        // Projections keep the row's ID
+        if is_id_reference(parts) {
+            return Some(unchanged);
+        }
+        match projected_field(parts[0], projections)? {
+            None => Some(unchanged),
+            Some(Expression::FieldReference(source, _))
+                if source.len() == 1 && source[0] == parts[0] =>
+            {
+                Some(unchanged)
+            }
+            Some(source) if rename && parts.len() == 1 => {
+                (!contains(&mut source.clone(), is_volatile)).then(|| source.clone())
+            }
+            Some(_) => None,
+        }
+    })
+}
+
+/// Returns the expression that produces the output field `name` of
+/// `projections`, `Some(None)` if a wildcard copies it from the input, or
+/// `None` if it is unknown or absent.
+fn projected_field<'e, 'a>(
+    name: &str,
+    projections: &'e [Expression<'a>],
+) -> Option<Option<&'e Expression<'a>>> {
+    let wildcard = projections
+        .iter()
+        .rposition(|p| matches!(p, Expression::Wildcard));
+    let named = projections
+        .iter()
+        .rposition(|p| !matches!(p, Expression::Wildcard) && projection_name(p) == name);
+    match (named, wildcard) {
+        // The wildcard overwrites the field if the input has it
+        (Some(named), Some(wildcard)) if wildcard > named => None,
+        (Some(named), _) => Some(Some(match &projections[named] {
+            Expression::Alias(expr, _) => &**expr,
+            other => other,
+        })),
+        (None, Some(_)) => Some(None),
+        (None, None) => None,
+    }
+}
+
+// Projection pushdown
+
+fn push_down_projections(plan: LogicalPlan<'_>) -> LogicalPlan<'_> {
+    match map_inputs(plan, push_down_projections) {
+        LogicalPlan::Project { input, projections } => project(*input, projections),
+        other => other,
+    }
+}
+
+/// Puts a projection above `input`, below any limits and offsets at its top
+/// so that it can be fused with the scan, and merged with a projection there.
+fn project<'a>(input: LogicalPlan<'a>, projections: Vec<Expression<'a>>) -> LogicalPlan<'a> {
+    match input {
+        LogicalPlan::Limit { input, limit } => LogicalPlan::Limit {
+            input: Box::new(project(*input, projections)),
+            limit,
+        },
+        LogicalPlan::Offset { input, offset } => LogicalPlan::Offset {
+            input: Box::new(project(*input, projections)),
+            offset,
+        },
+        LogicalPlan::Project {
+            input,
+            projections: inner,
+        } => match merge_projections(&projections, &inner) {
+            Some(projections) => LogicalPlan::Project { input, projections },
+            None => LogicalPlan::Project {
+                input: Box::new(LogicalPlan::Project {
+                    input,
+                    projections: inner,
+                }),
+                projections,
+            },
+        },
+        other => LogicalPlan::Project {
+            input: Box::new(other),
+            projections,
+        },
+    }
+}
+
+/// Returns a single projection equivalent to `outer` applied to the output of
+/// `inner`, if each output field keeps its name.
+fn merge_projections<'a>(
+    outer: &[Expression<'a>],
+    inner: &[Expression<'a>],
+) -> Option<Vec<Expression<'a>>> {
+    outer
+        .iter()
+        .map(|projection| match projection {
+            Expression::Alias(expr, name) => Some(Expression::Alias(
+                Box::new(unproject(expr, inner, true)?),
+                *name,
+            )),
+            Expression::FieldReference(parts, raw) if parts.len() == 1 => {
+                match unproject(projection, inner, true)? {
+                    Expression::FieldReference(parts, source) if source == *raw => {
+                        Some(Expression::FieldReference(parts, source))
+                    }
+                    renamed => Some(Expression::Alias(Box::new(renamed), *raw)),
+                }
+            }
+            Expression::Wildcard => None,
+            other => unproject(other, inner, false),
+        })
+        .collect()
+}
+
+// Limit pushdown
+
+fn push_down_limits(plan: LogicalPlan<'_>) -> LogicalPlan<'_> {
+    match map_inputs(plan, push_down_limits) {
+        LogicalPlan::Limit { input, limit: n } => limit(*input, n),
+        LogicalPlan::Offset { input, offset } => match *input {
+            LogicalPlan::Offset {
+                input,
+                offset: inner,
+            } => LogicalPlan::Offset {
+                input,
+                offset: offset.saturating_add(inner),
+            },
+            other => LogicalPlan::Offset {
+                input: Box::new(other),
+                offset,
+            },
+        },
+        other => other,
+    }
+}
+
+/// Puts a limit above `input`, pushing it into the input where that lets
+/// fewer rows be read.
+fn limit(input: LogicalPlan<'_>, n: usize) -> LogicalPlan<'_> {
+    match input {
+        LogicalPlan::Limit { input, limit: m } => limit(*input, min(n, m)),
+        // The first `n` rows after skipping `offset` are among the first `n + offset`
+        LogicalPlan::Offset { input, offset } => LogicalPlan::Offset {
+            input: Box::new(limit(*input, n.saturating_add(offset))),
+            offset,
+        },
+        // The first `n` rows of a concatenation are among the first `n` of each input
+        LogicalPlan::SetOperation {
+            left,
+            right,
+            op: SetOperator::Union,
+            all: true,
+        } => LogicalPlan::Limit {
+            input: Box::new(LogicalPlan::SetOperation {
+                left: Box::new(limit(*left, n)),
+                right: Box::new(limit(*right, n)),
+                op: SetOperator::Union,
+                all: true,
+            }),
+            limit: n,
+        },
+        other => LogicalPlan::Limit {
+            input: Box::new(other),
+            limit: n,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+    use crate::query::Statement;
+    use bumpalo::Bump;
+    use std::fmt::Display;
+
+    fn list<T: Display>(items: &[T]) -> String {
+        let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+        items.join(", ")
+    }
+
+    /// Renders the shape of a plan, with each node's inputs in parentheses.
+    fn render(plan: &LogicalPlan) -> String {
+        match plan {
+            LogicalPlan::Scan { collection } => collection.clone(),
+            LogicalPlan::Filter { input, predicate } => {
+                format!("Filter({}; {})", predicate, render(input))
+            }
+            LogicalPlan::Project { input, projections } => {
+                format!("Project({}; {})", list(projections), render(input))
+            }
+            LogicalPlan::Limit { input, limit } => format!("Limit({}; {})", limit, render(input)),
+            LogicalPlan::Offset { input, offset } => {
+                format!("Offset({}; {})", offset, render(input))
+            }
+            LogicalPlan::Sort { input, keys } => format!("Sort({}; {})", list(keys), render(input)),
+            LogicalPlan::Unnest { input, expr, alias } => {
+                format!("Unnest({} AS {}; {})", expr, alias, render(input))
+            }
+            LogicalPlan::Join { left, right, .. } => {
+                format!("Join({}; {})", render(left), render(right))
+            }
+            LogicalPlan::Window { input, .. } => format!("Window({})", render(input)),
+            LogicalPlan::Distinct { input, .. } => format!("Distinct({})", render(input)),
+            LogicalPlan::SetOperation { left, right, .. } => {
+                format!("SetOperation({}; {})", render(left), render(right))
+            }
+        }
+    }
+
+    fn optimized(sql: &str) -> String {
+        let arena = Bump::new();
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(plan) => render(&optimize(plan)),
+            _ => panic!("Expected Select"),
+        }
+    }
+
+    #[test]
+    fn test_fold_constants() {
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE a > 1 + 2 * 3"),
+            "Filter(a > 7; t)"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE b = UPPER('x') OR c IS NULL"),
+            "Filter((b = 'X') OR (c IS NULL); t)"
+        );
+        // Unaliased projections keep their text, which names them
+        assert_eq!(
+            optimized("SELECT a + (1 + 1) AS b, 2 * 3 FROM t"),
+            "Project(a + 2 AS b, 2 * 3; t)"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE a < RAND()"),
+            "Filter(a < RAND(); t)"
+        );
+        // Timestamps stay calls, which comparisons treat as instants
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE ts > TO_TIMESTAMP(0)"),
+            "Filter(ts > TO_TIMESTAMP(0); t)"
+        );
+        // One operand decides the result
+        assert_eq!(optimized("SELECT * FROM t WHERE a = 1 OR 2 > 1"), "t");
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE a = 1 AND 2 < 1"),
+            "Limit(0; t)"
+        );
+    }
+
+    #[test]
+    fn test_eliminate_constant_filters() {
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE 1 = 1 AND a > 1"),
+            "Filter(a > 1; t)"
+        );
+        assert_eq!(optimized("SELECT a FROM t WHERE TRUE"), "Project(a; t)");
+        assert_eq!(
+            optimized("SELECT a FROM t WHERE NULL"),
+            "Limit(0; Project(a; t))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM t WHERE a > 1 AND 'x' = 'y'"),
+            "Limit(0; t)"
+        );
+    }
+
+    #[test]
+    fn test_split_conjuncts_into_join() {
+        assert_eq!(
+            optimized(
+                "SELECT * FROM u JOIN o ON u.id = o.uid \
+                 WHERE u.age > 30 AND o.total > 5 AND u.x = o.y"
+            ),
+            "Filter(u.x = o.y; Join(Filter(age > 30; u); Filter(total > 5; o)))"
+        );
+        // Left join rows without a match must survive a right-side condition
+        assert_eq!(
+            optimized(
+                "SELECT * FROM u LEFT JOIN o ON u.id = o.uid WHERE u.age > 30 AND o.total > 5"
+            ),
+            "Filter(o.total > 5; Join(Filter(age > 30; u); o))"
+        );
+        // A join on `_id` looks up the unfiltered collection
+        assert_eq!(
+            optimized("SELECT * FROM o JOIN u ON o.uid = u._id WHERE u.age > 30 AND o.total > 5"),
+            "Filter(u.age > 30; Join(Filter(total > 5; o); u))"
+        );
+    }
+
+    #[test]
+    fn test_push_predicates_through_project() {
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT a AS b, c FROM t) s WHERE b > 1 AND c = 2"),
+            "Project(a AS b, c; Filter((a > 1) AND (c = 2); t))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT *, a + 1 AS b FROM t) s WHERE c = 1 AND b = 2"),
+            "Project(*, a + 1 AS b; Filter((c = 1) AND ((a + 1) = 2); t))"
+        );
+        // Evaluating RAND() again would give another value
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT RAND() AS r FROM t) s WHERE r < 0.5"),
+            "Filter(r < 0.5; Project(RAND() AS r; t))"
+        );
+    }
+
+    #[test]
+    fn test_push_predicates_through_operators() {
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT * FROM t ORDER BY a) s WHERE b = 1"),
+            "Sort(a; Filter(b = 1; t))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT * FROM t LIMIT 5) s WHERE b = 1"),
+            "Filter(b = 1; Limit(5; t))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM t, UNNEST(tags) AS tag WHERE tag = 'x' AND a = 1"),
+            "Filter(tag = 'x'; Unnest(tags AS tag; Filter(a = 1; t)))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT a FROM t UNION ALL SELECT a FROM u) s WHERE a > 1"),
+            "SetOperation(Project(a; Filter(a > 1; t)); Project(a; Filter(a > 1; u)))"
+        );
+        // Which duplicate is kept decides the ID
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT DISTINCT a FROM t) s WHERE _id = 'x' AND a = 1"),
+            "Filter(_id = 'x'; Distinct(Project(a; Filter(a = 1; t))))"
+        );
+    }
+
+    #[test]
+    fn test_push_down_projections() {
+        assert_eq!(
+            optimized("SELECT a FROM (SELECT * FROM t LIMIT 5) s"),
+            "Limit(5; Project(a; t))"
+        );
+        assert_eq!(
+            optimized("SELECT b AS c, d FROM (SELECT a AS b, d FROM t) s"),
+            "Project(a AS c, d; t)"
+        );
+        assert_eq!(
+            optimized("SELECT b FROM (SELECT a AS b FROM t) s"),
+            "Project(a AS b; t)"
+        );
+        // Merging would rename the `b + 1` column
+        assert_eq!(
+            optimized("SELECT b + 1 FROM (SELECT a AS b FROM t) s"),
+            "Project(b + 1; Project(a AS b; t))"
+        );
+    }
+
+    #[test]
+    fn test_push_down_limits() {
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT * FROM t LIMIT 10) s LIMIT 3"),
+            "Limit(3; t)"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM t LIMIT 2 OFFSET 3"),
+            "Offset(3; Limit(5; t))"
+        );
+        assert_eq!(
+            optimized("SELECT * FROM (SELECT * FROM t OFFSET 2) s OFFSET 3"),
+            "Offset(5; t)"
+        );
+        assert_eq!(
+            optimized("SELECT a FROM t UNION ALL SELECT a FROM u LIMIT 2"),
+            "Limit(2; SetOperation(Limit(2; Project(a; t)); Limit(2; Project(a; u))))"
+        );
+    }
+}
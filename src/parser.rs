@@ -1,15 +1,28 @@
-use crate::expression::{BinaryOperator, Expression, LogicalOperator, ScalarFunction};
-use crate::query::{LogicalPlan, Statement};
+use crate::expression::{
+    BinaryOperator, Expression, FrameBound, LogicalOperator, ScalarFunction, UnaryOperator,
+    WINDOW_FIELD_PREFIX, WindowCall, WindowFrame, WindowFunction, compile_regex,
+    get_i64_from_number, is_id_reference,
+};
+use crate::fulltext;
+use crate::query::{
+    JoinKind, LogicalPlan, PreparedStatement, SetOperator, SortKey, Statement, join_conjuncts,
+    split_conjuncts,
+};
+use crate::secondary::IndexKind;
+use crate::temporal::{self, Interval};
+use crate::vector::Metric;
 
 use crate::{Value, serde_to_jsonb};
 use bumpalo::Bump;
 use sqlparser::ast::{
-    self, BinaryOperator as SqlBinaryOperator, Expr, LimitClause, SetExpr, TableFactor, Values,
+    self, BinaryOperator as SqlBinaryOperator, Expr, JoinConstraint, JoinOperator, LimitClause,
+    SetExpr, SetOperator as SqlSetOperator, SetQuantifier, TableFactor, TrimWhereField,
+    UnaryOperator as SqlUnaryOperator, Values,
 };
 use sqlparser::dialect::Dialect;
 use sqlparser::keywords::Keyword;
 use sqlparser::parser::Parser;
-use sqlparser::tokenizer::Tokenizer;
+use sqlparser::tokenizer::{Token, TokenWithSpan, Tokenizer};
 
 #[derive(Debug)]
 struct ArgusDialect;
@@ -35,19 +48,66 @@ impl Dialect for ArgusDialect {
 pub fn parse<'a>(sql: &str, arena: &'a Bump) -> Result<Statement<'a>, String> {
     let dialect = ArgusDialect {};
     let mut tokenizer = Tokenizer::new(&dialect, sql);
-    let tokens = tokenizer.tokenize().map_err(|e| e.to_string())?;
-    let mut parser = Parser::new(&dialect).with_tokens(tokens);
+    let mut tokens = tokenizer
+        .tokenize_with_location()
+        .map_err(|e| e.to_string())?;
+    rewrite_is_missing(&mut tokens)?;
+    number_placeholders(&mut tokens)?;
+    let mut parser = Parser::new(&dialect).with_tokens_with_locations(tokens.clone());
 
     let token = parser.peek_token();
     let keyword = token.token.to_string().to_uppercase();
 
     if keyword == "CREATE" {
         parser.next_token();
+        if parser.parse_keyword(Keyword::INDEX) {
+            return parse_create_index(&mut parser, IndexKind::Value, arena);
+        }
+        if parser.parse_keyword(Keyword::FULLTEXT) {
+            parser
+                .expect_keyword(Keyword::INDEX)
+                .map_err(|e| e.to_string())?;
+            return parse_create_index(&mut parser, IndexKind::FullText, arena);
+        }
+        let kind = match parser
+            .peek_token()
+            .token
+            .to_string()
+            .to_uppercase()
+            .as_str()
+        {
+            "VECTOR" => Some(IndexKind::Vector),
+            "SPATIAL" => Some(IndexKind::Spatial),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            parser.next_token();
+            parser
+                .expect_keyword(Keyword::INDEX)
+                .map_err(|e| e.to_string())?;
+            return parse_create_index(&mut parser, kind, arena);
+        }
         parser.expect_keyword(Keyword::COLLECTION).unwrap();
         let name = parser.parse_object_name(false).unwrap().to_string();
+        if parser.parse_keyword(Keyword::AS) {
+            let query = parser.parse_query().map_err(|e| e.to_string())?;
+            parser.consume_token(&Token::SemiColon);
+            if parser.peek_token().token != Token::EOF {
+                return Err("Expected end of statement after query".to_string());
+            }
+            let plan = convert_query(*query, arena)?;
+            return Ok(Statement::CreateCollectionAs {
+                collection: name,
+                plan,
+            });
+        }
         return Ok(Statement::CreateCollection { collection: name });
     } else if keyword == "DROP" {
         parser.next_token();
+        if parser.parse_keyword(Keyword::INDEX) {
+            let index = parser.parse_identifier().map_err(|e| e.to_string())?.value;
+            return Ok(Statement::DropIndex { index });
+        }
         parser.expect_keyword(Keyword::COLLECTION).unwrap();
         let name = parser.parse_object_name(false).unwrap().to_string();
         return Ok(Statement::DropCollection { collection: name });
@@ -59,7 +119,10 @@ pub fn parse<'a>(sql: &str, arena: &'a Bump) -> Result<Statement<'a>, String> {
         }
     }
 
-    let mut ast = Parser::parse_sql(&dialect, sql).map_err(|e| e.to_string())?;
+    let mut ast = Parser::new(&dialect)
+        .with_tokens_with_locations(tokens)
+        .parse_statements()
+        .map_err(|e| e.to_string())?;
 
     if ast.len() != 1 {
         return Err("Expected exactly one statement".to_string());
@@ -68,20 +131,110 @@ pub fn parse<'a>(sql: &str, arena: &'a Bump) -> Result<Statement<'a>, String> {
     match ast.pop().unwrap() {
         ast::Statement::Insert(insert) => {
             let collection = insert.table.to_string();
-            let documents = convert_insert_source(insert.source)?;
-            Ok(Statement::Insert {
-                collection,
-                documents,
-            })
+            match insert.source {
+                Some(query) if !matches!(*query.body, SetExpr::Values(_)) => {
+                    if !insert.columns.is_empty() {
+                        return Err("INSERT column lists are not supported".to_string());
+                    }
+                    Ok(Statement::InsertSelect {
+                        collection,
+                        plan: convert_query(*query, arena)?,
+                    })
+                }
+                source => Ok(Statement::Insert {
+                    collection,
+                    documents: convert_insert_source(source)?,
+                }),
+            }
         }
         ast::Statement::Query(query) => {
             let logical_plan = convert_query(*query, arena)?;
             Ok(Statement::Select(logical_plan))
         }
+        ast::Statement::Explain {
+            analyze, statement, ..
+        } => match *statement {
+            ast::Statement::Query(query) => Ok(Statement::Explain {
+                plan: convert_query(*query, arena)?,
+                analyze,
+            }),
+            _ => Err("EXPLAIN only supports queries".to_string()),
+        },
         _ => Err("Unsupported statement".to_string()),
     }
 }
 
+/// Parses a statement with `$n` or `?` parameters, to be bound and executed
+/// repeatedly.
+pub fn prepare<'a>(sql: &str, arena: &'a Bump) -> Result<PreparedStatement<'a>, String> {
+    parse(sql, arena).map(PreparedStatement::new)
+}
+
+/// Rewrites `IS [NOT] MISSING`, which sqlparser does not know, into
+/// `IS [NOT] NORMALIZED` so it parses as `Expr::IsNormalized`; `convert_expr`
+/// maps that back to `Expression::IsMissing`. A written `IS [NOT] NORMALIZED`
+/// is rejected, so that it cannot be mistaken for `IS [NOT] MISSING`.
+fn rewrite_is_missing(tokens: &mut [TokenWithSpan]) -> Result<(), String> {
+    let is_keyword = |token: &Token, keyword: Keyword| matches!(token, Token::Word(w) if w.keyword == keyword && w.quote_style.is_none());
+    let mut previous: Vec<usize> = Vec::new(); // indices of preceding non-whitespace tokens
+    for i in 0..tokens.len() {
+        if matches!(tokens[i].token, Token::Whitespace(_)) {
+            continue;
+        }
+        let is_missing = matches!(
+            &tokens[i].token,
+            Token::Word(w) if w.quote_style.is_none() && w.value.eq_ignore_ascii_case("MISSING")
+        );
+        let is_normalized = is_keyword(&tokens[i].token, Keyword::NORMALIZED);
+        if is_missing || is_normalized {
+            let follows_is = match previous.as_slice() {
+                [.., is, not] if is_keyword(&tokens[*not].token, Keyword::NOT) => {
+                    is_keyword(&tokens[*is].token, Keyword::IS)
+                }
+                [.., is] => is_keyword(&tokens[*is].token, Keyword::IS),
+                [] => false,
+            };
+            if follows_is && is_normalized {
+                return Err("Unsupported predicate: IS NORMALIZED".to_string());
+            }
+            if follows_is {
+                tokens[i].token = Token::make_keyword("NORMALIZED");
+            }
+        }
+        previous.push(i);
+    }
+    Ok(())
+}
+
+/// Turns `$n`, which the dialect reads as an identifier, into a placeholder
+/// token, and numbers `?` placeholders `$1`, `$2`, ... in order of appearance.
+fn number_placeholders(tokens: &mut [TokenWithSpan]) -> Result<(), String> {
+    let (mut numbered, mut positional) = (false, 0);
+    for token in tokens.iter_mut() {
+        match &token.token {
+            Token::Word(w)
+                if w.quote_style.is_none()
+                    && w.value.len() > 1
+                    && w.value.starts_with('$')
+                    && w.value[1..].bytes().all(|b| b.is_ascii_digit()) =>
+            {
+                numbered = true;
+                token.token = Token::Placeholder(w.value.clone());
+            }
+            Token::Placeholder(p) if p == "?" => {
+                positional += 1;
+                token.token = Token::Placeholder(format!("${}", positional));
+            }
+            Token::Placeholder(p) => return Err(format!("Unsupported parameter: {}", p)),
+            _ => {}
+        }
+    }
+    if numbered && positional > 0 {
+        return Err("Cannot mix $n and ? parameters".to_string());
+    }
+    Ok(())
+}
+
 fn convert_insert_source(source: Option<Box<ast::Query>>) -> Result<Vec<Value>, String> {
     let query = source.ok_or("Insert must have a source")?;
 
@@ -122,12 +275,39 @@ fn convert_query<'a>(query: ast::Query, arena: &'a Bump) -> Result<LogicalPlan<'
         }
     }
 
-    // Body (SetExpr)
-    let plan = match *query.body {
-        SetExpr::Select(select) => convert_select(*select, arena)?,
-        _ => return Err("Only SELECT queries are supported (no UNION, etc.)".to_string()),
+    let order_by = match query.order_by {
+        Some(ast::OrderBy {
+            kind: ast::OrderByKind::Expressions(exprs),
+            ..
+        }) => exprs,
+        Some(_) => return Err("Unsupported ORDER BY clause".to_string()),
+        None => Vec::new(),
     };
 
+    // WITH: each CTE may refer to the ones before it
+    let mut ctes: Vec<(String, LogicalPlan<'a>)> = Vec::new();
+    if let Some(with) = query.with {
+        if with.recursive {
+            return Err("WITH RECURSIVE is not supported".to_string());
+        }
+        for cte in with.cte_tables {
+            if !cte.alias.columns.is_empty() {
+                return Err("CTE column lists are not supported".to_string());
+            }
+            let name = cte.alias.name.value;
+            if ctes.iter().any(|(n, _)| *n == name) {
+                return Err(format!("Duplicate CTE name: {}", name));
+            }
+            let mut cte_plan = convert_query(*cte.query, arena)?;
+            inline_ctes(&mut cte_plan, &ctes);
+            ctes.push((name, cte_plan));
+        }
+    }
+
+    // Body (SetExpr)
+    let mut plan = convert_set_expr(*query.body, order_by, arena)?;
+    inline_ctes(&mut plan, &ctes);
+
     // Wrap with Limit/Offset
     let plan = if let Some(o) = offset_val {
         LogicalPlan::Offset {
@@ -150,6 +330,89 @@ fn convert_query<'a>(query: ast::Query, arena: &'a Bump) -> Result<LogicalPlan<'
     Ok(plan)
 }
 
+/// Converts a query body: a SELECT, a set operation, or a parenthesized query.
+fn convert_set_expr<'a>(
+    body: SetExpr,
+    order_by: Vec<ast::OrderByExpr>,
+    arena: &'a Bump,
+) -> Result<LogicalPlan<'a>, String> {
+    let plan = match body {
+        SetExpr::Select(select) => return convert_select(*select, order_by, arena),
+        SetExpr::Query(query) => convert_query(*query, arena)?,
+        SetExpr::SetOperation {
+            op,
+            set_quantifier,
+            left,
+            right,
+        } => {
+            let op = match op {
+                SqlSetOperator::Union => SetOperator::Union,
+                SqlSetOperator::Intersect => SetOperator::Intersect,
+                SqlSetOperator::Except => SetOperator::Except,
+                _ => return Err(format!("Unsupported set operator: {}", op)),
+            };
+            let all = match set_quantifier {
+                SetQuantifier::All => true,
+                SetQuantifier::Distinct | SetQuantifier::None => false,
+                _ => return Err(format!("Unsupported set quantifier: {}", set_quantifier)),
+            };
+            LogicalPlan::SetOperation {
+                left: Box::new(convert_set_expr(*left, Vec::new(), arena)?),
+                right: Box::new(convert_set_expr(*right, Vec::new(), arena)?),
+                op,
+                all,
+            }
+        }
+        _ => return Err("Unsupported query body".to_string()),
+    };
+
+    // ORDER BY of a set operation or parenthesized query sorts its output rows
+    if order_by.is_empty() {
+        return Ok(plan);
+    }
+    let keys = order_by
+        .into_iter()
+        .map(|item| convert_sort_key(item, &[], arena))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(LogicalPlan::Sort {
+        input: Box::new(plan),
+        keys,
+    })
+}
+
+/// Replaces scans of a CTE by the CTE's plan, including in subqueries.
+fn inline_ctes<'a>(plan: &mut LogicalPlan<'a>, ctes: &[(String, LogicalPlan<'a>)]) {
+    if ctes.is_empty() {
+        return;
+    }
+    // Subqueries first, so that the inlined CTE plans are not visited again
+    for expr in plan.expressions_mut() {
+        inline_subquery_ctes(expr, ctes);
+    }
+    replace_cte_scans(plan, ctes);
+}
+
+fn inline_subquery_ctes<'a>(expr: &mut Expression<'a>, ctes: &[(String, LogicalPlan<'a>)]) {
+    if let Expression::InSubquery { subquery, .. } | Expression::Exists { subquery, .. } = expr {
+        inline_ctes(subquery, ctes);
+    }
+    for child in expr.children_mut() {
+        inline_subquery_ctes(child, ctes);
+    }
+}
+
+fn replace_cte_scans<'a>(plan: &mut LogicalPlan<'a>, ctes: &[(String, LogicalPlan<'a>)]) {
+    if let LogicalPlan::Scan { collection } = plan {
+        if let Some((_, cte)) = ctes.iter().find(|(name, _)| name == collection) {
+            *plan = cte.clone();
+        }
+        return;
+    }
+    for input in plan.inputs_mut() {
+        replace_cte_scans(input, ctes);
+    }
+}
+
 fn parse_limit_expr(expr: &Expr) -> Result<usize, String> {
     match expr {
         Expr::Value(val_span) => match &val_span.value {
@@ -162,22 +425,77 @@ fn parse_limit_expr(expr: &Expr) -> Result<usize, String> {
     }
 }
 
-fn convert_select<'a>(select: ast::Select, arena: &'a Bump) -> Result<LogicalPlan<'a>, String> {
-    // 1. FROM (Scan)
-    if select.from.len() != 1 {
-        return Err("FROM clause must have exactly one table".to_string());
+fn convert_select<'a>(
+    select: ast::Select,
+    order_by: Vec<ast::OrderByExpr>,
+    arena: &'a Bump,
+) -> Result<LogicalPlan<'a>, String> {
+    // 1. FROM (Scan, then one Join per JOIN and one Unnest per UNNEST relation)
+    let mut relations = select.from.into_iter();
+    let table = relations
+        .next()
+        .ok_or_else(|| "FROM clause must have a table".to_string())?;
+    let (mut plan, collection, alias) = convert_table(table.relation, arena)?;
+    let mut qualifiers = vec![collection.clone()];
+    qualifiers.extend(alias.map(str::to_string));
+    // (collection, alias) of each joined collection; empty unless the query joins
+    let mut joined: Vec<(String, &'a str)> = Vec::new();
+    if !table.joins.is_empty() {
+        let first_alias = alias.unwrap_or_else(|| arena.alloc_str(&collection));
+        joined.push((collection, first_alias));
+        let mut left_alias = Some(first_alias);
+        for join in table.joins {
+            let (kind, constraint) = match join.join_operator {
+                JoinOperator::Join(c) | JoinOperator::Inner(c) => (JoinKind::Inner, c),
+                JoinOperator::Left(c) | JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
+                _ => return Err("Only INNER and LEFT joins are supported".to_string()),
+            };
+            let JoinConstraint::On(on) = constraint else {
+                return Err("JOIN requires an ON condition".to_string());
+            };
+            let (right, right_collection, right_alias) = convert_table(join.relation, arena)?;
+            let right_alias = right_alias.unwrap_or_else(|| arena.alloc_str(&right_collection));
+            if joined.iter().any(|(_, a)| *a == right_alias) {
+                return Err(format!("Duplicate table alias: {}", right_alias));
+            }
+            joined.push((right_collection, right_alias));
+            let mut on = convert_expr(on, arena)?;
+            check_no_window(&mut on)?;
+            resolve_join_qualifiers(&mut on, &joined, arena)?;
+            let (keys, residual) = split_join_condition(on, right_alias)?;
+            plan = LogicalPlan::Join {
+                left: Box::new(plan),
+                right: Box::new(right),
+                kind,
+                left_alias: left_alias.take(),
+                right_alias,
+                keys,
+                residual,
+            };
+        }
+    }
+    for relation in relations {
+        if !relation.joins.is_empty() {
+            return Err("JOIN is only supported after the first FROM table".to_string());
+        }
+        let (mut expr, alias) = convert_unnest(relation.relation, arena)?;
+        resolve_qualifiers(&mut expr, &qualifiers, &joined, arena)?;
+        if !joined.is_empty() {
+            joined.push((alias.to_string(), alias));
+        }
+        plan = LogicalPlan::Unnest {
+            input: Box::new(plan),
+            expr,
+            alias,
+        };
     }
-    let table = select.from.into_iter().next().unwrap();
-    let collection = match table.relation {
-        TableFactor::Table { name, .. } => name.to_string(),
-        _ => return Err("Unsupported FROM clause".to_string()),
-    };
-
-    let mut plan = LogicalPlan::Scan { collection };
 
     // 2. WHERE (Filter)
     if let Some(selection) = select.selection {
-        let predicate = convert_expr(selection, arena)?;
+        let mut predicate = convert_expr(selection, arena)?;
+        check_no_window(&mut predicate)?;
+        match_as_condition(&mut predicate);
+        resolve_qualifiers(&mut predicate, &qualifiers, &joined, arena)?;
         plan = LogicalPlan::Filter {
             input: Box::new(plan),
             predicate,
@@ -191,24 +509,424 @@ fn convert_select<'a>(select: ast::Select, arena: &'a Bump) -> Result<LogicalPla
             ast::SelectItem::UnnamedExpr(expr) => {
                 projections.push(convert_expr(expr, arena)?);
             }
-            ast::SelectItem::ExprWithAlias { expr, alias: _ } => {
-                projections.push(convert_expr(expr, arena)?);
+            ast::SelectItem::ExprWithAlias { expr, alias } => {
+                let inner = convert_expr(expr, arena)?;
+                let name = arena.alloc_str(&alias.value);
+                projections.push(Expression::Alias(Box::new(inner), name));
             }
             ast::SelectItem::Wildcard(_) => {
-                return Err("Wildcard * not supported yet".to_string());
+                projections.push(Expression::Wildcard);
             }
             _ => return Err("Unsupported projection item".to_string()),
         }
     }
 
-    plan = LogicalPlan::Project {
-        input: Box::new(plan),
-        projections,
+    // 4. ORDER BY keys, evaluated on the input documents before projection
+    let mut keys = order_by
+        .into_iter()
+        .map(|item| convert_sort_key(item, &projections, arena))
+        .collect::<Result<Vec<_>, _>>()?;
+    for key in &mut keys {
+        resolve_qualifiers(&mut key.expr, &qualifiers, &joined, arena)?;
+    }
+    for expr in &mut projections {
+        resolve_qualifiers(expr, &qualifiers, &joined, arena)?;
+    }
+
+    // 5. Window functions in the SELECT list and ORDER BY (Window), computed
+    // before sorting so that ORDER BY can use their results
+    let mut windows = Vec::new();
+    for key in &mut keys {
+        extract_windows(&mut key.expr, &mut windows, arena);
+    }
+    for expr in &mut projections {
+        // The output field keeps the name of the call
+        if !matches!(expr, Expression::Alias(..)) && contains_window(expr) {
+            let name: &'a str = arena.alloc_str(&expr.to_string());
+            let inner = std::mem::replace(expr, Expression::Wildcard);
+            *expr = Expression::Alias(Box::new(inner), name);
+        }
+        extract_windows(expr, &mut windows, arena);
+    }
+    let has_windows = !windows.is_empty();
+    if has_windows {
+        plan = LogicalPlan::Window {
+            input: Box::new(plan),
+            windows,
+        };
+    }
+
+    // 6. ORDER BY (Sort)
+    if !keys.is_empty() {
+        plan = LogicalPlan::Sort {
+            input: Box::new(plan),
+            keys,
+        };
+    }
+
+    // 7. DISTINCT ON keeps the first input document (in ORDER BY order) per key,
+    // while DISTINCT compares the projected rows
+    let distinct = match select.distinct {
+        Some(ast::Distinct::On(exprs)) => {
+            let mut on = Vec::new();
+            for expr in exprs {
+                let mut expr = convert_expr(expr, arena)?;
+                check_no_window(&mut expr)?;
+                resolve_qualifiers(&mut expr, &qualifiers, &joined, arena)?;
+                on.push(expr);
+            }
+            plan = LogicalPlan::Distinct {
+                input: Box::new(plan),
+                on,
+            };
+            false
+        }
+        Some(ast::Distinct::Distinct) => true,
+        _ => false,
     };
 
+    // A bare `SELECT *` returns documents unchanged, so no Project is needed
+    // unless it has to drop window function results.
+    if has_windows || !matches!(projections.as_slice(), [Expression::Wildcard]) {
+        plan = LogicalPlan::Project {
+            input: Box::new(plan),
+            projections,
+        };
+    }
+    if distinct {
+        plan = LogicalPlan::Distinct {
+            input: Box::new(plan),
+            on: Vec::new(),
+        };
+    }
+
     Ok(plan)
 }
 
+/// Replaces each window function call in an expression by a reference to the
+/// field the `Window` node stores its result in, named after the call behind
+/// `WINDOW_FIELD_PREFIX`.
+fn extract_windows<'a>(
+    expr: &mut Expression<'a>,
+    windows: &mut Vec<(WindowCall<'a>, &'a str)>,
+    arena: &'a Bump,
+) {
+    if let Expression::Window(call) = expr {
+        let name: &'a str = arena.alloc_str(&format!("{}{}", WINDOW_FIELD_PREFIX, call));
+        if !windows.iter().any(|(_, n)| *n == name) {
+            windows.push(((**call).clone(), name));
+        }
+        *expr = Expression::FieldReference(vec![name], name);
+        return;
+    }
+    for child in expr.children_mut() {
+        extract_windows(child, windows, arena);
+    }
+}
+
+fn contains_window(expr: &mut Expression) -> bool {
+    matches!(expr, Expression::Window(_)) || expr.children_mut().into_iter().any(contains_window)
+}
+
+fn check_no_window(expr: &mut Expression) -> Result<(), String> {
+    if contains_window(expr) {
+        return Err("Window functions are only allowed in SELECT and ORDER BY".to_string());
+    }
+    Ok(())
+}
+
+/// Converts a relation in `FROM` or `JOIN` (a collection, or a subquery with
+/// an alias) to its plan, its name and its optional alias.
+fn convert_table<'a>(
+    relation: TableFactor,
+    arena: &'a Bump,
+) -> Result<(LogicalPlan<'a>, String, Option<&'a str>), String> {
+    match relation {
+        TableFactor::Table {
+            name,
+            alias,
+            args: None,
+            ..
+        } => Ok((
+            LogicalPlan::Scan {
+                collection: name.to_string(),
+            },
+            name.to_string(),
+            alias.map(|a| &*arena.alloc_str(&a.name.value)),
+        )),
+        TableFactor::Derived {
+            lateral: false,
+            subquery,
+            alias,
+            ..
+        } => {
+            let alias = alias.ok_or_else(|| "Subquery in FROM must have an alias".to_string())?;
+            let name = arena.alloc_str(&alias.name.value);
+            Ok((
+                convert_query(*subquery, arena)?,
+                name.to_string(),
+                Some(name),
+            ))
+        }
+        _ => Err("Unsupported FROM clause".to_string()),
+    }
+}
+
+/// Resolves the qualifiers of the field references in an expression of a
+/// SELECT: stripped for a single collection, or checked against the joined
+/// collections in a join.
+fn resolve_qualifiers<'a>(
+    expr: &mut Expression<'a>,
+    qualifiers: &[String],
+    joined: &[(String, &'a str)],
+    arena: &'a Bump,
+) -> Result<(), String> {
+    if joined.is_empty() {
+        strip_qualifiers(expr, qualifiers);
+        Ok(())
+    } else {
+        resolve_join_qualifiers(expr, joined, arena)
+    }
+}
+
+/// Checks that every field reference in a join query starts with the alias
+/// (or collection name) of a joined collection, since join rows hold each
+/// document under its alias. Collection names are rewritten to the alias.
+fn resolve_join_qualifiers<'a>(
+    expr: &mut Expression<'a>,
+    joined: &[(String, &'a str)],
+    arena: &'a Bump,
+) -> Result<(), String> {
+    if let Expression::FieldReference(parts, path) = expr {
+        if parts.len() < 2 {
+            return Err(format!(
+                "Field '{}' must be qualified with a collection name or alias in a join",
+                path
+            ));
+        }
+        if !joined.iter().any(|(_, alias)| *alias == parts[0]) {
+            let Some((_, alias)) = joined.iter().find(|(name, _)| name == parts[0]) else {
+                return Err(format!("Unknown collection or alias: {}", parts[0]));
+            };
+            let full: &str = *path;
+            let resolved: &'a str =
+                arena.alloc_str(&format!("{}{}", alias, &full[parts[0].len()..]));
+            *parts = resolved.split('.').collect();
+            *path = resolved;
+        }
+    }
+    for child in expr.children_mut() {
+        resolve_join_qualifiers(child, joined, arena)?;
+    }
+    Ok(())
+}
+
+/// Join keys as (left, right) expression pairs, and the residual condition.
+type JoinCondition<'a> = (
+    Vec<(Expression<'a>, Expression<'a>)>,
+    Option<Expression<'a>>,
+);
+
+/// Splits a join condition into equalities between an expression over the
+/// left inputs and one over the right collection, which become the join keys,
+/// and the remaining residual condition.
+fn split_join_condition<'a>(
+    on: Expression<'a>,
+    right_alias: &str,
+) -> Result<JoinCondition<'a>, String> {
+    let mut conjuncts = Vec::new();
+    split_conjuncts(on, &mut conjuncts);
+    let mut keys = Vec::new();
+    let mut residual = Vec::new();
+    for conjunct in conjuncts {
+        match conjunct {
+            Expression::Binary {
+                mut left,
+                op: BinaryOperator::Eq,
+                mut right,
+            } => match (
+                join_side(&mut left, right_alias),
+                join_side(&mut right, right_alias),
+            ) {
+                (Some(false), Some(true)) => keys.push((*left, *right)),
+                (Some(true), Some(false)) => keys.push((*right, *left)),
+                _ => residual.push(Expression::Binary {
+                    left,
+                    op: BinaryOperator::Eq,
+                    right,
+                }),
+            },
+            other => residual.push(other),
+        }
+    }
+    if keys.is_empty() {
+        return Err(
+            "JOIN condition must include an equality between the joined collections".to_string(),
+        );
+    }
+    Ok((keys, join_conjuncts(residual)))
+}
+
+/// Returns whether an expression refers only to the right collection
+/// (`Some(true)`) or only to the left inputs (`Some(false)`); `None` if it
+/// refers to both or to no fields.
+fn join_side(expr: &mut Expression, right_alias: &str) -> Option<bool> {
+    let mut qualifiers = Vec::new();
+    collect_qualifiers(expr, &mut qualifiers);
+    let right = qualifiers.iter().filter(|q| *q == right_alias).count();
+    match right {
+        _ if qualifiers.is_empty() => None,
+        0 => Some(false),
+        n if n == qualifiers.len() => Some(true),
+        _ => None,
+    }
+}
+
+fn collect_qualifiers(expr: &mut Expression, out: &mut Vec<String>) {
+    if let Expression::FieldReference(parts, _) = expr {
+        out.push(parts[0].to_string());
+    }
+    for child in expr.children_mut() {
+        collect_qualifiers(child, out);
+    }
+}
+
+/// Converts an `UNNEST(expr) [AS alias]` relation. The alias defaults to `unnest`.
+fn convert_unnest<'a>(
+    relation: TableFactor,
+    arena: &'a Bump,
+) -> Result<(Expression<'a>, &'a str), String> {
+    let (mut exprs, alias) = match relation {
+        TableFactor::UNNEST {
+            with_offset: true, ..
+        }
+        | TableFactor::UNNEST {
+            with_ordinality: true,
+            ..
+        } => return Err("UNNEST WITH OFFSET or ORDINALITY is not supported".to_string()),
+        TableFactor::UNNEST {
+            alias, array_exprs, ..
+        } => (array_exprs, alias),
+        TableFactor::Table {
+            name,
+            alias,
+            args: Some(args),
+            ..
+        } if name.to_string().eq_ignore_ascii_case("UNNEST") => {
+            let mut exprs = Vec::new();
+            for arg in args.args {
+                match arg {
+                    ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => exprs.push(e),
+                    _ => return Err("Unsupported argument type for UNNEST".to_string()),
+                }
+            }
+            (exprs, alias)
+        }
+        _ => return Err("Only UNNEST is supported after the first FROM table".to_string()),
+    };
+    if exprs.len() != 1 {
+        return Err(format!("UNNEST expects 1 argument, got {}", exprs.len()));
+    }
+    let expr = convert_expr(exprs.remove(0), arena)?;
+    let alias = alias.map_or("unnest", |a| arena.alloc_str(&a.name.value));
+    Ok((expr, alias))
+}
+
+/// Resolves field references qualified by the collection name or alias
+/// (`orders.total`) to the unqualified field (`total`).
+fn strip_qualifiers(expr: &mut Expression, qualifiers: &[String]) {
+    if let Expression::FieldReference(parts, path) = expr
+        && parts.len() > 1
+        && qualifiers.iter().any(|q| q == parts[0])
+    {
+        let full: &str = *path;
+        *path = &full[parts[0].len() + 1..];
+        parts.remove(0);
+    }
+    for child in expr.children_mut() {
+        strip_qualifiers(child, qualifiers);
+    }
+}
+
+/// Converts an ORDER BY item. Output column aliases and 1-based column
+/// positions refer to the corresponding SELECT expression.
+fn convert_sort_key<'a>(
+    item: ast::OrderByExpr,
+    projections: &[Expression<'a>],
+    arena: &'a Bump,
+) -> Result<SortKey<'a>, String> {
+    let aliased = match &item.expr {
+        Expr::Identifier(ident) => projections.iter().find_map(|p| match p {
+            Expression::Alias(inner, name) if *name == ident.value => Some(inner.as_ref().clone()),
+            _ => None,
+        }),
+        Expr::Value(val_span) => match &val_span.value {
+            ast::Value::Number(n, _) => {
+                let position = n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|p| (1..=projections.len()).contains(p))
+                    .ok_or_else(|| format!("ORDER BY position {} is out of range", n))?;
+                match &projections[position - 1] {
+                    Expression::Alias(inner, _) => Some(inner.as_ref().clone()),
+                    Expression::Wildcard => {
+                        return Err("ORDER BY position cannot refer to *".to_string());
+                    }
+                    other => Some(other.clone()),
+                }
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    let expr = match aliased {
+        Some(expr) => expr,
+        None => convert_expr(item.expr, arena)?,
+    };
+    let descending = item.options.asc == Some(false);
+    Ok(SortKey {
+        expr,
+        descending,
+        // NULL sorts lowest: first when ascending, last when descending
+        nulls_first: item.options.nulls_first.unwrap_or(!descending),
+    })
+}
+
+/// Parses the rest of
+/// `CREATE [FULLTEXT | VECTOR | SPATIAL] INDEX name ON collection (field)`.
+fn parse_create_index<'a>(
+    parser: &mut Parser,
+    kind: IndexKind,
+    arena: &'a Bump,
+) -> Result<Statement<'a>, String> {
+    let index = parser.parse_identifier().map_err(|e| e.to_string())?.value;
+    parser
+        .expect_keyword(Keyword::ON)
+        .map_err(|e| e.to_string())?;
+    let collection = parser
+        .parse_object_name(false)
+        .map_err(|e| e.to_string())?
+        .to_string();
+    parser
+        .expect_token(&Token::LParen)
+        .map_err(|e| e.to_string())?;
+    let expr = convert_expr(parser.parse_expr().map_err(|e| e.to_string())?, arena)?;
+    parser
+        .expect_token(&Token::RParen)
+        .map_err(|e| e.to_string())?;
+    match expr {
+        Expression::FieldReference(parts, _) if !is_id_reference(&parts) => {
+            Ok(Statement::CreateIndex {
+                index,
+                collection,
+                path: parts.iter().map(|part| part.to_string()).collect(),
+                kind,
+            })
+        }
+        _ => Err("Indexes can only be created on document fields".to_string()),
+    }
+}
+
 fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, String> {
     match expr {
         Expr::Identifier(ident) => {
@@ -265,6 +983,10 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
                 use jsonb_schema::Value as JsonbValue;
                 Ok(Expression::Literal(JsonbValue::Null))
             }
+            ast::Value::Placeholder(p) => match p[1..].parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Expression::Parameter(n)),
+                _ => Err(format!("Invalid parameter: {}", p)),
+            },
             _ => Err(format!("Unsupported literal: {:?}", val_span.value)),
         },
         Expr::BinaryOp { left, op, right } => {
@@ -278,6 +1000,14 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
                 SqlBinaryOperator::LtEq => (false, Some(BinaryOperator::Lte), None),
                 SqlBinaryOperator::Gt => (false, Some(BinaryOperator::Gt), None),
                 SqlBinaryOperator::GtEq => (false, Some(BinaryOperator::Gte), None),
+                SqlBinaryOperator::Plus => (false, Some(BinaryOperator::Plus), None),
+                SqlBinaryOperator::Minus => (false, Some(BinaryOperator::Minus), None),
+                SqlBinaryOperator::Multiply => (false, Some(BinaryOperator::Multiply), None),
+                SqlBinaryOperator::Divide => (false, Some(BinaryOperator::Divide), None),
+                SqlBinaryOperator::Modulo => (false, Some(BinaryOperator::Modulo), None),
+                SqlBinaryOperator::StringConcat => (false, Some(BinaryOperator::Concat), None),
+                SqlBinaryOperator::AtArrow => (false, Some(BinaryOperator::Contains), None),
+                SqlBinaryOperator::ArrowAt => (false, Some(BinaryOperator::ContainedBy), None),
                 SqlBinaryOperator::And => (true, None, Some(LogicalOperator::And)),
                 SqlBinaryOperator::Or => (true, None, Some(LogicalOperator::Or)),
                 _ => return Err(format!("Unsupported binary operator: {:?}", op)),
@@ -297,8 +1027,133 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
                 })
             }
         }
+        Expr::UnaryOp { op, expr } => {
+            let inner = convert_expr(*expr, arena)?;
+            match op {
+                SqlUnaryOperator::Plus => Ok(inner),
+                SqlUnaryOperator::Minus => Ok(negate(inner)),
+                SqlUnaryOperator::Not => Ok(Expression::Unary {
+                    op: UnaryOperator::Not,
+                    expr: Box::new(inner),
+                }),
+                _ => Err(format!("Unsupported unary operator: {:?}", op)),
+            }
+        }
+        Expr::Nested(inner) => convert_expr(*inner, arena),
+        Expr::AnyOp {
+            left,
+            compare_op,
+            right,
+            ..
+        } => convert_quantified(*left, compare_op, *right, false, arena),
+        Expr::AllOp {
+            left,
+            compare_op,
+            right,
+        } => convert_quantified(*left, compare_op, *right, true, arena),
+        Expr::Array(array) => {
+            use jsonb_schema::Value as JsonbValue;
+            let mut elements = Vec::with_capacity(array.elem.len());
+            for elem in array.elem {
+                match convert_expr(elem, arena)? {
+                    Expression::Literal(val) => elements.push(val),
+                    _ => return Err("ARRAY elements must be constants".to_string()),
+                }
+            }
+            Ok(Expression::Literal(JsonbValue::Array(elements)))
+        }
+        Expr::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let expr = Box::new(convert_expr(*expr, arena)?);
+            let list = list
+                .into_iter()
+                .map(|item| convert_expr(item, arena))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Expression::InList {
+                expr,
+                list,
+                negated,
+            })
+        }
+        Expr::InSubquery {
+            expr,
+            subquery,
+            negated,
+        } => Ok(Expression::InSubquery {
+            expr: Box::new(convert_expr(*expr, arena)?),
+            subquery: Box::new(convert_query(*subquery, arena)?),
+            negated,
+        }),
+        Expr::Exists { subquery, negated } => Ok(Expression::Exists {
+            subquery: Box::new(convert_query(*subquery, arena)?),
+            negated,
+        }),
+        Expr::Between {
+            expr,
+            negated,
+            low,
+            high,
+        } => Ok(Expression::Between {
+            expr: Box::new(convert_expr(*expr, arena)?),
+            low: Box::new(convert_expr(*low, arena)?),
+            high: Box::new(convert_expr(*high, arena)?),
+            negated,
+        }),
+        Expr::Like {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+            ..
+        } => convert_like(
+            *expr,
+            *pattern,
+            negated,
+            false,
+            escape_char.is_some(),
+            arena,
+        ),
+        Expr::ILike {
+            negated,
+            expr,
+            pattern,
+            escape_char,
+            ..
+        } => convert_like(*expr, *pattern, negated, true, escape_char.is_some(), arena),
+        Expr::IsNull(expr) => Ok(Expression::IsNull {
+            expr: Box::new(convert_expr(*expr, arena)?),
+            negated: false,
+        }),
+        Expr::IsNotNull(expr) => Ok(Expression::IsNull {
+            expr: Box::new(convert_expr(*expr, arena)?),
+            negated: true,
+        }),
+        // `IS [NOT] MISSING`, see `rewrite_is_missing`
+        Expr::IsNormalized {
+            expr,
+            form: None,
+            negated,
+        } => Ok(Expression::IsMissing {
+            expr: Box::new(convert_expr(*expr, arena)?),
+            negated,
+        }),
         Expr::Function(func) => {
             let name = func.name.to_string().to_uppercase();
+            if name == "JSON_EXISTS" {
+                return convert_json_exists(func.args, arena);
+            }
+            if name == "MATCH" {
+                return convert_match(func.args, arena);
+            }
+            if let Some(over) = func.over {
+                return convert_window(&name, func.args, over, arena);
+            }
+            if window_function(&name).is_some() {
+                return Err(format!("Function {} requires an OVER clause", name));
+            }
             let scalar_func = match name.as_str() {
                 "ABS" => ScalarFunction::Abs,
                 "ACOS" => ScalarFunction::Acos,
@@ -324,16 +1179,40 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
                 "SQRT" => ScalarFunction::Sqrt,
                 "TAN" => ScalarFunction::Tan,
                 "TANH" => ScalarFunction::Tanh,
+                "LOWER" => ScalarFunction::Lower,
+                "UPPER" => ScalarFunction::Upper,
+                "LENGTH" => ScalarFunction::Length,
+                "LTRIM" => ScalarFunction::Ltrim,
+                "RTRIM" => ScalarFunction::Rtrim,
+                "REPLACE" => ScalarFunction::Replace,
+                "SPLIT" => ScalarFunction::Split,
+                "REGEXP_MATCH" => ScalarFunction::RegexpMatch,
+                "JSON_TYPEOF" => ScalarFunction::JsonTypeof,
+                "ARRAY_LENGTH" => ScalarFunction::ArrayLength,
+                "KEYS" => ScalarFunction::Keys,
+                "TO_JSON" => ScalarFunction::ToJson,
+                "PARSE_JSON" => ScalarFunction::ParseJson,
+                "COALESCE" => ScalarFunction::Coalesce,
+                "NULLIF" => ScalarFunction::Nullif,
+                "NOW" | "CURRENT_TIMESTAMP" => ScalarFunction::Now,
+                "TO_TIMESTAMP" => ScalarFunction::ToTimestamp,
+                "DATE_TRUNC" => ScalarFunction::DateTrunc,
+                "DATE_PART" => ScalarFunction::DatePart,
+                "VECTOR_DISTANCE" => ScalarFunction::VectorDistance,
+                "ST_POINT" => ScalarFunction::StPoint,
+                "ST_DISTANCE" => ScalarFunction::StDistance,
+                "ST_DWITHIN" => ScalarFunction::StDwithin,
+                "ST_CONTAINS" => ScalarFunction::StContains,
+                "ST_WITHIN_BBOX" => ScalarFunction::StWithinBbox,
                 _ => return Err(format!("Unsupported function: {}", name)),
             };
 
             let args_list = match func.args {
                 sqlparser::ast::FunctionArguments::List(list) => list.args,
+                sqlparser::ast::FunctionArguments::None => Vec::new(),
                 _ => return Err(format!("Function {} expects arguments", name)),
             };
 
-            // Check arity checks (omitted for brevity, assume similar to before)
-
             let mut expr_args = Vec::new();
             for arg in args_list {
                 match arg {
@@ -346,9 +1225,84 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
                 }
             }
 
-            Ok(Expression::Function {
-                func: scalar_func,
-                args: expr_args,
+            build_function(scalar_func, expr_args)
+        }
+        Expr::Substring {
+            expr,
+            substring_from,
+            substring_for,
+            ..
+        } => {
+            let mut args = vec![convert_expr(*expr, arena)?];
+            match substring_from {
+                Some(from) => args.push(convert_expr(*from, arena)?),
+                None => {
+                    use jsonb_schema::{Number, Value as JsonbValue};
+                    args.push(Expression::Literal(JsonbValue::Number(Number::Int64(1))))
+                }
+            }
+            if let Some(len) = substring_for {
+                args.push(convert_expr(*len, arena)?);
+            }
+            build_function(ScalarFunction::Substr, args)
+        }
+        Expr::Trim {
+            expr,
+            trim_where,
+            trim_what,
+            trim_characters,
+        } => {
+            let func = match trim_where {
+                Some(TrimWhereField::Leading) => ScalarFunction::Ltrim,
+                Some(TrimWhereField::Trailing) => ScalarFunction::Rtrim,
+                Some(TrimWhereField::Both) | None => ScalarFunction::Trim,
+            };
+            let mut args = vec![convert_expr(*expr, arena)?];
+            if let Some(what) = trim_what {
+                args.push(convert_expr(*what, arena)?);
+            }
+            for chars in trim_characters.into_iter().flatten() {
+                args.push(convert_expr(chars, arena)?);
+            }
+            build_function(func, args)
+        }
+        Expr::Extract { field, expr, .. } => {
+            use jsonb_schema::Value as JsonbValue;
+            let field = field.to_string().to_lowercase();
+            build_function(
+                ScalarFunction::DatePart,
+                vec![
+                    Expression::Literal(JsonbValue::String(field.into())),
+                    convert_expr(*expr, arena)?,
+                ],
+            )
+        }
+        Expr::Interval(interval) => convert_interval(interval),
+        Expr::Case {
+            operand,
+            conditions,
+            else_result,
+            ..
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(Box::new(convert_expr(*operand, arena)?)),
+                None => None,
+            };
+            let mut branches = Vec::with_capacity(conditions.len());
+            for when in conditions {
+                branches.push((
+                    convert_expr(when.condition, arena)?,
+                    convert_expr(when.result, arena)?,
+                ));
+            }
+            let else_result = match else_result {
+                Some(else_result) => Some(Box::new(convert_expr(*else_result, arena)?)),
+                None => None,
+            };
+            Ok(Expression::Case {
+                operand,
+                branches,
+                else_result,
             })
         }
         Expr::JsonAccess { .. } => Err("JsonAccess not implemented".to_string()),
@@ -356,6 +1310,412 @@ fn convert_expr<'a>(expr: Expr, arena: &'a Bump) -> Result<Expression<'a>, Strin
     }
 }
 
+/// Checks a function call's arity and validates literal regular expressions.
+fn window_function(name: &str) -> Option<WindowFunction> {
+    Some(match name {
+        "ROW_NUMBER" => WindowFunction::RowNumber,
+        "RANK" => WindowFunction::Rank,
+        "DENSE_RANK" => WindowFunction::DenseRank,
+        "LAG" => WindowFunction::Lag,
+        "LEAD" => WindowFunction::Lead,
+        "FIRST_VALUE" => WindowFunction::FirstValue,
+        "LAST_VALUE" => WindowFunction::LastValue,
+        "COUNT" => WindowFunction::Count,
+        "SUM" => WindowFunction::Sum,
+        "AVG" => WindowFunction::Avg,
+        "MIN" => WindowFunction::Min,
+        "MAX" => WindowFunction::Max,
+        _ => return None,
+    })
+}
+
+/// Converts a window function call `name(args) OVER (...)`.
+fn convert_window<'a>(
+    name: &str,
+    args: ast::FunctionArguments,
+    over: ast::WindowType,
+    arena: &'a Bump,
+) -> Result<Expression<'a>, String> {
+    let func = window_function(name)
+        .ok_or_else(|| format!("Function {} is not a window function", name))?;
+    let spec = match over {
+        ast::WindowType::WindowSpec(spec) if spec.window_name.is_none() => spec,
+        _ => return Err("Named windows are not supported".to_string()),
+    };
+
+    let args_list = match args {
+        ast::FunctionArguments::List(list) => {
+            if matches!(
+                list.duplicate_treatment,
+                Some(ast::DuplicateTreatment::Distinct)
+            ) {
+                return Err(format!("DISTINCT is not supported in {}", name));
+            }
+            list.args
+        }
+        ast::FunctionArguments::None => Vec::new(),
+        _ => return Err(format!("Function {} expects arguments", name)),
+    };
+    let mut call_args = Vec::new();
+    for arg in args_list {
+        match arg {
+            ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Expr(e)) => {
+                call_args.push(convert_expr(e, arena)?)
+            }
+            // COUNT(*) counts rows and takes no argument
+            ast::FunctionArg::Unnamed(ast::FunctionArgExpr::Wildcard)
+                if func == WindowFunction::Count => {}
+            _ => return Err(format!("Unsupported argument type for function {}", name)),
+        }
+    }
+    let (min, max) = func.arity();
+    if call_args.len() < min || call_args.len() > max {
+        let expected = if min == max {
+            min.to_string()
+        } else {
+            format!("{} to {}", min, max)
+        };
+        return Err(format!(
+            "Function {} expects {} arguments, got {}",
+            name,
+            expected,
+            call_args.len()
+        ));
+    }
+    if matches!(func, WindowFunction::Lag | WindowFunction::Lead)
+        && let Some(offset) = call_args.get(1)
+        && !matches!(
+            offset,
+            Expression::Literal(Value::Number(n)) if get_i64_from_number(n).is_some_and(|n| n >= 0)
+        )
+    {
+        return Err(format!("{} offset must be a non-negative integer", name));
+    }
+
+    let partition_by = spec
+        .partition_by
+        .into_iter()
+        .map(|e| convert_expr(e, arena))
+        .collect::<Result<Vec<_>, _>>()?;
+    let order_by = spec
+        .order_by
+        .into_iter()
+        .map(|item| convert_sort_key(item, &[], arena))
+        .collect::<Result<Vec<_>, _>>()?;
+    let frame = spec.window_frame.map(convert_window_frame).transpose()?;
+    let mut call = WindowCall {
+        func,
+        args: call_args,
+        partition_by,
+        order_by,
+        frame,
+    };
+    if call.expressions_mut().into_iter().any(contains_window) {
+        return Err("Window function calls cannot be nested".to_string());
+    }
+    Ok(Expression::Window(Box::new(call)))
+}
+
+fn convert_window_frame(frame: ast::WindowFrame) -> Result<WindowFrame, String> {
+    let rows = match frame.units {
+        ast::WindowFrameUnits::Rows => true,
+        ast::WindowFrameUnits::Range => false,
+        ast::WindowFrameUnits::Groups => {
+            return Err("GROUPS window frames are not supported".to_string());
+        }
+    };
+    let start = convert_frame_bound(frame.start_bound)?;
+    // `ROWS n PRECEDING` is short for `ROWS BETWEEN n PRECEDING AND CURRENT ROW`
+    let end = match frame.end_bound {
+        Some(bound) => convert_frame_bound(bound)?,
+        None => FrameBound::CurrentRow,
+    };
+    let offset =
+        |bound: FrameBound| matches!(bound, FrameBound::Preceding(_) | FrameBound::Following(_));
+    if !rows && (offset(start) || offset(end)) {
+        return Err("RANGE frames only support UNBOUNDED and CURRENT ROW bounds".to_string());
+    }
+    if start == FrameBound::UnboundedFollowing || end == FrameBound::UnboundedPreceding {
+        return Err("Invalid window frame".to_string());
+    }
+    Ok(WindowFrame { rows, start, end })
+}
+
+fn convert_frame_bound(bound: ast::WindowFrameBound) -> Result<FrameBound, String> {
+    let offset = |expr: Box<Expr>| {
+        parse_limit_expr(&expr)
+            .map_err(|_| "Window frame offset must be a non-negative integer".to_string())
+    };
+    Ok(match bound {
+        ast::WindowFrameBound::CurrentRow => FrameBound::CurrentRow,
+        ast::WindowFrameBound::Preceding(None) => FrameBound::UnboundedPreceding,
+        ast::WindowFrameBound::Preceding(Some(expr)) => FrameBound::Preceding(offset(expr)?),
+        ast::WindowFrameBound::Following(None) => FrameBound::UnboundedFollowing,
+        ast::WindowFrameBound::Following(Some(expr)) => FrameBound::Following(offset(expr)?),
+    })
+}
+
+fn build_function<'a>(
+    func: ScalarFunction,
+    args: Vec<Expression<'a>>,
+) -> Result<Expression<'a>, String> {
+    let (min, max) = func.arity();
+    let expected = match max {
+        Some(max) if max == min => format!("{}", min),
+        Some(max) => format!("{} to {}", min, max),
+        None => format!("at least {}", min),
+    };
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        return Err(format!(
+            "Function {} expects {} arguments, got {}",
+            func.name(),
+            expected,
+            args.len()
+        ));
+    }
+
+    use jsonb_schema::Value as JsonbValue;
+    if func == ScalarFunction::RegexpMatch
+        && let Expression::Literal(JsonbValue::String(pattern)) = &args[1]
+    {
+        let flags = match args.get(2) {
+            Some(Expression::Literal(JsonbValue::String(flags))) => flags.to_string(),
+            _ => String::new(),
+        };
+        compile_regex(pattern, &flags)?;
+    }
+
+    if func == ScalarFunction::VectorDistance
+        && let Expression::Literal(JsonbValue::String(metric)) = &args[2]
+        && Metric::parse(metric).is_none()
+    {
+        return Err(format!("Unsupported vector metric: '{}'", metric));
+    }
+
+    if let Some(Expression::Literal(JsonbValue::String(unit))) = args.first() {
+        match func {
+            ScalarFunction::ParseJson => {
+                serde_json::from_str::<serde_json::Value>(unit)
+                    .map_err(|e| format!("Invalid JSON: {}", e))?;
+            }
+            ScalarFunction::DateTrunc if !temporal::is_trunc_unit(unit) => {
+                return Err(format!("Unsupported DATE_TRUNC unit: '{}'", unit));
+            }
+            ScalarFunction::DatePart if !temporal::is_date_part_field(unit) => {
+                return Err(format!("Unsupported date part: '{}'", unit));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(Expression::Function { func, args })
+}
+
+/// Converts `left op ANY(right)` or `left op ALL(right)`, where `right` is an
+/// array-valued expression.
+fn convert_quantified<'a>(
+    left: Expr,
+    compare_op: SqlBinaryOperator,
+    right: Expr,
+    all: bool,
+    arena: &'a Bump,
+) -> Result<Expression<'a>, String> {
+    let op = match compare_op {
+        SqlBinaryOperator::Eq => BinaryOperator::Eq,
+        SqlBinaryOperator::NotEq => BinaryOperator::Neq,
+        SqlBinaryOperator::Lt => BinaryOperator::Lt,
+        SqlBinaryOperator::LtEq => BinaryOperator::Lte,
+        SqlBinaryOperator::Gt => BinaryOperator::Gt,
+        SqlBinaryOperator::GtEq => BinaryOperator::Gte,
+        other => {
+            return Err(format!(
+                "Unsupported operator for {}: {}",
+                if all { "ALL" } else { "ANY" },
+                other
+            ));
+        }
+    };
+    Ok(Expression::Quantified {
+        left: Box::new(convert_expr(left, arena)?),
+        op,
+        right: Box::new(convert_expr(right, arena)?),
+        all,
+    })
+}
+
+/// Converts `JSON_EXISTS(expr, 'path')`; the path must be a string literal so
+/// it can be compiled once.
+fn convert_json_exists<'a>(
+    args: sqlparser::ast::FunctionArguments,
+    arena: &'a Bump,
+) -> Result<Expression<'a>, String> {
+    use sqlparser::ast::{FunctionArg, FunctionArgExpr, FunctionArguments};
+    let args = match args {
+        FunctionArguments::List(list) => list.args,
+        _ => Vec::new(),
+    };
+    if args.len() != 2 {
+        return Err(format!(
+            "Function JSON_EXISTS expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+    let mut exprs = Vec::with_capacity(2);
+    for arg in args {
+        match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => exprs.push(e),
+            _ => return Err("Unsupported argument type for function JSON_EXISTS".to_string()),
+        }
+    }
+    let path = exprs.pop().unwrap();
+    let expr = convert_expr(exprs.pop().unwrap(), arena)?;
+    let raw: &'a str = match path {
+        Expr::Value(val_span) => match val_span.value {
+            ast::Value::SingleQuotedString(s) => arena.alloc_str(&s),
+            _ => return Err("JSON_EXISTS path must be a string literal".to_string()),
+        },
+        _ => return Err("JSON_EXISTS path must be a string literal".to_string()),
+    };
+    let parsed = jsonb_schema::jsonpath::parse_json_path(raw.as_bytes())
+        .map_err(|e| format!("Invalid JSON path: {}", e))?;
+    Ok(Expression::JsonExists {
+        expr: Box::new(expr),
+        path: Box::new(parsed),
+        raw,
+    })
+}
+
+/// Converts `MATCH(expr, 'query')`; the query must be a string literal so
+/// its terms can be found once.
+fn convert_match<'a>(
+    args: sqlparser::ast::FunctionArguments,
+    arena: &'a Bump,
+) -> Result<Expression<'a>, String> {
+    use sqlparser::ast::{FunctionArg, FunctionArgExpr, FunctionArguments};
+    let args = match args {
+        FunctionArguments::List(list) => list.args,
+        _ => Vec::new(),
+    };
+    if args.len() != 2 {
+        return Err(format!(
+            "Function MATCH expects 2 arguments, got {}",
+            args.len()
+        ));
+    }
+    let mut exprs = Vec::with_capacity(2);
+    for arg in args {
+        match arg {
+            FunctionArg::Unnamed(FunctionArgExpr::Expr(e)) => exprs.push(e),
+            _ => return Err("Unsupported argument type for function MATCH".to_string()),
+        }
+    }
+    let query = exprs.pop().unwrap();
+    let expr = convert_expr(exprs.pop().unwrap(), arena)?;
+    let query: &'a str = match query {
+        Expr::Value(val_span) => match val_span.value {
+            ast::Value::SingleQuotedString(s) => arena.alloc_str(&s),
+            _ => return Err("MATCH query must be a string literal".to_string()),
+        },
+        _ => return Err("MATCH query must be a string literal".to_string()),
+    };
+    Ok(Expression::Match {
+        expr: Box::new(expr),
+        query,
+        terms: fulltext::query_terms(query),
+        stats: None,
+    })
+}
+
+/// Makes each `MATCH` used as a condition of `predicate` a test that its
+/// score is positive, i.e. that the text holds a query term.
+fn match_as_condition(predicate: &mut Expression) {
+    match predicate {
+        Expression::Match { .. } => {
+            let score = std::mem::replace(predicate, Expression::Wildcard);
+            *predicate = Expression::Binary {
+                left: Box::new(score),
+                op: BinaryOperator::Gt,
+                right: Box::new(Expression::Literal(Value::Number(
+                    jsonb_schema::Number::Int64(0),
+                ))),
+            };
+        }
+        Expression::Logical { left, right, .. } => {
+            match_as_condition(left);
+            match_as_condition(right);
+        }
+        Expression::Unary {
+            op: UnaryOperator::Not,
+            expr,
+        } => match_as_condition(expr),
+        _ => {}
+    }
+}
+
+/// Converts an `INTERVAL` literal to its ISO-8601 duration string, e.g.
+/// `INTERVAL '90 minutes'` or `INTERVAL '90' MINUTE` to `'PT1H30M'`.
+fn convert_interval<'a>(interval: ast::Interval) -> Result<Expression<'a>, String> {
+    use jsonb_schema::Value as JsonbValue;
+    if interval.last_field.is_some() {
+        return Err("Unsupported interval qualifier".to_string());
+    }
+    let text = match *interval.value {
+        Expr::Value(val_span) => match val_span.value {
+            ast::Value::SingleQuotedString(s) | ast::Value::Number(s, _) => s,
+            other => return Err(format!("Unsupported interval value: {}", other)),
+        },
+        other => return Err(format!("Unsupported interval value: {}", other)),
+    };
+    let parsed = match interval.leading_field {
+        Some(field) => text
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .and_then(|amount| Interval::of_unit(amount, &field.to_string()))
+            .ok_or_else(|| format!("Invalid interval: '{}' {}", text, field))?,
+        None => Interval::parse(&text)?,
+    };
+    Ok(Expression::Literal(JsonbValue::String(
+        parsed.to_string().into(),
+    )))
+}
+
+fn convert_like<'a>(
+    expr: Expr,
+    pattern: Expr,
+    negated: bool,
+    case_insensitive: bool,
+    has_escape: bool,
+    arena: &'a Bump,
+) -> Result<Expression<'a>, String> {
+    if has_escape {
+        return Err("ESCAPE is not supported in LIKE, use \\ instead".to_string());
+    }
+    Ok(Expression::Like {
+        expr: Box::new(convert_expr(expr, arena)?),
+        pattern: Box::new(convert_expr(pattern, arena)?),
+        negated,
+        case_insensitive,
+    })
+}
+
+/// Negates an expression, folding numeric literals so `-5` stays a literal.
+fn negate(expr: Expression) -> Expression {
+    use jsonb_schema::{Number, Value as JsonbValue};
+    match expr {
+        Expression::Literal(JsonbValue::Number(Number::Int64(i))) if i != i64::MIN => {
+            Expression::Literal(JsonbValue::Number(Number::Int64(-i)))
+        }
+        Expression::Literal(JsonbValue::Number(Number::Float64(f))) => {
+            Expression::Literal(JsonbValue::Number(Number::Float64(-f)))
+        }
+        other => Expression::Unary {
+            op: UnaryOperator::Neg,
+            expr: Box::new(other),
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -441,6 +1801,268 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_arithmetic() {
+        let sql = "SELECT -(a + 2) * b % 3, NOT c, a || 'x', -1.5 FROM t WHERE a / 2 > -4";
+        let arena = Bump::new();
+        let stmt = parse(sql, &arena).unwrap();
+        match stmt {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                let names: Vec<String> = projections.iter().map(|p| p.to_string()).collect();
+                assert_eq!(
+                    names,
+                    vec!["((-(a + 2)) * b) % 3", "NOT c", "a || 'x'", "-1.5"]
+                );
+                match *input {
+                    LogicalPlan::Filter { predicate, .. } => {
+                        assert_eq!(predicate.to_string(), "(a / 2) > -4");
+                        match predicate {
+                            Expression::Binary { right, .. } => assert!(matches!(
+                                *right,
+                                Expression::Literal(Value::Number(jsonb_schema::Number::Int64(-4)))
+                            )),
+                            _ => panic!("Expected Binary"),
+                        }
+                    }
+                    _ => panic!("Expected Filter"),
+                }
+            }
+            _ => panic!("Expected Select Project"),
+        }
+    }
+
+    #[test]
+    fn test_parse_string_and_conditional_functions() {
+        let sql = "SELECT lower(name), SUBSTR(name, 2, 3), SUBSTRING(name FROM 2), \
+                   TRIM(name), TRIM(LEADING 'x' FROM name), RTRIM(name), \
+                   COALESCE(a, b, 0), CASE WHEN a > 1 THEN 'big' ELSE 'small' END, \
+                   CASE a WHEN 1 THEN 'one' END FROM t";
+        let arena = Bump::new();
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Project { projections, .. }) => {
+                let names: Vec<String> = projections.iter().map(|p| p.to_string()).collect();
+                assert_eq!(
+                    names,
+                    vec![
+                        "LOWER(name)",
+                        "SUBSTR(name, 2, 3)",
+                        "SUBSTR(name, 2)",
+                        "TRIM(name)",
+                        "LTRIM(name, 'x')",
+                        "RTRIM(name)",
+                        "COALESCE(a, b, 0)",
+                        "CASE WHEN a > 1 THEN 'big' ELSE 'small' END",
+                        "CASE a WHEN 1 THEN 'one' END",
+                    ]
+                );
+            }
+            _ => panic!("Expected Select Project"),
+        }
+    }
+
+    #[test]
+    fn test_parse_temporal() {
+        let sql = "SELECT EXTRACT(YEAR FROM ts), DATE_TRUNC('day', ts), \
+                   NOW() - INTERVAL '1 hour', CURRENT_TIMESTAMP FROM t \
+                   WHERE ts > NOW() - INTERVAL '90' MINUTE";
+        let arena = Bump::new();
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                let names: Vec<String> = projections.iter().map(|p| p.to_string()).collect();
+                assert_eq!(
+                    names,
+                    vec![
+                        "DATE_PART('year', ts)",
+                        "DATE_TRUNC('day', ts)",
+                        "NOW() - 'PT1H'",
+                        "NOW()",
+                    ]
+                );
+                match *input {
+                    LogicalPlan::Filter { predicate, .. } => {
+                        assert_eq!(predicate.to_string(), "ts > (NOW() - 'PT1H30M')")
+                    }
+                    _ => panic!("Expected Filter"),
+                }
+            }
+            _ => panic!("Expected Select Project"),
+        }
+
+        for (sql, expected) in [
+            (
+                "SELECT DATE_TRUNC('fortnight', ts) FROM t",
+                "Unsupported DATE_TRUNC unit: 'fortnight'",
+            ),
+            (
+                "SELECT EXTRACT(TIMEZONE FROM ts) FROM t",
+                "Unsupported date part: 'timezone'",
+            ),
+            ("SELECT INTERVAL 'soon' FROM t", "Invalid interval: 'soon'"),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_function_arity() {
+        let arena = Bump::new();
+        for (sql, expected) in [
+            (
+                "SELECT ABS(a, b) FROM t",
+                "Function ABS expects 1 arguments, got 2",
+            ),
+            (
+                "SELECT REPLACE(a, 'x') FROM t",
+                "Function REPLACE expects 3 arguments, got 2",
+            ),
+            (
+                "SELECT ROUND() FROM t",
+                "Function ROUND expects 1 to 2 arguments, got 0",
+            ),
+            (
+                "SELECT COALESCE() FROM t",
+                "Function COALESCE expects at least 1 arguments, got 0",
+            ),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+
+        let err = parse("SELECT REGEXP_MATCH(a, '(') FROM t", &arena).unwrap_err();
+        assert!(err.starts_with("Invalid regular expression"), "{}", err);
+        assert!(parse("SELECT REGEXP_MATCH(a, '[0-9]+', 'i') FROM t", &arena).is_ok());
+
+        assert_eq!(
+            parse(
+                "SELECT VECTOR_DISTANCE(v, ARRAY[1, 2], 'manhattan') FROM t",
+                &arena
+            )
+            .unwrap_err(),
+            "Unsupported vector metric: 'manhattan'"
+        );
+        assert!(
+            parse(
+                "SELECT VECTOR_DISTANCE(v, ARRAY[1, 2], 'L2') FROM t",
+                &arena
+            )
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_parse_unnest() {
+        let sql = "SELECT o.customer, item.sku FROM orders AS o, UNNEST(o.items) AS item \
+                   WHERE item.qty > 2 AND orders.total > 10";
+        let arena = Bump::new();
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                let names: Vec<String> = projections.iter().map(|p| p.to_string()).collect();
+                assert_eq!(names, vec!["customer", "item.sku"]);
+                match *input {
+                    LogicalPlan::Filter { input, predicate } => {
+                        assert_eq!(predicate.to_string(), "(item.qty > 2) AND (total > 10)");
+                        match *input {
+                            LogicalPlan::Unnest { input, expr, alias } => {
+                                assert_eq!(expr.to_string(), "items");
+                                assert_eq!(alias, "item");
+                                assert!(matches!(*input, LogicalPlan::Scan { .. }));
+                            }
+                            _ => panic!("Expected Unnest"),
+                        }
+                    }
+                    _ => panic!("Expected Filter"),
+                }
+            }
+            _ => panic!("Expected Select Project"),
+        }
+
+        match parse("SELECT * FROM t, UNNEST(tags)", &arena).unwrap() {
+            Statement::Select(LogicalPlan::Unnest { alias, .. }) => assert_eq!(alias, "unnest"),
+            _ => panic!("Expected Unnest"),
+        }
+        assert_eq!(
+            parse("SELECT * FROM a, b", &arena).unwrap_err(),
+            "Only UNNEST is supported after the first FROM table"
+        );
+    }
+
+    #[test]
+    fn test_parse_predicates() {
+        let sql = "SELECT a FROM t WHERE a IN (1, 2) AND b NOT BETWEEN 1 AND 5 \
+                   AND c LIKE 'x%' AND d NOT ILIKE '%y' AND e IS NULL AND f IS NOT NULL \
+                   AND g IS MISSING AND h IS NOT MISSING AND missing = 1";
+        let arena = Bump::new();
+        let stmt = parse(sql, &arena).unwrap();
+        match stmt {
+            Statement::Select(LogicalPlan::Project { input, .. }) => match *input {
+                LogicalPlan::Filter { predicate, .. } => {
+                    let mut conjuncts = Vec::new();
+                    crate::query::split_conjuncts(predicate, &mut conjuncts);
+                    let rendered: Vec<String> = conjuncts.iter().map(|c| c.to_string()).collect();
+                    assert_eq!(
+                        rendered,
+                        vec![
+                            "a IN (1, 2)",
+                            "b NOT BETWEEN 1 AND 5",
+                            "c LIKE 'x%'",
+                            "d NOT ILIKE '%y'",
+                            "e IS NULL",
+                            "f IS NOT NULL",
+                            "g IS MISSING",
+                            "h IS NOT MISSING",
+                            "missing = 1",
+                        ]
+                    );
+                }
+                _ => panic!("Expected Filter"),
+            },
+            _ => panic!("Expected Select Project"),
+        }
+
+        let sql = "SELECT a FROM t WHERE a LIKE 'x!%' ESCAPE '!'";
+        assert!(parse(sql, &arena).is_err());
+
+        // IS NORMALIZED is not taken for IS MISSING
+        for sql in [
+            "SELECT a FROM t WHERE a IS NORMALIZED",
+            "SELECT a FROM t WHERE a IS NOT NORMALIZED",
+        ] {
+            assert_eq!(
+                parse(sql, &arena).err().as_deref(),
+                Some("Unsupported predicate: IS NORMALIZED")
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_order_by() {
+        let sql = "SELECT a, b + 1 AS c FROM t ORDER BY c DESC, 1, d NULLS LAST LIMIT 5";
+        let arena = Bump::new();
+        let stmt = parse(sql, &arena).unwrap();
+        let Statement::Select(LogicalPlan::Limit { input, .. }) = stmt else {
+            panic!("Expected Limit");
+        };
+        let LogicalPlan::Project { input, .. } = *input else {
+            panic!("Expected Project");
+        };
+        let LogicalPlan::Sort { keys, .. } = *input else {
+            panic!("Expected Sort");
+        };
+        let rendered: Vec<(String, bool, bool)> = keys
+            .iter()
+            .map(|k| (k.expr.to_string(), k.descending, k.nulls_first))
+            .collect();
+        assert_eq!(
+            rendered,
+            vec![
+                ("b + 1".to_string(), true, false),
+                ("a".to_string(), false, true),
+                ("d".to_string(), false, false),
+            ]
+        );
+
+        assert!(parse("SELECT a FROM t ORDER BY 2", &arena).is_err());
+    }
+
     #[test]
     fn test_parse_ddl() {
         let arena = Bump::new();
@@ -472,15 +2094,30 @@ mod tests {
         let sql = "SELECT FROM WHERE";
         assert!(parse(sql, &arena).is_err());
 
-        // INSERT without VALUES
-        let sql = "INSERT INTO users SELECT * FROM other";
-        assert!(parse(sql, &arena).is_err());
+        // INSERT ... SELECT
+        let sql = "INSERT INTO users SELECT name FROM other WHERE age > 3";
+        match parse(sql, &arena).unwrap() {
+            Statement::InsertSelect { collection, plan } => {
+                assert_eq!(collection, "users");
+                assert!(matches!(plan, LogicalPlan::Project { .. }));
+            }
+            _ => panic!("Expected InsertSelect"),
+        }
+        let sql = "INSERT INTO users (a) SELECT a FROM other";
+        assert_eq!(
+            parse(sql, &arena).unwrap_err(),
+            "INSERT column lists are not supported"
+        );
 
-        // SELECT * (Wildcard unsupported)
-        let sql = "SELECT * FROM users";
-        let res = parse(sql, &arena);
-        assert!(res.is_err());
-        assert!(res.unwrap_err().contains("Wildcard * not supported"));
+        // CREATE COLLECTION ... AS SELECT
+        let sql = "CREATE COLLECTION adults AS SELECT * FROM users WHERE age >= 18;";
+        match parse(sql, &arena).unwrap() {
+            Statement::CreateCollectionAs { collection, plan } => {
+                assert_eq!(collection, "adults");
+                assert!(matches!(plan, LogicalPlan::Filter { .. }));
+            }
+            _ => panic!("Expected CreateCollectionAs"),
+        }
 
         // Invalid JSON in INSERT
         let sql = "INSERT INTO users VALUES (`{invalid json}`)";
@@ -494,4 +2131,438 @@ mod tests {
         assert!(res.is_err());
         assert!(res.unwrap_err().contains("Expected exactly one statement"));
     }
+
+    #[test]
+    fn test_parse_array_predicates() {
+        let arena = Bump::new();
+        let filter = |sql: &str| match parse(sql, &Bump::new()).unwrap() {
+            Statement::Select(LogicalPlan::Filter { predicate, .. }) => predicate.to_string(),
+            _ => panic!("Expected Filter"),
+        };
+        assert_eq!(
+            filter("SELECT * FROM t WHERE 'x' = ANY(tags)"),
+            "'x' = ANY(tags)"
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE 3 < ALL(scores)"),
+            "3 < ALL(scores)"
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE JSON_EXISTS(doc, '$.a ? (@ > 3)')"),
+            "JSON_EXISTS(doc, '$.a ? (@ > 3)')"
+        );
+        match parse("SELECT * FROM t WHERE tags @> ARRAY['a', -1]", &arena).unwrap() {
+            Statement::Select(LogicalPlan::Filter {
+                predicate:
+                    Expression::Binary {
+                        op: BinaryOperator::Contains,
+                        right,
+                        ..
+                    },
+                ..
+            }) => match *right {
+                Expression::Literal(val) => {
+                    assert_eq!(val, serde_to_jsonb(serde_json::json!(["a", -1])))
+                }
+                _ => panic!("Expected ARRAY to fold to a literal"),
+            },
+            _ => panic!("Expected containment filter"),
+        }
+        assert_eq!(
+            filter("SELECT * FROM t WHERE doc <@ PARSE_JSON('{\"a\": 1}')"),
+            "doc <@ PARSE_JSON('{\"a\": 1}')"
+        );
+
+        let err = parse("SELECT * FROM t WHERE tags @> ARRAY[a]", &arena).unwrap_err();
+        assert_eq!(err, "ARRAY elements must be constants");
+        let err = parse("SELECT * FROM t WHERE JSON_EXISTS(doc, p)", &arena).unwrap_err();
+        assert_eq!(err, "JSON_EXISTS path must be a string literal");
+        assert!(parse("SELECT * FROM t WHERE JSON_EXISTS(doc, '$[')", &arena).is_err());
+        assert!(parse("SELECT PARSE_JSON('{oops') FROM t", &arena).is_err());
+    }
+
+    #[test]
+    fn test_parse_join() {
+        let arena = Bump::new();
+        let sql = "SELECT u.name, events.kind FROM users AS u \
+                   JOIN events ON events.user = u._id AND events.n > u.min \
+                   LEFT JOIN tags t ON t.kind = events.kind WHERE u.age > 3";
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                let names: Vec<String> = projections.iter().map(|p| p.to_string()).collect();
+                assert_eq!(names, vec!["u.name", "events.kind"]);
+                let LogicalPlan::Filter { input, predicate } = *input else {
+                    panic!("Expected Filter");
+                };
+                assert_eq!(predicate.to_string(), "u.age > 3");
+                let LogicalPlan::Join {
+                    left,
+                    kind: JoinKind::Left,
+                    left_alias: None,
+                    right_alias: "t",
+                    keys,
+                    residual: None,
+                    ..
+                } = *input
+                else {
+                    panic!("Expected LEFT JOIN");
+                };
+                assert_eq!(keys[0].0.to_string(), "events.kind");
+                assert_eq!(keys[0].1.to_string(), "t.kind");
+                match *left {
+                    LogicalPlan::Join {
+                        kind: JoinKind::Inner,
+                        left_alias: Some("u"),
+                        right_alias: "events",
+                        keys,
+                        residual: Some(residual),
+                        ..
+                    } => {
+                        // Keys are oriented (left, right) whichever way they are written
+                        assert_eq!(keys[0].0.to_string(), "u._id");
+                        assert_eq!(keys[0].1.to_string(), "events.user");
+                        assert_eq!(residual.to_string(), "events.n > u.min");
+                    }
+                    _ => panic!("Expected inner Join"),
+                }
+            }
+            _ => panic!("Expected Select Project"),
+        }
+
+        // Collection names resolve to their alias
+        match parse(
+            "SELECT * FROM users u JOIN events e ON users.id = e.user",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Join { keys, .. }) => {
+                assert_eq!(keys[0].0.to_string(), "u.id")
+            }
+            _ => panic!("Expected Join"),
+        }
+
+        for (sql, expected) in [
+            (
+                "SELECT name FROM users u JOIN events e ON u.id = e.user",
+                "Field 'name' must be qualified with a collection name or alias in a join",
+            ),
+            (
+                "SELECT * FROM users u JOIN events e ON u.id > e.user",
+                "JOIN condition must include an equality between the joined collections",
+            ),
+            (
+                "SELECT * FROM users u JOIN events e ON u.id = x.user",
+                "Unknown collection or alias: x",
+            ),
+            (
+                "SELECT * FROM users u RIGHT JOIN events e ON u.id = e.user",
+                "Only INNER and LEFT joins are supported",
+            ),
+            (
+                "SELECT * FROM users u JOIN events u ON u.id = u.user",
+                "Duplicate table alias: u",
+            ),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_distinct() {
+        let arena = Bump::new();
+        match parse("SELECT DISTINCT a.b FROM t ORDER BY a.b", &arena).unwrap() {
+            Statement::Select(LogicalPlan::Distinct { input, on }) => {
+                assert!(on.is_empty());
+                let LogicalPlan::Project { input, .. } = *input else {
+                    panic!("Expected Project");
+                };
+                assert!(matches!(*input, LogicalPlan::Sort { .. }));
+            }
+            _ => panic!("Expected Distinct over Project"),
+        }
+
+        match parse(
+            "SELECT DISTINCT ON (t.kind) kind, n FROM t ORDER BY kind, n DESC",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Project { input, .. }) => {
+                let LogicalPlan::Distinct { input, on } = *input else {
+                    panic!("Expected Distinct");
+                };
+                assert_eq!(on[0].to_string(), "kind");
+                assert!(matches!(*input, LogicalPlan::Sort { .. }));
+            }
+            _ => panic!("Expected Project over Distinct"),
+        }
+    }
+
+    #[test]
+    fn test_parse_parameters() {
+        let arena = Bump::new();
+        let filter = |sql| match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Filter { predicate, .. }) => predicate.to_string(),
+            _ => panic!("Expected Filter"),
+        };
+        assert_eq!(
+            filter("SELECT * FROM t WHERE a = $1 AND b > $2"),
+            "(a = $1) AND (b > $2)"
+        );
+        assert_eq!(
+            filter("SELECT * FROM t WHERE a = ? OR b IN (?, ?)"),
+            "(a = $1) OR (b IN ($2, $3))"
+        );
+        // `$` alone still starts a JSON path
+        assert_eq!(filter("SELECT * FROM t WHERE $.a = $1"), "$.a = $1");
+
+        let prepared = prepare(
+            "SELECT * FROM t WHERE id IN (SELECT id FROM u WHERE n = $3) AND x = $1",
+            &arena,
+        )
+        .unwrap();
+        assert_eq!(prepared.parameter_count(), 3);
+        assert_eq!(
+            prepare("SELECT * FROM t", &arena)
+                .unwrap()
+                .parameter_count(),
+            0
+        );
+
+        let prepared = prepare("SELECT * FROM t WHERE a = $1", &arena).unwrap();
+        match prepared.bind(&[Value::String("x".into())]).unwrap() {
+            Statement::Select(LogicalPlan::Filter { predicate, .. }) => {
+                assert_eq!(predicate.to_string(), "a = 'x'")
+            }
+            _ => panic!("Expected Filter"),
+        }
+        assert_eq!(
+            prepared.bind(&[]).unwrap_err(),
+            "Expected 1 parameters, got 0"
+        );
+
+        for (sql, expected) in [
+            (
+                "SELECT * FROM t WHERE a = $1 AND b = ?",
+                "Cannot mix $n and ? parameters",
+            ),
+            ("SELECT * FROM t WHERE a = $0", "Invalid parameter: $0"),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_explain() {
+        let arena = Bump::new();
+        match parse("EXPLAIN SELECT * FROM t WHERE a > 1", &arena).unwrap() {
+            Statement::Explain { plan, analyze } => {
+                assert!(!analyze);
+                assert!(matches!(plan, LogicalPlan::Filter { .. }));
+            }
+            _ => panic!("Expected Explain"),
+        }
+        match parse("EXPLAIN ANALYZE SELECT * FROM t", &arena).unwrap() {
+            Statement::Explain { plan, analyze } => {
+                assert!(analyze);
+                assert!(matches!(plan, LogicalPlan::Scan { .. }));
+            }
+            _ => panic!("Expected Explain"),
+        }
+        assert_eq!(
+            parse("EXPLAIN INSERT INTO t VALUES ('{}')", &arena).unwrap_err(),
+            "EXPLAIN only supports queries"
+        );
+    }
+
+    #[test]
+    fn test_parse_window() {
+        let arena = Bump::new();
+        let sql = "SELECT name, ROW_NUMBER() OVER (PARTITION BY t.dept ORDER BY salary DESC) AS rn, \
+                   SUM(x) OVER (ORDER BY ts ROWS 2 PRECEDING) FROM t ORDER BY rn";
+        let row_number = "ROW_NUMBER() OVER (PARTITION BY dept ORDER BY salary DESC)";
+        let sum = "SUM(x) OVER (ORDER BY ts ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)";
+        match parse(sql, &arena).unwrap() {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                // Results are stored in hidden fields, and output under the
+                // call's name unless aliased
+                let hidden = |call: &str| format!("{}{}", WINDOW_FIELD_PREFIX, call);
+                let Expression::Alias(inner, name) = &projections[2] else {
+                    panic!("Expected Alias");
+                };
+                assert_eq!((inner.to_string(), *name), (hidden(sum), sum));
+                let LogicalPlan::Sort { input, keys } = *input else {
+                    panic!("Expected Sort");
+                };
+                assert_eq!(keys[0].expr.to_string(), hidden(row_number));
+                let LogicalPlan::Window { windows, .. } = *input else {
+                    panic!("Expected Window");
+                };
+                let names: Vec<&str> = windows.iter().map(|(_, name)| *name).collect();
+                assert_eq!(names, vec![hidden(row_number), hidden(sum)]);
+                assert_eq!(
+                    windows[1].0.frame,
+                    Some(WindowFrame {
+                        rows: true,
+                        start: FrameBound::Preceding(2),
+                        end: FrameBound::CurrentRow,
+                    })
+                );
+            }
+            _ => panic!("Expected Project"),
+        }
+
+        for (sql, expected) in [
+            (
+                "SELECT RANK() FROM t",
+                "Function RANK requires an OVER clause",
+            ),
+            (
+                "SELECT LOWER(a) OVER () FROM t",
+                "Function LOWER is not a window function",
+            ),
+            (
+                "SELECT LAG(a, -1) OVER (ORDER BY b) FROM t",
+                "LAG offset must be a non-negative integer",
+            ),
+            (
+                "SELECT SUM(a) OVER (ORDER BY b RANGE 1 PRECEDING) FROM t",
+                "RANGE frames only support UNBOUNDED and CURRENT ROW bounds",
+            ),
+            (
+                "SELECT SUM(a) OVER (ORDER BY b GROUPS UNBOUNDED PRECEDING) FROM t",
+                "GROUPS window frames are not supported",
+            ),
+            (
+                "SELECT * FROM t WHERE ROW_NUMBER() OVER () > 1",
+                "Window functions are only allowed in SELECT and ORDER BY",
+            ),
+            (
+                "SELECT SUM(RANK() OVER ()) OVER () FROM t",
+                "Window function calls cannot be nested",
+            ),
+            (
+                "SELECT ROW_NUMBER(a) OVER () FROM t",
+                "Function ROW_NUMBER expects 0 arguments, got 1",
+            ),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn test_parse_subqueries() {
+        let arena = Bump::new();
+
+        // Derived table: qualifiers by its alias are stripped
+        match parse(
+            "SELECT t.name FROM (SELECT name FROM users WHERE age > 3) AS t",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Project { input, projections }) => {
+                assert_eq!(projections[0].to_string(), "name");
+                assert!(matches!(*input, LogicalPlan::Project { .. }));
+            }
+            _ => panic!("Expected Project over a subquery"),
+        }
+
+        // CTEs are inlined, and may refer to earlier CTEs
+        match parse(
+            "WITH a AS (SELECT * FROM users), b AS (SELECT * FROM a WHERE x = 1) \
+             SELECT * FROM b",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Filter { input, .. }) => match *input {
+                LogicalPlan::Scan { collection } => assert_eq!(collection, "users"),
+                _ => panic!("Expected Scan"),
+            },
+            _ => panic!("Expected inlined CTE"),
+        }
+
+        // Set operations, with ORDER BY and LIMIT over the result
+        match parse(
+            "SELECT a FROM x UNION ALL SELECT a FROM y EXCEPT SELECT a FROM z ORDER BY a LIMIT 3",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Limit { input, limit: 3 }) => {
+                let LogicalPlan::Sort { input, keys } = *input else {
+                    panic!("Expected Sort");
+                };
+                assert_eq!(keys[0].expr.to_string(), "a");
+                let LogicalPlan::SetOperation {
+                    left,
+                    op: SetOperator::Except,
+                    all: false,
+                    ..
+                } = *input
+                else {
+                    panic!("Expected EXCEPT");
+                };
+                assert!(matches!(
+                    *left,
+                    LogicalPlan::SetOperation {
+                        op: SetOperator::Union,
+                        all: true,
+                        ..
+                    }
+                ));
+            }
+            _ => panic!("Expected Limit"),
+        }
+
+        // IN and EXISTS subqueries, which may use CTEs of the outer query
+        match parse(
+            "WITH v AS (SELECT id FROM vips) SELECT * FROM users \
+             WHERE id NOT IN (SELECT id FROM v) AND EXISTS (SELECT * FROM v)",
+            &arena,
+        )
+        .unwrap()
+        {
+            Statement::Select(LogicalPlan::Filter { predicate, .. }) => {
+                assert_eq!(
+                    predicate.to_string(),
+                    "(id NOT IN (subquery)) AND EXISTS (subquery)"
+                );
+                let Expression::Logical { left, .. } = predicate else {
+                    panic!("Expected AND");
+                };
+                let Expression::InSubquery { subquery, .. } = *left else {
+                    panic!("Expected IN subquery");
+                };
+                let LogicalPlan::Project { input, .. } = *subquery else {
+                    panic!("Expected Project");
+                };
+                assert!(matches!(*input, LogicalPlan::Project { .. }));
+            }
+            _ => panic!("Expected Filter"),
+        }
+
+        for (sql, expected) in [
+            (
+                "SELECT * FROM (SELECT * FROM users)",
+                "Subquery in FROM must have an alias",
+            ),
+            (
+                "WITH RECURSIVE t AS (SELECT * FROM users) SELECT * FROM t",
+                "WITH RECURSIVE is not supported",
+            ),
+            (
+                "WITH t (a) AS (SELECT * FROM users) SELECT * FROM t",
+                "CTE column lists are not supported",
+            ),
+            (
+                "WITH t AS (SELECT * FROM a), t AS (SELECT * FROM b) SELECT * FROM t",
+                "Duplicate CTE name: t",
+            ),
+        ] {
+            assert_eq!(parse(sql, &arena).unwrap_err(), expected, "{}", sql);
+        }
+    }
 }
@@ -1,9 +1,25 @@
-use crate::db::DB;
+use crate::db::{DB, IdRange};
+use crate::explain::Profiler;
 pub use crate::expression::*;
-use crate::{ExecutionResult, Value};
+use crate::geo::{self, BBox, Geometry};
+use crate::optimizer::optimize;
+use crate::secondary::{IndexKind, IndexRange};
+use crate::temporal;
+use crate::vector::{self, Metric};
+use crate::{ExecutionResult, SerdeWrapper, Value, make_static};
 use jsonb_schema;
-use std::cmp::min;
-use std::collections::BTreeMap;
+use jsonb_schema::Number;
+use std::cell::RefCell;
+use std::cmp::{Ordering, min};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Bound;
+use std::rc::Rc;
+use std::sync::Arc;
 use tracing::{Level, span};
 
 #[derive(Debug, Clone)]
@@ -27,6 +43,193 @@ pub enum LogicalPlan<'a> {
         input: Box<LogicalPlan<'a>>,
         offset: usize,
     },
+    Sort {
+        input: Box<LogicalPlan<'a>>,
+        keys: Vec<SortKey<'a>>,
+    },
+    Unnest {
+        input: Box<LogicalPlan<'a>>,
+        expr: Expression<'a>,
+        alias: &'a str,
+    },
+    /// An equi-join. Output rows are objects with each input document under
+    /// its alias, e.g. `{"u": {..}, "o": {..}}`.
+    Join {
+        left: Box<LogicalPlan<'a>>,
+        right: Box<LogicalPlan<'a>>,
+        kind: JoinKind,
+        left_alias: Option<&'a str>, // None if `left` is a join, already keyed by alias
+        right_alias: &'a str,
+        keys: Vec<(Expression<'a>, Expression<'a>)>, // (left key, right key)
+        residual: Option<Expression<'a>>,
+    },
+    /// Computes window functions over the whole input, adding each result to
+    /// its row as a field with the given name.
+    Window {
+        input: Box<LogicalPlan<'a>>,
+        windows: Vec<(WindowCall<'a>, &'a str)>,
+    },
+    /// Removes rows equal to an earlier row, or with `on`, rows whose `on`
+    /// values equal those of an earlier row.
+    Distinct {
+        input: Box<LogicalPlan<'a>>,
+        on: Vec<Expression<'a>>, // empty: compare whole rows
+    },
+    /// `UNION`, `INTERSECT` or `EXCEPT` of two inputs, comparing whole rows.
+    SetOperation {
+        left: Box<LogicalPlan<'a>>,
+        right: Box<LogicalPlan<'a>>,
+        op: SetOperator,
+        all: bool, // keep duplicates
+    },
+}
+
+fn expression_reads_collection(expr: &mut Expression, collection: &str) -> bool {
+    if let Expression::InSubquery { subquery, .. } | Expression::Exists { subquery, .. } = expr
+        && subquery.reads_collection(collection)
+    {
+        return true;
+    }
+    expr.children_mut()
+        .into_iter()
+        .any(|child| expression_reads_collection(child, collection))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOperator {
+    Union,
+    Intersect,
+    Except,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinKind {
+    Inner,
+    Left,
+}
+
+impl<'a> LogicalPlan<'a> {
+    /// Returns every expression in the plan tree, for rewriting.
+    pub fn expressions_mut(&mut self) -> Vec<&mut Expression<'a>> {
+        let mut exprs = Vec::new();
+        self.collect_expressions_mut(&mut exprs);
+        exprs
+    }
+
+    fn collect_expressions_mut<'s>(&'s mut self, out: &mut Vec<&'s mut Expression<'a>>) {
+        match self {
+            LogicalPlan::Scan { .. } => {}
+            LogicalPlan::Filter { input, predicate } => {
+                out.push(predicate);
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Project { input, projections } => {
+                out.extend(projections.iter_mut());
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Limit { input, .. } | LogicalPlan::Offset { input, .. } => {
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Sort { input, keys } => {
+                out.extend(keys.iter_mut().map(|key| &mut key.expr));
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Unnest { input, expr, .. } => {
+                out.push(expr);
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Join {
+                left,
+                right,
+                keys,
+                residual,
+                ..
+            } => {
+                for (left_key, right_key) in keys.iter_mut() {
+                    out.push(left_key);
+                    out.push(right_key);
+                }
+                out.extend(residual.iter_mut());
+                left.collect_expressions_mut(out);
+                right.collect_expressions_mut(out);
+            }
+            LogicalPlan::Window { input, windows } => {
+                for (call, _) in windows.iter_mut() {
+                    out.extend(call.expressions_mut());
+                }
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::Distinct { input, on } => {
+                out.extend(on.iter_mut());
+                input.collect_expressions_mut(out);
+            }
+            LogicalPlan::SetOperation { left, right, .. } => {
+                left.collect_expressions_mut(out);
+                right.collect_expressions_mut(out);
+            }
+        }
+    }
+
+    /// Returns true if the plan, including its subqueries, scans `collection`.
+    pub fn reads_collection(&mut self, collection: &str) -> bool {
+        if let LogicalPlan::Scan {
+            collection: scanned,
+        } = self
+        {
+            return scanned == collection;
+        }
+        self.expressions_mut()
+            .into_iter()
+            .any(|expr| expression_reads_collection(expr, collection))
+            || self
+                .inputs_mut()
+                .into_iter()
+                .any(|input| input.reads_collection(collection))
+    }
+
+    /// Returns the input plans of this node.
+    pub fn inputs_mut(&mut self) -> Vec<&mut LogicalPlan<'a>> {
+        match self {
+            LogicalPlan::Scan { .. } => Vec::new(),
+            LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Limit { input, .. }
+            | LogicalPlan::Offset { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Unnest { input, .. }
+            | LogicalPlan::Window { input, .. }
+            | LogicalPlan::Distinct { input, .. } => vec![input.as_mut()],
+            LogicalPlan::Join { left, right, .. }
+            | LogicalPlan::SetOperation { left, right, .. } => {
+                vec![left.as_mut(), right.as_mut()]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SortKey<'a> {
+    pub expr: Expression<'a>,
+    pub descending: bool,
+    pub nulls_first: bool,
+}
+
+impl fmt::Display for SortKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        // Only the non-default null placement is shown
+        if self.nulls_first == self.descending {
+            write!(
+                f,
+                " NULLS {}",
+                if self.nulls_first { "FIRST" } else { "LAST" }
+            )?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -35,14 +238,143 @@ pub enum Statement<'a> {
         collection: String,
         documents: Vec<Value>,
     },
+    /// `INSERT INTO collection SELECT ...`
+    InsertSelect {
+        collection: String,
+        plan: LogicalPlan<'a>,
+    },
     Select(LogicalPlan<'a>),
     CreateCollection {
         collection: String,
     },
+    /// `CREATE COLLECTION collection AS SELECT ...`
+    CreateCollectionAs {
+        collection: String,
+        plan: LogicalPlan<'a>,
+    },
     DropCollection {
         collection: String,
     },
+    /// `CREATE [FULLTEXT] INDEX index ON collection (path)`
+    CreateIndex {
+        index: String,
+        collection: String,
+        path: Vec<String>,
+        kind: IndexKind,
+    },
+    DropIndex {
+        index: String,
+    },
     ShowCollections,
+    /// `EXPLAIN [ANALYZE] SELECT ...`
+    Explain {
+        plan: LogicalPlan<'a>,
+        analyze: bool,
+    },
+}
+
+impl<'a> Statement<'a> {
+    /// Returns the query plan of the statement, if it has one.
+    pub fn plan_mut(&mut self) -> Option<&mut LogicalPlan<'a>> {
+        match self {
+            Statement::InsertSelect { plan, .. }
+            | Statement::Select(plan)
+            | Statement::CreateCollectionAs { plan, .. }
+            | Statement::Explain { plan, .. } => Some(plan),
+            Statement::Insert { .. }
+            | Statement::CreateCollection { .. }
+            | Statement::DropCollection { .. }
+            | Statement::CreateIndex { .. }
+            | Statement::DropIndex { .. }
+            | Statement::ShowCollections => None,
+        }
+    }
+}
+
+/// A statement parsed once, to be bound to parameter values and executed any
+/// number of times.
+#[derive(Debug, Clone)]
+pub struct PreparedStatement<'a> {
+    statement: Statement<'a>,
+    parameter_count: usize,
+}
+
+impl<'a> PreparedStatement<'a> {
+    pub fn new(mut statement: Statement<'a>) -> Self {
+        let parameter_count = statement.plan_mut().map_or(0, |plan| {
+            plan.expressions_mut()
+                .into_iter()
+                .map(max_parameter)
+                .max()
+                .unwrap_or(0)
+        });
+        PreparedStatement {
+            statement,
+            parameter_count,
+        }
+    }
+
+    /// Returns the number of parameters, i.e. the highest `$n` used.
+    pub fn parameter_count(&self) -> usize {
+        self.parameter_count
+    }
+
+    /// Returns the statement with its parameters unbound.
+    pub fn statement(&self) -> &Statement<'a> {
+        &self.statement
+    }
+
+    /// Returns a copy of the statement with `$n` replaced by `params[n - 1]`.
+    pub fn bind(&self, params: &[Value]) -> Result<Statement<'a>, String> {
+        if params.len() != self.parameter_count {
+            return Err(format!(
+                "Expected {} parameters, got {}",
+                self.parameter_count,
+                params.len()
+            ));
+        }
+        let mut statement = self.statement.clone();
+        if let Some(plan) = statement.plan_mut() {
+            bind_plan(plan, params);
+        }
+        Ok(statement)
+    }
+}
+
+fn max_parameter(expr: &mut Expression) -> usize {
+    let own = match expr {
+        Expression::Parameter(n) => *n,
+        Expression::InSubquery { subquery, .. } | Expression::Exists { subquery, .. } => subquery
+            .expressions_mut()
+            .into_iter()
+            .map(max_parameter)
+            .max()
+            .unwrap_or(0),
+        _ => 0,
+    };
+    expr.children_mut()
+        .into_iter()
+        .map(max_parameter)
+        .fold(own, usize::max)
+}
+
+fn bind_plan(plan: &mut LogicalPlan, params: &[Value]) {
+    for expr in plan.expressions_mut() {
+        bind_expression(expr, params);
+    }
+}
+
+fn bind_expression(expr: &mut Expression, params: &[Value]) {
+    match expr {
+        Expression::Parameter(n) => *expr = Expression::Literal(params[*n - 1].clone()),
+        Expression::InSubquery { subquery, .. } | Expression::Exists { subquery, .. } => {
+            bind_plan(subquery, params)
+        }
+        _ => {}
+    }
+    for child in expr.children_mut() {
+        bind_expression(child, params);
+    }
 }
 
 // Iterator implementations for operators
@@ -82,15 +414,7 @@ impl<'a> Iterator for FilterOperator<'a> {
     type Item = ExecutionResult;
     fn next(&mut self) -> Option<Self::Item> {
         for item in self.child.by_ref() {
-            let keep = match &item {
-                ExecutionResult::Value(_, doc) => {
-                    evaluate_expression(&self.predicate, doc) == Value::Bool(true)
-                }
-                ExecutionResult::Lazy(doc) => {
-                    evaluate_expression_lazy(&self.predicate, doc) == Value::Bool(true)
-                }
-            };
-            if keep {
+            if evaluate_result(&self.predicate, &item) == Value::Bool(true) {
                 return Some(item);
             }
         }
@@ -117,25 +441,8 @@ impl<'a> Iterator for ProjectOperator<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(item) = self.child.next() {
             let id = item.id().to_string();
-            let mut new_doc = BTreeMap::new();
-            for expr in &self.projections {
-                let value = match &item {
-                    ExecutionResult::Value(_, doc) => evaluate_expression(expr, doc),
-                    ExecutionResult::Lazy(doc) => evaluate_expression_lazy(expr, doc),
-                };
-                match expr {
-                    Expression::FieldReference(_, raw) => {
-                        new_doc.insert(raw.to_string(), value);
-                    }
-                    Expression::JsonPath(_, raw) => {
-                        new_doc.insert(raw.to_string(), value);
-                    }
-                    _ => {
-                        // Fallback/TODO: Handle computed columns alias
-                    }
-                }
-            }
-            return Some(ExecutionResult::Value(id, Value::Object(new_doc)));
+            let new_doc = project(&self.projections, |expr| evaluate_result(expr, &item));
+            return Some(ExecutionResult::Value(id, new_doc));
         }
         None
     }
@@ -198,543 +505,3128 @@ impl<'a> Iterator for OffsetOperator<'a> {
     }
 }
 
-// Vectorized Execution
-
-const BATCH_SIZE: usize = 4096;
+/// Sorts its whole input by the total ordering of the sort keys. The input is
+/// consumed on the first call to `next`.
+pub struct SortOperator<'a> {
+    child: Option<Box<dyn Iterator<Item = ExecutionResult> + 'a>>,
+    keys: Vec<SortKey<'a>>,
+    sorted: std::vec::IntoIter<ExecutionResult>,
+}
 
-#[derive(Debug)]
-pub struct Batch {
-    pub items: Vec<ExecutionResult>,
+impl<'a> SortOperator<'a> {
+    pub fn new(
+        child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        keys: Vec<SortKey<'a>>,
+    ) -> Self {
+        SortOperator {
+            child: Some(child),
+            keys,
+            sorted: Vec::new().into_iter(),
+        }
+    }
 }
 
-impl Default for Batch {
-    fn default() -> Self {
-        Self::new()
+impl<'a> Iterator for SortOperator<'a> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(child) = self.child.take() {
+            let mut rows: Vec<(Vec<Value>, ExecutionResult)> = child
+                .map(|item| {
+                    let key_values = self
+                        .keys
+                        .iter()
+                        .map(|key| evaluate_result(&key.expr, &item))
+                        .collect();
+                    (key_values, item)
+                })
+                .collect();
+            rows.sort_by(|(a, _), (b, _)| compare_sort_keys(&self.keys, a, b));
+            self.sorted = rows
+                .into_iter()
+                .map(|(_, item)| item)
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+        self.sorted.next()
     }
 }
 
-impl Batch {
-    pub fn new() -> Self {
-        Batch {
-            items: Vec::with_capacity(BATCH_SIZE),
+/// Computes window functions over the whole input. For each function a
+/// `SortOperator` sorts the rows by its partition and sort keys, and the
+/// function's value for each row of each partition is added to the row under
+/// the window's name. Rows are output in the order of the last function.
+pub struct WindowOperator<'a> {
+    child: Option<Box<dyn Iterator<Item = ExecutionResult> + 'a>>,
+    windows: Vec<(WindowCall<'a>, &'a str)>,
+    rows: std::vec::IntoIter<ExecutionResult>,
+}
+
+impl<'a> WindowOperator<'a> {
+    pub fn new(
+        child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        windows: Vec<(WindowCall<'a>, &'a str)>,
+    ) -> Result<Self, String> {
+        for (call, _) in &windows {
+            if matches!(call.func, WindowFunction::Lag | WindowFunction::Lead) {
+                window_offset(call)?;
+            }
         }
+        Ok(WindowOperator {
+            child: Some(child),
+            windows,
+            rows: Vec::new().into_iter(),
+        })
     }
+}
 
-    pub fn from_vec(items: Vec<ExecutionResult>) -> Self {
-        Batch { items }
+impl Iterator for WindowOperator<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(child) = self.child.take() {
+            let mut rows: Vec<ExecutionResult> = child.collect();
+            for (call, name) in &self.windows {
+                rows = evaluate_window(call, name, rows);
+            }
+            self.rows = rows.into_iter();
+        }
+        self.rows.next()
     }
 }
 
-pub struct BatchScanOperator<'a> {
-    iter: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
-    batch_size: usize,
+/// Returns the offset of a `LAG` or `LEAD` call, which must be a
+/// non-negative integer literal, or 1 if not given.
+fn window_offset(call: &WindowCall) -> Result<usize, String> {
+    let offset = match call.args.get(1) {
+        None => return Ok(1),
+        Some(Expression::Literal(Value::Number(n))) => get_i64_from_number(n),
+        Some(_) => None,
+    };
+    offset
+        .and_then(|n| usize::try_from(n).ok())
+        .ok_or_else(|| format!("{} offset must be a non-negative integer", call.func.name()))
 }
 
-impl<'a> BatchScanOperator<'a> {
-    pub fn new(iter: Box<dyn Iterator<Item = ExecutionResult> + 'a>, batch_size: usize) -> Self {
-        BatchScanOperator { iter, batch_size }
+/// Compares partition key values, treating values equal under the total
+/// ordering as the same partition.
+fn compare_partition_keys(a: &[Value], b: &[Value]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .map(|(x, y)| total_cmp(x, y))
+        .find(|ord| ord.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Sorts the rows by a window function's partition and sort keys, and adds
+/// the function's value to each row under `name`.
+fn evaluate_window(
+    call: &WindowCall,
+    name: &str,
+    rows: Vec<ExecutionResult>,
+) -> Vec<ExecutionResult> {
+    let keys: Vec<SortKey> = call
+        .partition_by
+        .iter()
+        .map(|expr| SortKey {
+            expr: expr.clone(),
+            descending: false,
+            nulls_first: true,
+        })
+        .chain(call.order_by.iter().cloned())
+        .collect();
+    let mut rows: Vec<(String, Value)> = SortOperator::new(Box::new(rows.into_iter()), keys)
+        .map(|item| (item.id().to_string(), item.get_value()))
+        .collect();
+
+    let eval = |expr: &Expression, i: usize| evaluate_document(expr, Some(&rows[i].0), &rows[i].1);
+    let partition_keys: Vec<Vec<Value>> = (0..rows.len())
+        .map(|i| call.partition_by.iter().map(|e| eval(e, i)).collect())
+        .collect();
+    let sort_keys: Vec<Vec<Value>> = (0..rows.len())
+        .map(|i| call.order_by.iter().map(|key| eval(&key.expr, i)).collect())
+        .collect();
+    let order: Vec<usize> = (0..rows.len()).collect();
+    let mut results = Vec::with_capacity(rows.len());
+    for partition in order.chunk_by(|&a, &b| {
+        compare_partition_keys(&partition_keys[a], &partition_keys[b]) == Ordering::Equal
+    }) {
+        let peers = |a: usize, b: usize| {
+            compare_sort_keys(&call.order_by, &sort_keys[a], &sort_keys[b]) == Ordering::Equal
+        };
+        results.extend(evaluate_window_partition(call, partition, peers, eval));
+    }
+
+    for ((_, row), result) in rows.iter_mut().zip(results) {
+        if let Value::Object(fields) = row {
+            fields.insert(name.to_string(), result);
+        }
     }
+    rows.into_iter()
+        .map(|(id, row)| ExecutionResult::Value(id, row))
+        .collect()
 }
 
-impl<'a> Iterator for BatchScanOperator<'a> {
-    type Item = Batch;
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut batch = Batch {
-            items: Vec::with_capacity(self.batch_size),
+/// Computes a window function over one partition, given as row indices in
+/// sort order; `peers` tells whether two rows sort equal.
+fn evaluate_window_partition(
+    call: &WindowCall,
+    partition: &[usize],
+    peers: impl Fn(usize, usize) -> bool,
+    eval: impl Fn(&Expression, usize) -> Value,
+) -> Vec<Value> {
+    let n = partition.len();
+    // The first and one past the last position of each row's peer group
+    let mut peer_start = vec![0; n];
+    for pos in 1..n {
+        peer_start[pos] = if peers(partition[pos - 1], partition[pos]) {
+            peer_start[pos - 1]
+        } else {
+            pos
         };
-        for _ in 0..self.batch_size {
-            if let Some(item) = self.iter.next() {
-                batch.items.push(item);
+    }
+    let mut peer_end = vec![n; n];
+    for pos in (0..n.saturating_sub(1)).rev() {
+        peer_end[pos] = if peers(partition[pos], partition[pos + 1]) {
+            peer_end[pos + 1]
+        } else {
+            pos + 1
+        };
+    }
+    let int = |i: usize| Value::Number(Number::Int64(i as i64));
+
+    match call.func {
+        WindowFunction::RowNumber => (1..=n).map(int).collect(),
+        WindowFunction::Rank => peer_start.iter().map(|start| int(start + 1)).collect(),
+        WindowFunction::DenseRank => {
+            let mut rank = 0;
+            (0..n)
+                .map(|pos| {
+                    if peer_start[pos] == pos {
+                        rank += 1;
+                    }
+                    int(rank)
+                })
+                .collect()
+        }
+        WindowFunction::Lag | WindowFunction::Lead => {
+            let offset = window_offset(call).expect("offset checked by WindowOperator::new");
+            (0..n)
+                .map(|pos| {
+                    let target = if call.func == WindowFunction::Lag {
+                        pos.checked_sub(offset)
+                    } else {
+                        pos.checked_add(offset).filter(|&target| target < n)
+                    };
+                    match (target, call.args.get(2)) {
+                        (Some(target), _) => eval(&call.args[0], partition[target]),
+                        (None, Some(default)) => eval(default, partition[pos]),
+                        (None, None) => Value::Null,
+                    }
+                })
+                .collect()
+        }
+        _ => {
+            let frame = call.effective_frame();
+            let bounds: Vec<(usize, usize)> = (0..n)
+                .map(|pos| frame_bounds(&frame, pos, n, peer_start[pos], peer_end[pos]))
+                .collect();
+            let values: Vec<Value> = partition
+                .iter()
+                .map(|&row| match call.args.first() {
+                    Some(arg) => eval(arg, row),
+                    None => Value::Bool(true), // COUNT(*) counts every row
+                })
+                .collect();
+            evaluate_frame_aggregate(call.func, &values, &bounds)
+        }
+    }
+}
+
+/// Returns the positions `[start, end)` of a row's frame within its partition.
+fn frame_bounds(
+    frame: &WindowFrame,
+    pos: usize,
+    n: usize,
+    peer_start: usize,
+    peer_end: usize,
+) -> (usize, usize) {
+    let start = match frame.start {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(k) => pos.saturating_sub(k),
+        FrameBound::CurrentRow if frame.rows => pos,
+        FrameBound::CurrentRow => peer_start,
+        FrameBound::Following(k) => pos.saturating_add(k).min(n),
+        FrameBound::UnboundedFollowing => n,
+    };
+    let end = match frame.end {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(k) => (pos + 1).saturating_sub(k),
+        FrameBound::CurrentRow if frame.rows => pos + 1,
+        FrameBound::CurrentRow => peer_end,
+        FrameBound::Following(k) => pos.saturating_add(k).saturating_add(1).min(n),
+        FrameBound::UnboundedFollowing => n,
+    };
+    (start, end.max(start))
+}
+
+/// Running totals of a partition's values, so that a frame's count and sum
+/// are the difference of two entries.
+#[derive(Clone, Copy, Default)]
+struct FrameTotals {
+    count: usize,   // non-NULL values
+    numbers: usize, // numeric values
+    floats: usize,  // non-integer numeric values
+    int_sum: i128,
+    float_sum: f64,
+}
+
+/// Computes a frame-based window function for each row, given each row's
+/// argument value and frame. Frame bounds never move backwards, which lets
+/// `MIN` and `MAX` keep a monotonic queue of candidates.
+fn evaluate_frame_aggregate(
+    func: WindowFunction,
+    values: &[Value],
+    bounds: &[(usize, usize)],
+) -> Vec<Value> {
+    match func {
+        WindowFunction::FirstValue => bounds
+            .iter()
+            .map(|&(start, end)| {
+                if start < end {
+                    values[start].clone()
+                } else {
+                    Value::Null
+                }
+            })
+            .collect(),
+        WindowFunction::LastValue => bounds
+            .iter()
+            .map(|&(start, end)| {
+                if start < end {
+                    values[end - 1].clone()
+                } else {
+                    Value::Null
+                }
+            })
+            .collect(),
+        WindowFunction::Min | WindowFunction::Max => {
+            // Candidate positions, their values ordered from best to worst
+            let worse = if func == WindowFunction::Min {
+                Ordering::Greater
             } else {
-                break;
-            }
+                Ordering::Less
+            };
+            let mut candidates: VecDeque<usize> = VecDeque::new();
+            let mut next = 0;
+            bounds
+                .iter()
+                .map(|&(start, end)| {
+                    while next < end {
+                        if !matches!(values[next], Value::Null) {
+                            while let Some(&last) = candidates.back()
+                                && total_cmp(&values[next], &values[last]) != worse
+                            {
+                                candidates.pop_back();
+                            }
+                            candidates.push_back(next);
+                        }
+                        next += 1;
+                    }
+                    while candidates.front().is_some_and(|&first| first < start) {
+                        candidates.pop_front();
+                    }
+                    candidates
+                        .front()
+                        .map_or(Value::Null, |&best| values[best].clone())
+                })
+                .collect()
         }
-        if batch.items.is_empty() {
-            None
-        } else {
-            Some(batch)
+        _ => {
+            let mut totals = vec![FrameTotals::default()];
+            for value in values {
+                let mut next = *totals.last().unwrap();
+                match value {
+                    Value::Null => {}
+                    Value::Number(n) => {
+                        next.count += 1;
+                        next.numbers += 1;
+                        match get_i64_from_number(n) {
+                            Some(i) => next.int_sum += i as i128,
+                            None => {
+                                next.floats += 1;
+                                next.float_sum += get_f64_from_number(n).unwrap_or(0.0);
+                            }
+                        }
+                    }
+                    _ => next.count += 1,
+                }
+                totals.push(next);
+            }
+            bounds
+                .iter()
+                .map(|&(start, end)| {
+                    let (from, to) = (totals[start], totals[end]);
+                    let numbers = to.numbers - from.numbers;
+                    let int_sum = to.int_sum - from.int_sum;
+                    let sum = int_sum as f64 + (to.float_sum - from.float_sum);
+                    match func {
+                        WindowFunction::Count => {
+                            Value::Number(Number::Int64((to.count - from.count) as i64))
+                        }
+                        _ if numbers == 0 || !sum.is_finite() => Value::Null,
+                        WindowFunction::Avg => Value::Number(Number::Float64(sum / numbers as f64)),
+                        _ => match i64::try_from(int_sum) {
+                            Ok(i) if to.floats == from.floats => Value::Number(Number::Int64(i)),
+                            _ => Value::Number(Number::Float64(sum)),
+                        },
+                    }
+                })
+                .collect()
         }
     }
 }
 
-pub struct FlattenOperator<'a> {
-    input: Box<dyn Iterator<Item = Batch> + 'a>,
-    current_batch: Option<std::vec::IntoIter<ExecutionResult>>,
+/// Emits one row per element of an array, each a copy of the parent document
+/// with the element added under `alias`. Rows whose value is not an array, or
+/// is an empty array, produce nothing.
+pub struct UnnestOperator<'a> {
+    child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    expr: Expression<'a>,
+    alias: &'a str,
+    current: Option<(String, Value, std::vec::IntoIter<Value>)>,
 }
 
-impl<'a> FlattenOperator<'a> {
-    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>) -> Self {
-        FlattenOperator {
-            input,
-            current_batch: None,
+impl<'a> UnnestOperator<'a> {
+    pub fn new(
+        child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        expr: Expression<'a>,
+        alias: &'a str,
+    ) -> Self {
+        UnnestOperator {
+            child,
+            expr,
+            alias,
+            current: None,
         }
     }
 }
 
-impl<'a> Iterator for FlattenOperator<'a> {
+impl<'a> Iterator for UnnestOperator<'a> {
     type Item = ExecutionResult;
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if let Some(iter) = &mut self.current_batch
-                && let Some(item) = iter.next()
+            if let Some((id, parent, elements)) = &mut self.current
+                && let Some(element) = elements.next()
             {
-                return Some(item);
+                let mut fields = match parent {
+                    Value::Object(obj) => obj.clone(),
+                    _ => Default::default(),
+                };
+                fields.insert(self.alias.to_string(), element);
+                return Some(ExecutionResult::Value(id.clone(), Value::Object(fields)));
             }
-            // Need next batch
-            if let Some(batch) = self.input.next() {
-                self.current_batch = Some(batch.items.into_iter());
+            let item = self.child.next()?;
+            if let Value::Array(elements) = evaluate_result(&self.expr, &item) {
+                self.current = Some((
+                    item.id().to_string(),
+                    item.get_value(),
+                    elements.into_iter(),
+                ));
             } else {
-                return None;
+                self.current = None;
             }
         }
     }
 }
 
-pub struct BatchLimitOperator<'a> {
-    input: Box<dyn Iterator<Item = Batch> + 'a>,
-    limit: usize,
-    count: usize,
+/// Number of partitions a hash join splits both inputs into when it spills.
+const SPILL_PARTITIONS: usize = 16;
+
+/// Returns a row's document with its ID added as `_id`, so that `alias._id`
+/// can be referenced once joined.
+fn document_with_id(item: &ExecutionResult) -> Value {
+    let mut doc = item.get_value();
+    if let Value::Object(fields) = &mut doc {
+        fields.insert(
+            ID_FIELD.to_string(),
+            Value::String(item.id().to_string().into()),
+        );
+    }
+    doc
 }
 
-impl<'a> BatchLimitOperator<'a> {
-    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, limit: usize) -> Self {
-        BatchLimitOperator {
-            input,
-            limit,
-            count: 0,
-        }
+/// Returns a join input row: the document keyed by `alias`, or without an
+/// alias, the row of an inner join as is.
+fn join_input_row(alias: Option<&str>, item: &ExecutionResult) -> Value {
+    match alias {
+        Some(alias) => join_output_row(&Value::Null, alias, document_with_id(item)),
+        None => item.get_value(),
     }
 }
 
-impl<'a> Iterator for BatchLimitOperator<'a> {
-    type Item = Batch;
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.count >= self.limit {
+/// Adds the right document (or `NULL` for an unmatched left row) to a left row.
+fn join_output_row(left: &Value, right_alias: &str, right: Value) -> Value {
+    let mut fields = match left {
+        Value::Object(fields) => fields.clone(),
+        _ => Default::default(),
+    };
+    fields.insert(right_alias.to_string(), right);
+    Value::Object(fields)
+}
+
+/// Returns a hash key for a value, the same for all values equal under `=` or
+/// the total ordering.
+fn value_key(value: &Value) -> String {
+    let mut key = String::new();
+    write_value_key(value, &mut key);
+    key
+}
+
+/// Returns the hash table key for a row's join key values, or `None` if any
+/// is `NULL` since `NULL` never joins.
+///
+/// Values that are equal under `=` share a key: numbers are normalized across
+/// integer and float representations and timestamps to their instant. The key
+/// may be coarser than `=`, so matches are checked with `=` afterwards.
+fn join_hash_key(values: &[Value]) -> Option<String> {
+    let mut key = String::new();
+    for value in values {
+        if matches!(value, Value::Null) {
             return None;
         }
-        let mut batch = self.input.next()?;
-        let remaining = self.limit - self.count;
-        if batch.items.len() > remaining {
-            batch.items.truncate(remaining);
+        write_value_key(value, &mut key);
+        key.push('\u{0}');
+    }
+    Some(key)
+}
+
+fn write_value_key(value: &Value, out: &mut String) {
+    match value {
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => {
+            let f = get_f64_from_number(n).unwrap_or(f64::NAN);
+            // -0.0 = 0.0
+            let _ = write!(out, "{}", if f == 0.0 { 0.0 } else { f });
         }
-        self.count += batch.items.len();
-        if batch.items.is_empty() {
-            return None;
+        Value::String(s) => match temporal::parse_timestamp(s) {
+            Some(ts) => {
+                out.push('@');
+                out.push_str(&temporal::format_timestamp(&ts));
+            }
+            None => out.push_str(&serde_json::Value::String(s.to_string()).to_string()),
+        },
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_value_key(item, out);
+            }
+            out.push(']');
         }
-        Some(batch)
+        Value::Object(fields) => {
+            out.push('{');
+            for (i, (name, item)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::Value::String(name.clone()).to_string());
+                out.push(':');
+                write_value_key(item, out);
+            }
+            out.push('}');
+        }
+        _ => out.push_str("null"),
     }
 }
 
-pub struct BatchOffsetOperator<'a> {
+/// Evaluates a row's join key expressions.
+fn join_key_values(exprs: &[Expression], row: &Value) -> Vec<Value> {
+    exprs
+        .iter()
+        .map(|expr| evaluate_expression(expr, row))
+        .collect()
+}
+
+fn join_keys_equal(left: &[Value], right: &[Value]) -> bool {
+    left.iter()
+        .zip(right)
+        .all(|(l, r)| evaluate_comparison(l, &BinaryOperator::Eq, r) == Value::Bool(true))
+}
+
+fn spill_partition(key: &str) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % SPILL_PARTITIONS as u64) as usize
+}
+
+/// An anonymous temporary file of length-prefixed `[key, id, row]` records,
+/// written by an operator whose state does not fit in memory.
+struct SpillFile {
+    writer: BufWriter<File>,
+}
+
+impl SpillFile {
+    fn new(db: &DB) -> Result<Self, String> {
+        Ok(SpillFile {
+            writer: BufWriter::new(db.spill_file()?),
+        })
+    }
+
+    fn push(&mut self, key: Option<&str>, id: &str, row: &Value) -> Result<(), String> {
+        let record = (key, id, SerdeWrapper(row));
+        let blob = jsonb_schema::to_owned_jsonb(&record).map_err(|e| e.to_string())?;
+        let bytes = blob.to_vec();
+        self.writer
+            .write_all(&(bytes.len() as u32).to_le_bytes())
+            .and_then(|_| self.writer.write_all(&bytes))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Rewinds the file for reading back the records written so far.
+    fn into_reader(self) -> Result<SpillReader, String> {
+        let mut file = self.writer.into_inner().map_err(|e| e.to_string())?;
+        file.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+        Ok(SpillReader {
+            reader: BufReader::new(file),
+        })
+    }
+}
+
+/// A `[key, id, row]` record read back from a spill file.
+type SpillRecord = (Option<String>, String, Value);
+
+/// Reads back the records of a spill file.
+struct SpillReader {
+    reader: BufReader<File>,
+}
+
+impl SpillReader {
+    fn read_record(&mut self) -> Result<Option<SpillRecord>, String> {
+        let read_error = |e: std::io::Error| format!("Failed to read spill file: {}", e);
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(read_error(e)),
+        }
+        let mut bytes = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.reader.read_exact(&mut bytes).map_err(read_error)?;
+        let record = jsonb_schema::from_slice(&bytes).map_err(|_| "Corrupt spill file")?;
+        match make_static(&record) {
+            Value::Array(mut fields) if fields.len() == 3 => {
+                let row = fields.pop().unwrap();
+                let id = match fields.pop().unwrap() {
+                    Value::String(id) => id.to_string(),
+                    _ => String::new(),
+                };
+                let key = match fields.pop().unwrap() {
+                    Value::String(key) => Some(key.to_string()),
+                    _ => None,
+                };
+                Ok(Some((key, id, row)))
+            }
+            _ => Err("Corrupt spill file".to_string()),
+        }
+    }
+}
+
+impl Iterator for SpillReader {
+    type Item = Result<SpillRecord, String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Where a hash join reads its probe (left) rows from: the left input
+/// directly, or once spilled, one partition file at a time.
+enum ProbeSource<'a> {
+    Input(Box<dyn Iterator<Item = ExecutionResult> + 'a>),
+    Spilled {
+        partitions: std::vec::IntoIter<(SpillFile, SpillFile)>, // (build, probe)
+        current: Option<SpillReader>,
+    },
+}
+
+/// Joins its left input with its right input on equal keys by building a hash
+/// table of the right rows and probing it with each left row.
+///
+/// If the right input has more rows than the DB's join spill threshold, both
+/// inputs are partitioned by key into temporary files and joined one
+/// partition at a time, so only one build partition is in memory at once.
+pub struct HashJoinOperator<'a> {
+    probe: ProbeSource<'a>,
+    table: HashMap<String, Vec<(Vec<Value>, Value)>>, // key -> (key values, right row)
+    kind: JoinKind,
+    left_alias: Option<&'a str>,
+    right_alias: &'a str,
+    left_keys: Vec<Expression<'a>>,
+    right_keys: Vec<Expression<'a>>,
+    residual: Option<Expression<'a>>,
+    pending: VecDeque<ExecutionResult>,
+    errors: ErrorSlot,
+}
+
+impl<'a> HashJoinOperator<'a> {
+    /// Builds the hash table from `right`, spilling both inputs if it grows
+    /// past the threshold.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        left: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        right: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        kind: JoinKind,
+        left_alias: Option<&'a str>,
+        right_alias: &'a str,
+        keys: Vec<(Expression<'a>, Expression<'a>)>,
+        residual: Option<Expression<'a>>,
+        db: &'a DB,
+        errors: ErrorSlot,
+    ) -> Result<Self, String> {
+        let (left_keys, right_keys): (Vec<_>, Vec<_>) = keys.into_iter().unzip();
+        let mut table: HashMap<String, Vec<(Vec<Value>, Value)>> = HashMap::new();
+        let mut rows = 0;
+        let mut right = right.peekable();
+        while rows < db.join_spill_threshold()
+            && let Some(item) = right.next()
+        {
+            let row = join_input_row(Some(right_alias), &item);
+            let values = join_key_values(&right_keys, &row);
+            if let Some(key) = join_hash_key(&values) {
+                table.entry(key).or_default().push((values, row));
+                rows += 1;
+            }
+        }
+
+        let probe = if right.peek().is_none() {
+            ProbeSource::Input(left)
+        } else {
+            // Too large: partition the buffered and remaining right rows, then the left rows
+            let mut build = (0..SPILL_PARTITIONS)
+                .map(|_| SpillFile::new(db))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (key, entries) in table.drain() {
+                let partition = &mut build[spill_partition(&key)];
+                for (_, row) in entries {
+                    partition.push(Some(&key), "", &row)?;
+                }
+            }
+            for item in right {
+                let row = join_input_row(Some(right_alias), &item);
+                if let Some(key) = join_hash_key(&join_key_values(&right_keys, &row)) {
+                    build[spill_partition(&key)].push(Some(&key), "", &row)?;
+                }
+            }
+            let mut probe = (0..SPILL_PARTITIONS)
+                .map(|_| SpillFile::new(db))
+                .collect::<Result<Vec<_>, _>>()?;
+            for item in left {
+                let row = join_input_row(left_alias, &item);
+                let key = join_hash_key(&join_key_values(&left_keys, &row));
+                // Rows with a NULL key match nothing; any partition will do
+                let partition = key.as_deref().map_or(0, spill_partition);
+                probe[partition].push(key.as_deref(), item.id(), &row)?;
+            }
+            ProbeSource::Spilled {
+                partitions: build.into_iter().zip(probe).collect::<Vec<_>>().into_iter(),
+                current: None,
+            }
+        };
+
+        Ok(HashJoinOperator {
+            probe,
+            table,
+            kind,
+            left_alias,
+            right_alias,
+            left_keys,
+            right_keys,
+            residual,
+            pending: VecDeque::new(),
+            errors,
+        })
+    }
+
+    /// Returns the next probe row as (id, row, key values), loading the next
+    /// spilled partition into the hash table when one is exhausted.
+    fn next_probe(&mut self) -> Result<Option<(String, Value, Vec<Value>)>, String> {
+        match &mut self.probe {
+            ProbeSource::Input(input) => {
+                let Some(item) = input.next() else {
+                    return Ok(None);
+                };
+                let row = join_input_row(self.left_alias, &item);
+                let values = join_key_values(&self.left_keys, &row);
+                Ok(Some((item.id().to_string(), row, values)))
+            }
+            ProbeSource::Spilled {
+                partitions,
+                current,
+            } => loop {
+                if let Some(reader) = current
+                    && let Some(record) = reader.next()
+                {
+                    let (_, id, row) = record?;
+                    let values = join_key_values(&self.left_keys, &row);
+                    return Ok(Some((id, row, values)));
+                }
+                let Some((build, probe)) = partitions.next() else {
+                    return Ok(None);
+                };
+                self.table.clear();
+                for record in build.into_reader()? {
+                    let (key, _, row) = record?;
+                    let values = join_key_values(&self.right_keys, &row);
+                    self.table
+                        .entry(key.unwrap_or_default())
+                        .or_default()
+                        .push((values, row));
+                }
+                *current = Some(probe.into_reader()?);
+            },
+        }
+    }
+}
+
+impl Iterator for HashJoinOperator<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.pending.pop_front() {
+                return Some(item);
+            }
+            let (id, row, values) = match self.next_probe() {
+                Ok(probe) => probe?,
+                Err(e) => {
+                    self.errors.report(e);
+                    self.probe = ProbeSource::Input(Box::new(std::iter::empty()));
+                    return None;
+                }
+            };
+            let mut matched = false;
+            if let Some(key) = join_hash_key(&values)
+                && let Some(entries) = self.table.get(&key)
+            {
+                for (right_values, right_row) in entries {
+                    if !join_keys_equal(&values, right_values) {
+                        continue;
+                    }
+                    let out = join_output_row(&row, self.right_alias, right_row.clone());
+                    if join_residual_holds(self.residual.as_ref(), &out) {
+                        matched = true;
+                        self.pending
+                            .push_back(ExecutionResult::Value(id.clone(), out));
+                    }
+                }
+            }
+            if !matched && self.kind == JoinKind::Left {
+                let out = join_output_row(&row, self.right_alias, Value::Null);
+                self.pending.push_back(ExecutionResult::Value(id, out));
+            }
+        }
+    }
+}
+
+fn join_residual_holds(residual: Option<&Expression>, row: &Value) -> bool {
+    residual.is_none_or(|p| evaluate_expression(p, row) == Value::Bool(true))
+}
+
+/// Joins each left row with the document of the right collection whose `_id`
+/// equals the left key, fetched with a point lookup.
+pub struct IndexJoinOperator<'a> {
+    child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    db: &'a DB,
+    collection: String,
+    kind: JoinKind,
+    left_alias: Option<&'a str>,
+    right_alias: &'a str,
+    left_key: Expression<'a>,
+    residual: Option<Expression<'a>>,
+    errors: ErrorSlot,
+}
+
+impl<'a> IndexJoinOperator<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        child: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        db: &'a DB,
+        collection: String,
+        kind: JoinKind,
+        left_alias: Option<&'a str>,
+        right_alias: &'a str,
+        left_key: Expression<'a>,
+        residual: Option<Expression<'a>>,
+        errors: ErrorSlot,
+    ) -> Result<Self, String> {
+        if !db.has_collection(&collection) {
+            return Err(format!("Collection '{}' not found", collection));
+        }
+        Ok(IndexJoinOperator {
+            child,
+            db,
+            collection,
+            kind,
+            left_alias,
+            right_alias,
+            left_key,
+            residual,
+            errors,
+        })
+    }
+}
+
+impl Iterator for IndexJoinOperator<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let item = self.child.next()?;
+            let row = join_input_row(self.left_alias, &item);
+            let doc = match evaluate_expression(&self.left_key, &row) {
+                Value::String(key) => match self.db.get(&self.collection, &key) {
+                    Ok(doc) => doc.map(|doc| (key, doc)),
+                    Err(e) => {
+                        self.errors.report(e);
+                        self.child = Box::new(std::iter::empty());
+                        return None;
+                    }
+                },
+                _ => None,
+            };
+            if let Some((key, doc)) = doc {
+                let right = document_with_id(&ExecutionResult::Value(key.to_string(), doc));
+                let out = join_output_row(&row, self.right_alias, right);
+                if join_residual_holds(self.residual.as_ref(), &out) {
+                    return Some(ExecutionResult::Value(item.id().to_string(), out));
+                }
+            }
+            if self.kind == JoinKind::Left {
+                let out = join_output_row(&row, self.right_alias, Value::Null);
+                return Some(ExecutionResult::Value(item.id().to_string(), out));
+            }
+        }
+    }
+}
+
+/// A multiset of values, where values are the same if they are equal under
+/// the total ordering (so `1` and `1.0` are the same, and so are two `NULL`s).
+#[derive(Default)]
+pub struct ValueCounts {
+    buckets: HashMap<String, Vec<(Value, usize)>>,
+}
+
+impl ValueCounts {
+    /// Adds a value, returning how many times it is now present.
+    pub fn insert(&mut self, value: Value) -> usize {
+        let bucket = self.buckets.entry(value_key(&value)).or_default();
+        match bucket
+            .iter_mut()
+            .find(|(v, _)| total_cmp(v, &value) == Ordering::Equal)
+        {
+            Some((_, count)) => {
+                *count += 1;
+                *count
+            }
+            None => {
+                bucket.push((value, 1));
+                1
+            }
+        }
+    }
+
+    /// Returns true if the value is present.
+    pub fn contains(&self, value: &Value) -> bool {
+        self.buckets.get(&value_key(value)).is_some_and(|bucket| {
+            bucket
+                .iter()
+                .any(|(v, count)| *count > 0 && total_cmp(v, value) == Ordering::Equal)
+        })
+    }
+
+    /// Removes one occurrence of the value, returning false if it was absent.
+    pub fn remove(&mut self, value: &Value) -> bool {
+        let Some(bucket) = self.buckets.get_mut(&value_key(value)) else {
+            return false;
+        };
+        match bucket
+            .iter_mut()
+            .find(|(v, count)| *count > 0 && total_cmp(v, value) == Ordering::Equal)
+        {
+            Some((_, count)) => {
+                *count -= 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Applies a set operation to two inputs. `UNION ALL` streams both inputs;
+/// otherwise the rows seen so far, and for `INTERSECT` and `EXCEPT` all the
+/// right rows, are kept in memory.
+pub struct SetOperationOperator<'a> {
+    left: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    right: Option<Box<dyn Iterator<Item = ExecutionResult> + 'a>>, // UNION only
+    right_rows: ValueCounts,
+    emitted: ValueCounts,
+    op: SetOperator,
+    all: bool,
+}
+
+impl<'a> SetOperationOperator<'a> {
+    pub fn new(
+        left: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        right: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        op: SetOperator,
+        all: bool,
+    ) -> Self {
+        let mut right_rows = ValueCounts::default();
+        let right = if op == SetOperator::Union {
+            Some(right)
+        } else {
+            for item in right {
+                right_rows.insert(item.get_value());
+            }
+            None
+        };
+        SetOperationOperator {
+            left,
+            right,
+            right_rows,
+            emitted: ValueCounts::default(),
+            op,
+            all,
+        }
+    }
+}
+
+impl Iterator for SetOperationOperator<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(item) = self.left.next() else {
+                self.left = self.right.take()?;
+                continue;
+            };
+            let row = item.get_value();
+            let keep = match (self.op, self.all) {
+                (SetOperator::Union, _) => true,
+                (SetOperator::Intersect, true) => self.right_rows.remove(&row),
+                (SetOperator::Intersect, false) => self.right_rows.contains(&row),
+                (SetOperator::Except, true) => !self.right_rows.remove(&row),
+                (SetOperator::Except, false) => !self.right_rows.contains(&row),
+            };
+            if !keep {
+                continue;
+            }
+            if self.all || self.emitted.insert(row.clone()) == 1 {
+                return Some(ExecutionResult::Value(item.id().to_string(), row));
+            }
+        }
+    }
+}
+
+/// Removes duplicate rows, keeping the first occurrence of each key (the
+/// whole row, or the values of the `DISTINCT ON` expressions).
+///
+/// Rows are streamed while the DB's distinct spill threshold allows more keys
+/// in memory. The rest of the input is then partitioned by key into temporary
+/// files, each partition is deduplicated in turn, and the partitions' rows are
+/// merged back in input order.
+pub struct DistinctOperator<'a> {
+    input: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    on: Vec<Expression<'a>>,
+    seen: ValueCounts,
+    distinct: usize,
+    db: &'a DB,
+    spilled: Option<Vec<std::iter::Peekable<SpillReader>>>, // deduplicated partitions
+    errors: ErrorSlot,
+}
+
+impl<'a> DistinctOperator<'a> {
+    pub fn new(
+        input: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+        on: Vec<Expression<'a>>,
+        db: &'a DB,
+        errors: ErrorSlot,
+    ) -> Self {
+        DistinctOperator {
+            input,
+            on,
+            seen: ValueCounts::default(),
+            distinct: 0,
+            db,
+            spilled: None,
+            errors,
+        }
+    }
+
+    /// Partitions `first` and the rest of the input whose keys are not yet
+    /// seen, then deduplicates each partition into a file of `[seq, row]`.
+    fn spill(&mut self, first: (String, Value, Value)) -> Result<(), String> {
+        let mut partitions = (0..SPILL_PARTITIONS)
+            .map(|_| SpillFile::new(self.db))
+            .collect::<Result<Vec<_>, _>>()?;
+        let rest = Some(first).into_iter().chain(std::iter::from_fn(|| {
+            self.input.next().map(|item| {
+                let row = item.get_value();
+                let key = distinct_key(&self.on, item.id(), &row);
+                (item.id().to_string(), row, key)
+            })
+        }));
+        let mut seq = 0u64;
+        for (id, row, key) in rest {
+            if self.seen.contains(&key) {
+                continue;
+            }
+            let partition = spill_partition(&value_key(&key));
+            let record = Value::Array(vec![Value::Number(Number::UInt64(seq)), key, row]);
+            partitions[partition].push(None, &id, &record)?;
+            seq += 1;
+        }
+        self.seen = ValueCounts::default();
+
+        let mut deduplicated = Vec::new();
+        for partition in partitions {
+            let mut seen = ValueCounts::default();
+            let mut output = SpillFile::new(self.db)?;
+            for record in partition.into_reader()? {
+                let (_, id, record) = record?;
+                let Value::Array(mut fields) = record else {
+                    continue;
+                };
+                let row = fields.pop().unwrap_or(Value::Null);
+                let key = fields.pop().unwrap_or(Value::Null);
+                if seen.insert(key) == 1 {
+                    fields.push(row);
+                    output.push(None, &id, &Value::Array(fields))?;
+                }
+            }
+            deduplicated.push(output.into_reader()?.peekable());
+        }
+        self.spilled = Some(deduplicated);
+        Ok(())
+    }
+}
+
+/// Returns a row's `DISTINCT` key: the row itself, or its `on` values.
+fn distinct_key(on: &[Expression], id: &str, row: &Value) -> Value {
+    if on.is_empty() {
+        row.clone()
+    } else {
+        Value::Array(
+            on.iter()
+                .map(|expr| evaluate_document(expr, Some(id), row))
+                .collect(),
+        )
+    }
+}
+
+/// Returns the sequence number of a spilled `[seq, ..]` record.
+fn record_seq(record: &Value) -> u64 {
+    match record {
+        Value::Array(fields) => match &fields[0] {
+            Value::Number(n) => get_f64_from_number(n).unwrap_or(0.0) as u64,
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+impl Iterator for DistinctOperator<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(partitions) = &mut self.spilled {
+            // Merge the partitions by sequence number to restore input order
+            let mut next: Option<(u64, usize)> = None;
+            for (i, partition) in partitions.iter_mut().enumerate() {
+                match partition.peek() {
+                    Some(Ok((_, _, record))) => {
+                        let seq = record_seq(record);
+                        if next.is_none_or(|(min, _)| seq < min) {
+                            next = Some((seq, i));
+                        }
+                    }
+                    Some(Err(e)) => {
+                        self.errors.report(e.clone());
+                        return None;
+                    }
+                    None => {}
+                }
+            }
+            let (_, id, record) = partitions[next?.1].next()?.ok()?;
+            let row = match record {
+                Value::Array(mut fields) => fields.pop().unwrap_or(Value::Null),
+                other => other,
+            };
+            return Some(ExecutionResult::Value(id, row));
+        }
+        loop {
+            let item = self.input.next()?;
+            let row = item.get_value();
+            let key = distinct_key(&self.on, item.id(), &row);
+            if self.seen.contains(&key) {
+                continue;
+            }
+            if self.distinct < self.db.distinct_spill_threshold() {
+                self.seen.insert(key);
+                self.distinct += 1;
+                return Some(ExecutionResult::Value(item.id().to_string(), row));
+            }
+            if let Err(e) = self.spill((item.id().to_string(), row, key)) {
+                self.errors.report(e);
+                // No partitions: the rows end here
+                self.spilled = Some(Vec::new());
+                return None;
+            }
+            return self.next();
+        }
+    }
+}
+
+/// Replaces the uncorrelated subqueries in a plan's expressions by their
+/// results: `x IN (SELECT ..)` by an `IN` list of the subquery's values and
+/// `EXISTS (SELECT ..)` by a boolean. Fails on parameters left unbound.
+pub(crate) fn resolve_subqueries<'a>(plan: &mut LogicalPlan<'a>, db: &'a DB) -> Result<(), String> {
+    for expr in plan.expressions_mut() {
+        resolve_expression_subqueries(expr, db)?;
+    }
+    Ok(())
+}
+
+fn resolve_expression_subqueries<'a>(expr: &mut Expression<'a>, db: &'a DB) -> Result<(), String> {
+    if let Expression::Parameter(n) = expr {
+        return Err(format!("No value bound for parameter ${}", n));
+    }
+    if matches!(
+        expr,
+        Expression::InSubquery { .. } | Expression::Exists { .. }
+    ) {
+        *expr = match std::mem::replace(expr, Expression::Wildcard) {
+            Expression::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let mut list = Vec::new();
+                let mut rows = execute_plan(*subquery, db)?;
+                for item in rows.by_ref() {
+                    match item.get_value() {
+                        Value::Object(fields) if fields.len() == 1 => {
+                            list.extend(fields.into_values().map(Expression::Literal))
+                        }
+                        _ => return Err("Subquery must return exactly one column".to_string()),
+                    }
+                }
+                rows.check()?;
+                Expression::InList {
+                    expr,
+                    list,
+                    negated,
+                }
+            }
+            Expression::Exists { subquery, negated } => {
+                let mut rows = execute_plan(*subquery, db)?;
+                let exists = rows.next().is_some();
+                rows.check()?;
+                Expression::Literal(Value::Bool(exists != negated))
+            }
+            other => other,
+        };
+    }
+    for child in expr.children_mut() {
+        resolve_expression_subqueries(child, db)?;
+    }
+    Ok(())
+}
+
+/// Compares two rows' evaluated sort keys, honouring direction and null placement.
+pub fn compare_sort_keys(keys: &[SortKey], a: &[Value], b: &[Value]) -> Ordering {
+    for ((key, x), y) in keys.iter().zip(a).zip(b) {
+        let ord = match (matches!(x, Value::Null), matches!(y, Value::Null)) {
+            (true, true) => Ordering::Equal,
+            (true, false) if key.nulls_first => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) if key.nulls_first => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) if key.descending => total_cmp(x, y).reverse(),
+            (false, false) => total_cmp(x, y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+// Vectorized Execution
+
+const BATCH_SIZE: usize = 4096;
+
+#[derive(Debug)]
+pub struct Batch {
+    pub items: Vec<ExecutionResult>,
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Batch {
+            items: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    pub fn from_vec(items: Vec<ExecutionResult>) -> Self {
+        Batch { items }
+    }
+}
+
+pub struct BatchScanOperator<'a> {
+    iter: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    batch_size: usize,
+}
+
+impl<'a> BatchScanOperator<'a> {
+    pub fn new(iter: Box<dyn Iterator<Item = ExecutionResult> + 'a>, batch_size: usize) -> Self {
+        BatchScanOperator { iter, batch_size }
+    }
+}
+
+impl<'a> Iterator for BatchScanOperator<'a> {
+    type Item = Batch;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch = Batch {
+            items: Vec::with_capacity(self.batch_size),
+        };
+        for _ in 0..self.batch_size {
+            if let Some(item) = self.iter.next() {
+                batch.items.push(item);
+            } else {
+                break;
+            }
+        }
+        if batch.items.is_empty() {
+            None
+        } else {
+            Some(batch)
+        }
+    }
+}
+
+pub struct FlattenOperator<'a> {
+    input: Box<dyn Iterator<Item = Batch> + 'a>,
+    current_batch: Option<std::vec::IntoIter<ExecutionResult>>,
+}
+
+impl<'a> FlattenOperator<'a> {
+    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>) -> Self {
+        FlattenOperator {
+            input,
+            current_batch: None,
+        }
+    }
+}
+
+impl<'a> Iterator for FlattenOperator<'a> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(iter) = &mut self.current_batch
+                && let Some(item) = iter.next()
+            {
+                return Some(item);
+            }
+            // Need next batch
+            if let Some(batch) = self.input.next() {
+                self.current_batch = Some(batch.items.into_iter());
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+pub struct BatchLimitOperator<'a> {
+    input: Box<dyn Iterator<Item = Batch> + 'a>,
+    limit: usize,
+    count: usize,
+}
+
+impl<'a> BatchLimitOperator<'a> {
+    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, limit: usize) -> Self {
+        BatchLimitOperator {
+            input,
+            limit,
+            count: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for BatchLimitOperator<'a> {
+    type Item = Batch;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.count >= self.limit {
+            return None;
+        }
+        let mut batch = self.input.next()?;
+        let remaining = self.limit - self.count;
+        if batch.items.len() > remaining {
+            batch.items.truncate(remaining);
+        }
+        self.count += batch.items.len();
+        if batch.items.is_empty() {
+            return None;
+        }
+        Some(batch)
+    }
+}
+
+pub struct BatchOffsetOperator<'a> {
+    input: Box<dyn Iterator<Item = Batch> + 'a>,
+    offset: usize,
+    skipped: usize,
+}
+
+impl<'a> BatchOffsetOperator<'a> {
+    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, offset: usize) -> Self {
+        BatchOffsetOperator {
+            input,
+            offset,
+            skipped: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for BatchOffsetOperator<'a> {
+    type Item = Batch;
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.skipped < self.offset {
+            let mut batch = self.input.next()?;
+            let needed = self.offset - self.skipped;
+            if batch.items.len() <= needed {
+                self.skipped += batch.items.len();
+                continue;
+            } else {
+                batch.items.drain(0..needed);
+                self.skipped += needed;
+                return Some(batch);
+            }
+        }
+        self.input.next()
+    }
+}
+
+pub struct BatchFilterOperator<'a> {
+    input: Box<dyn Iterator<Item = Batch> + 'a>,
+    predicate: Expression<'a>,
+    buf_values: Vec<f64>,
+    buf_valid: Vec<bool>,
+    buf_right_values: Vec<f64>,
+    buf_right_valid: Vec<bool>,
+}
+
+impl<'a> BatchFilterOperator<'a> {
+    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, predicate: Expression<'a>) -> Self {
+        BatchFilterOperator {
+            input,
+            predicate,
+            buf_values: Vec::with_capacity(BATCH_SIZE),
+            buf_valid: Vec::with_capacity(BATCH_SIZE),
+            buf_right_values: Vec::with_capacity(BATCH_SIZE),
+            buf_right_valid: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    fn filter_batch(&mut self, batch: &mut Batch) {
+        if let Expression::Binary { left, op, right } = &self.predicate
+            && is_vectorizable_predicate(&self.predicate)
+        {
+            let left_exact = evaluate_numeric_batch(
+                left,
+                &batch.items,
+                &mut self.buf_values,
+                &mut self.buf_valid,
+            );
+            let right_exact = evaluate_numeric_batch(
+                right,
+                &batch.items,
+                &mut self.buf_right_values,
+                &mut self.buf_right_valid,
+            );
+            if !(left_exact && right_exact) {
+                self.fallback_filter(batch);
+                return;
+            }
+
+            let mut i = 0;
+            let (l_values, l_valid) = (&self.buf_values, &self.buf_valid);
+            let (r_values, r_valid) = (&self.buf_right_values, &self.buf_right_valid);
+
+            batch.items.retain(|_| {
+                let valid = l_valid[i] && r_valid[i];
+                let (l, r) = (l_values[i], r_values[i]);
+                let keep = match op {
+                    BinaryOperator::Gt => valid && l > r,
+                    BinaryOperator::Lt => valid && l < r,
+                    BinaryOperator::Gte => valid && l >= r,
+                    BinaryOperator::Lte => valid && l <= r,
+                    BinaryOperator::Eq => valid && l == r,
+                    _ => false,
+                };
+                i += 1;
+                keep
+            });
+            return;
+        }
+        self.fallback_filter(batch);
+    }
+
+    fn fallback_filter(&self, batch: &mut Batch) {
+        batch
+            .items
+            .retain(|item| evaluate_result(&self.predicate, item) == Value::Bool(true));
+    }
+}
+
+impl<'a> Iterator for BatchFilterOperator<'a> {
+    type Item = Batch;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut batch = self.input.next()?;
+            self.filter_batch(&mut batch);
+            if !batch.items.is_empty() {
+                return Some(batch);
+            }
+        }
+    }
+}
+
+/// Returns true if `expr` is built only from numeric fields, numeric literals,
+/// arithmetic operators and negation, so it can be evaluated column-wise.
+fn is_numeric_expr(expr: &Expression) -> bool {
+    match expr {
+        Expression::FieldReference(parts, _) => !is_id_reference(parts),
+        Expression::Literal(Value::Number(_)) => true,
+        Expression::Binary { left, op, right } => {
+            op.is_arithmetic() && is_numeric_expr(left) && is_numeric_expr(right)
+        }
+        Expression::Unary {
+            op: UnaryOperator::Neg,
+            expr,
+        } => is_numeric_expr(expr),
+        _ => false,
+    }
+}
+
+/// Returns true if a filter predicate is a numeric comparison that
+/// `BatchFilterOperator` can evaluate without the row-at-a-time fallback.
+fn is_vectorizable_predicate(predicate: &Expression) -> bool {
+    match predicate {
+        Expression::Binary { left, op, right } => {
+            op.is_comparison()
+                && *op != BinaryOperator::Neq
+                && is_numeric_expr(left)
+                && is_numeric_expr(right)
+        }
+        _ => false,
+    }
+}
+
+/// Magnitude from which not every integer is representable as an `f64`.
+const MAX_EXACT_F64: f64 = 9_007_199_254_740_992.0; // 2^53
+
+/// Evaluates a numeric expression over a batch into `values`, with `valid`
+/// marking the rows whose result is not `NULL`.
+///
+/// Returns false if any value reached a magnitude where `f64` arithmetic may
+/// differ from the row evaluator's exact integer semantics; the caller must
+/// then evaluate the batch row by row.
+fn evaluate_numeric_batch(
+    expr: &Expression,
+    items: &[ExecutionResult],
+    values: &mut Vec<f64>,
+    valid: &mut Vec<bool>,
+) -> bool {
+    values.clear();
+    valid.clear();
+    match expr {
+        Expression::Literal(Value::Number(n)) => {
+            let f = get_f64_from_number(n);
+            values.resize(items.len(), f.unwrap_or(0.0));
+            valid.resize(items.len(), f.is_some());
+        }
+        Expression::Binary { left, op, right } => {
+            let left_exact = evaluate_numeric_batch(left, items, values, valid);
+            let mut r_values = Vec::with_capacity(items.len());
+            let mut r_valid = Vec::with_capacity(items.len());
+            let right_exact = evaluate_numeric_batch(right, items, &mut r_values, &mut r_valid);
+            if !(left_exact && right_exact) {
+                return false;
+            }
+            for ((l, l_ok), (r, r_ok)) in values
+                .iter_mut()
+                .zip(valid.iter_mut())
+                .zip(r_values.iter().zip(&r_valid))
+            {
+                let res = if *l_ok && *r_ok {
+                    apply_arithmetic_f64(op, *l, *r)
+                } else {
+                    None
+                };
+                *l = res.unwrap_or(0.0);
+                *l_ok = res.is_some();
+            }
+        }
+        Expression::Unary {
+            op: UnaryOperator::Neg,
+            expr,
+        } => {
+            if !evaluate_numeric_batch(expr, items, values, valid) {
+                return false;
+            }
+            for v in values.iter_mut() {
+                *v = -*v;
+            }
+        }
+        _ => {
+            for item in items {
+                let maybe_f = match item {
+                    ExecutionResult::Value(_, _) => match evaluate_result(expr, item) {
+                        Value::Number(n) => get_f64_from_number(&n),
+                        _ => None,
+                    },
+                    ExecutionResult::Lazy(doc) => evaluate_to_f64_lazy(expr, doc),
+                };
+                values.push(maybe_f.unwrap_or(0.0));
+                valid.push(maybe_f.is_some());
+            }
+        }
+    }
+    values.iter().all(|v| v.abs() < MAX_EXACT_F64)
+}
+
+pub struct BatchProjectOperator<'a> {
     input: Box<dyn Iterator<Item = Batch> + 'a>,
-    offset: usize,
-    skipped: usize,
+    projections: Vec<Expression<'a>>,
+}
+
+impl<'a> BatchProjectOperator<'a> {
+    pub fn new(
+        input: Box<dyn Iterator<Item = Batch> + 'a>,
+        projections: Vec<Expression<'a>>,
+    ) -> Self {
+        BatchProjectOperator { input, projections }
+    }
+}
+
+impl<'a> Iterator for BatchProjectOperator<'a> {
+    type Item = Batch;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut batch = self.input.next()?;
+        let new_items = batch
+            .items
+            .drain(..)
+            .map(|item| {
+                let id = item.id().to_string();
+                let new_doc = project(&self.projections, |expr| evaluate_result(expr, &item));
+                ExecutionResult::Value(id, new_doc)
+            })
+            .collect();
+
+        Some(Batch { items: new_items })
+    }
+}
+
+// Document ID lookups
+
+/// Constraints on the document ID extracted from a filter predicate.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdLookup {
+    /// A set of exact document IDs, answered with point lookups.
+    Keys(Vec<String>),
+    /// A contiguous range of document IDs, answered with an index seek.
+    Range(Bound<String>, Bound<String>),
+}
+
+impl IdLookup {
+    /// Narrows this lookup to the IDs that also satisfy `other`.
+    fn intersect(self, other: IdLookup) -> IdLookup {
+        match (self, other) {
+            (IdLookup::Keys(a), IdLookup::Keys(b)) => {
+                IdLookup::Keys(a.into_iter().filter(|k| b.contains(k)).collect())
+            }
+            (IdLookup::Keys(keys), IdLookup::Range(lower, upper))
+            | (IdLookup::Range(lower, upper), IdLookup::Keys(keys)) => {
+                let range = (lower, upper);
+                IdLookup::Keys(
+                    keys.into_iter()
+                        .filter(|k| std::ops::RangeBounds::contains(&range, k))
+                        .collect(),
+                )
+            }
+            (IdLookup::Range(l1, u1), IdLookup::Range(l2, u2)) => {
+                IdLookup::Range(tighter_lower(l1, l2), tighter_upper(u1, u2))
+            }
+        }
+    }
+}
+
+fn tighter_lower(a: Bound<String>, b: Bound<String>) -> Bound<String> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if x > y || (x == y && matches!(a, Bound::Excluded(_))) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+fn tighter_upper(a: Bound<String>, b: Bound<String>) -> Bound<String> {
+    match (&a, &b) {
+        (Bound::Unbounded, _) => b,
+        (_, Bound::Unbounded) => a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => {
+            if x < y || (x == y && matches!(a, Bound::Excluded(_))) {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+/// Splits a predicate into its top-level `AND` conjuncts.
+pub fn split_conjuncts<'a>(predicate: Expression<'a>, out: &mut Vec<Expression<'a>>) {
+    match predicate {
+        Expression::Logical {
+            left,
+            op: LogicalOperator::And,
+            right,
+        } => {
+            split_conjuncts(*left, out);
+            split_conjuncts(*right, out);
+        }
+        other => out.push(other),
+    }
+}
+
+/// Joins conjuncts back into a single predicate with `AND`.
+pub fn join_conjuncts<'a>(conjuncts: Vec<Expression<'a>>) -> Option<Expression<'a>> {
+    conjuncts
+        .into_iter()
+        .reduce(|left, right| Expression::Logical {
+            left: Box::new(left),
+            op: LogicalOperator::And,
+            right: Box::new(right),
+        })
+}
+
+fn is_id_expression(expr: &Expression) -> bool {
+    matches!(expr, Expression::FieldReference(parts, _) if is_id_reference(parts))
+}
+
+fn id_literal(expr: &Expression) -> Option<String> {
+    match expr {
+        Expression::Literal(Value::String(s)) => Some(s.to_string()),
+        _ => None,
+    }
+}
+
+/// Converts a single conjunct on `_id` into an ID lookup, if possible.
+fn conjunct_to_id_lookup(expr: &Expression) -> Option<IdLookup> {
+    match expr {
+        Expression::Binary { left, op, right } => {
+            let (key, op) =
+                match (left.as_ref(), right.as_ref()) {
+                    (
+                        Expression::FieldReference(parts, _),
+                        Expression::Literal(Value::String(s)),
+                    ) if is_id_reference(parts) => (s.to_string(), op.clone()),
+                    (
+                        Expression::Literal(Value::String(s)),
+                        Expression::FieldReference(parts, _),
+                    ) if is_id_reference(parts) => (s.to_string(), op.flip()?),
+                    _ => return None,
+                };
+            match op {
+                BinaryOperator::Eq => Some(IdLookup::Keys(vec![key])),
+                BinaryOperator::Gt => Some(IdLookup::Range(Bound::Excluded(key), Bound::Unbounded)),
+                BinaryOperator::Gte => {
+                    Some(IdLookup::Range(Bound::Included(key), Bound::Unbounded))
+                }
+                BinaryOperator::Lt => Some(IdLookup::Range(Bound::Unbounded, Bound::Excluded(key))),
+                BinaryOperator::Lte => {
+                    Some(IdLookup::Range(Bound::Unbounded, Bound::Included(key)))
+                }
+                _ => None,
+            }
+        }
+        Expression::InList {
+            expr,
+            list,
+            negated: false,
+        } if is_id_expression(expr) => list
+            .iter()
+            .map(id_literal)
+            .collect::<Option<Vec<_>>>()
+            .map(IdLookup::Keys),
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } if is_id_expression(expr) => Some(IdLookup::Range(
+            Bound::Included(id_literal(low)?),
+            Bound::Included(id_literal(high)?),
+        )),
+        Expression::Logical {
+            left,
+            op: LogicalOperator::Or,
+            right,
+        } => match (conjunct_to_id_lookup(left)?, conjunct_to_id_lookup(right)?) {
+            (IdLookup::Keys(mut a), IdLookup::Keys(b)) => {
+                a.extend(b);
+                Some(IdLookup::Keys(a))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Extracts the constraints on `_id` from a predicate, returning the lookup
+/// and the residual predicate that still has to be evaluated per document.
+pub fn extract_id_lookup<'a>(
+    predicate: &Expression<'a>,
+) -> Option<(IdLookup, Option<Expression<'a>>)> {
+    let mut conjuncts = Vec::new();
+    split_conjuncts(predicate.clone(), &mut conjuncts);
+
+    let mut lookup: Option<IdLookup> = None;
+    let mut residual = Vec::new();
+    for conjunct in conjuncts {
+        match conjunct_to_id_lookup(&conjunct) {
+            Some(l) => {
+                lookup = Some(match lookup {
+                    Some(existing) => existing.intersect(l),
+                    None => l,
+                });
+            }
+            None => residual.push(conjunct),
+        }
+    }
+
+    lookup.map(|l| (l, join_conjuncts(residual)))
+}
+
+/// The operators a `LogicalPlan` runs as, with filters and projections pushed
+/// into the storage scan where possible. `EXPLAIN` shows this tree.
+///
+/// Row operators produce `ExecutionResult`s and batch operators `Batch`es;
+/// `Flatten` and `BatchScan` convert between the two.
+#[derive(Debug)]
+pub enum PhysicalPlan<'a> {
+    /// Reads the documents of a collection, or only those whose IDs fall in
+    /// `range`, filtering and projecting them in the storage layer.
+    /// Unprojected documents are narrowed to `fields` if given.
+    Scan {
+        collection: String,
+        range: Option<IdRange>,
+        predicate: Option<Expression<'a>>,
+        projections: Option<Vec<Expression<'a>>>,
+        fields: Option<FieldSet>,
+    },
+    /// Fetches the documents with the given IDs.
+    IdLookup {
+        collection: String,
+        keys: Vec<String>,
+    },
+    /// Fetches the documents whose value in `index` falls in any of `ranges`,
+    /// keeping those that satisfy `predicate` and projecting them.
+    IndexScan {
+        collection: String,
+        index: String,
+        ranges: Vec<IndexRange>,
+        predicate: Expression<'a>,
+        projections: Option<Vec<Expression<'a>>>,
+    },
+    /// Fetches about the `k` documents whose vectors in `index` are nearest
+    /// to `vector`, nearest first.
+    VectorScan {
+        collection: String,
+        index: String,
+        vector: Vec<f64>,
+        metric: Metric,
+        k: usize,
+    },
+    Filter {
+        input: Box<PhysicalPlan<'a>>,
+        predicate: Expression<'a>,
+    },
+    Project {
+        input: Box<PhysicalPlan<'a>>,
+        projections: Vec<Expression<'a>>,
+    },
+    Limit {
+        input: Box<PhysicalPlan<'a>>,
+        limit: usize,
+    },
+    Offset {
+        input: Box<PhysicalPlan<'a>>,
+        offset: usize,
+    },
+    Sort {
+        input: Box<PhysicalPlan<'a>>,
+        keys: Vec<SortKey<'a>>,
+    },
+    Unnest {
+        input: Box<PhysicalPlan<'a>>,
+        expr: Expression<'a>,
+        alias: &'a str,
+    },
+    HashJoin {
+        left: Box<PhysicalPlan<'a>>,
+        right: Box<PhysicalPlan<'a>>,
+        kind: JoinKind,
+        left_alias: Option<&'a str>,
+        right_alias: &'a str,
+        keys: Vec<(Expression<'a>, Expression<'a>)>,
+        residual: Option<Expression<'a>>,
+    },
+    /// Joins each left row with the document of `collection` whose ID is the
+    /// row's `left_key`, using point lookups.
+    IndexJoin {
+        left: Box<PhysicalPlan<'a>>,
+        collection: String,
+        kind: JoinKind,
+        left_alias: Option<&'a str>,
+        right_alias: &'a str,
+        left_key: Expression<'a>,
+        residual: Option<Expression<'a>>,
+    },
+    Window {
+        input: Box<PhysicalPlan<'a>>,
+        windows: Vec<(WindowCall<'a>, &'a str)>,
+    },
+    Distinct {
+        input: Box<PhysicalPlan<'a>>,
+        on: Vec<Expression<'a>>,
+    },
+    SetOperation {
+        left: Box<PhysicalPlan<'a>>,
+        right: Box<PhysicalPlan<'a>>,
+        op: SetOperator,
+        all: bool,
+    },
+    /// Turns batches into rows.
+    Flatten { input: Box<PhysicalPlan<'a>> },
+    /// Groups the rows of its input into batches.
+    BatchScan {
+        input: Box<PhysicalPlan<'a>>,
+        batch_size: usize,
+    },
+    BatchFilter {
+        input: Box<PhysicalPlan<'a>>,
+        predicate: Expression<'a>,
+    },
+    BatchProject {
+        input: Box<PhysicalPlan<'a>>,
+        projections: Vec<Expression<'a>>,
+    },
+    BatchLimit {
+        input: Box<PhysicalPlan<'a>>,
+        limit: usize,
+    },
+    BatchOffset {
+        input: Box<PhysicalPlan<'a>>,
+        offset: usize,
+    },
+}
+
+impl<'a> PhysicalPlan<'a> {
+    /// Returns the input operators, in the order they are opened.
+    pub fn inputs(&self) -> Vec<&PhysicalPlan<'a>> {
+        match self {
+            PhysicalPlan::Scan { .. }
+            | PhysicalPlan::IdLookup { .. }
+            | PhysicalPlan::IndexScan { .. }
+            | PhysicalPlan::VectorScan { .. } => Vec::new(),
+            PhysicalPlan::HashJoin { left, right, .. }
+            | PhysicalPlan::SetOperation { left, right, .. } => vec![left.as_ref(), right.as_ref()],
+            PhysicalPlan::IndexJoin { left, .. } => vec![left.as_ref()],
+            PhysicalPlan::Filter { input, .. }
+            | PhysicalPlan::Project { input, .. }
+            | PhysicalPlan::Limit { input, .. }
+            | PhysicalPlan::Offset { input, .. }
+            | PhysicalPlan::Sort { input, .. }
+            | PhysicalPlan::Unnest { input, .. }
+            | PhysicalPlan::Window { input, .. }
+            | PhysicalPlan::Distinct { input, .. }
+            | PhysicalPlan::Flatten { input }
+            | PhysicalPlan::BatchScan { input, .. }
+            | PhysicalPlan::BatchFilter { input, .. }
+            | PhysicalPlan::BatchProject { input, .. }
+            | PhysicalPlan::BatchLimit { input, .. }
+            | PhysicalPlan::BatchOffset { input, .. } => vec![input.as_ref()],
+        }
+    }
+
+    /// Returns the input operators, mutably.
+    pub fn inputs_mut(&mut self) -> Vec<&mut PhysicalPlan<'a>> {
+        match self {
+            PhysicalPlan::Scan { .. }
+            | PhysicalPlan::IdLookup { .. }
+            | PhysicalPlan::IndexScan { .. }
+            | PhysicalPlan::VectorScan { .. } => Vec::new(),
+            PhysicalPlan::HashJoin { left, right, .. }
+            | PhysicalPlan::SetOperation { left, right, .. } => vec![left.as_mut(), right.as_mut()],
+            PhysicalPlan::IndexJoin { left, .. } => vec![left.as_mut()],
+            PhysicalPlan::Filter { input, .. }
+            | PhysicalPlan::Project { input, .. }
+            | PhysicalPlan::Limit { input, .. }
+            | PhysicalPlan::Offset { input, .. }
+            | PhysicalPlan::Sort { input, .. }
+            | PhysicalPlan::Unnest { input, .. }
+            | PhysicalPlan::Window { input, .. }
+            | PhysicalPlan::Distinct { input, .. }
+            | PhysicalPlan::Flatten { input }
+            | PhysicalPlan::BatchScan { input, .. }
+            | PhysicalPlan::BatchFilter { input, .. }
+            | PhysicalPlan::BatchProject { input, .. }
+            | PhysicalPlan::BatchLimit { input, .. }
+            | PhysicalPlan::BatchOffset { input, .. } => vec![input.as_mut()],
+        }
+    }
+}
+
+/// Chooses the operators for a plan: batch operators if the whole plan can be
+/// vectorized, row operators otherwise. Scans filtered on an indexed field
+/// look the documents up in the index, scans on several threads evaluate the
+/// filter above them, and scans whose documents are read only in part return
+/// just the fields their consumers use.
+pub fn plan_physical<'a>(mut plan: LogicalPlan<'a>, db: &DB) -> PhysicalPlan<'a> {
+    bind_text_stats(&mut plan, db);
+    bind_date_times(&mut plan, db);
+    let mut physical = plan_operators(plan);
+    use_indexes(&mut physical, db);
+    parallelize_filters(&mut physical, db);
+    narrow_scans(&mut physical, None);
+    physical
+}
+
+fn plan_operators(plan: LogicalPlan<'_>) -> PhysicalPlan<'_> {
+    if is_vectorizable(&plan) {
+        PhysicalPlan::Flatten {
+            input: Box::new(plan_batches(plan, None)),
+        }
+    } else {
+        plan_rows(plan)
+    }
+}
+
+/// Plans a scan with `predicate` and `projections` pushed into storage, using
+/// point lookups or a range scan if the predicate constrains `_id`.
+fn plan_scan<'a>(
+    collection: String,
+    predicate: Option<Expression<'a>>,
+    projections: Option<Vec<Expression<'a>>>,
+) -> PhysicalPlan<'a> {
+    let Some((lookup, residual)) = predicate.as_ref().and_then(extract_id_lookup) else {
+        return PhysicalPlan::Scan {
+            collection,
+            range: None,
+            predicate,
+            projections,
+            fields: None,
+        };
+    };
+    match lookup {
+        IdLookup::Keys(mut keys) => {
+            keys.sort();
+            keys.dedup();
+            let mut plan = PhysicalPlan::IdLookup { collection, keys };
+            if let Some(predicate) = residual {
+                plan = PhysicalPlan::Filter {
+                    input: Box::new(plan),
+                    predicate,
+                };
+            }
+            if let Some(projections) = projections {
+                plan = PhysicalPlan::Project {
+                    input: Box::new(plan),
+                    projections,
+                };
+            }
+            plan
+        }
+        IdLookup::Range(lower, upper) => PhysicalPlan::Scan {
+            collection,
+            range: Some((lower, upper)),
+            predicate: residual,
+            projections,
+            fields: None,
+        },
+    }
+}
+
+fn plan_rows(plan: LogicalPlan<'_>) -> PhysicalPlan<'_> {
+    match plan {
+        LogicalPlan::Scan { collection } => plan_scan(collection, None, None),
+        LogicalPlan::Filter { input, predicate } => match *input {
+            LogicalPlan::Scan { collection } => plan_scan(collection, Some(predicate), None),
+            other_input => PhysicalPlan::Filter {
+                input: Box::new(plan_rows(other_input)),
+                predicate,
+            },
+        },
+        LogicalPlan::Project { input, projections } => match *input {
+            LogicalPlan::Scan { collection } => plan_scan(collection, None, Some(projections)),
+            LogicalPlan::Filter {
+                input: inner,
+                predicate,
+            } => match *inner {
+                LogicalPlan::Scan { collection } => {
+                    plan_scan(collection, Some(predicate), Some(projections))
+                }
+                other_inner => PhysicalPlan::Project {
+                    input: Box::new(plan_rows(LogicalPlan::Filter {
+                        input: Box::new(other_inner),
+                        predicate,
+                    })),
+                    projections,
+                },
+            },
+            other_input => PhysicalPlan::Project {
+                input: Box::new(plan_rows(other_input)),
+                projections,
+            },
+        },
+        LogicalPlan::Limit { input, limit } => PhysicalPlan::Limit {
+            input: Box::new(plan_rows(*input)),
+            limit,
+        },
+        LogicalPlan::Offset { input, offset } => PhysicalPlan::Offset {
+            input: Box::new(plan_rows(*input)),
+            offset,
+        },
+        LogicalPlan::Sort { input, keys } => PhysicalPlan::Sort {
+            input: Box::new(plan_operators(*input)),
+            keys,
+        },
+        LogicalPlan::Unnest { input, expr, alias } => PhysicalPlan::Unnest {
+            input: Box::new(plan_operators(*input)),
+            expr,
+            alias,
+        },
+        LogicalPlan::Join {
+            left,
+            right,
+            kind,
+            left_alias,
+            right_alias,
+            mut keys,
+            residual,
+        } => {
+            let left = Box::new(plan_operators(*left));
+            // Joining on the right collection's `_id` uses point lookups
+            if let LogicalPlan::Scan { collection } = right.as_ref()
+                && let Some(i) = keys
+                    .iter()
+                    .position(|(_, right_key)| is_alias_id_reference(right_key, right_alias))
+            {
+                let (left_key, _) = keys.remove(i);
+                let mut conditions: Vec<Expression> = keys
+                    .into_iter()
+                    .map(|(l, r)| Expression::Binary {
+                        left: Box::new(l),
+                        op: BinaryOperator::Eq,
+                        right: Box::new(r),
+                    })
+                    .collect();
+                conditions.extend(residual);
+                return PhysicalPlan::IndexJoin {
+                    left,
+                    collection: collection.clone(),
+                    kind,
+                    left_alias,
+                    right_alias,
+                    left_key,
+                    residual: join_conjuncts(conditions),
+                };
+            }
+            PhysicalPlan::HashJoin {
+                left,
+                right: Box::new(plan_operators(*right)),
+                kind,
+                left_alias,
+                right_alias,
+                keys,
+                residual,
+            }
+        }
+        LogicalPlan::Window { input, windows } => PhysicalPlan::Window {
+            input: Box::new(plan_operators(*input)),
+            windows,
+        },
+        LogicalPlan::Distinct { input, on } => PhysicalPlan::Distinct {
+            input: Box::new(plan_operators(*input)),
+            on,
+        },
+        LogicalPlan::SetOperation {
+            left,
+            right,
+            op,
+            all,
+        } => PhysicalPlan::SetOperation {
+            left: Box::new(plan_operators(*left)),
+            right: Box::new(plan_operators(*right)),
+            op,
+            all,
+        },
+    }
+}
+
+/// Plans a vectorizable plan as batch operators. `batch_size_hint` caps the
+/// batch size when a limit needs only a few rows.
+fn plan_batches(plan: LogicalPlan<'_>, batch_size_hint: Option<usize>) -> PhysicalPlan<'_> {
+    let batch_size = batch_size_hint
+        .map_or(BATCH_SIZE, |limit| min(limit, BATCH_SIZE))
+        .max(1);
+    let batch_scan = |input| PhysicalPlan::BatchScan {
+        input: Box::new(input),
+        batch_size,
+    };
+    match plan {
+        LogicalPlan::Scan { collection } => batch_scan(plan_scan(collection, None, None)),
+        LogicalPlan::Filter { input, predicate } => match *input {
+            // The predicate is evaluated by the batch filter, not pushed down
+            LogicalPlan::Scan { collection } => PhysicalPlan::BatchFilter {
+                input: Box::new(batch_scan(plan_scan(collection, None, None))),
+                predicate,
+            },
+            other_input => PhysicalPlan::BatchFilter {
+                input: Box::new(plan_batches(other_input, batch_size_hint)),
+                predicate,
+            },
+        },
+        LogicalPlan::Project { input, projections } => match *input {
+            LogicalPlan::Scan { collection } => {
+                batch_scan(plan_scan(collection, None, Some(projections)))
+            }
+            LogicalPlan::Filter {
+                input: inner,
+                predicate,
+            } => match *inner {
+                // A batch filter above a projecting scan would not see the
+                // fields it needs, so the predicate is pushed down as well
+                LogicalPlan::Scan { collection } => {
+                    batch_scan(plan_scan(collection, Some(predicate), Some(projections)))
+                }
+                other_inner => PhysicalPlan::BatchProject {
+                    input: Box::new(plan_batches(
+                        LogicalPlan::Filter {
+                            input: Box::new(other_inner),
+                            predicate,
+                        },
+                        batch_size_hint,
+                    )),
+                    projections,
+                },
+            },
+            other_input => PhysicalPlan::BatchProject {
+                input: Box::new(plan_batches(other_input, batch_size_hint)),
+                projections,
+            },
+        },
+        LogicalPlan::Limit { input, limit } => PhysicalPlan::BatchLimit {
+            input: Box::new(plan_batches(*input, Some(limit))),
+            limit,
+        },
+        LogicalPlan::Offset { input, offset } => PhysicalPlan::BatchOffset {
+            input: Box::new(plan_batches(*input, batch_size_hint.map(|l| l + offset))),
+            offset,
+        },
+        // Operators without a batch implementation run row-at-a-time and are rebatched.
+        other @ (LogicalPlan::Sort { .. }
+        | LogicalPlan::Unnest { .. }
+        | LogicalPlan::Join { .. }
+        | LogicalPlan::Window { .. }
+        | LogicalPlan::Distinct { .. }
+        | LogicalPlan::SetOperation { .. }) => PhysicalPlan::BatchScan {
+            input: Box::new(plan_rows(other)),
+            batch_size: BATCH_SIZE,
+        },
+    }
+}
+
+/// Turns scans of whole collections filtered on an indexed field into index
+/// scans, taking the predicate of a batch filter right above the scan along,
+/// and those sorted by distance to a vector for a limit into vector scans.
+fn use_indexes(plan: &mut PhysicalPlan, db: &DB) {
+    if use_vector_index(plan, db) {
+        return;
+    }
+    if let PhysicalPlan::Scan {
+        collection,
+        range: None,
+        predicate: Some(predicate),
+        projections,
+        ..
+    } = plan
+        && let Some((index, ranges)) = choose_index(db, collection, predicate)
+    {
+        *plan = PhysicalPlan::IndexScan {
+            collection: std::mem::take(collection),
+            index,
+            ranges,
+            predicate: predicate.clone(),
+            projections: projections.take(),
+        };
+        return;
+    }
+    if let PhysicalPlan::BatchFilter { input, predicate } = plan
+        && let PhysicalPlan::BatchScan { input: scan, .. } = input.as_mut()
+        && let PhysicalPlan::Scan {
+            collection,
+            range: None,
+            predicate: None,
+            projections: None,
+            ..
+        } = scan.as_mut()
+        && let Some((index, ranges)) = choose_index(db, collection, predicate)
+    {
+        **scan = PhysicalPlan::IndexScan {
+            collection: std::mem::take(collection),
+            index,
+            ranges,
+            predicate: predicate.clone(),
+            projections: None,
+        };
+        let placeholder = PhysicalPlan::IdLookup {
+            collection: String::new(),
+            keys: Vec::new(),
+        };
+        *plan = std::mem::replace(input.as_mut(), placeholder);
+        return;
+    }
+    for input in plan.inputs_mut() {
+        use_indexes(input, db);
+    }
+}
+
+/// Moves the predicate of a batch filter into the scan of a whole collection
+/// below it if the scan runs on several threads, so that the workers rather
+/// than the calling thread evaluate it.
+fn parallelize_filters(plan: &mut PhysicalPlan, db: &DB) {
+    if let PhysicalPlan::BatchFilter { input, predicate } = plan
+        && let PhysicalPlan::BatchScan { input: scan, .. } = input.as_mut()
+        && let PhysicalPlan::Scan {
+            collection,
+            range: None,
+            predicate: pushed,
+            projections: None,
+            ..
+        } = scan.as_mut()
+        && pushed.is_none()
+        && db.scans_in_parallel(collection)
+    {
+        *pushed = Some(predicate.clone());
+        let placeholder = PhysicalPlan::IdLookup {
+            collection: String::new(),
+            keys: Vec::new(),
+        };
+        *plan = std::mem::replace(input.as_mut(), placeholder);
+        return;
+    }
+    for input in plan.inputs_mut() {
+        parallelize_filters(input, db);
+    }
 }
 
-impl<'a> BatchOffsetOperator<'a> {
-    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, offset: usize) -> Self {
-        BatchOffsetOperator {
-            input,
-            offset,
-            skipped: 0,
+/// Replaces the scan below `ORDER BY VECTOR_DISTANCE(field, [..], metric)
+/// NULLS LAST LIMIT k` with a search of a vector index on the field, if the
+/// whole collection is read. The sort and limit stay above it, ordering the
+/// candidates found by their exact distances. Documents without a vector of
+/// the query's length have a NULL distance and are not found, so the sort
+/// must put NULLs last for them to be past the limit.
+fn use_vector_index(plan: &mut PhysicalPlan, db: &DB) -> bool {
+    let PhysicalPlan::Limit { input, limit } = plan else {
+        return false;
+    };
+    let mut k = *limit;
+    let node = match input.as_mut() {
+        PhysicalPlan::Offset { input, offset } => {
+            k += *offset;
+            input.as_mut()
+        }
+        other => other,
+    };
+    let node = match node {
+        PhysicalPlan::Project { input, .. } => input.as_mut(),
+        other => other,
+    };
+    let PhysicalPlan::Sort { input, keys } = node else {
+        return false;
+    };
+    let Some(SortKey {
+        expr:
+            Expression::Function {
+                func: ScalarFunction::VectorDistance,
+                args,
+            },
+        descending: false,
+        nulls_first: false,
+    }) = keys.first()
+    else {
+        return false;
+    };
+    let [
+        Expression::FieldReference(parts, _),
+        Expression::Literal(query),
+        Expression::Literal(Value::String(metric)),
+    ] = args.as_slice()
+    else {
+        return false;
+    };
+    let (Some(vector), Some(metric)) = (vector::to_vector(query), Metric::parse(metric)) else {
+        return false;
+    };
+    let scan = match input.as_mut() {
+        PhysicalPlan::Flatten { input } => match input.as_mut() {
+            PhysicalPlan::BatchScan { input, .. } => input.as_mut(),
+            _ => return false,
+        },
+        other => other,
+    };
+    let PhysicalPlan::Scan {
+        collection,
+        range: None,
+        predicate: None,
+        projections: None,
+        ..
+    } = scan
+    else {
+        return false;
+    };
+    let Some(index) = db.find_index(collection, parts, IndexKind::Vector) else {
+        return false;
+    };
+    let index = index.to_string();
+    let collection = std::mem::take(collection);
+    **input = PhysicalPlan::VectorScan {
+        collection,
+        index,
+        vector,
+        metric,
+        k,
+    };
+    true
+}
+
+/// Chooses an index to look up the documents satisfying `predicate` in, with
+/// the ranges of values to look up: one on a field its conjuncts compare with
+/// values, a full-text index on a field they require to `MATCH`, or a
+/// spatial index on a field they restrict to a region. An index on a field
+/// compared for equality is preferred, then a full-text index, then a
+/// spatial one.
+fn choose_index(
+    db: &DB,
+    collection: &str,
+    predicate: &Expression,
+) -> Option<(String, Vec<IndexRange>)> {
+    let mut ranges = Vec::new();
+    index_ranges(predicate, &mut ranges);
+    let mut candidates: Vec<(&str, IndexRange)> = Vec::new();
+    for (parts, range) in ranges {
+        let Some(index) = db.find_index(collection, parts, IndexKind::Value) else {
+            continue;
+        };
+        match candidates.iter_mut().find(|(name, _)| *name == index) {
+            Some((_, existing)) => existing.intersect(range),
+            None => candidates.push((index, range)),
+        }
+    }
+    if let Some(chosen) = candidates.iter().position(|(_, range)| range.is_point()) {
+        let (index, range) = candidates.swap_remove(chosen);
+        return Some((index.to_string(), vec![range]));
+    }
+    let mut matches = Vec::new();
+    match_conjuncts(predicate, &mut matches);
+    for (parts, terms) in matches {
+        if let Some(index) = db.find_index(collection, parts, IndexKind::FullText) {
+            let ranges = terms.iter().map(|term| IndexRange::term(term)).collect();
+            return Some((index.to_string(), ranges));
+        }
+    }
+    let mut regions = Vec::new();
+    spatial_conjuncts(predicate, &mut regions);
+    for (parts, bbox) in regions {
+        if let Some(index) = db.find_index(collection, parts, IndexKind::Spatial)
+            && let Some(cover) = geo::cover(&bbox)
+        {
+            return Some((index.to_string(), IndexRange::overlapping(&cover)));
         }
     }
+    let (index, range) = candidates.into_iter().next()?;
+    Some((index.to_string(), vec![range]))
 }
 
-impl<'a> Iterator for BatchOffsetOperator<'a> {
-    type Item = Batch;
-    fn next(&mut self) -> Option<Self::Item> {
-        while self.skipped < self.offset {
-            let mut batch = self.input.next()?;
-            let needed = self.offset - self.skipped;
-            if batch.items.len() <= needed {
-                self.skipped += batch.items.len();
-                continue;
-            } else {
-                batch.items.drain(0..needed);
-                self.skipped += needed;
-                return Some(batch);
+/// Collects the fields and query terms of the top-level conjuncts of
+/// `predicate` that only hold if a `MATCH` finds a term, such as
+/// `MATCH(body, 'fox') > 0`.
+fn match_conjuncts<'e, 'a>(
+    predicate: &'e Expression<'a>,
+    out: &mut Vec<(&'e [&'a str], &'e [String])>,
+) {
+    match predicate {
+        Expression::Logical {
+            left,
+            op: LogicalOperator::And,
+            right,
+        } => {
+            match_conjuncts(left, out);
+            match_conjuncts(right, out);
+        }
+        Expression::Binary { left, op, right } => {
+            if let Expression::Match { expr, terms, .. } = left.as_ref()
+                && let Expression::FieldReference(parts, _) = expr.as_ref()
+                && !is_id_reference(parts)
+                && let Expression::Literal(Value::Number(n)) = right.as_ref()
+                && let Some(n) = get_f64_from_number(n)
+                && match op {
+                    BinaryOperator::Gt => n >= 0.0,
+                    BinaryOperator::Gte => n > 0.0,
+                    _ => false,
+                }
+            {
+                out.push((parts.as_slice(), terms.as_slice()));
             }
         }
-        self.input.next()
+        _ => {}
     }
 }
 
-pub struct BatchFilterOperator<'a> {
-    input: Box<dyn Iterator<Item = Batch> + 'a>,
-    predicate: Expression<'a>,
-    buf_values: Vec<f64>,
-    buf_valid: Vec<bool>,
+/// Collects the fields and bounding boxes of the top-level conjuncts of
+/// `predicate` that only hold for a geometry in a region.
+fn spatial_conjuncts<'e, 'a>(predicate: &'e Expression<'a>, out: &mut Vec<(&'e [&'a str], BBox)>) {
+    match predicate {
+        Expression::Logical {
+            left,
+            op: LogicalOperator::And,
+            right,
+        } => {
+            spatial_conjuncts(left, out);
+            spatial_conjuncts(right, out);
+        }
+        other => out.extend(conjunct_to_region(other)),
+    }
 }
 
-impl<'a> BatchFilterOperator<'a> {
-    pub fn new(input: Box<dyn Iterator<Item = Batch> + 'a>, predicate: Expression<'a>) -> Self {
-        BatchFilterOperator {
-            input,
-            predicate,
-            buf_values: Vec::with_capacity(BATCH_SIZE),
-            buf_valid: Vec::with_capacity(BATCH_SIZE),
+/// Converts `ST_DWITHIN` of a field and a constant point, `ST_CONTAINS` of a
+/// constant polygon and a field, or `ST_WITHIN_BBOX` of a field into the
+/// field and a box its geometry must overlap.
+fn conjunct_to_region<'e, 'a>(expr: &'e Expression<'a>) -> Option<(&'e [&'a str], BBox)> {
+    let Expression::Function { func, args } = expr else {
+        return None;
+    };
+    let field = |i: usize| match args.get(i)? {
+        Expression::FieldReference(parts, _) if !is_id_reference(parts) => Some(parts.as_slice()),
+        _ => None,
+    };
+    let literal = |i: usize| match args.get(i)? {
+        Expression::Literal(value) => Some(value),
+        _ => None,
+    };
+    let number = |i: usize| match literal(i)? {
+        Value::Number(n) => get_f64_from_number(n),
+        _ => None,
+    };
+    match func {
+        ScalarFunction::StDwithin => {
+            // Either geometry may be the field
+            let (parts, center) = match field(0) {
+                Some(parts) => (parts, literal(1)?),
+                None => (field(1)?, literal(0)?),
+            };
+            let Geometry::Point(center) = Geometry::from_value(center)? else {
+                return None;
+            };
+            Some((parts, BBox::around(center, number(2)?)))
         }
+        ScalarFunction::StContains => match Geometry::from_value(literal(0)?)? {
+            region @ (Geometry::Polygon(_) | Geometry::MultiPolygon(_)) => {
+                Some((field(1)?, region.bbox()))
+            }
+            _ => None,
+        },
+        ScalarFunction::StWithinBbox => {
+            let bbox = BBox {
+                min_lon: number(1)?,
+                min_lat: number(2)?,
+                max_lon: number(3)?,
+                max_lat: number(4)?,
+            };
+            Some((field(0)?, bbox))
+        }
+        _ => None,
     }
+}
 
-    fn filter_batch(&mut self, batch: &mut Batch) {
-        if let Expression::Binary { left, op, right } = &self.predicate
-            && let (Expression::FieldReference(_, _), Expression::Literal(Value::Number(n))) =
-                (left.as_ref(), right.as_ref())
-        {
-            let threshold = match n {
-                jsonb_schema::Number::Float64(f) => *f,
-                jsonb_schema::Number::Int64(i) => *i as f64,
-                jsonb_schema::Number::UInt64(u) => *u as f64,
-                _ => {
-                    self.fallback_filter(batch);
-                    return;
-                }
-            };
+/// Makes the comparisons in filters on scans treat the fields that only hold
+/// `date-time` strings as timestamps, by wrapping them in `TO_TIMESTAMP`.
+fn bind_date_times(plan: &mut LogicalPlan, db: &DB) {
+    if let LogicalPlan::Filter { input, predicate } = plan
+        && let LogicalPlan::Scan { collection } = input.as_ref()
+    {
+        wrap_date_times(predicate, collection, db);
+    }
+    for input in plan.inputs_mut() {
+        bind_date_times(input, db);
+    }
+}
 
-            // Recycle buffers
-            self.buf_values.clear();
-            self.buf_valid.clear();
+fn wrap_date_times(expr: &mut Expression, collection: &str, db: &DB) {
+    match expr {
+        Expression::Binary { left, op, right } if op.is_comparison() => {
+            wrap_date_time(left, collection, db);
+            wrap_date_time(right, collection, db);
+        }
+        Expression::Between {
+            expr, low, high, ..
+        } => {
+            for operand in [expr, low, high] {
+                wrap_date_time(operand, collection, db);
+            }
+        }
+        _ => {
+            for child in expr.children_mut() {
+                wrap_date_times(child, collection, db);
+            }
+        }
+    }
+}
 
-            for item in &batch.items {
-                let maybe_f = match item {
-                    ExecutionResult::Value(_, v) => match evaluate_expression(left, v) {
-                        Value::Number(n) => get_f64_from_number(&n),
-                        _ => None,
-                    },
-                    ExecutionResult::Lazy(doc) => evaluate_to_f64_lazy(left, doc),
-                };
+fn wrap_date_time(operand: &mut Expression, collection: &str, db: &DB) {
+    if let Expression::FieldReference(parts, _) = operand
+        && db.is_date_time(collection, parts)
+    {
+        let field = std::mem::replace(operand, Expression::Literal(Value::Null));
+        *operand = Expression::Function {
+            func: ScalarFunction::ToTimestamp,
+            args: vec![field],
+        };
+    }
+}
 
-                if let Some(f) = maybe_f {
-                    self.buf_values.push(f);
-                    self.buf_valid.push(true);
-                } else {
-                    self.buf_values.push(0.0);
-                    self.buf_valid.push(false);
-                }
-            }
+/// Gives each `MATCH` on a field with a full-text index the statistics of
+/// that index, for BM25 scoring, if the plan reads a single collection.
+fn bind_text_stats(plan: &mut LogicalPlan, db: &DB) {
+    let mut collections = Vec::new();
+    scanned_collections(plan, &mut collections);
+    collections.sort();
+    collections.dedup();
+    let [collection] = collections.as_slice() else {
+        return;
+    };
+    for expr in plan.expressions_mut() {
+        bind_match_stats(expr, collection, db);
+    }
+}
 
-            let mut i = 0;
-            let buf_values = &self.buf_values;
-            let buf_valid = &self.buf_valid;
+fn scanned_collections(plan: &mut LogicalPlan, out: &mut Vec<String>) {
+    if let LogicalPlan::Scan { collection } = plan {
+        out.push(collection.clone());
+    }
+    for input in plan.inputs_mut() {
+        scanned_collections(input, out);
+    }
+}
 
-            batch.items.retain(|_| {
-                let valid = buf_valid[i];
-                let val = buf_values[i];
-                let keep = match op {
-                    BinaryOperator::Gt => valid && val > threshold,
-                    BinaryOperator::Lt => valid && val < threshold,
-                    BinaryOperator::Gte => valid && val >= threshold,
-                    BinaryOperator::Lte => valid && val <= threshold,
-                    BinaryOperator::Eq => valid && (val - threshold).abs() < f64::EPSILON,
-                    _ => false,
-                };
-                i += 1;
-                keep
-            });
-            return;
+fn bind_match_stats(expr: &mut Expression, collection: &str, db: &DB) {
+    if let Expression::Match {
+        expr: text,
+        terms,
+        stats,
+        ..
+    } = expr
+        && let Expression::FieldReference(parts, _) = text.as_ref()
+        && let Ok(Some(found)) = db.text_stats(collection, parts, terms)
+    {
+        *stats = Some(Arc::new(found));
+    }
+    for child in expr.children_mut() {
+        bind_match_stats(child, collection, db);
+    }
+}
+
+/// Collects the ranges of values the top-level conjuncts of `predicate`
+/// restrict fields to.
+fn index_ranges<'e, 'a>(predicate: &'e Expression<'a>, out: &mut Vec<(&'e [&'a str], IndexRange)>) {
+    match predicate {
+        Expression::Logical {
+            left,
+            op: LogicalOperator::And,
+            right,
+        } => {
+            index_ranges(left, out);
+            index_ranges(right, out);
         }
-        self.fallback_filter(batch);
+        other => out.extend(conjunct_to_index_range(other)),
     }
+}
 
-    fn fallback_filter(&self, batch: &mut Batch) {
-        batch.items.retain(|item| {
-            let val = match item {
-                ExecutionResult::Value(_, doc) => evaluate_expression(&self.predicate, doc),
-                ExecutionResult::Lazy(doc) => evaluate_expression_lazy(&self.predicate, doc),
+/// Converts a conjunct comparing a field other than `_id` with a value into
+/// the field and the range of values it allows, if possible.
+fn conjunct_to_index_range<'e, 'a>(
+    expr: &'e Expression<'a>,
+) -> Option<(&'e [&'a str], IndexRange)> {
+    let (parts, range) = match expr {
+        Expression::Binary { left, op, right } => {
+            let (parts, value, op) = match (left.as_ref(), right.as_ref()) {
+                (Expression::FieldReference(parts, _), Expression::Literal(value)) => {
+                    (parts, value.clone(), op.clone())
+                }
+                (Expression::Literal(value), Expression::FieldReference(parts, _)) => {
+                    (parts, value.clone(), op.flip()?)
+                }
+                _ => return None,
             };
-            val == Value::Bool(true)
-        });
-    }
+            let (lower, upper) = match op {
+                BinaryOperator::Eq => (Bound::Included(value.clone()), Bound::Included(value)),
+                BinaryOperator::Gt => (Bound::Excluded(value), Bound::Unbounded),
+                BinaryOperator::Gte => (Bound::Included(value), Bound::Unbounded),
+                BinaryOperator::Lt => (Bound::Unbounded, Bound::Excluded(value)),
+                BinaryOperator::Lte => (Bound::Unbounded, Bound::Included(value)),
+                _ => return None,
+            };
+            (parts, IndexRange { lower, upper })
+        }
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } => match (expr.as_ref(), low.as_ref(), high.as_ref()) {
+            (
+                Expression::FieldReference(parts, _),
+                Expression::Literal(low),
+                Expression::Literal(high),
+            ) => (
+                parts,
+                IndexRange {
+                    lower: Bound::Included(low.clone()),
+                    upper: Bound::Included(high.clone()),
+                },
+            ),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    (!is_id_reference(parts)).then_some((parts.as_slice(), range))
 }
 
-impl<'a> Iterator for BatchFilterOperator<'a> {
-    type Item = Batch;
-    fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let mut batch = self.input.next()?;
-            self.filter_batch(&mut batch);
-            if !batch.items.is_empty() {
-                return Some(batch);
-            }
+/// Sets the fields each unprojected scan returns to those read above it.
+/// `needed` is the fields of `plan`'s rows that are read, or `None` if whole
+/// rows are.
+fn narrow_scans(plan: &mut PhysicalPlan, needed: Option<FieldSet>) {
+    match plan {
+        PhysicalPlan::Scan {
+            predicate,
+            projections: None,
+            fields,
+            ..
+        } => *fields = with_fields(needed, predicate.iter_mut()),
+        PhysicalPlan::Scan { .. }
+        | PhysicalPlan::IdLookup { .. }
+        | PhysicalPlan::IndexScan { .. }
+        | PhysicalPlan::VectorScan { .. } => {}
+        PhysicalPlan::Filter { input, predicate }
+        | PhysicalPlan::BatchFilter { input, predicate } => {
+            narrow_scans(input, with_fields(needed, [predicate]))
+        }
+        PhysicalPlan::Project { input, projections }
+        | PhysicalPlan::BatchProject { input, projections } => {
+            // A wildcard passes the rows through, otherwise they are replaced
+            let needed = if projections
+                .iter()
+                .any(|p| matches!(p, Expression::Wildcard))
+            {
+                needed
+            } else {
+                Some(FieldSet::default())
+            };
+            let exprs = projections
+                .iter_mut()
+                .filter(|p| !matches!(p, Expression::Wildcard));
+            narrow_scans(input, with_fields(needed, exprs))
+        }
+        PhysicalPlan::Limit { input, .. }
+        | PhysicalPlan::Offset { input, .. }
+        | PhysicalPlan::Flatten { input }
+        | PhysicalPlan::BatchScan { input, .. }
+        | PhysicalPlan::BatchLimit { input, .. }
+        | PhysicalPlan::BatchOffset { input, .. } => narrow_scans(input, needed),
+        PhysicalPlan::Sort { input, keys } => narrow_scans(
+            input,
+            with_fields(needed, keys.iter_mut().map(|k| &mut k.expr)),
+        ),
+        PhysicalPlan::Unnest { input, expr, .. } => {
+            // Paths under the alias name the element, and are absent below
+            narrow_scans(input, with_fields(needed, [expr]))
+        }
+        PhysicalPlan::Window { input, windows } => {
+            let exprs = windows
+                .iter_mut()
+                .flat_map(|(call, _)| call.expressions_mut());
+            narrow_scans(input, with_fields(needed, exprs))
+        }
+        // Whole rows are compared
+        PhysicalPlan::Distinct { input, on } if on.is_empty() => narrow_scans(input, None),
+        PhysicalPlan::Distinct { input, on } => narrow_scans(input, with_fields(needed, on)),
+        PhysicalPlan::SetOperation { left, right, .. } => {
+            narrow_scans(left, None);
+            narrow_scans(right, None);
+        }
+        // Join rows hold each input under its alias
+        PhysicalPlan::HashJoin {
+            left,
+            right,
+            left_alias,
+            right_alias,
+            keys,
+            residual,
+            ..
+        } => {
+            let exprs = keys
+                .iter_mut()
+                .flat_map(|(l, r)| [l, r])
+                .chain(residual.iter_mut());
+            let row_fields = with_fields(needed, exprs);
+            let right_fields = row_fields.as_ref().and_then(|f| f.under(right_alias));
+            narrow_scans(left, join_input_fields(row_fields, *left_alias));
+            narrow_scans(right, right_fields);
+        }
+        PhysicalPlan::IndexJoin {
+            left,
+            left_alias,
+            left_key,
+            residual,
+            ..
+        } => {
+            let exprs = std::iter::once(left_key).chain(residual.iter_mut());
+            let row_fields = with_fields(needed, exprs);
+            narrow_scans(left, join_input_fields(row_fields, *left_alias));
         }
     }
 }
 
-pub struct BatchProjectOperator<'a> {
-    input: Box<dyn Iterator<Item = Batch> + 'a>,
-    projections: Vec<Expression<'a>>,
+/// Adds the fields `exprs` read to `needed`, returning `None` if whole rows
+/// are needed.
+fn with_fields<'e, 'a: 'e>(
+    needed: Option<FieldSet>,
+    exprs: impl IntoIterator<Item = &'e mut Expression<'a>>,
+) -> Option<FieldSet> {
+    let mut fields = needed?;
+    exprs
+        .into_iter()
+        .all(|expr| fields.add_expression(expr))
+        .then_some(fields)
 }
 
-impl<'a> BatchProjectOperator<'a> {
-    pub fn new(
-        input: Box<dyn Iterator<Item = Batch> + 'a>,
-        projections: Vec<Expression<'a>>,
-    ) -> Self {
-        BatchProjectOperator { input, projections }
+/// Returns the fields of a join's left input read, given those of its rows.
+fn join_input_fields(row_fields: Option<FieldSet>, alias: Option<&str>) -> Option<FieldSet> {
+    match alias {
+        Some(alias) => row_fields?.under(alias),
+        // The left input is a join, whose rows are keyed by alias already
+        None => row_fields,
     }
 }
 
-impl<'a> Iterator for BatchProjectOperator<'a> {
-    type Item = Batch;
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut batch = self.input.next()?;
-        let new_items = batch
-            .items
-            .drain(..)
-            .map(|item| {
-                let id = item.id().to_string();
-                let mut new_doc = BTreeMap::new();
-                for expr in &self.projections {
-                    let value = match &item {
-                        ExecutionResult::Value(_, doc) => evaluate_expression(expr, doc),
-                        ExecutionResult::Lazy(doc) => evaluate_expression_lazy(expr, doc),
-                    };
-                    match expr {
-                        Expression::FieldReference(_, raw) => {
-                            new_doc.insert(raw.to_string(), value);
-                        }
-                        Expression::JsonPath(_, raw) => {
-                            new_doc.insert(raw.to_string(), value);
-                        }
-                        _ => {}
-                    }
-                }
-                ExecutionResult::Value(id, Value::Object(new_doc))
-            })
-            .collect();
+// Evaluator
 
-        Some(Batch { items: new_items })
+/// Where the operators of a query report an error that ends their rows
+/// early, such as a failed read of a spill file. The first error is kept.
+#[derive(Clone, Default)]
+pub struct ErrorSlot(Rc<RefCell<Option<String>>>);
+
+impl ErrorSlot {
+    fn report(&self, error: String) {
+        let mut slot = self.0.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(error);
+        }
+    }
+
+    /// Returns the error reported, if any.
+    pub fn check(&self) -> Result<(), String> {
+        match self.0.borrow_mut().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
     }
 }
 
-// Evaluator
+/// The rows of a query. Once they run out, `check` tells whether an operator
+/// ended them early with an error, in which case they are incomplete.
+pub struct QueryRows<'a> {
+    rows: Box<dyn Iterator<Item = ExecutionResult> + 'a>,
+    errors: ErrorSlot,
+}
 
-pub fn execute_plan<'a>(
-    plan: LogicalPlan<'a>,
-    db: &'a DB,
-) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
+impl QueryRows<'_> {
+    /// Returns the error that ended the rows early, if any.
+    pub fn check(&self) -> Result<(), String> {
+        self.errors.check()
+    }
+}
+
+impl Iterator for QueryRows<'_> {
+    type Item = ExecutionResult;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rows.next()
+    }
+}
+
+pub fn execute_plan<'a>(mut plan: LogicalPlan<'a>, db: &'a DB) -> Result<QueryRows<'a>, String> {
     let span = span!(Level::DEBUG, "plan", plan = ?plan);
     let _enter = span.enter();
 
-    if is_vectorizable(&plan) {
-        let batch_iter = execute_batch_plan(plan, db, None)?;
-        Ok(Box::new(FlattenOperator::new(batch_iter)))
-    } else {
-        execute_row_plan(plan, db)
-    }
+    resolve_subqueries(&mut plan, db)?;
+    let errors = ErrorSlot::default();
+    let rows = open_rows(plan_physical(optimize(plan), db), db, None, &errors)?;
+    Ok(QueryRows { rows, errors })
 }
 
 fn is_vectorizable(plan: &LogicalPlan) -> bool {
     match plan {
         LogicalPlan::Scan { .. } => true,
+        LogicalPlan::Filter { predicate, .. } if extract_id_lookup(predicate).is_some() => false,
         LogicalPlan::Filter { input, predicate } => {
-            let simple_pred = if let Expression::Binary { left, op: _, right } = predicate {
-                matches!(
-                    (left.as_ref(), right.as_ref()),
-                    (
-                        Expression::FieldReference(_, _),
-                        Expression::Literal(Value::Number(_))
-                    )
-                )
-            } else {
-                false
-            };
-
-            if simple_pred {
-                is_vectorizable(input)
-            } else {
-                false
-            }
+            is_vectorizable_predicate(predicate) && is_vectorizable(input)
         }
         LogicalPlan::Project { input, .. } => is_vectorizable(input),
         LogicalPlan::Limit { input, .. } => is_vectorizable(input),
         LogicalPlan::Offset { input, .. } => is_vectorizable(input),
+        LogicalPlan::Sort { .. }
+        | LogicalPlan::Unnest { .. }
+        | LogicalPlan::Join { .. }
+        | LogicalPlan::Window { .. }
+        | LogicalPlan::Distinct { .. }
+        | LogicalPlan::SetOperation { .. } => false,
     }
 }
 
-fn execute_row_plan<'a>(
-    plan: LogicalPlan<'a>,
+/// Returns true if `expr` is `alias._id`.
+pub(crate) fn is_alias_id_reference(expr: &Expression, alias: &str) -> bool {
+    matches!(expr, Expression::FieldReference(parts, _)
+        if parts.len() == 2 && parts[0] == alias && is_id_reference(&parts[1..]))
+}
+
+/// Opens the row operators of a physical plan, recording their statistics
+/// with `profiler` if given and their errors in `errors`.
+pub(crate) fn open_rows<'a>(
+    plan: PhysicalPlan<'a>,
     db: &'a DB,
+    profiler: Option<&Profiler>,
+    errors: &ErrorSlot,
 ) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
-    match plan {
-        LogicalPlan::Scan { collection } => {
-            let iter = db.scan(&collection, None, None)?;
-            Ok(Box::new(ScanOperator::new(iter)))
-        }
-        LogicalPlan::Filter { input, predicate } => match *input {
-            LogicalPlan::Scan { collection } => {
-                let iter = db.scan(&collection, Some(predicate), None)?;
-                Ok(Box::new(ScanOperator::new(iter)))
-            }
-            other_input => {
-                let child = execute_row_plan(other_input, db)?;
-                Ok(Box::new(FilterOperator::new(child, predicate)))
-            }
-        },
-        LogicalPlan::Project { input, projections } => match *input {
-            LogicalPlan::Scan { collection } => {
-                let iter = db.scan(&collection, None, Some(projections))?;
-                Ok(Box::new(ScanOperator::new(iter)))
-            }
-            LogicalPlan::Filter {
-                input: inner,
-                predicate,
-            } => match *inner {
-                LogicalPlan::Scan { collection } => {
-                    let iter = db.scan(&collection, Some(predicate), Some(projections))?;
-                    Ok(Box::new(ScanOperator::new(iter)))
-                }
-                other_inner => {
-                    let input_node = LogicalPlan::Filter {
-                        input: Box::new(other_inner),
-                        predicate,
-                    };
-                    let child = execute_row_plan(input_node, db)?;
-                    Ok(Box::new(ProjectOperator::new(child, projections)))
-                }
-            },
-            other_input => {
-                let child = execute_row_plan(other_input, db)?;
-                Ok(Box::new(ProjectOperator::new(child, projections)))
-            }
-        },
-        LogicalPlan::Limit { input, limit } => {
-            let child = execute_row_plan(*input, db)?;
-            Ok(Box::new(LimitOperator::new(child, limit)))
-        }
-        LogicalPlan::Offset { input, offset } => {
-            let child = execute_row_plan(*input, db)?;
-            Ok(Box::new(OffsetOperator::new(child, offset)))
-        }
+    match profiler {
+        Some(p) => p.profile(db, || open_row_operator(plan, db, profiler, errors)),
+        None => open_row_operator(plan, db, None, errors),
     }
 }
 
-fn execute_batch_plan<'a>(
-    plan: LogicalPlan<'a>,
+fn open_row_operator<'a>(
+    plan: PhysicalPlan<'a>,
     db: &'a DB,
-    batch_size_hint: Option<usize>,
-) -> Result<Box<dyn Iterator<Item = Batch> + 'a>, String> {
-    match plan {
-        LogicalPlan::Scan { collection } => {
-            let iter = db.scan(&collection, None, None)?;
-            let batch_size = if let Some(limit) = batch_size_hint {
-                min(limit, BATCH_SIZE)
-            } else {
-                BATCH_SIZE
+    profiler: Option<&Profiler>,
+    errors: &ErrorSlot,
+) -> Result<Box<dyn Iterator<Item = ExecutionResult> + 'a>, String> {
+    let iter: Box<dyn Iterator<Item = ExecutionResult> + 'a> = match plan {
+        PhysicalPlan::Scan {
+            collection,
+            range,
+            predicate,
+            projections,
+            fields,
+        } => {
+            let iter = match range {
+                Some(range) => db.scan_range(&collection, range, predicate, projections, fields)?,
+                None => db.scan(&collection, predicate, projections, fields)?,
             };
-            let batch_size = std::cmp::max(batch_size, 1);
-            Ok(Box::new(BatchScanOperator::new(iter, batch_size)))
+            Box::new(ScanOperator::new(iter))
         }
-        LogicalPlan::Filter { input, predicate } => match *input {
-            LogicalPlan::Scan { collection } => {
-                // Since we verified vectorizability, we assume Simple Predicate.
-                // We DISABLE pushdown to use BatchFilter.
-                let iter = db.scan(&collection, None, None)?;
-                let batch_size = if let Some(limit) = batch_size_hint {
-                    min(limit, BATCH_SIZE)
-                } else {
-                    BATCH_SIZE
-                };
-                let batch_size = std::cmp::max(batch_size, 1);
-
-                let scan = Box::new(BatchScanOperator::new(iter, batch_size));
-                Ok(Box::new(BatchFilterOperator::new(scan, predicate)))
-            }
-            other_input => {
-                let child = execute_batch_plan(other_input, db, batch_size_hint)?;
-                Ok(Box::new(BatchFilterOperator::new(child, predicate)))
-            }
-        },
-        LogicalPlan::Project { input, projections } => {
-            match *input {
-                LogicalPlan::Scan { collection } => {
-                    let iter = db.scan(&collection, None, Some(projections))?;
-                    let batch_size = if let Some(limit) = batch_size_hint {
-                        min(limit, BATCH_SIZE)
-                    } else {
-                        BATCH_SIZE
-                    };
-                    let batch_size = std::cmp::max(batch_size, 1);
-                    Ok(Box::new(BatchScanOperator::new(iter, batch_size)))
-                }
-                LogicalPlan::Filter {
-                    input: inner,
-                    predicate,
-                } => {
-                    match *inner {
-                        LogicalPlan::Scan { collection } => {
-                            // If we are here, is_vectorizable(Project) was true.
-                            // is_vectorizable(Filter) must be true.
-                            // So simple predicate. We do NOT push down predicate.
-                            // But what about Project pushdown?
-                            // DB::scan takes (predicate, projections).
-                            // If we push down Project but NOT Filter?
-                            // db.scan(None, Some(projections)).
-                            // But BatchFilter needs fields to check predicate.
-                            // If we project out fields needed for predicate, we fail.
-                            // MergedIterator will project out everything except 'projections'.
-                            // If 'predicate' uses field 'x', and 'projections' uses 'y',
-                            // we receive 'y'. BatchFilter checks 'x' -> Null -> Fail.
-
-                            // COMPLEXITY:
-                            // To use BatchFilter, we must ensure fields are available.
-                            // If we push down Project, we must ensure predicate fields are included.
-                            // This logic is missing.
-                            // SAFE BET: If Project is involved, fallback to Row Plan?
-                            // OR: Just push down everything in Batch Plan too?
-                            // If we push down everything, we lose BatchFilter SIMD.
-                            // If we don't push down Project, we pay read cost.
-
-                            // DECISION: For simplicity and correctness, if Project is involved,
-                            // we fallback to Row Plan (Pushdown everything).
-                            // My is_vectorizable check for Project just recurses.
-                            // I should change is_vectorizable to return false if Project is present?
-                            // Or handle it.
-                            // If I return false for Project, then Project queries use Row Plan.
-                            // This ensures safety.
-                            // The benchmark queries `query_0*.sql` use Projection (SELECT TAN(...)).
-                            // So they will use Row Plan.
-                            // This GUARANTEES baseline performance for them.
-                            // AND simpler filters will use Batch.
-
-                            // I will modify is_vectorizable to return FALSE for Project for now.
-                            // This effectively limits Vectorization to `SELECT * FROM ... WHERE simple`.
-                            // This is a safe starting point.
-
-                            let iter = db.scan(&collection, Some(predicate), Some(projections))?;
-                            let batch_size = if let Some(limit) = batch_size_hint {
-                                min(limit, BATCH_SIZE)
-                            } else {
-                                BATCH_SIZE
-                            };
-                            let batch_size = std::cmp::max(batch_size, 1);
-                            Ok(Box::new(BatchScanOperator::new(iter, batch_size)))
-                        }
-                        other_inner => {
-                            let input_node = LogicalPlan::Filter {
-                                input: Box::new(other_inner),
-                                predicate,
-                            };
-                            let child = execute_batch_plan(input_node, db, batch_size_hint)?;
-                            Ok(Box::new(BatchProjectOperator::new(child, projections)))
-                        }
-                    }
-                }
-                other_input => {
-                    let child = execute_batch_plan(other_input, db, batch_size_hint)?;
-                    Ok(Box::new(BatchProjectOperator::new(child, projections)))
-                }
+        PhysicalPlan::IndexScan {
+            collection,
+            index,
+            ranges,
+            predicate,
+            projections,
+        } => db.index_scan(&collection, &index, &ranges, predicate, projections)?,
+        PhysicalPlan::VectorScan {
+            collection,
+            index,
+            vector,
+            metric,
+            k,
+        } => db.vector_scan(&collection, &index, &vector, metric, k)?,
+        PhysicalPlan::IdLookup { collection, keys } => {
+            if !db.has_collection(&collection) {
+                return Err(format!("Collection '{}' not found", collection));
             }
+            Box::new(keys.into_iter().filter_map(move |key| {
+                let doc = db.get(&collection, &key).ok().flatten()?;
+                Some(ExecutionResult::Value(key, doc))
+            }))
+        }
+        PhysicalPlan::Filter { input, predicate } => Box::new(FilterOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            predicate,
+        )),
+        PhysicalPlan::Project { input, projections } => Box::new(ProjectOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            projections,
+        )),
+        PhysicalPlan::Limit { input, limit } => Box::new(LimitOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            limit,
+        )),
+        PhysicalPlan::Offset { input, offset } => Box::new(OffsetOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            offset,
+        )),
+        PhysicalPlan::Sort { input, keys } => Box::new(SortOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            keys,
+        )),
+        PhysicalPlan::Unnest { input, expr, alias } => Box::new(UnnestOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            expr,
+            alias,
+        )),
+        PhysicalPlan::HashJoin {
+            left,
+            right,
+            kind,
+            left_alias,
+            right_alias,
+            keys,
+            residual,
+        } => {
+            let left = open_rows(*left, db, profiler, errors)?;
+            let right = open_rows(*right, db, profiler, errors)?;
+            Box::new(HashJoinOperator::new(
+                left,
+                right,
+                kind,
+                left_alias,
+                right_alias,
+                keys,
+                residual,
+                db,
+                errors.clone(),
+            )?)
         }
-        LogicalPlan::Limit { input, limit } => {
-            let child = execute_batch_plan(*input, db, Some(limit))?;
-            Ok(Box::new(BatchLimitOperator::new(child, limit)))
+        PhysicalPlan::IndexJoin {
+            left,
+            collection,
+            kind,
+            left_alias,
+            right_alias,
+            left_key,
+            residual,
+        } => Box::new(IndexJoinOperator::new(
+            open_rows(*left, db, profiler, errors)?,
+            db,
+            collection,
+            kind,
+            left_alias,
+            right_alias,
+            left_key,
+            residual,
+            errors.clone(),
+        )?),
+        PhysicalPlan::Window { input, windows } => Box::new(WindowOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            windows,
+        )?),
+        PhysicalPlan::Distinct { input, on } => Box::new(DistinctOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            on,
+            db,
+            errors.clone(),
+        )),
+        PhysicalPlan::SetOperation {
+            left,
+            right,
+            op,
+            all,
+        } => {
+            let left = open_rows(*left, db, profiler, errors)?;
+            let right = open_rows(*right, db, profiler, errors)?;
+            Box::new(SetOperationOperator::new(left, right, op, all))
         }
-        LogicalPlan::Offset { input, offset } => {
-            let child_hint = batch_size_hint.map(|l| l + offset);
-            let child = execute_batch_plan(*input, db, child_hint)?;
-            Ok(Box::new(BatchOffsetOperator::new(child, offset)))
+        PhysicalPlan::Flatten { input } => Box::new(FlattenOperator::new(open_batches(
+            *input, db, profiler, errors,
+        )?)),
+        PhysicalPlan::BatchScan { .. }
+        | PhysicalPlan::BatchFilter { .. }
+        | PhysicalPlan::BatchProject { .. }
+        | PhysicalPlan::BatchLimit { .. }
+        | PhysicalPlan::BatchOffset { .. } => {
+            unreachable!("batch operator where rows are expected")
         }
+    };
+    Ok(iter)
+}
+
+fn open_batches<'a>(
+    plan: PhysicalPlan<'a>,
+    db: &'a DB,
+    profiler: Option<&Profiler>,
+    errors: &ErrorSlot,
+) -> Result<Box<dyn Iterator<Item = Batch> + 'a>, String> {
+    match profiler {
+        Some(p) => p.profile(db, || open_batch_operator(plan, db, profiler, errors)),
+        None => open_batch_operator(plan, db, None, errors),
     }
 }
+
+fn open_batch_operator<'a>(
+    plan: PhysicalPlan<'a>,
+    db: &'a DB,
+    profiler: Option<&Profiler>,
+    errors: &ErrorSlot,
+) -> Result<Box<dyn Iterator<Item = Batch> + 'a>, String> {
+    let iter: Box<dyn Iterator<Item = Batch> + 'a> = match plan {
+        PhysicalPlan::BatchScan { input, batch_size } => Box::new(BatchScanOperator::new(
+            open_rows(*input, db, profiler, errors)?,
+            batch_size,
+        )),
+        PhysicalPlan::BatchFilter { input, predicate } => Box::new(BatchFilterOperator::new(
+            open_batches(*input, db, profiler, errors)?,
+            predicate,
+        )),
+        PhysicalPlan::BatchProject { input, projections } => Box::new(BatchProjectOperator::new(
+            open_batches(*input, db, profiler, errors)?,
+            projections,
+        )),
+        PhysicalPlan::BatchLimit { input, limit } => Box::new(BatchLimitOperator::new(
+            open_batches(*input, db, profiler, errors)?,
+            limit,
+        )),
+        PhysicalPlan::BatchOffset { input, offset } => Box::new(BatchOffsetOperator::new(
+            open_batches(*input, db, profiler, errors)?,
+            offset,
+        )),
+        _ => unreachable!("row operator where batches are expected"),
+    };
+    Ok(iter)
+}
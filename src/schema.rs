@@ -1,8 +1,13 @@
 use crate::Value;
+use crate::expression::{BinaryOperator, Expression, LogicalOperator, is_id_reference};
+use crate::temporal;
 pub use jsonb_schema::schema::{InstanceType, Schema, SingleOrVec};
 use jsonb_schema::{Number, Value as JsonbValue};
 use std::collections::BTreeMap;
 
+/// JSON Schema `format` for RFC 3339 date-time strings.
+pub const DATE_TIME_FORMAT: &str = "date-time";
+
 pub trait SchemaExt {
     fn new(instance_type: InstanceType) -> Self;
     fn merge(&mut self, other: Self);
@@ -17,6 +22,15 @@ impl SchemaExt for Schema {
     }
 
     fn merge(&mut self, other: Self) {
+        // A string format survives only if every string seen so far had it
+        if has_type(&other, &InstanceType::String) {
+            if !has_type(self, &InstanceType::String) {
+                self.format = other.format.clone();
+            } else if self.format != other.format {
+                self.format = None;
+            }
+        }
+
         // Merge instance_type
         if let Some(other_type) = other.instance_type {
             match &mut self.instance_type {
@@ -69,6 +83,14 @@ impl SchemaExt for Schema {
     }
 }
 
+fn has_type(schema: &Schema, instance_type: &InstanceType) -> bool {
+    match &schema.instance_type {
+        Some(SingleOrVec::Single(t)) => t == instance_type,
+        Some(SingleOrVec::Vec(v)) => v.contains(instance_type),
+        None => false,
+    }
+}
+
 pub fn infer_schema(doc: &Value) -> Schema {
     match doc {
         JsonbValue::Null => Schema::new(InstanceType::Null),
@@ -78,7 +100,13 @@ pub fn infer_schema(doc: &Value) -> Schema {
             Number::Float64(_) => Schema::new(InstanceType::Number),
             _ => Schema::new(InstanceType::Number),
         },
-        JsonbValue::String(_) => Schema::new(InstanceType::String),
+        JsonbValue::String(s) => {
+            let mut schema = Schema::new(InstanceType::String);
+            if temporal::is_date_time(s) {
+                schema.format = Some(DATE_TIME_FORMAT.to_string());
+            }
+            schema
+        }
         JsonbValue::Array(arr) => {
             let mut items_schema = if let Some(first) = arr.first() {
                 infer_schema(first)
@@ -107,6 +135,129 @@ pub fn infer_schema(doc: &Value) -> Schema {
     }
 }
 
+/// Returns false if no document described by `schema` can satisfy
+/// `predicate`, as a field it compares with a value is absent or never of the
+/// value's type.
+pub fn may_match(schema: &Schema, predicate: &Expression) -> bool {
+    match predicate {
+        Expression::Logical { left, op, right } => match op {
+            LogicalOperator::And => may_match(schema, left) && may_match(schema, right),
+            LogicalOperator::Or => may_match(schema, left) || may_match(schema, right),
+        },
+        Expression::Binary { left, op, right } => {
+            let comparison = matches!(
+                op,
+                BinaryOperator::Eq
+                    | BinaryOperator::Lt
+                    | BinaryOperator::Lte
+                    | BinaryOperator::Gt
+                    | BinaryOperator::Gte
+            );
+            match (&**left, &**right) {
+                (Expression::FieldReference(parts, _), Expression::Literal(literal))
+                | (Expression::Literal(literal), Expression::FieldReference(parts, _))
+                    if comparison =>
+                {
+                    may_compare(schema, parts, literal)
+                }
+                _ => true,
+            }
+        }
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated: false,
+        } => match (&**expr, &**low, &**high) {
+            (
+                Expression::FieldReference(parts, _),
+                Expression::Literal(low),
+                Expression::Literal(high),
+            ) => may_compare(schema, parts, low) && may_compare(schema, parts, high),
+            _ => true,
+        },
+        Expression::InList {
+            expr,
+            list,
+            negated: false,
+        } => match &**expr {
+            Expression::FieldReference(parts, _) => list.iter().any(|item| match item {
+                Expression::Literal(literal) => may_compare(schema, parts, literal),
+                _ => true,
+            }),
+            _ => true,
+        },
+        Expression::IsNull {
+            expr,
+            negated: true,
+        } => match &**expr {
+            Expression::FieldReference(parts, _) if !is_id_reference(parts) => {
+                may_have(schema, parts, &|_| true)
+            }
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+/// Returns true if the field at `parts` is a `date-time` string wherever it
+/// is present and not `NULL`.
+pub fn is_date_time(schema: &Schema, parts: &[&str]) -> bool {
+    match parts.split_first() {
+        None => {
+            schema.format.as_deref() == Some(DATE_TIME_FORMAT)
+                && match &schema.instance_type {
+                    Some(SingleOrVec::Single(t)) => *t == InstanceType::String,
+                    Some(SingleOrVec::Vec(v)) => v
+                        .iter()
+                        .all(|t| matches!(t, InstanceType::String | InstanceType::Null)),
+                    None => false,
+                }
+        }
+        Some((name, rest)) => schema
+            .properties
+            .as_ref()
+            .and_then(|properties| properties.get(*name))
+            .is_some_and(|field| is_date_time(field, rest)),
+    }
+}
+
+/// Returns false if the field at `parts` is never of a type `literal`
+/// compares with.
+fn may_compare(schema: &Schema, parts: &[&str], literal: &Value) -> bool {
+    let types: &[InstanceType] = match literal {
+        JsonbValue::Bool(_) => &[InstanceType::Boolean],
+        JsonbValue::Number(_) => &[InstanceType::Integer, InstanceType::Number],
+        JsonbValue::String(_) => &[InstanceType::String],
+        JsonbValue::Array(_) => &[InstanceType::Array],
+        JsonbValue::Object(_) => &[InstanceType::Object],
+        _ => return true,
+    };
+    is_id_reference(parts) || may_have(schema, parts, &|t| types.contains(t))
+}
+
+/// Returns whether the field at `parts` may be present with a type for which
+/// `accepts` holds. A schema without types may describe anything.
+fn may_have(schema: &Schema, parts: &[&str], accepts: &dyn Fn(&InstanceType) -> bool) -> bool {
+    let types = match &schema.instance_type {
+        Some(SingleOrVec::Single(t)) => std::slice::from_ref(t),
+        Some(SingleOrVec::Vec(v)) => v.as_slice(),
+        None => return true,
+    };
+    match parts.split_first() {
+        None => types.iter().any(accepts),
+        // Only objects have fields
+        Some((name, rest)) => {
+            types.contains(&InstanceType::Object)
+                && schema.properties.as_ref().is_none_or(|properties| {
+                    properties
+                        .get(*name)
+                        .is_some_and(|field| may_have(field, rest, accepts))
+                })
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -223,6 +374,52 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_infer_date_time_format() {
+        let schema = infer_schema(&serde_to_jsonb(json!({
+            "ts": "2024-05-16T13:45:30Z",
+            "name": "2024 report"
+        })));
+        let props = schema.properties.as_ref().unwrap();
+        assert_eq!(
+            props.get("ts").unwrap().format.as_deref(),
+            Some(DATE_TIME_FORMAT)
+        );
+        assert_eq!(props.get("name").unwrap().format, None);
+
+        // Non-string types do not affect the format
+        let mut merged = infer_schema(&serde_to_jsonb(json!("2024-05-16T13:45:30Z")));
+        merged.merge(infer_schema(&serde_to_jsonb(json!(1715867130))));
+        assert_eq!(merged.format.as_deref(), Some(DATE_TIME_FORMAT));
+        let mut merged_rev = infer_schema(&serde_to_jsonb(json!(1715867130)));
+        merged_rev.merge(infer_schema(&serde_to_jsonb(json!("2024-05-16T13:45:30Z"))));
+        assert_eq!(merged_rev.format.as_deref(), Some(DATE_TIME_FORMAT));
+
+        // Any plain string drops it
+        merged.merge(infer_schema(&serde_to_jsonb(json!("yesterday"))));
+        assert_eq!(merged.format, None);
+        merged.merge(infer_schema(&serde_to_jsonb(json!("2024-05-16T13:45:30Z"))));
+        assert_eq!(merged.format, None);
+    }
+
+    #[test]
+    fn test_is_date_time() {
+        let mut schema = infer_schema(&serde_to_jsonb(json!({
+            "ts": "2024-05-16T13:45:30Z",
+            "at": {"start": "2024-05-16T13:45:30+02:00"},
+            "name": "2024 report"
+        })));
+        schema.merge(infer_schema(&serde_to_jsonb(json!({"ts": null}))));
+        assert!(is_date_time(&schema, &["ts"]));
+        assert!(is_date_time(&schema, &["at", "start"]));
+        assert!(!is_date_time(&schema, &["name"]));
+        assert!(!is_date_time(&schema, &["missing"]));
+
+        // Numbers are not compared as timestamps
+        schema.merge(infer_schema(&serde_to_jsonb(json!({"ts": 1715867130}))));
+        assert!(!is_date_time(&schema, &["ts"]));
+    }
+
     #[test]
     fn test_schema_type_variants() {
         assert_eq!(
@@ -254,4 +451,73 @@ mod tests {
             r#""array""#
         );
     }
+
+    fn compare<'a>(path: &'a str, op: BinaryOperator, value: serde_json::Value) -> Expression<'a> {
+        Expression::Binary {
+            left: Box::new(Expression::FieldReference(path.split('.').collect(), path)),
+            op,
+            right: Box::new(Expression::Literal(serde_to_jsonb(value))),
+        }
+    }
+
+    #[test]
+    fn test_may_match() {
+        let mut schema = infer_schema(&serde_to_jsonb(json!({
+            "x": "a",
+            "n": 1,
+            "o": {"p": true},
+            "tags": ["a"]
+        })));
+        schema.merge(infer_schema(&serde_to_jsonb(json!({"n": 2.5}))));
+        let check = |path, op, value| may_match(&schema, &compare(path, op, value));
+
+        assert!(check("x", BinaryOperator::Eq, json!("b")));
+        assert!(check("n", BinaryOperator::Gt, json!(5)));
+        assert!(check("o.p", BinaryOperator::Eq, json!(false)));
+        assert!(check("_id", BinaryOperator::Eq, json!(1)));
+        // Absent fields
+        assert!(!check("y", BinaryOperator::Eq, json!("a")));
+        assert!(!check("o.q", BinaryOperator::Eq, json!(false)));
+        assert!(!check("x.y", BinaryOperator::Eq, json!(1)));
+        assert!(!check("tags.a", BinaryOperator::Eq, json!("a")));
+        // Impossible types
+        assert!(!check("x", BinaryOperator::Gt, json!(5)));
+        assert!(!check("n", BinaryOperator::Lte, json!("a")));
+        assert!(check("x", BinaryOperator::Neq, json!(5)));
+
+        let impossible = compare("x", BinaryOperator::Gt, json!(5));
+        let possible = compare("n", BinaryOperator::Gt, json!(5));
+        let or = Expression::Logical {
+            left: Box::new(impossible.clone()),
+            op: LogicalOperator::Or,
+            right: Box::new(possible.clone()),
+        };
+        assert!(may_match(&schema, &or));
+        let and = Expression::Logical {
+            left: Box::new(impossible),
+            op: LogicalOperator::And,
+            right: Box::new(possible),
+        };
+        assert!(!may_match(&schema, &and));
+
+        let field = |path| Box::new(Expression::FieldReference(vec![path], path));
+        let literal = |value| Expression::Literal(serde_to_jsonb(value));
+        let in_list = |list| Expression::InList {
+            expr: field("n"),
+            list,
+            negated: false,
+        };
+        assert!(may_match(
+            &schema,
+            &in_list(vec![literal(json!("a")), literal(json!(2))])
+        ));
+        assert!(!may_match(&schema, &in_list(vec![literal(json!("a"))])));
+        let is_null = |path, negated| Expression::IsNull {
+            expr: field(path),
+            negated,
+        };
+        assert!(may_match(&schema, &is_null("o", true)));
+        assert!(!may_match(&schema, &is_null("y", true)));
+        assert!(may_match(&schema, &is_null("y", false)));
+    }
 }
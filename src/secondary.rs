@@ -0,0 +1,645 @@
+use crate::expression::{compare_values, get_i64_from_number, total_cmp, type_rank};
+use crate::fulltext;
+use crate::geo::{self, Geometry};
+use crate::vector;
+use crate::{SerdeWrapper, Value, make_static};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// File listing the secondary indexes of a collection, in its directory.
+pub const DEFINITIONS_FILE: &str = "indexes.json";
+
+/// The name, field path and kind of a secondary index, as persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub name: String,
+    pub path: Vec<String>,
+    #[serde(default)]
+    pub kind: IndexKind,
+}
+
+/// What an index maps to document IDs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum IndexKind {
+    /// The value of the field, for comparisons.
+    #[default]
+    Value,
+    /// Each term of the field's text, for `MATCH`.
+    FullText,
+    /// The field's array of numbers, for nearest neighbours by
+    /// `VECTOR_DISTANCE`.
+    Vector,
+    /// The geohash of the smallest cell holding the field's GeoJSON geometry,
+    /// for spatial predicates.
+    Spatial,
+}
+
+/// A secondary index on a document field, kept as its own LSM tree: a
+/// memtable of `(value, id)` entries, frozen alongside the collection's
+/// memtable while it is flushed, and an index table written next to each
+/// JSTable, sorted by value or, for a vector index, clustered.
+///
+/// Entries are not removed when a document is updated or deleted, so a
+/// lookup returns candidate IDs whose current documents must be checked.
+pub struct SecondaryIndex {
+    pub name: String,
+    pub path: Vec<String>,
+    pub kind: IndexKind,
+    pub memtable: IndexMemTable,
+    pub frozen_memtable: Option<Arc<IndexMemTable>>,
+}
+
+impl SecondaryIndex {
+    pub fn new(definition: IndexDefinition) -> Self {
+        SecondaryIndex {
+            name: definition.name,
+            path: definition.path,
+            kind: definition.kind,
+            memtable: IndexMemTable::default(),
+            frozen_memtable: None,
+        }
+    }
+
+    pub fn definition(&self) -> IndexDefinition {
+        IndexDefinition {
+            name: self.name.clone(),
+            path: self.path.clone(),
+            kind: self.kind,
+        }
+    }
+
+    /// Returns true if the index is of the given kind on the field at `parts`.
+    pub fn covers(&self, parts: &[&str], kind: IndexKind) -> bool {
+        self.kind == kind
+            && self.path.len() == parts.len()
+            && self.path.iter().zip(parts).all(|(a, b)| a == b)
+    }
+}
+
+/// Returns the path of the index table written alongside JSTable `table`.
+pub fn table_path(dir: &Path, table: u64, file_name: &str) -> PathBuf {
+    dir.join(format!("jstable-{}.{}.index", table, file_name))
+}
+
+/// Returns the value of `doc` at `path`, if it has one.
+pub fn field<'v>(doc: &'v Value, path: &[String]) -> Option<&'v Value> {
+    let mut current = doc;
+    for part in path {
+        match current {
+            Value::Object(fields) => current = fields.get(part)?,
+            _ => return None,
+        }
+    }
+    Some(current)
+}
+
+/// A value ordered by `total_cmp`.
+#[derive(Debug, Clone)]
+struct IndexKey(Value);
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        total_cmp(&self.0, &other.0)
+    }
+}
+
+/// The in-memory `(value, id)` entries of an index, sorted by value, and
+/// the number of documents and terms indexed, for full-text statistics.
+#[derive(Default)]
+pub struct IndexMemTable {
+    entries: BTreeSet<(IndexKey, String)>,
+    documents: u64,
+    tokens: u64,
+}
+
+impl IndexMemTable {
+    /// Adds the entries for the value of `doc` at `path`: the value itself,
+    /// unless it is missing or null, as no comparison holds for those, for
+    /// a full-text index each distinct term of a string, for a vector index
+    /// an array of numbers, and for a spatial index the geohash of the cell
+    /// of a geometry.
+    pub fn insert(&mut self, kind: IndexKind, path: &[String], id: &str, doc: &Value) {
+        let Some(current) = field(doc, path) else {
+            return;
+        };
+        match (kind, current) {
+            (_, Value::Null) => {}
+            (IndexKind::Value, value) => {
+                self.entries
+                    .insert((IndexKey(value.clone()), id.to_string()));
+                self.documents += 1;
+            }
+            (IndexKind::FullText, Value::String(text)) => {
+                let terms = fulltext::tokenize(text);
+                self.documents += 1;
+                self.tokens += terms.len() as u64;
+                for term in terms {
+                    let term = Value::String(term.into());
+                    self.entries.insert((IndexKey(term), id.to_string()));
+                }
+            }
+            (IndexKind::Vector, value) => {
+                if vector::to_vector(value).is_some() {
+                    self.entries
+                        .insert((IndexKey(value.clone()), id.to_string()));
+                    self.documents += 1;
+                }
+            }
+            (IndexKind::Spatial, value) => {
+                if let Some(geometry) = Geometry::from_value(value) {
+                    let cell = Value::String(geo::cell(&geometry).into());
+                    self.entries.insert((IndexKey(cell), id.to_string()));
+                    self.documents += 1;
+                }
+            }
+            (IndexKind::FullText, _) => {}
+        }
+    }
+
+    /// Returns the number of documents and terms indexed.
+    pub fn counts(&self) -> (u64, u64) {
+        (self.documents, self.tokens)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns the IDs of the entries whose values fall in `range`.
+    pub fn ids<'a>(&'a self, range: &'a IndexRange) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .skip_while(move |(key, _)| range.position(&key.0) == Ordering::Less)
+            .take_while(move |(key, _)| range.position(&key.0) == Ordering::Equal)
+            .map(|(_, id)| id.as_str())
+    }
+
+    /// Returns the IDs and vectors of the entries of a vector index.
+    pub fn vectors(&self) -> impl Iterator<Item = (&str, Vec<f64>)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, id)| Some((id.as_str(), vector::to_vector(&key.0)?)))
+    }
+
+    /// Writes the entries as an index table: a length-prefixed JSONB
+    /// `[documents, tokens]` record holding the counts, a `[values, offsets]`
+    /// record holding a sparse index of the entries, then `[value, id]`
+    /// records in value order. The sparse index has the value and offset
+    /// (from the first entry) of the first entry and of every entry starting
+    /// at least `index_threshold` bytes after the previous indexed one. A
+    /// vector index is written as a table of clustered vectors instead, see
+    /// `vector::write_table`.
+    pub fn write(&self, kind: IndexKind, path: &Path, index_threshold: u64) -> io::Result<()> {
+        if kind == IndexKind::Vector {
+            return vector::write_table(path, self.vectors());
+        }
+        // Entries are laid out first, as the sparse index precedes them
+        let mut entries = Vec::new();
+        let mut values = Vec::new();
+        let mut offsets: Vec<u64> = Vec::new();
+        for (key, id) in &self.entries {
+            let offset = entries.len() as u64;
+            if offsets
+                .last()
+                .is_none_or(|last| offset - last >= index_threshold)
+            {
+                values.push(SerdeWrapper(&key.0));
+                offsets.push(offset);
+            }
+            write_record(&mut entries, &(SerdeWrapper(&key.0), id))?;
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        write_record(&mut file, &(self.documents, self.tokens))?;
+        write_record(&mut file, &(values, offsets))?;
+        file.write_all(&entries)?;
+        file.flush()
+    }
+}
+
+fn write_record<W: Write, T: Serialize>(writer: &mut W, record: &T) -> io::Result<()> {
+    let record_bytes = jsonb_schema::to_owned_jsonb(record)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .to_vec();
+    writer.write_all(&(record_bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&record_bytes)
+}
+
+/// Reads the next record of an index table as a pair, or None at its end.
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<(Value, Value)>> {
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut record_blob = vec![0u8; u32::from_le_bytes(len_buf) as usize];
+    reader.read_exact(&mut record_blob)?;
+    let record = jsonb_schema::from_slice(&record_blob)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    match make_static(&record) {
+        Value::Array(mut record) if record.len() == 2 => {
+            let second = record.pop().unwrap();
+            Ok(Some((record.pop().unwrap(), second)))
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid index record",
+        )),
+    }
+}
+
+/// Reads the number of documents and terms indexed in an index table.
+pub fn read_counts(path: &Path) -> io::Result<(u64, u64)> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match read_record(&mut reader)? {
+        Some((Value::Number(documents), Value::Number(tokens))) => Ok((
+            get_i64_from_number(&documents).unwrap_or(0) as u64,
+            get_i64_from_number(&tokens).unwrap_or(0) as u64,
+        )),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid index table header",
+        )),
+    }
+}
+
+/// Reads the IDs of the entries of an index table whose values fall in
+/// `range`, starting from the last entry of its sparse index before the
+/// range.
+pub fn read_ids(path: &Path, range: &IndexRange) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    // Skip the counts
+    read_record(&mut reader)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid index table");
+    let (Value::Array(values), Value::Array(offsets)) =
+        read_record(&mut reader)?.ok_or_else(invalid)?
+    else {
+        return Err(invalid());
+    };
+    let before = values
+        .partition_point(|value| range.position(value) == Ordering::Less)
+        .saturating_sub(1);
+    let offset = match offsets.get(before) {
+        Some(Value::Number(offset)) => get_i64_from_number(offset).ok_or_else(invalid)?,
+        Some(_) => return Err(invalid()),
+        None => 0, // No entries
+    };
+    reader.seek_relative(offset)?;
+
+    let mut ids = Vec::new();
+    while let Some((value, id)) = read_record(&mut reader)? {
+        let Value::String(id) = id else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid index record",
+            ));
+        };
+        match range.position(&value) {
+            Ordering::Less => {}
+            Ordering::Equal => ids.push(id.to_string()),
+            Ordering::Greater => break,
+        }
+    }
+    Ok(ids)
+}
+
+/// A range of indexed values, bounded on at least one side. Values of other
+/// types than its bounds lie outside it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexRange {
+    pub lower: Bound<Value>,
+    pub upper: Bound<Value>,
+}
+
+impl IndexRange {
+    /// Returns whether `value` sorts before, within or after the range, in
+    /// index order. Within a type, `compare_values` agrees with that order,
+    /// so the values in the range are contiguous.
+    pub fn position(&self, value: &Value) -> Ordering {
+        let bound = match (&self.lower, &self.upper) {
+            (Bound::Included(v) | Bound::Excluded(v), _)
+            | (_, Bound::Included(v) | Bound::Excluded(v)) => v,
+            (Bound::Unbounded, Bound::Unbounded) => return Ordering::Equal,
+        };
+        match type_rank(value).cmp(&type_rank(bound)) {
+            Ordering::Equal => {}
+            other => return other,
+        }
+        let below = match &self.lower {
+            Bound::Included(v) => compare_values(value, v) == Some(Ordering::Less),
+            Bound::Excluded(v) => compare_values(value, v) != Some(Ordering::Greater),
+            Bound::Unbounded => false,
+        };
+        let above = match &self.upper {
+            Bound::Included(v) => compare_values(value, v) == Some(Ordering::Greater),
+            Bound::Excluded(v) => compare_values(value, v) != Some(Ordering::Less),
+            Bound::Unbounded => false,
+        };
+        if below {
+            Ordering::Less
+        } else if above {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }
+    }
+
+    /// Narrows the range to its intersection with `other`. Bounds of other
+    /// types are ignored, which only widens the range.
+    pub fn intersect(&mut self, other: IndexRange) {
+        if tighter(&other.lower, &self.lower, Ordering::Greater) {
+            self.lower = other.lower;
+        }
+        if tighter(&other.upper, &self.upper, Ordering::Less) {
+            self.upper = other.upper;
+        }
+    }
+
+    /// Returns the range holding just a string, such as a full-text term.
+    pub fn term(term: &str) -> Self {
+        let term = Value::String(term.to_string().into());
+        IndexRange {
+            lower: Bound::Included(term.clone()),
+            upper: Bound::Included(term),
+        }
+    }
+
+    /// Returns the range holding the geohashes that start with `prefix`,
+    /// as no geohash character sorts after `~`.
+    pub fn prefix(prefix: &str) -> Self {
+        IndexRange {
+            lower: Bound::Included(Value::String(prefix.to_string().into())),
+            upper: Bound::Excluded(Value::String(format!("{}~", prefix).into())),
+        }
+    }
+
+    /// Returns the ranges of the cells of a spatial index overlapping any of
+    /// the geohash cells `cover`: the cells in them, and the larger cells
+    /// holding them.
+    pub fn overlapping(cover: &[String]) -> Vec<Self> {
+        let mut holding: Vec<&str> = cover
+            .iter()
+            .flat_map(|cell| (0..cell.len()).map(move |len| &cell[..len]))
+            .collect();
+        holding.sort();
+        holding.dedup();
+        let mut ranges: Vec<IndexRange> = cover.iter().map(|cell| Self::prefix(cell)).collect();
+        ranges.extend(holding.into_iter().map(Self::term));
+        ranges
+    }
+
+    /// Returns true if the range holds at most one value.
+    pub fn is_point(&self) -> bool {
+        matches!((&self.lower, &self.upper), (Bound::Included(low), Bound::Included(high))
+            if compare_values(low, high) == Some(Ordering::Equal))
+    }
+}
+
+/// Returns true if `bound` excludes more values than `current`, where
+/// `direction` is the ordering of a tighter bound's value.
+fn tighter(bound: &Bound<Value>, current: &Bound<Value>, direction: Ordering) -> bool {
+    match (bound, current) {
+        (Bound::Unbounded, _) => false,
+        (_, Bound::Unbounded) => true,
+        (
+            Bound::Included(new) | Bound::Excluded(new),
+            Bound::Included(old) | Bound::Excluded(old),
+        ) => match compare_values(new, old) {
+            Some(Ordering::Equal) => {
+                matches!((bound, current), (Bound::Excluded(_), Bound::Included(_)))
+            }
+            ordering => ordering == Some(direction),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_to_jsonb;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn value(v: serde_json::Value) -> Value {
+        serde_to_jsonb(v)
+    }
+
+    fn range(lower: Bound<serde_json::Value>, upper: Bound<serde_json::Value>) -> IndexRange {
+        IndexRange {
+            lower: lower.map(value),
+            upper: upper.map(value),
+        }
+    }
+
+    #[test]
+    fn test_index_range_position() {
+        let r = range(Bound::Excluded(json!(5)), Bound::Included(json!(10)));
+        assert_eq!(r.position(&value(json!(5))), Ordering::Less);
+        assert_eq!(r.position(&value(json!(5.5))), Ordering::Equal);
+        assert_eq!(r.position(&value(json!(10))), Ordering::Equal);
+        assert_eq!(r.position(&value(json!(11))), Ordering::Greater);
+        assert_eq!(r.position(&value(json!(true))), Ordering::Less);
+        assert_eq!(r.position(&value(json!("7"))), Ordering::Greater);
+
+        let mut r = range(Bound::Included(json!(1)), Bound::Unbounded);
+        r.intersect(range(Bound::Excluded(json!(1)), Bound::Excluded(json!(3))));
+        assert_eq!(
+            r,
+            range(Bound::Excluded(json!(1)), Bound::Excluded(json!(3)))
+        );
+        r.intersect(range(
+            Bound::Included(json!(0)),
+            Bound::Included(json!("z")),
+        ));
+        assert_eq!(
+            r,
+            range(Bound::Excluded(json!(1)), Bound::Excluded(json!(3)))
+        );
+        assert!(!r.is_point());
+        assert!(range(Bound::Included(json!(2)), Bound::Included(json!(2.0))).is_point());
+    }
+
+    #[test]
+    fn test_index_memtable_and_table() -> io::Result<()> {
+        let path = vec!["a".to_string(), "b".to_string()];
+        let mut memtable = IndexMemTable::default();
+        for (id, doc) in [
+            ("1", json!({"a": {"b": 3}})),
+            ("2", json!({"a": {"b": "x"}})),
+            ("3", json!({"a": {"b": 1.5}})),
+            ("4", json!({"a": {"b": null}})),
+            ("5", json!({"a": 1})),
+            ("6", json!({"a": {"b": 3}})),
+        ] {
+            memtable.insert(IndexKind::Value, &path, id, &value(doc));
+        }
+        assert_eq!(memtable.len(), 4);
+
+        let r = range(Bound::Included(json!(1)), Bound::Included(json!(3)));
+        assert_eq!(memtable.ids(&r).collect::<Vec<_>>(), vec!["3", "1", "6"]);
+
+        let dir = tempdir()?;
+        let table = table_path(dir.path(), 0, "idx");
+        memtable.write(IndexKind::Value, &table, 1024)?;
+        assert_eq!(read_ids(&table, &r)?, vec!["3", "1", "6"]);
+        let r = range(Bound::Included(json!("x")), Bound::Included(json!("x")));
+        assert_eq!(read_ids(&table, &r)?, vec!["2"]);
+        assert_eq!(read_counts(&table)?, (4, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_table_seek() -> io::Result<()> {
+        let path = vec!["a".to_string()];
+        let mut memtable = IndexMemTable::default();
+        for i in 0..100 {
+            let doc = value(json!({ "a": i }));
+            memtable.insert(IndexKind::Value, &path, &format!("{:03}", i), &doc);
+        }
+        let dir = tempdir()?;
+        let table = table_path(dir.path(), 0, "idx");
+        // About one sparse index entry per three entries
+        memtable.write(IndexKind::Value, &table, 64)?;
+
+        let ids = |lower, upper| read_ids(&table, &range(lower, upper));
+        assert_eq!(
+            ids(Bound::Excluded(json!(40)), Bound::Included(json!(43)))?,
+            vec!["041", "042", "043"]
+        );
+        assert_eq!(
+            ids(Bound::Unbounded, Bound::Excluded(json!(2)))?,
+            vec!["000", "001"]
+        );
+        assert_eq!(
+            ids(Bound::Included(json!(98)), Bound::Unbounded)?,
+            vec!["098", "099"]
+        );
+        assert!(ids(Bound::Included(json!(100)), Bound::Unbounded)?.is_empty());
+        assert!(ids(Bound::Included(json!("a")), Bound::Unbounded)?.is_empty());
+
+        IndexMemTable::default().write(IndexKind::Value, &table, 64)?;
+        assert!(ids(Bound::Included(json!(1)), Bound::Unbounded)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_text_memtable() -> io::Result<()> {
+        let path = vec!["body".to_string()];
+        let mut memtable = IndexMemTable::default();
+        for (id, doc) in [
+            ("1", json!({"body": "The quick fox"})),
+            ("2", json!({"body": "Foxes and dogs"})),
+            ("3", json!({"body": 7})),
+        ] {
+            memtable.insert(IndexKind::FullText, &path, id, &value(doc));
+        }
+        assert_eq!(memtable.counts(), (2, 6));
+
+        assert_eq!(
+            memtable.ids(&IndexRange::term("fox")).collect::<Vec<_>>(),
+            vec!["1", "2"]
+        );
+        let dir = tempdir()?;
+        let table = table_path(dir.path(), 0, "idx");
+        memtable.write(IndexKind::FullText, &table, 1024)?;
+        assert_eq!(read_counts(&table)?, (2, 6));
+        assert_eq!(read_ids(&table, &IndexRange::term("dog"))?, vec!["2"]);
+        assert!(read_ids(&table, &IndexRange::term("Fox"))?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_vector_memtable() -> io::Result<()> {
+        let path = vec!["embedding".to_string()];
+        let mut memtable = IndexMemTable::default();
+        for (id, doc) in [
+            ("1", json!({"embedding": [1, 0]})),
+            ("2", json!({"embedding": [0.5, 2.5]})),
+            ("3", json!({"embedding": [1, "x"]})),
+            ("4", json!({"embedding": "1,0"})),
+        ] {
+            memtable.insert(IndexKind::Vector, &path, id, &value(doc));
+        }
+        let mut vectors: Vec<_> = memtable.vectors().collect();
+        vectors.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(vectors, vec![("1", vec![1.0, 0.0]), ("2", vec![0.5, 2.5])]);
+
+        let dir = tempdir()?;
+        let table = table_path(dir.path(), 0, "idx");
+        memtable.write(IndexKind::Vector, &table, 1024)?;
+        let nearest = vector::read_nearest(&table, &[0.0, 3.0], vector::Metric::L2, 1)?;
+        assert_eq!(nearest, vec!["2"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spatial_memtable() {
+        let path = vec!["loc".to_string()];
+        let mut memtable = IndexMemTable::default();
+        for (id, doc) in [
+            (
+                "1",
+                json!({"loc": {"type": "Point", "coordinates": [2.35, 48.85]}}),
+            ),
+            (
+                "2",
+                json!({"loc": {"type": "Point", "coordinates": [2.36, 48.86]}}),
+            ),
+            (
+                "3",
+                json!({"loc": {"type": "Point", "coordinates": [-0.13, 51.51]}}),
+            ),
+            ("4", json!({"loc": [2.35, 48.85]})),
+            (
+                "5",
+                json!({"loc": {"type": "LineString", "coordinates": [[2.35, 48.85], [2.351, 48.851]]}}),
+            ),
+        ] {
+            memtable.insert(IndexKind::Spatial, &path, id, &value(doc));
+        }
+        assert_eq!(memtable.len(), 4);
+        // Entries sort by cell, with larger cells before the cells in them
+        assert_eq!(
+            memtable
+                .ids(&IndexRange::prefix("u09t"))
+                .collect::<Vec<_>>(),
+            vec!["5", "1", "2"]
+        );
+        assert_eq!(
+            memtable
+                .ids(&IndexRange::prefix("gcpv"))
+                .collect::<Vec<_>>(),
+            vec!["3"]
+        );
+
+        let ranges = IndexRange::overlapping(&["u09tvk".to_string(), "gc".to_string()]);
+        assert_eq!(ranges.len(), 2 + 7);
+        let mut ids: Vec<&str> = ranges.iter().flat_map(|r| memtable.ids(r)).collect();
+        ids.sort();
+        assert_eq!(ids, vec!["1", "3", "5"]);
+    }
+}
@@ -1,11 +1,12 @@
 use crate::Value;
 use crate::jstable::{JSTable, StoredValue};
 use crate::schema::{Schema, SchemaExt, infer_schema};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 pub struct MemTable {
     pub documents: HashMap<String, Value>,
     schema: Schema,
+    overwrites: HashSet<String>, // updated or deleted IDs
 }
 
 impl Default for MemTable {
@@ -19,6 +20,7 @@ impl MemTable {
         MemTable {
             documents: HashMap::new(),
             schema: Schema::default(),
+            overwrites: HashSet::new(),
         }
     }
 
@@ -30,6 +32,10 @@ impl MemTable {
         self.documents.is_empty()
     }
 
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
     pub fn flush(
         &self,
         path: &str,
@@ -48,7 +54,8 @@ impl MemTable {
             .map(|(k, v)| (k.clone(), StoredValue::Static(v.clone())))
             .collect();
 
-        let jstable = JSTable::new(timestamp, collection, self.schema.clone(), sorted_docs);
+        let mut jstable = JSTable::new(timestamp, collection, self.schema.clone(), sorted_docs);
+        jstable.overwrites = self.overwrites.clone();
         jstable.write(path, index_threshold)
     }
 
@@ -62,11 +69,13 @@ impl MemTable {
         let doc_schema = infer_schema(&doc);
         self.schema.merge(doc_schema);
         self.documents.insert(id.to_string(), doc);
+        self.overwrites.insert(id.to_string());
     }
 
     pub fn delete(&mut self, id: &str) {
         use jsonb_schema::Value as JsonbValue;
         self.documents.insert(id.to_string(), JsonbValue::Null);
+        self.overwrites.insert(id.to_string());
     }
 }
 
@@ -0,0 +1,473 @@
+use chrono::{
+    DateTime, Datelike, Months, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeDelta,
+    Timelike, Utc,
+};
+use std::cmp::Ordering;
+use std::fmt;
+
+const MICROS_PER_SECOND: i64 = 1_000_000;
+const MICROS_PER_MINUTE: i64 = 60 * MICROS_PER_SECOND;
+const MICROS_PER_HOUR: i64 = 60 * MICROS_PER_MINUTE;
+const MICROS_PER_DAY: i64 = 24 * MICROS_PER_HOUR;
+
+/// Returns true if a string starts like an ISO-8601 date (`YYYY-MM-DD`).
+/// This is a cheap check done before attempting a full parse.
+fn looks_like_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() >= 10
+        && b[..4].iter().all(u8::is_ascii_digit)
+        && b[4] == b'-'
+        && b[5..7].iter().all(u8::is_ascii_digit)
+        && b[7] == b'-'
+        && b[8..10].iter().all(u8::is_ascii_digit)
+}
+
+/// Parses an ISO-8601 timestamp. Accepts RFC 3339 (`2024-01-01T12:00:00Z`),
+/// a space instead of `T`, timestamps without an offset (taken as UTC), and
+/// plain dates (midnight UTC).
+pub fn parse_timestamp(s: &str) -> Option<DateTime<Utc>> {
+    if !looks_like_date(s) {
+        return None;
+    }
+    if s.len() == 10 {
+        return NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            .ok()
+            .map(|d| d.and_time(NaiveTime::MIN).and_utc());
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f%:z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(dt) = DateTime::parse_from_str(s, format) {
+            return Some(dt.with_timezone(&Utc));
+        }
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+            return Some(dt.and_utc());
+        }
+    }
+    None
+}
+
+/// Parses a timestamp with an explicit `strftime`-style format. Formats
+/// without an offset are taken as UTC, and formats without a time as midnight.
+pub fn parse_timestamp_with_format(s: &str, format: &str) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_str(s, format) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(s, format) {
+        return Some(dt.and_utc());
+    }
+    NaiveDate::parse_from_str(s, format)
+        .ok()
+        .map(|d| d.and_time(NaiveTime::MIN).and_utc())
+}
+
+/// Returns true if a string is an RFC 3339 date-time, i.e. matches the JSON
+/// Schema `date-time` format.
+pub fn is_date_time(s: &str) -> bool {
+    looks_like_date(s) && DateTime::parse_from_rfc3339(s).is_ok()
+}
+
+/// Converts seconds since the Unix epoch to a timestamp.
+pub fn timestamp_from_epoch(secs: f64) -> Option<DateTime<Utc>> {
+    if !secs.is_finite() {
+        return None;
+    }
+    let micros = (secs * MICROS_PER_SECOND as f64).round();
+    if micros.abs() >= i64::MAX as f64 {
+        return None;
+    }
+    DateTime::from_timestamp_micros(micros as i64)
+}
+
+/// Formats a timestamp as RFC 3339 in UTC, with only as many fractional
+/// digits as needed (e.g. `2024-01-01T12:00:00Z`).
+pub fn format_timestamp(dt: &DateTime<Utc>) -> String {
+    dt.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+/// Compares two strings as instants if both are timestamps.
+pub fn compare_timestamps(a: &str, b: &str) -> Option<Ordering> {
+    Some(parse_timestamp(a)?.cmp(&parse_timestamp(b)?))
+}
+
+/// A span of time, kept as separate months, days and microseconds because
+/// months and days vary in length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Interval {
+    pub months: i64,
+    pub days: i64,
+    pub micros: i64,
+}
+
+impl Interval {
+    /// Parses interval text such as `1 hour`, `2 days 30 minutes` or `-1.5 seconds`.
+    pub fn parse(s: &str) -> Result<Interval, String> {
+        let invalid = || format!("Invalid interval: '{}'", s);
+        let tokens: Vec<&str> = s.split_whitespace().collect();
+        if tokens.is_empty() || tokens.len() % 2 == 1 {
+            return Err(invalid());
+        }
+        let mut interval = Interval::default();
+        for pair in tokens.chunks(2) {
+            let amount: f64 = pair[0].parse().map_err(|_| invalid())?;
+            interval = interval
+                .checked_add(&Interval::of_unit(amount, pair[1]).ok_or_else(invalid)?)
+                .ok_or_else(invalid)?;
+        }
+        Ok(interval)
+    }
+
+    /// Builds an interval of `amount` units, e.g. `(2.0, "hours")`.
+    pub fn of_unit(amount: f64, unit: &str) -> Option<Interval> {
+        let (months, micros_per_unit) = match unit.to_ascii_lowercase().as_str() {
+            "year" | "years" | "yr" | "yrs" | "y" => (12.0, 0),
+            "month" | "months" | "mon" | "mons" => (1.0, 0),
+            "week" | "weeks" | "w" => return Some(Interval::from_days(amount * 7.0)),
+            "day" | "days" | "d" => return Some(Interval::from_days(amount)),
+            "hour" | "hours" | "hr" | "hrs" | "h" => (0.0, MICROS_PER_HOUR),
+            "minute" | "minutes" | "min" | "mins" | "m" => (0.0, MICROS_PER_MINUTE),
+            "second" | "seconds" | "sec" | "secs" | "s" => (0.0, MICROS_PER_SECOND),
+            "millisecond" | "milliseconds" | "ms" => (0.0, 1_000),
+            "microsecond" | "microseconds" | "us" => (0.0, 1),
+            _ => return None,
+        };
+        if months > 0.0 {
+            let total = amount * months;
+            if total.fract() != 0.0 {
+                return None;
+            }
+            return Some(Interval {
+                months: total as i64,
+                ..Default::default()
+            });
+        }
+        let micros = (amount * micros_per_unit as f64).round();
+        (micros.abs() < i64::MAX as f64).then(|| Interval {
+            micros: micros as i64,
+            ..Default::default()
+        })
+    }
+
+    fn from_days(days: f64) -> Interval {
+        Interval {
+            months: 0,
+            days: days.trunc() as i64,
+            micros: (days.fract() * MICROS_PER_DAY as f64).round() as i64,
+        }
+    }
+
+    /// Parses an ISO-8601 duration such as `P1Y2M3DT4H5M6.5S`. Each component
+    /// may carry its own sign, as produced by `Display`.
+    pub fn parse_iso8601(s: &str) -> Option<Interval> {
+        let rest = s.strip_prefix('P')?;
+        if rest.is_empty() {
+            return None;
+        }
+        let mut interval = Interval::default();
+        let mut in_time = false;
+        let mut number = String::new();
+        for c in rest.chars() {
+            match c {
+                'T' if !in_time && number.is_empty() => in_time = true,
+                '0'..='9' | '.' | '-' | '+' => number.push(c),
+                _ => {
+                    let amount: f64 = number.parse().ok()?;
+                    number.clear();
+                    let unit = match (c, in_time) {
+                        ('Y', false) => "year",
+                        ('M', false) => "month",
+                        ('W', false) => "week",
+                        ('D', false) => "day",
+                        ('H', true) => "hour",
+                        ('M', true) => "minute",
+                        ('S', true) => "second",
+                        _ => return None,
+                    };
+                    interval = interval.checked_add(&Interval::of_unit(amount, unit)?)?;
+                }
+            }
+        }
+        number.is_empty().then_some(interval)
+    }
+
+    pub fn checked_add(&self, other: &Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            days: self.days.checked_add(other.days)?,
+            micros: self.micros.checked_add(other.micros)?,
+        })
+    }
+
+    pub fn checked_neg(&self) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_neg()?,
+            days: self.days.checked_neg()?,
+            micros: self.micros.checked_neg()?,
+        })
+    }
+
+    /// Adds the interval to a timestamp, applying months, then days, then time.
+    pub fn add_to(&self, dt: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let months = u32::try_from(self.months.unsigned_abs()).ok()?;
+        let dt = if self.months >= 0 {
+            dt.checked_add_months(Months::new(months))?
+        } else {
+            dt.checked_sub_months(Months::new(months))?
+        };
+        dt.checked_add_signed(TimeDelta::try_days(self.days)?)?
+            .checked_add_signed(TimeDelta::microseconds(self.micros))
+    }
+
+    /// The interval between two timestamps, as days and microseconds.
+    pub fn between(from: DateTime<Utc>, to: DateTime<Utc>) -> Option<Interval> {
+        let micros = (to - from).num_microseconds()?;
+        Some(Interval {
+            months: 0,
+            days: micros / MICROS_PER_DAY,
+            micros: micros % MICROS_PER_DAY,
+        })
+    }
+}
+
+impl fmt::Display for Interval {
+    /// Formats as an ISO-8601 duration, e.g. `P1DT2H` or `PT-1H-30M`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if *self == Interval::default() {
+            return write!(f, "PT0S");
+        }
+        write!(f, "P")?;
+        let (years, months) = (self.months / 12, self.months % 12);
+        for (amount, unit) in [(years, 'Y'), (months, 'M'), (self.days, 'D')] {
+            if amount != 0 {
+                write!(f, "{}{}", amount, unit)?;
+            }
+        }
+        if self.micros != 0 {
+            write!(f, "T")?;
+            let hours = self.micros / MICROS_PER_HOUR;
+            let minutes = self.micros % MICROS_PER_HOUR / MICROS_PER_MINUTE;
+            let micros = self.micros % MICROS_PER_MINUTE;
+            for (amount, unit) in [(hours, 'H'), (minutes, 'M')] {
+                if amount != 0 {
+                    write!(f, "{}{}", amount, unit)?;
+                }
+            }
+            if micros != 0 {
+                let seconds = format!("{:.6}", micros as f64 / MICROS_PER_SECOND as f64);
+                let seconds = seconds.trim_end_matches('0').trim_end_matches('.');
+                write!(f, "{}S", seconds)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Truncates a timestamp to the start of the given unit
+/// (`second`, `minute`, `hour`, `day`, `week`, `month`, `quarter` or `year`).
+pub fn date_trunc(unit: &str, dt: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let date = dt.date_naive();
+    let start_of = |d: NaiveDate| Some(d.and_time(NaiveTime::MIN).and_utc());
+    match unit.to_ascii_lowercase().as_str() {
+        "microsecond" | "microseconds" => Some(dt),
+        "millisecond" | "milliseconds" => {
+            dt.with_nanosecond(dt.nanosecond() / 1_000_000 * 1_000_000)
+        }
+        "second" => dt.with_nanosecond(0),
+        "minute" => dt.with_nanosecond(0)?.with_second(0),
+        "hour" => dt.with_nanosecond(0)?.with_second(0)?.with_minute(0),
+        "day" => start_of(date),
+        "week" => start_of(date - TimeDelta::days(date.weekday().num_days_from_monday() as i64)),
+        "month" => start_of(date.with_day(1)?),
+        "quarter" => start_of(NaiveDate::from_ymd_opt(
+            date.year(),
+            (date.month0() / 3) * 3 + 1,
+            1,
+        )?),
+        "year" => start_of(NaiveDate::from_ymd_opt(date.year(), 1, 1)?),
+        _ => None,
+    }
+}
+
+/// Returns true if `date_trunc` accepts the unit.
+pub fn is_trunc_unit(unit: &str) -> bool {
+    date_trunc(unit, DateTime::UNIX_EPOCH).is_some()
+}
+
+/// A component extracted from a timestamp: either whole or fractional.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DatePart {
+    Int(i64),
+    Float(f64),
+}
+
+/// Extracts a field from a timestamp: `year`, `quarter`, `month`, `week`
+/// (ISO week), `day`, `dow` (0 = Sunday), `isodow` (7 = Sunday), `doy`,
+/// `hour`, `minute`, `second` (with fraction), `millisecond`, `microsecond`
+/// or `epoch` (seconds since 1970-01-01, with fraction).
+pub fn date_part(field: &str, dt: DateTime<Utc>) -> Option<DatePart> {
+    let frac_seconds = |whole: i64| {
+        let micros = dt.timestamp_subsec_micros() as i64;
+        if micros == 0 {
+            DatePart::Int(whole)
+        } else {
+            DatePart::Float(whole as f64 + micros as f64 / MICROS_PER_SECOND as f64)
+        }
+    };
+    let part = match field.to_ascii_lowercase().as_str() {
+        "year" | "years" => DatePart::Int(dt.year() as i64),
+        "quarter" => DatePart::Int(dt.month0() as i64 / 3 + 1),
+        "month" | "months" => DatePart::Int(dt.month() as i64),
+        "week" => DatePart::Int(dt.iso_week().week() as i64),
+        "day" | "days" => DatePart::Int(dt.day() as i64),
+        "dow" => DatePart::Int(dt.weekday().num_days_from_sunday() as i64),
+        "isodow" => DatePart::Int(dt.weekday().number_from_monday() as i64),
+        "doy" => DatePart::Int(dt.ordinal() as i64),
+        "hour" | "hours" => DatePart::Int(dt.hour() as i64),
+        "minute" | "minutes" => DatePart::Int(dt.minute() as i64),
+        "second" | "seconds" => frac_seconds(dt.second() as i64),
+        "millisecond" | "milliseconds" => DatePart::Float(
+            dt.second() as f64 * 1_000.0 + dt.timestamp_subsec_micros() as f64 / 1_000.0,
+        ),
+        "microsecond" | "microseconds" => DatePart::Int(
+            dt.second() as i64 * MICROS_PER_SECOND + dt.timestamp_subsec_micros() as i64,
+        ),
+        "epoch" => frac_seconds(dt.timestamp()),
+        _ => return None,
+    };
+    Some(part)
+}
+
+/// Returns true if `date_part` accepts the field.
+pub fn is_date_part_field(field: &str) -> bool {
+    date_part(field, DateTime::UNIX_EPOCH).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> DateTime<Utc> {
+        parse_timestamp(s).unwrap()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = ts("2024-03-01T12:30:00Z");
+        assert_eq!(ts("2024-03-01T13:30:00+01:00"), expected);
+        assert_eq!(ts("2024-03-01 12:30:00"), expected);
+        assert_eq!(ts("2024-03-01T12:30"), expected);
+        assert_eq!(ts("2024-03-01"), ts("2024-03-01T00:00:00Z"));
+        assert_eq!(parse_timestamp("2024-13-01"), None);
+        assert_eq!(parse_timestamp("hello"), None);
+
+        assert!(is_date_time("2024-03-01T12:30:00.5Z"));
+        assert!(!is_date_time("2024-03-01"));
+        assert!(!is_date_time("2024-03-01 12:30:00"));
+
+        assert_eq!(
+            parse_timestamp_with_format("01/03/2024 12:30", "%d/%m/%Y %H:%M"),
+            Some(expected)
+        );
+        assert_eq!(
+            parse_timestamp_with_format("01/03/2024", "%d/%m/%Y"),
+            Some(ts("2024-03-01"))
+        );
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(
+            format_timestamp(&timestamp_from_epoch(0.0).unwrap()),
+            "1970-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            format_timestamp(&timestamp_from_epoch(1.5).unwrap()),
+            "1970-01-01T00:00:01.500Z"
+        );
+        assert_eq!(timestamp_from_epoch(f64::NAN), None);
+    }
+
+    #[test]
+    fn test_interval_parse_and_display() {
+        let hour = Interval::parse("1 hour").unwrap();
+        assert_eq!(hour.micros, MICROS_PER_HOUR);
+        assert_eq!(hour.to_string(), "PT1H");
+
+        let mixed =
+            Interval::parse("1 year 2 months 3 days 4 hours 5 minutes 6.5 seconds").unwrap();
+        assert_eq!(mixed.to_string(), "P1Y2M3DT4H5M6.5S");
+        assert_eq!(Interval::parse_iso8601(&mixed.to_string()), Some(mixed));
+
+        let negative = Interval::parse("-90 minutes").unwrap();
+        assert_eq!(negative.to_string(), "PT-1H-30M");
+        assert_eq!(Interval::parse_iso8601("PT-1H-30M"), Some(negative));
+        assert_eq!(
+            Interval::parse_iso8601("P1W"),
+            Interval::parse("7 days").ok()
+        );
+
+        assert_eq!(Interval::default().to_string(), "PT0S");
+        assert!(Interval::parse("1 fortnight").is_err());
+        assert!(Interval::parse("hour").is_err());
+        assert!(Interval::parse("1.5 months").is_err());
+        assert_eq!(Interval::parse_iso8601("2024-01-01"), None);
+        assert_eq!(Interval::parse_iso8601("P1H"), None);
+    }
+
+    #[test]
+    fn test_interval_arithmetic() {
+        let start = ts("2024-01-31T12:00:00Z");
+        let month = Interval::parse("1 month").unwrap();
+        assert_eq!(month.add_to(start), Some(ts("2024-02-29T12:00:00Z")));
+        assert_eq!(
+            month.checked_neg().unwrap().add_to(start),
+            Some(ts("2023-12-31T12:00:00Z"))
+        );
+        let day_and_hour = Interval::parse("1 day 1 hour").unwrap();
+        assert_eq!(day_and_hour.add_to(start), Some(ts("2024-02-01T13:00:00Z")));
+
+        let diff = Interval::between(start, ts("2024-02-01T13:00:00Z")).unwrap();
+        assert_eq!(diff, day_and_hour);
+    }
+
+    #[test]
+    fn test_date_trunc_and_part() {
+        let dt = ts("2024-05-16T13:45:30.25Z"); // a Thursday
+        let trunc = |unit: &str| format_timestamp(&date_trunc(unit, dt).unwrap());
+        assert_eq!(trunc("second"), "2024-05-16T13:45:30Z");
+        assert_eq!(trunc("minute"), "2024-05-16T13:45:00Z");
+        assert_eq!(trunc("HOUR"), "2024-05-16T13:00:00Z");
+        assert_eq!(trunc("day"), "2024-05-16T00:00:00Z");
+        assert_eq!(trunc("week"), "2024-05-13T00:00:00Z");
+        assert_eq!(trunc("month"), "2024-05-01T00:00:00Z");
+        assert_eq!(trunc("quarter"), "2024-04-01T00:00:00Z");
+        assert_eq!(trunc("year"), "2024-01-01T00:00:00Z");
+        assert!(!is_trunc_unit("fortnight"));
+
+        let part = |field: &str| date_part(field, dt).unwrap();
+        assert_eq!(part("year"), DatePart::Int(2024));
+        assert_eq!(part("quarter"), DatePart::Int(2));
+        assert_eq!(part("month"), DatePart::Int(5));
+        assert_eq!(part("week"), DatePart::Int(20));
+        assert_eq!(part("day"), DatePart::Int(16));
+        assert_eq!(part("dow"), DatePart::Int(4));
+        assert_eq!(part("isodow"), DatePart::Int(4));
+        assert_eq!(part("doy"), DatePart::Int(137));
+        assert_eq!(part("hour"), DatePart::Int(13));
+        assert_eq!(part("second"), DatePart::Float(30.25));
+        assert_eq!(part("millisecond"), DatePart::Float(30250.0));
+        assert_eq!(
+            date_part("epoch", ts("1970-01-02")),
+            Some(DatePart::Int(86400))
+        );
+        assert!(!is_date_part_field("fortnight"));
+    }
+}
@@ -0,0 +1,361 @@
+use crate::expression::get_f64_from_number;
+use crate::{Value, make_static};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Rounds of k-means refinement when building an index table.
+const KMEANS_ROUNDS: usize = 10;
+/// Fewest lists probed per index table; more are probed in large tables.
+const MIN_PROBES: usize = 4;
+
+/// How `VECTOR_DISTANCE` compares two vectors. Smaller is nearer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// One minus the cosine of the angle between the vectors.
+    Cosine,
+    /// The Euclidean distance.
+    L2,
+    /// The negated dot product.
+    Dot,
+}
+
+impl Metric {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "cosine" => Some(Metric::Cosine),
+            "l2" => Some(Metric::L2),
+            "dot" => Some(Metric::Dot),
+            _ => None,
+        }
+    }
+
+    /// Returns the distance between two vectors, or None if their lengths
+    /// differ or, for cosine, either is zero.
+    pub fn distance(self, a: &[f64], b: &[f64]) -> Option<f64> {
+        if a.len() != b.len() {
+            return None;
+        }
+        let dot = || a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+        match self {
+            Metric::Cosine => {
+                let norms = (a.iter().map(|x| x * x).sum::<f64>()
+                    * b.iter().map(|y| y * y).sum::<f64>())
+                .sqrt();
+                (norms > 0.0).then(|| 1.0 - dot() / norms)
+            }
+            Metric::L2 => Some(l2_squared(a, b).sqrt()),
+            Metric::Dot => Some(-dot()),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Cosine => write!(f, "cosine"),
+            Metric::L2 => write!(f, "l2"),
+            Metric::Dot => write!(f, "dot"),
+        }
+    }
+}
+
+fn l2_squared(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+/// Returns the numbers of a non-empty array of numbers.
+pub fn to_vector(value: &Value) -> Option<Vec<f64>> {
+    match value {
+        Value::Array(items) if !items.is_empty() => items
+            .iter()
+            .map(|item| match item {
+                Value::Number(n) => get_f64_from_number(n),
+                _ => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+/// Returns the IDs of the `k` vectors nearest to `query`, nearest first.
+/// Vectors of another length than the query are skipped.
+pub fn nearest<'v>(
+    entries: impl IntoIterator<Item = (&'v str, Vec<f64>)>,
+    query: &[f64],
+    metric: Metric,
+    k: usize,
+) -> Vec<&'v str> {
+    let mut scored: Vec<(f64, &str)> = entries
+        .into_iter()
+        .filter_map(|(id, vector)| Some((metric.distance(query, &vector)?, id)))
+        .collect();
+    scored.sort_by(|a, b| a.0.total_cmp(&b.0));
+    scored.into_iter().take(k).map(|(_, id)| id).collect()
+}
+
+/// Writes vectors as an IVF (inverted file) index table: the vectors are
+/// clustered with k-means into about `sqrt(n)` lists, and the table holds
+/// a length-prefixed JSONB record of the list centroids followed by one
+/// record per list of its `[id, vector]` entries.
+pub fn write_table<'v>(
+    path: &Path,
+    entries: impl IntoIterator<Item = (&'v str, Vec<f64>)>,
+) -> io::Result<()> {
+    let entries: Vec<(&str, Vec<f64>)> = entries.into_iter().collect();
+    let vectors: Vec<&[f64]> = entries.iter().map(|(_, v)| v.as_slice()).collect();
+    let centroids = kmeans(&vectors, (entries.len() as f64).sqrt().ceil() as usize);
+    let mut lists: Vec<Vec<(&str, &[f64])>> = vec![Vec::new(); centroids.len()];
+    for (id, vector) in &entries {
+        lists[closest(&centroids, vector)].push((*id, vector.as_slice()));
+    }
+
+    let mut file = BufWriter::new(File::create(path)?);
+    write_record(&mut file, &centroids)?;
+    for list in &lists {
+        write_record(&mut file, list)?;
+    }
+    file.flush()
+}
+
+/// Reads the IDs of the `k` vectors of an index table nearest to `query`
+/// among the lists whose centroids are nearest to it, nearest first. While
+/// the probed lists hold fewer than `k` entries, twice as many are probed,
+/// so fewer IDs are only returned when the table has no more.
+pub fn read_nearest(
+    path: &Path,
+    query: &[f64],
+    metric: Metric,
+    k: usize,
+) -> io::Result<Vec<String>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let Some(centroids) = read_record(&mut reader)? else {
+        return Ok(Vec::new());
+    };
+    let centroids: Vec<Vec<f64>> = match centroids {
+        Value::Array(items) => items.iter().filter_map(to_vector).collect(),
+        _ => return Err(invalid_table()),
+    };
+    let lists_start = reader.stream_position()?;
+    let mut ranked: Vec<(f64, usize)> = centroids
+        .iter()
+        .enumerate()
+        .map(|(i, centroid)| {
+            let distance = metric.distance(query, centroid).unwrap_or(f64::INFINITY);
+            (distance, i)
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut probes = (centroids.len() / 10).max(MIN_PROBES);
+    loop {
+        let mut probed: Vec<usize> = ranked.iter().take(probes).map(|(_, i)| *i).collect();
+        probed.sort();
+        let candidates = read_lists(&mut reader, centroids.len(), &probed)?;
+        if candidates.len() < k && probes < centroids.len() {
+            probes *= 2;
+            reader.seek(SeekFrom::Start(lists_start))?;
+            continue;
+        }
+        let ids = nearest(
+            candidates
+                .iter()
+                .map(|(id, vector)| (id.as_str(), vector.clone())),
+            query,
+            metric,
+            k,
+        );
+        return Ok(ids.into_iter().map(str::to_string).collect());
+    }
+}
+
+/// Reads the `[id, vector]` entries of the `probed` lists, in ascending
+/// order, of the `lists` that follow the centroids.
+fn read_lists(
+    reader: &mut BufReader<File>,
+    lists: usize,
+    probed: &[usize],
+) -> io::Result<Vec<(String, Vec<f64>)>> {
+    let mut candidates = Vec::new();
+    for list in 0..lists {
+        if probed.binary_search(&list).is_err() {
+            skip_record(reader)?;
+            continue;
+        }
+        let Some(Value::Array(entries)) = read_record(reader)? else {
+            return Err(invalid_table());
+        };
+        for entry in entries {
+            match entry {
+                Value::Array(pair) if pair.len() == 2 => match (&pair[0], to_vector(&pair[1])) {
+                    (Value::String(id), Some(vector)) => candidates.push((id.to_string(), vector)),
+                    _ => return Err(invalid_table()),
+                },
+                _ => return Err(invalid_table()),
+            }
+        }
+    }
+    Ok(candidates)
+}
+
+/// Clusters vectors of the most common length around `lists` centroids,
+/// starting from vectors spread evenly through the input.
+fn kmeans(vectors: &[&[f64]], lists: usize) -> Vec<Vec<f64>> {
+    let mut lengths: BTreeMap<usize, usize> = BTreeMap::new();
+    for vector in vectors {
+        *lengths.entry(vector.len()).or_default() += 1;
+    }
+    // Ties go to the shortest length, so the clustering is deterministic
+    let Some(dimensions) = lengths
+        .iter()
+        .rev()
+        .max_by_key(|&(_, count)| count)
+        .map(|(&len, _)| len)
+    else {
+        return Vec::new();
+    };
+    let vectors: Vec<&[f64]> = vectors
+        .iter()
+        .copied()
+        .filter(|v| v.len() == dimensions)
+        .collect();
+    let lists = lists.clamp(1, vectors.len());
+    let mut centroids: Vec<Vec<f64>> = (0..lists)
+        .map(|i| vectors[i * vectors.len() / lists].to_vec())
+        .collect();
+    for _ in 0..KMEANS_ROUNDS {
+        let mut sums = vec![vec![0.0; dimensions]; lists];
+        let mut counts = vec![0usize; lists];
+        for vector in &vectors {
+            let list = closest(&centroids, vector);
+            counts[list] += 1;
+            for (sum, x) in sums[list].iter_mut().zip(vector.iter()) {
+                *sum += x;
+            }
+        }
+        let mut moved = false;
+        for ((centroid, sum), count) in centroids.iter_mut().zip(sums).zip(counts) {
+            // An empty list keeps its centroid
+            if count == 0 {
+                continue;
+            }
+            let mean: Vec<f64> = sum.into_iter().map(|s| s / count as f64).collect();
+            moved |= mean != *centroid;
+            *centroid = mean;
+        }
+        if !moved {
+            break;
+        }
+    }
+    centroids
+}
+
+/// Returns the index of the centroid nearest to `vector`, or 0 if its
+/// length differs from theirs.
+fn closest(centroids: &[Vec<f64>], vector: &[f64]) -> usize {
+    centroids
+        .iter()
+        .enumerate()
+        .filter(|(_, centroid)| centroid.len() == vector.len())
+        .min_by(|(_, a), (_, b)| l2_squared(a, vector).total_cmp(&l2_squared(b, vector)))
+        .map_or(0, |(i, _)| i)
+}
+
+fn invalid_table() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid vector index table")
+}
+
+fn write_record<W: Write, T: serde::Serialize>(writer: &mut W, record: &T) -> io::Result<()> {
+    let record_bytes = jsonb_schema::to_owned_jsonb(record)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .to_vec();
+    writer.write_all(&(record_bytes.len() as u32).to_le_bytes())?;
+    writer.write_all(&record_bytes)
+}
+
+fn read_length<R: Read>(reader: &mut R) -> io::Result<Option<usize>> {
+    let mut len_buf = [0u8; 4];
+    match reader.read_exact(&mut len_buf) {
+        Ok(_) => Ok(Some(u32::from_le_bytes(len_buf) as usize)),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<Value>> {
+    let Some(len) = read_length(reader)? else {
+        return Ok(None);
+    };
+    let mut record_blob = vec![0u8; len];
+    reader.read_exact(&mut record_blob)?;
+    let record = jsonb_schema::from_slice(&record_blob)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(Some(make_static(&record)))
+}
+
+fn skip_record(reader: &mut BufReader<File>) -> io::Result<()> {
+    match read_length(reader)? {
+        Some(len) => reader.seek_relative(len as i64),
+        None => Err(invalid_table()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_to_jsonb;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_distance() {
+        let (a, b) = ([1.0, 0.0], [3.0, 4.0]);
+        assert_eq!(Metric::L2.distance(&a, &b), Some(20f64.sqrt()));
+        assert_eq!(Metric::Dot.distance(&a, &b), Some(-3.0));
+        assert!((Metric::Cosine.distance(&a, &b).unwrap() - 0.4).abs() < 1e-12);
+        assert_eq!(Metric::Cosine.distance(&a, &[0.0, 0.0]), None);
+        assert_eq!(Metric::L2.distance(&a, &[1.0]), None);
+        assert_eq!(Metric::parse("COSINE"), Some(Metric::Cosine));
+        assert_eq!(Metric::parse("manhattan"), None);
+
+        assert_eq!(
+            to_vector(&serde_to_jsonb(json!([1, 2.5]))),
+            Some(vec![1.0, 2.5])
+        );
+        assert_eq!(to_vector(&serde_to_jsonb(json!([1, "x"]))), None);
+        assert_eq!(to_vector(&serde_to_jsonb(json!([]))), None);
+    }
+
+    #[test]
+    fn test_index_table() -> io::Result<()> {
+        // Points on a 10x10 grid
+        let ids: Vec<String> = (0..100).map(|i| format!("{:03}", i)).collect();
+        let entries: Vec<(&str, Vec<f64>)> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| (id.as_str(), vec![(i % 10) as f64, (i / 10) as f64]))
+            .collect();
+        let dir = tempdir()?;
+        let path = dir.path().join("table.index");
+        write_table(&path, entries.clone())?;
+
+        let query = [2.1, 3.2];
+        let exact = nearest(entries, &query, Metric::L2, 3);
+        assert_eq!(exact, vec!["032", "042", "033"]);
+        assert_eq!(read_nearest(&path, &query, Metric::L2, 3)?, exact);
+        // More lists are probed for more entries, up to the whole table
+        assert_eq!(read_nearest(&path, &query, Metric::L2, 60)?.len(), 60);
+        assert_eq!(read_nearest(&path, &query, Metric::L2, 200)?.len(), 100);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kmeans_most_common_length() {
+        let vectors: Vec<&[f64]> = vec![&[9.0], &[0.0, 0.0], &[0.0, 1.0], &[8.0], &[1.0, 0.0]];
+        let centroids = kmeans(&vectors, 2);
+        assert_eq!(centroids.len(), 2);
+        assert!(centroids.iter().all(|c| c.len() == 2), "{:?}", centroids);
+    }
+}
@@ -0,0 +1,400 @@
+use crate::Value;
+use crate::expression::{
+    BinaryOperator, Expression, LogicalOperator, compare_values, is_id_reference,
+};
+use jsonb_schema::Number;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// Number of registers in a distinct-count sketch.
+const SKETCH_REGISTERS: usize = 64;
+
+/// The value statistics of a JSTable: one zone map for the whole table, and
+/// one per sparse index block, in index order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TableStats {
+    pub table: ZoneMap,
+    pub blocks: Vec<ZoneMap>,
+}
+
+/// Statistics of the documents in a table or block, used to skip it when a
+/// predicate cannot hold for any of them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ZoneMap {
+    pub rows: u64,
+    /// Set if the zone holds updates or deletes, which may shadow documents in
+    /// older tables and so must be read even if they do not match.
+    pub overwrites: bool,
+    pub fields: BTreeMap<String, FieldStats>, // keyed by dotted path
+}
+
+/// Statistics of one field over the documents of a zone.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FieldStats {
+    pub count: u64, // non-null values
+    pub null_count: u64,
+    /// The smallest and largest value of each scalar type seen.
+    pub ranges: Vec<ValueRange>,
+    /// Set if a number without a usable bound (NaN or infinite) was seen.
+    pub unbounded: bool,
+    pub distinct: DistinctSketch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ValueRange {
+    #[serde(with = "crate::serde_value")]
+    pub min: Value,
+    #[serde(with = "crate::serde_value")]
+    pub max: Value,
+}
+
+/// A HyperLogLog sketch estimating the number of distinct values added to it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistinctSketch {
+    registers: Vec<u8>,
+}
+
+impl Default for DistinctSketch {
+    fn default() -> Self {
+        DistinctSketch {
+            registers: vec![0; SKETCH_REGISTERS],
+        }
+    }
+}
+
+impl DistinctSketch {
+    pub fn insert(&mut self, value: &Value) {
+        let hash = sketch_hash(value.to_string().as_bytes());
+        let register = hash as usize % SKETCH_REGISTERS;
+        let rank = ((hash >> 6).trailing_zeros().min(57) + 1) as u8;
+        self.registers[register] = self.registers[register].max(rank);
+    }
+
+    pub fn merge(&mut self, other: &DistinctSketch) {
+        for (register, rank) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*rank);
+        }
+    }
+
+    /// Returns the estimated number of distinct values.
+    pub fn estimate(&self) -> u64 {
+        let m = SKETCH_REGISTERS as f64;
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = 0.709 * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // Small cardinalities are estimated better by linear counting
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
+
+/// Hashes a value for a distinct-count sketch. Sketches are persisted with
+/// their table, so this is a fixed function (FNV-1a, with the MurmurHash3
+/// finalizer to spread its bits) rather than the std hasher, which may change
+/// between releases.
+fn sketch_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+impl FieldStats {
+    fn add(&mut self, value: &Value) {
+        if matches!(value, Value::Null) {
+            self.null_count += 1;
+            return;
+        }
+        self.count += 1;
+        self.distinct.insert(value);
+        match value {
+            Value::Number(Number::Float64(f)) if !f.is_finite() => self.unbounded = true,
+            Value::Bool(_) | Value::Number(_) | Value::String(_) => self.widen(value),
+            _ => {}
+        }
+    }
+
+    /// Widens the range of the value's type to include it.
+    fn widen(&mut self, value: &Value) {
+        match self
+            .ranges
+            .iter_mut()
+            .find(|range| compare_values(&range.min, value).is_some())
+        {
+            Some(range) => {
+                if compare_values(value, &range.min) == Some(Ordering::Less) {
+                    range.min = value.clone();
+                }
+                if compare_values(value, &range.max) == Some(Ordering::Greater) {
+                    range.max = value.clone();
+                }
+            }
+            None => self.ranges.push(ValueRange {
+                min: value.clone(),
+                max: value.clone(),
+            }),
+        }
+    }
+
+    fn merge(&mut self, other: &FieldStats) {
+        self.count += other.count;
+        self.null_count += other.null_count;
+        self.unbounded |= other.unbounded;
+        self.distinct.merge(&other.distinct);
+        for range in &other.ranges {
+            self.widen(&range.min);
+            self.widen(&range.max);
+        }
+    }
+
+    /// Returns false if `field op literal`, for a comparison `op`, cannot be
+    /// true for any value.
+    fn may_compare(&self, op: &BinaryOperator, literal: &Value) -> bool {
+        if !matches!(
+            literal,
+            Value::Bool(_) | Value::Number(_) | Value::String(_)
+        ) || self.unbounded
+        {
+            return true;
+        }
+        // Values of other types never compare true, except under `!=`
+        self.ranges.iter().any(|range| {
+            let (Some(low), Some(high)) = (
+                compare_values(&range.min, literal),
+                compare_values(&range.max, literal),
+            ) else {
+                return false;
+            };
+            match op {
+                BinaryOperator::Eq => low != Ordering::Greater && high != Ordering::Less,
+                BinaryOperator::Lt => low == Ordering::Less,
+                BinaryOperator::Lte => low != Ordering::Greater,
+                BinaryOperator::Gt => high == Ordering::Greater,
+                BinaryOperator::Gte => high != Ordering::Less,
+                _ => true,
+            }
+        })
+    }
+}
+
+impl ZoneMap {
+    /// Adds a document, or a tombstone if it is null.
+    pub fn add_document(&mut self, doc: &Value) {
+        self.rows += 1;
+        match doc {
+            Value::Null => self.overwrites = true,
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    self.add_field(name.clone(), value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_field(&mut self, path: String, value: &Value) {
+        if let Value::Object(fields) = value {
+            for (name, child) in fields {
+                self.add_field(format!("{}.{}", path, name), child);
+            }
+        }
+        self.fields.entry(path).or_default().add(value);
+    }
+
+    pub fn merge(&mut self, other: &ZoneMap) {
+        self.rows += other.rows;
+        self.overwrites |= other.overwrites;
+        for (path, stats) in &other.fields {
+            self.fields.entry(path.clone()).or_default().merge(stats);
+        }
+    }
+
+    /// Returns false if `predicate` cannot be true for any document of the
+    /// zone. Fields the zone has no statistics for are missing from all of
+    /// its documents, so they compare as `NULL`.
+    pub fn may_match(&self, predicate: &Expression) -> bool {
+        match predicate {
+            Expression::Logical { left, op, right } => match op {
+                LogicalOperator::And => self.may_match(left) && self.may_match(right),
+                LogicalOperator::Or => self.may_match(left) || self.may_match(right),
+            },
+            Expression::Binary { left, op, right } => match (&**left, &**right) {
+                (Expression::FieldReference(parts, _), Expression::Literal(literal)) => {
+                    self.may_compare(parts, op, literal)
+                }
+                (Expression::Literal(literal), Expression::FieldReference(parts, _)) => {
+                    match op.flip() {
+                        Some(op) => self.may_compare(parts, &op, literal),
+                        None => true,
+                    }
+                }
+                _ => true,
+            },
+            Expression::Between {
+                expr,
+                low,
+                high,
+                negated: false,
+            } => match (&**expr, &**low, &**high) {
+                (
+                    Expression::FieldReference(parts, _),
+                    Expression::Literal(low),
+                    Expression::Literal(high),
+                ) => {
+                    self.may_compare(parts, &BinaryOperator::Gte, low)
+                        && self.may_compare(parts, &BinaryOperator::Lte, high)
+                }
+                _ => true,
+            },
+            Expression::InList {
+                expr,
+                list,
+                negated: false,
+            } => match &**expr {
+                Expression::FieldReference(parts, _) => list.iter().any(|item| match item {
+                    Expression::Literal(literal) => {
+                        self.may_compare(parts, &BinaryOperator::Eq, literal)
+                    }
+                    _ => true,
+                }),
+                _ => true,
+            },
+            Expression::IsNull {
+                expr,
+                negated: true,
+            } => match &**expr {
+                Expression::FieldReference(parts, _) if !is_id_reference(parts) => self
+                    .fields
+                    .get(&parts.join("."))
+                    .is_some_and(|stats| stats.count > 0),
+                _ => true,
+            },
+            _ => true,
+        }
+    }
+
+    fn may_compare(&self, parts: &[&str], op: &BinaryOperator, literal: &Value) -> bool {
+        let comparison = matches!(
+            op,
+            BinaryOperator::Eq
+                | BinaryOperator::Lt
+                | BinaryOperator::Lte
+                | BinaryOperator::Gt
+                | BinaryOperator::Gte
+        );
+        if !comparison || is_id_reference(parts) {
+            return true;
+        }
+        self.fields
+            .get(&parts.join("."))
+            .is_some_and(|stats| stats.may_compare(op, literal))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serde_to_jsonb;
+    use serde_json::json;
+
+    fn zone(docs: &[serde_json::Value]) -> ZoneMap {
+        let mut zone = ZoneMap::default();
+        for doc in docs {
+            zone.add_document(&serde_to_jsonb(doc.clone()));
+        }
+        zone
+    }
+
+    fn compare<'a>(field: &'a str, op: BinaryOperator, value: serde_json::Value) -> Expression<'a> {
+        Expression::Binary {
+            left: Box::new(Expression::FieldReference(vec![field], field)),
+            op,
+            right: Box::new(Expression::Literal(serde_to_jsonb(value))),
+        }
+    }
+
+    #[test]
+    fn test_zone_map_ranges() {
+        let zone = zone(&[
+            json!({"a": 5, "s": "m"}),
+            json!({"a": 10.5, "s": "x", "n": null}),
+            json!({"a": "text"}),
+        ]);
+        let stats = &zone.fields["a"];
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.ranges.len(), 2);
+        assert_eq!(zone.fields["n"].null_count, 1);
+
+        assert!(zone.may_match(&compare("a", BinaryOperator::Gt, json!(10))));
+        assert!(!zone.may_match(&compare("a", BinaryOperator::Gt, json!(10.5))));
+        assert!(zone.may_match(&compare("a", BinaryOperator::Gte, json!(10.5))));
+        assert!(!zone.may_match(&compare("a", BinaryOperator::Lt, json!(5))));
+        assert!(zone.may_match(&compare("a", BinaryOperator::Eq, json!(7.5))));
+        assert!(!zone.may_match(&compare("a", BinaryOperator::Eq, json!(11))));
+        assert!(zone.may_match(&compare("a", BinaryOperator::Eq, json!("text"))));
+        // No boolean values, and no field `b` at all
+        assert!(!zone.may_match(&compare("a", BinaryOperator::Eq, json!(true))));
+        assert!(!zone.may_match(&compare("b", BinaryOperator::Lt, json!(1))));
+        assert!(zone.may_match(&compare("b", BinaryOperator::Neq, json!(1))));
+
+        let either = Expression::Logical {
+            left: Box::new(compare("s", BinaryOperator::Gt, json!("x"))),
+            op: LogicalOperator::Or,
+            right: Box::new(compare("a", BinaryOperator::Lt, json!(6))),
+        };
+        assert!(zone.may_match(&either));
+        let both = Expression::Logical {
+            left: Box::new(compare("s", BinaryOperator::Gt, json!("x"))),
+            op: LogicalOperator::And,
+            right: Box::new(compare("a", BinaryOperator::Lt, json!(6))),
+        };
+        assert!(!zone.may_match(&both));
+    }
+
+    #[test]
+    fn test_zone_map_nested_and_merged() {
+        let mut merged = zone(&[json!({"t": {"ts": "2024-01-01T00:00:00Z"}})]);
+        merged.merge(&zone(&[
+            json!({"t": {"ts": "2024-03-01T00:00:00Z"}}),
+            json!(null),
+        ]));
+        assert_eq!(merged.rows, 3);
+        assert!(merged.overwrites);
+
+        let ts = |op, value| Expression::Binary {
+            left: Box::new(Expression::FieldReference(vec!["t", "ts"], "t.ts")),
+            op,
+            right: Box::new(Expression::Literal(serde_to_jsonb(json!(value)))),
+        };
+        assert!(merged.may_match(&ts(BinaryOperator::Gt, "2024-02-01T00:00:00Z")));
+        assert!(!merged.may_match(&ts(BinaryOperator::Gt, "2024-03-01T00:00:00Z")));
+        assert!(!merged.may_match(&ts(BinaryOperator::Lt, "2023-12-31T23:00:00-01:00")));
+    }
+
+    #[test]
+    fn test_distinct_sketch_estimate() {
+        let mut sketch = DistinctSketch::default();
+        for i in 0..1000 {
+            sketch.insert(&serde_to_jsonb(json!(i % 100)));
+        }
+        let estimate = sketch.estimate();
+        assert!((50..=200).contains(&estimate), "{}", estimate);
+    }
+
+    #[test]
+    fn test_sketch_hash_is_stable() {
+        // Persisted sketches depend on these values
+        assert_eq!(sketch_hash(b"42"), 0x810b196a56ee3cec);
+        assert_eq!(sketch_hash(b"\"argus\""), 0x13209e45aae6bc16);
+    }
+}
@@ -126,3 +126,38 @@ fn test_missing_math_functions() {
     let res = evaluate_expression(&expr, &doc);
     assert!((res.as_f64().unwrap() - 1.0).abs() < 1e-10);
 }
+
+#[test]
+fn test_string_and_conditional_functions_via_sql() {
+    let sql = "SELECT UPPER(TRIM(name)), SUBSTRING(name FROM 3 FOR 2), \
+               ARRAY_LENGTH(SPLIT(tags, ',')), COALESCE(nick, 'none'), \
+               CASE WHEN age >= 18 THEN 'adult' ELSE 'minor' END, \
+               REGEXP_MATCH(email, '@(.+)$') FROM t";
+    let arena = Bump::new();
+    let projections = match parse(sql, &arena).unwrap() {
+        Statement::Select(LogicalPlan::Project { projections, .. }) => projections,
+        _ => panic!("Unexpected plan"),
+    };
+
+    let doc = serde_to_jsonb(json!({
+        "name": "  bob ",
+        "tags": "a,b,c",
+        "age": 12,
+        "email": "bob@example.com"
+    }));
+    let results: Vec<Value> = projections
+        .iter()
+        .map(|expr| evaluate_expression(expr, &doc))
+        .collect();
+    assert_eq!(
+        results,
+        vec![
+            serde_to_jsonb(json!("BOB")),
+            serde_to_jsonb(json!("bo")),
+            serde_to_jsonb(json!(3)),
+            serde_to_jsonb(json!("none")),
+            serde_to_jsonb(json!("minor")),
+            serde_to_jsonb(json!(["example.com"])),
+        ]
+    );
+}
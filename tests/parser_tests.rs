@@ -1,5 +1,7 @@
+use argusdb::expression::{Expression, projection_name};
 use argusdb::parser::parse;
-use argusdb::query::Statement;
+use argusdb::query::{LogicalPlan, Statement};
+use argusdb::secondary::IndexKind;
 use bumpalo::Bump;
 
 #[test]
@@ -26,6 +28,53 @@ fn test_parse_drop_collection() {
     }
 }
 
+#[test]
+fn test_parse_create_and_drop_index() {
+    let arena = Bump::new();
+    match parse("CREATE INDEX idx ON test (a.b)", &arena).unwrap() {
+        Statement::CreateIndex {
+            index,
+            collection,
+            path,
+            kind,
+        } => {
+            assert_eq!(index, "idx");
+            assert_eq!(collection, "test");
+            assert_eq!(path, vec!["a", "b"]);
+            assert_eq!(kind, IndexKind::Value);
+        }
+        _ => panic!("Expected CreateIndex"),
+    }
+    match parse("CREATE FULLTEXT INDEX idx ON test (body)", &arena).unwrap() {
+        Statement::CreateIndex { path, kind, .. } => {
+            assert_eq!(path, vec!["body"]);
+            assert_eq!(kind, IndexKind::FullText);
+        }
+        _ => panic!("Expected CreateIndex"),
+    }
+    match parse("CREATE VECTOR INDEX idx ON test (embedding)", &arena).unwrap() {
+        Statement::CreateIndex { path, kind, .. } => {
+            assert_eq!(path, vec!["embedding"]);
+            assert_eq!(kind, IndexKind::Vector);
+        }
+        _ => panic!("Expected CreateIndex"),
+    }
+    match parse("CREATE SPATIAL INDEX idx ON test (loc)", &arena).unwrap() {
+        Statement::CreateIndex { path, kind, .. } => {
+            assert_eq!(path, vec!["loc"]);
+            assert_eq!(kind, IndexKind::Spatial);
+        }
+        _ => panic!("Expected CreateIndex"),
+    }
+    assert!(parse("CREATE INDEX idx ON test (_id)", &arena).is_err());
+    assert!(parse("CREATE INDEX idx ON test (a + 1)", &arena).is_err());
+
+    match parse("DROP INDEX idx", &arena).unwrap() {
+        Statement::DropIndex { index } => assert_eq!(index, "idx"),
+        _ => panic!("Expected DropIndex"),
+    }
+}
+
 #[test]
 fn test_parse_show_collections() {
     let sql = "SHOW COLLECTIONS";
@@ -54,9 +103,40 @@ fn test_parse_insert_invalid_json() {
 }
 
 #[test]
-fn test_parse_wildcard_unsupported() {
+fn test_parse_wildcard() {
     let sql = "SELECT * FROM test";
     let arena = Bump::new();
-    let res = parse(sql, &arena);
-    assert!(res.is_err()); // "Wildcard * not supported yet"
+    let stmt = parse(sql, &arena).unwrap();
+    if let Statement::Select(LogicalPlan::Scan { collection }) = stmt {
+        assert_eq!(collection, "test");
+    } else {
+        panic!("Expected bare Scan for SELECT *");
+    }
+}
+
+#[test]
+fn test_parse_wildcard_with_columns() {
+    let sql = "SELECT *, a AS x FROM test";
+    let arena = Bump::new();
+    let stmt = parse(sql, &arena).unwrap();
+    if let Statement::Select(LogicalPlan::Project { projections, .. }) = stmt {
+        assert_eq!(projections.len(), 2);
+        assert!(matches!(projections[0], Expression::Wildcard));
+        assert!(matches!(projections[1], Expression::Alias(_, "x")));
+    } else {
+        panic!("Expected Project");
+    }
+}
+
+#[test]
+fn test_parse_alias() {
+    let sql = "SELECT a AS first, b FROM test";
+    let arena = Bump::new();
+    let stmt = parse(sql, &arena).unwrap();
+    if let Statement::Select(LogicalPlan::Project { projections, .. }) = stmt {
+        assert_eq!(projection_name(&projections[0]), "first");
+        assert_eq!(projection_name(&projections[1]), "b");
+    } else {
+        panic!("Expected Project");
+    }
 }
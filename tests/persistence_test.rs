@@ -57,7 +57,7 @@ fn test_multiple_jstable_recovery() {
         // Since we can't easily access private fields like jstable_count without creating a test in src/lib,
         // we will verify data presence which implicitly proves they were loaded.
 
-        let results: Vec<_> = db.scan("test", None, None).unwrap().collect();
+        let results: Vec<_> = db.scan("test", None, None, None).unwrap().collect();
         assert_eq!(results.len(), 15, "Should recover all 15 documents");
     }
 }
@@ -1,7 +1,16 @@
 use argusdb::db::DB;
-use argusdb::expression::{BinaryOperator, Expression, LogicalOperator};
-use argusdb::query::{LogicalPlan, execute_plan};
-use argusdb::{Value, serde_to_jsonb};
+use argusdb::explain::explain;
+use argusdb::expression::{
+    BinaryOperator, Expression, LogicalOperator, ScalarFunction, UnaryOperator, WindowCall,
+    WindowFunction,
+};
+use argusdb::parser::{parse, prepare};
+use argusdb::query::{LogicalPlan, SortKey, Statement, execute_plan};
+use argusdb::secondary::IndexKind;
+use argusdb::temporal::format_timestamp;
+use argusdb::{Value, jsonb_to_serde, serde_to_jsonb};
+use bumpalo::Bump;
+use chrono::{FixedOffset, TimeDelta, Utc};
 use serde_json::json;
 use tempfile::tempdir;
 
@@ -22,6 +31,18 @@ fn setup_db() -> (DB, tempfile::TempDir) {
     (db, dir)
 }
 
+fn run_sql(db: &DB, sql: &str) -> Vec<serde_json::Value> {
+    let arena = Bump::new();
+    let plan = match parse(sql, &arena).unwrap() {
+        Statement::Select(plan) => plan,
+        _ => panic!("Expected Select"),
+    };
+    execute_plan(plan, db)
+        .unwrap()
+        .map(|r| jsonb_to_serde(&r.get_value()))
+        .collect()
+}
+
 #[test]
 fn test_execute_scan() {
     let (mut db, _dir) = setup_db();
@@ -293,3 +314,1732 @@ fn test_vectorized_filter_disk() {
     let results: Vec<Value> = iter.map(|r| r.get_value()).collect();
     assert_eq!(results.len(), 1); // 10
 }
+
+fn id_predicate(op: BinaryOperator, id: &str) -> Expression<'static> {
+    Expression::Binary {
+        left: Box::new(Expression::FieldReference(vec!["_id"], "_id")),
+        op,
+        right: Box::new(Expression::Literal(Value::String(id.to_string().into()))),
+    }
+}
+
+#[test]
+fn test_execute_id_point_lookup() {
+    let (mut db, _dir) = setup_db();
+    let id = db.insert("test", serde_to_jsonb(json!({"a": 1}))).unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 2}))).unwrap();
+
+    // Flush to disk so the lookup goes through the JSTable filter and index
+    for i in 0..MEMTABLE_THRESHOLD {
+        db.insert("test", serde_to_jsonb(json!({ "fill": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: id_predicate(BinaryOperator::Eq, &id),
+    };
+
+    let results: Vec<_> = execute_plan(plan, &db).unwrap().collect();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id(), id);
+    assert_eq!(results[0].get_value(), serde_to_jsonb(json!({"a": 1})));
+}
+
+#[test]
+fn test_execute_id_lookup_with_residual() {
+    let (mut db, _dir) = setup_db();
+    let id1 = db.insert("test", serde_to_jsonb(json!({"a": 1}))).unwrap();
+    let id2 = db.insert("test", serde_to_jsonb(json!({"a": 2}))).unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 3}))).unwrap();
+
+    // (_id = id1 OR _id = id2) AND a > 1
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: Expression::Logical {
+            left: Box::new(Expression::Logical {
+                left: Box::new(id_predicate(BinaryOperator::Eq, &id1)),
+                op: LogicalOperator::Or,
+                right: Box::new(id_predicate(BinaryOperator::Eq, &id2)),
+            }),
+            op: LogicalOperator::And,
+            right: Box::new(Expression::Binary {
+                left: Box::new(Expression::FieldReference(vec!["a"], "a")),
+                op: BinaryOperator::Gt,
+                right: Box::new(Expression::Literal(serde_to_jsonb(json!(1)))),
+            }),
+        },
+    };
+
+    let results: Vec<_> = execute_plan(plan, &db).unwrap().collect();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id(), id2);
+}
+
+#[test]
+fn test_execute_id_range() {
+    let (mut db, _dir) = setup_db();
+    let mut ids = Vec::new();
+    for i in 0..MEMTABLE_THRESHOLD + 10 {
+        ids.push(
+            db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                .unwrap(),
+        );
+    }
+    db.wait_for_flush("test").unwrap();
+    ids.sort();
+
+    // ids[10] <= _id < ids[20], spanning documents on disk
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: Expression::Logical {
+            left: Box::new(id_predicate(BinaryOperator::Gte, &ids[10])),
+            op: LogicalOperator::And,
+            right: Box::new(id_predicate(BinaryOperator::Lt, &ids[20])),
+        },
+    };
+
+    let mut results: Vec<String> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| r.id().to_string())
+        .collect();
+    results.sort();
+    assert_eq!(results, ids[10..20].to_vec());
+}
+
+#[test]
+fn test_execute_project_wildcard_and_alias() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"a": -1, "b": 2})))
+        .unwrap();
+
+    let plan = LogicalPlan::Project {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        projections: vec![
+            Expression::Wildcard,
+            Expression::Alias(Box::new(Expression::FieldReference(vec!["b"], "b")), "x"),
+        ],
+    };
+
+    let results: Vec<Value> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| r.get_value())
+        .collect();
+    assert_eq!(
+        results,
+        vec![serde_to_jsonb(json!({"a": -1, "b": 2, "x": 2}))]
+    );
+}
+
+#[test]
+fn test_execute_project_computed_columns() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"a": -1, "b": -2})))
+        .unwrap();
+
+    let abs = |field: &'static str| Expression::Function {
+        func: ScalarFunction::Abs,
+        args: vec![Expression::FieldReference(vec![field], field)],
+    };
+    let plan = LogicalPlan::Project {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        projections: vec![abs("a"), abs("b")],
+    };
+
+    let results: Vec<Value> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| r.get_value())
+        .collect();
+    assert_eq!(
+        results,
+        vec![serde_to_jsonb(json!({"ABS(a)": 1.0, "ABS(b)": 2.0}))]
+    );
+}
+
+#[test]
+fn test_vectorized_arithmetic_filter() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"a": 1, "b": 2})))
+        .unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 10, "b": 0})))
+        .unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 10}))).unwrap();
+
+    // Flush the first documents to disk so they are evaluated lazily
+    for i in 0..MEMTABLE_THRESHOLD {
+        db.insert("test", serde_to_jsonb(json!({ "fill": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 6, "b": 1})))
+        .unwrap();
+
+    // a * 2 - b > 10: 10 * 2 - 0 and 6 * 2 - 1 match, missing `b` is NULL
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: Expression::Binary {
+            left: Box::new(Expression::Binary {
+                left: Box::new(Expression::Binary {
+                    left: Box::new(Expression::FieldReference(vec!["a"], "a")),
+                    op: BinaryOperator::Multiply,
+                    right: Box::new(Expression::Literal(serde_to_jsonb(json!(2)))),
+                }),
+                op: BinaryOperator::Minus,
+                right: Box::new(Expression::FieldReference(vec!["b"], "b")),
+            }),
+            op: BinaryOperator::Gt,
+            right: Box::new(Expression::Literal(serde_to_jsonb(json!(10)))),
+        },
+    };
+
+    let mut results: Vec<i64> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| jsonb_to_serde(&r.get_value())["a"].as_i64().unwrap())
+        .collect();
+    results.sort();
+    assert_eq!(results, vec![6, 10]);
+}
+
+#[test]
+fn test_execute_id_in_list() {
+    let (mut db, _dir) = setup_db();
+    let mut ids = Vec::new();
+    for i in 0..5 {
+        ids.push(
+            db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                .unwrap(),
+        );
+    }
+
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: Expression::InList {
+            expr: Box::new(Expression::FieldReference(vec!["_id"], "_id")),
+            list: vec![
+                Expression::Literal(Value::String(ids[1].clone().into())),
+                Expression::Literal(Value::String(ids[3].clone().into())),
+                Expression::Literal(Value::String("no-such-id".into())),
+            ],
+            negated: false,
+        },
+    };
+
+    let mut results: Vec<String> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| r.id().to_string())
+        .collect();
+    results.sort();
+    let mut expected = vec![ids[1].clone(), ids[3].clone()];
+    expected.sort();
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn test_execute_is_missing_on_disk() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"a": null})))
+        .unwrap();
+    for i in 0..MEMTABLE_THRESHOLD {
+        db.insert("test", serde_to_jsonb(json!({ "a": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+    db.insert("test", serde_to_jsonb(json!({"b": 1}))).unwrap();
+
+    let count = |predicate: Expression<'static>| {
+        let plan = LogicalPlan::Filter {
+            input: Box::new(LogicalPlan::Scan {
+                collection: "test".to_string(),
+            }),
+            predicate,
+        };
+        execute_plan(plan, &db).unwrap().count()
+    };
+
+    let a = || Box::new(Expression::FieldReference(vec!["a"], "a"));
+    assert_eq!(
+        count(Expression::IsMissing {
+            expr: a(),
+            negated: false
+        }),
+        1
+    );
+    assert_eq!(
+        count(Expression::IsNull {
+            expr: a(),
+            negated: false
+        }),
+        2
+    );
+    assert_eq!(
+        count(Expression::Between {
+            expr: a(),
+            low: Box::new(Expression::Literal(serde_to_jsonb(json!(10)))),
+            high: Box::new(Expression::Literal(serde_to_jsonb(json!(19)))),
+            negated: false
+        }),
+        10
+    );
+}
+
+#[test]
+fn test_execute_sort() {
+    let (mut db, _dir) = setup_db();
+    for doc in [
+        json!({"k": 1, "a": 2}),
+        json!({"k": 2, "a": "x"}),
+        json!({"k": 3, "a": null}),
+        json!({"k": 4, "a": 1.5}),
+        json!({"k": 5}),
+        json!({"k": 6, "a": true}),
+        json!({"k": 7, "a": 2}),
+    ] {
+        db.insert("test", serde_to_jsonb(doc)).unwrap();
+    }
+
+    let sorted_keys = |descending: bool, nulls_first: bool| -> Vec<i64> {
+        let plan = LogicalPlan::Sort {
+            input: Box::new(LogicalPlan::Scan {
+                collection: "test".to_string(),
+            }),
+            keys: vec![
+                SortKey {
+                    expr: Expression::FieldReference(vec!["a"], "a"),
+                    descending,
+                    nulls_first,
+                },
+                SortKey {
+                    expr: Expression::FieldReference(vec!["k"], "k"),
+                    descending: false,
+                    nulls_first: true,
+                },
+            ],
+        };
+        execute_plan(plan, &db)
+            .unwrap()
+            .map(|r| jsonb_to_serde(&r.get_value())["k"].as_i64().unwrap())
+            .collect()
+    };
+
+    // null/missing < booleans < numbers < strings, ties broken by `k`
+    assert_eq!(sorted_keys(false, true), vec![3, 5, 6, 4, 1, 7, 2]);
+    assert_eq!(sorted_keys(true, false), vec![2, 1, 7, 4, 6, 3, 5]);
+    assert_eq!(sorted_keys(false, false), vec![6, 4, 1, 7, 2, 3, 5]);
+}
+
+#[test]
+fn test_execute_filter_three_valued() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"a": 1}))).unwrap();
+    db.insert("test", serde_to_jsonb(json!({"a": 2}))).unwrap();
+    db.insert("test", serde_to_jsonb(json!({"b": 1}))).unwrap();
+
+    // NOT (a = 1) is NULL, not true, when `a` is missing
+    let plan = LogicalPlan::Filter {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        predicate: Expression::Unary {
+            op: UnaryOperator::Not,
+            expr: Box::new(Expression::Binary {
+                left: Box::new(Expression::FieldReference(vec!["a"], "a")),
+                op: BinaryOperator::Eq,
+                right: Box::new(Expression::Literal(serde_to_jsonb(json!(1)))),
+            }),
+        },
+    };
+
+    let results: Vec<serde_json::Value> = execute_plan(plan, &db)
+        .unwrap()
+        .map(|r| jsonb_to_serde(&r.get_value()))
+        .collect();
+    assert_eq!(results, vec![json!({"a": 2})]);
+}
+
+#[test]
+fn test_execute_time_window() {
+    let (mut db, _dir) = setup_db();
+    let now = Utc::now();
+    let recent = now - TimeDelta::minutes(30);
+    let old = (now - TimeDelta::hours(2)).with_timezone(&FixedOffset::east_opt(7200).unwrap());
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({"n": 1, "ts": format_timestamp(&recent)})),
+    )
+    .unwrap();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({"n": 2, "ts": old.to_rfc3339()})),
+    )
+    .unwrap();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({"n": 3, "ts": recent.timestamp()})),
+    )
+    .unwrap();
+
+    let sql = "SELECT n FROM test WHERE TO_TIMESTAMP(ts) > NOW() - INTERVAL '1 hour' ORDER BY n";
+    assert_eq!(run_sql(&db, sql), vec![json!({"n": 1}), json!({"n": 3})]);
+}
+
+#[test]
+fn test_execute_mixed_timestamp_strings() {
+    let (mut db, _dir) = setup_db();
+    for (n, s) in [
+        (1, "2024-01-01"),
+        (2, "10 apples"),
+        (3, "2024-01-01T00:00:00Z"),
+        (4, "2024-01-01T01:00:00+02:00"),
+    ] {
+        db.insert("test", serde_to_jsonb(json!({"n": n, "s": s})))
+            .unwrap();
+    }
+
+    // Plain strings compare and sort by code point, timestamps or not
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM test WHERE s > '10 apples' ORDER BY n"),
+        vec![json!({"n": 1}), json!({"n": 3}), json!({"n": 4})]
+    );
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM test WHERE s = '2024-01-01T00:00:00Z'"),
+        vec![json!({"n": 3})]
+    );
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM test ORDER BY s"),
+        vec![
+            json!({"n": 2}),
+            json!({"n": 1}),
+            json!({"n": 3}),
+            json!({"n": 4})
+        ]
+    );
+
+    // A computed timestamp compares as an instant
+    assert_eq!(
+        run_sql(
+            &db,
+            "SELECT n FROM test WHERE s >= TO_TIMESTAMP('2024-01-01') ORDER BY n"
+        ),
+        vec![json!({"n": 1}), json!({"n": 3})]
+    );
+    assert_eq!(
+        run_sql(
+            &db,
+            "SELECT n FROM test WHERE s BETWEEN TO_TIMESTAMP('2023-12-31T22:00:00Z') \
+             AND TO_TIMESTAMP('2023-12-31T23:00:00Z') + INTERVAL '1 second' ORDER BY n"
+        ),
+        vec![json!({"n": 4})]
+    );
+
+    // So does a field that only holds date-time strings
+    db.create_collection("events").unwrap();
+    for (n, at) in [
+        (1, "2024-01-01T00:00:00Z"),
+        (2, "2024-01-01T01:00:00+02:00"),
+    ] {
+        db.insert("events", serde_to_jsonb(json!({"n": n, "at": at})))
+            .unwrap();
+    }
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM events WHERE at < '2024-01-01'"),
+        vec![json!({"n": 2})]
+    );
+}
+
+#[test]
+fn test_execute_unnest() {
+    let (mut db, _dir) = setup_db();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({
+            "customer": "ann",
+            "items": [{"sku": "a", "qty": 1}, {"sku": "b", "qty": 3}]
+        })),
+    )
+    .unwrap();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({"customer": "cat", "items": []})),
+    )
+    .unwrap();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({"customer": "dan", "items": "none"})),
+    )
+    .unwrap();
+
+    // Flush the first documents to disk so they are unnested lazily
+    for i in 0..MEMTABLE_THRESHOLD {
+        db.insert("test", serde_to_jsonb(json!({ "fill": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+    db.insert(
+        "test",
+        serde_to_jsonb(json!({
+            "customer": "bob",
+            "items": [{"sku": "c", "qty": 5}, {"sku": "d", "qty": 2}]
+        })),
+    )
+    .unwrap();
+
+    let sql = "SELECT t.customer, item.sku FROM test AS t, UNNEST(t.items) AS item \
+               WHERE item.qty > 1 ORDER BY item.sku";
+    assert_eq!(
+        run_sql(&db, sql),
+        vec![
+            json!({"customer": "ann", "item.sku": "b"}),
+            json!({"customer": "bob", "item.sku": "c"}),
+            json!({"customer": "bob", "item.sku": "d"}),
+        ]
+    );
+
+    // Unnested rows keep the parent document's fields
+    let rows = run_sql(
+        &db,
+        "SELECT * FROM test, UNNEST(items) AS item WHERE customer = 'ann'",
+    );
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0]["item"], json!({"sku": "a", "qty": 1}));
+    assert_eq!(rows[0]["items"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_execute_array_predicates() {
+    let (mut db, _dir) = setup_db();
+    for doc in [
+        json!({"name": "a", "tags": ["red", "big"], "scores": [4, 6], "meta": {"k": [1, 5]}}),
+        json!({"name": "b", "tags": ["blue"], "scores": [1, 9], "meta": {"k": [2]}}),
+        json!({"name": "c", "tags": [], "scores": [], "meta": {"j": 1}}),
+    ] {
+        db.insert("test", serde_to_jsonb(doc)).unwrap();
+    }
+    let names = |sql: &str| -> Vec<serde_json::Value> {
+        run_sql(&db, sql)
+            .into_iter()
+            .map(|row| row["name"].clone())
+            .collect()
+    };
+
+    assert_eq!(
+        names("SELECT name FROM test WHERE 'red' = ANY(tags) ORDER BY name"),
+        vec![json!("a")]
+    );
+    assert_eq!(
+        names("SELECT name FROM test WHERE 3 < ALL(scores) ORDER BY name"),
+        vec![json!("a"), json!("c")]
+    );
+    assert_eq!(
+        names("SELECT name FROM test WHERE JSON_EXISTS(meta, '$.k ? (@ > 3)') ORDER BY name"),
+        vec![json!("a")]
+    );
+    assert_eq!(
+        names("SELECT name FROM test WHERE tags @> ARRAY['big', 'red'] ORDER BY name"),
+        vec![json!("a")]
+    );
+    assert_eq!(
+        names("SELECT name FROM test WHERE meta @> PARSE_JSON('{\"k\": [2]}') ORDER BY name"),
+        vec![json!("b")]
+    );
+    assert_eq!(
+        names("SELECT name FROM test WHERE tags <@ ARRAY['blue', 'green'] ORDER BY name"),
+        vec![json!("b"), json!("c")]
+    );
+}
+
+#[test]
+fn test_execute_join() {
+    let (mut db, _dir) = setup_db();
+    db.create_collection("users").unwrap();
+    db.create_collection("events").unwrap();
+    let ann = db
+        .insert("users", serde_to_jsonb(json!({"name": "ann", "id": 1})))
+        .unwrap();
+    // Keys equal under `=` join even if their representations differ
+    let bob = db
+        .insert("users", serde_to_jsonb(json!({"name": "bob", "id": 2.0})))
+        .unwrap();
+    db.insert("users", serde_to_jsonb(json!({"name": "cat", "id": 3})))
+        .unwrap();
+    for event in [
+        json!({"user": 1, "user_id": ann, "kind": "click"}),
+        json!({"user": 1, "user_id": ann, "kind": "view"}),
+        json!({"user": 2, "user_id": bob, "kind": "click"}),
+        json!({"user": null, "kind": "orphan"}),
+    ] {
+        db.insert("events", serde_to_jsonb(event)).unwrap();
+    }
+
+    let inner = "SELECT u.name, e.kind FROM users u JOIN events e ON e.user = u.id \
+                 ORDER BY u.name, e.kind";
+    let left = "SELECT users.name, e.kind FROM users LEFT JOIN events e \
+                ON users.id = e.user AND e.kind != 'view' ORDER BY users.name";
+    let expected_inner = vec![
+        json!({"u.name": "ann", "e.kind": "click"}),
+        json!({"u.name": "ann", "e.kind": "view"}),
+        json!({"u.name": "bob", "e.kind": "click"}),
+    ];
+    let expected_left = vec![
+        json!({"users.name": "ann", "e.kind": "click"}),
+        json!({"users.name": "bob", "e.kind": "click"}),
+        json!({"users.name": "cat", "e.kind": null}),
+    ];
+    assert_eq!(run_sql(&db, inner), expected_inner);
+    assert_eq!(run_sql(&db, left), expected_left);
+
+    // Joining on `_id` looks up each document
+    assert_eq!(
+        run_sql(
+            &db,
+            "SELECT e.kind, u.name FROM events e LEFT JOIN users u ON u._id = e.user_id \
+             ORDER BY e.kind, u.name"
+        ),
+        vec![
+            json!({"e.kind": "click", "u.name": "ann"}),
+            json!({"e.kind": "click", "u.name": "bob"}),
+            json!({"e.kind": "orphan", "u.name": null}),
+            json!({"e.kind": "view", "u.name": "ann"}),
+        ]
+    );
+    let rows = run_sql(
+        &db,
+        "SELECT * FROM events e JOIN users u ON e.user_id = u._id WHERE e.kind = 'view'",
+    );
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["u"]["name"], json!("ann"));
+    assert_eq!(rows[0]["u"]["_id"], json!(ann));
+
+    // Three collections, then spilling the build side to disk
+    db.create_collection("names").unwrap();
+    db.insert(
+        "names",
+        serde_to_jsonb(json!({"kind": "click", "label": "Click"})),
+    )
+    .unwrap();
+    let three = "SELECT u.name, n.label FROM users u JOIN events e ON u.id = e.user \
+                 JOIN names n ON n.kind = e.kind ORDER BY u.name";
+    let expected_three = vec![
+        json!({"u.name": "ann", "n.label": "Click"}),
+        json!({"u.name": "bob", "n.label": "Click"}),
+    ];
+    assert_eq!(run_sql(&db, three), expected_three);
+
+    db.set_join_spill_threshold(1);
+    assert_eq!(run_sql(&db, inner), expected_inner);
+    assert_eq!(run_sql(&db, left), expected_left);
+    assert_eq!(run_sql(&db, three), expected_three);
+}
+
+#[test]
+fn test_execute_subqueries_and_set_operations() {
+    let (mut db, _dir) = setup_db();
+    db.create_collection("a").unwrap();
+    db.create_collection("b").unwrap();
+    for x in [1, 1, 2, 3] {
+        db.insert("a", serde_to_jsonb(json!({"x": x}))).unwrap();
+    }
+    // 2.0 = 2, so set operations treat them as the same row
+    for x in [json!(1), json!(2.0), json!(4)] {
+        db.insert("b", serde_to_jsonb(json!({"x": x}))).unwrap();
+    }
+    let xs = |sql: &str| -> Vec<f64> {
+        run_sql(&db, sql)
+            .iter()
+            .map(|row| row["x"].as_f64().unwrap())
+            .collect()
+    };
+
+    assert_eq!(
+        xs("SELECT x FROM a UNION ALL SELECT x FROM b ORDER BY x"),
+        vec![1.0, 1.0, 1.0, 2.0, 2.0, 3.0, 4.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a UNION SELECT x FROM b ORDER BY x"),
+        vec![1.0, 2.0, 3.0, 4.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a INTERSECT SELECT x FROM b ORDER BY x"),
+        vec![1.0, 2.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a INTERSECT ALL SELECT x FROM a WHERE x < 3 ORDER BY x"),
+        vec![1.0, 1.0, 2.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a EXCEPT SELECT x FROM b ORDER BY x"),
+        vec![3.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a EXCEPT ALL SELECT x FROM b ORDER BY x"),
+        vec![1.0, 3.0]
+    );
+    assert_eq!(
+        xs(
+            "(SELECT x FROM a ORDER BY x DESC LIMIT 1) UNION ALL (SELECT x FROM b ORDER BY x LIMIT 1)"
+        ),
+        vec![3.0, 1.0]
+    );
+
+    // Derived tables and CTEs
+    assert_eq!(
+        xs("SELECT t.y AS x FROM (SELECT x * 10 AS y FROM a WHERE x > 1) t ORDER BY t.y"),
+        vec![20.0, 30.0]
+    );
+    assert_eq!(
+        xs("WITH big AS (SELECT x FROM a WHERE x > 1), \
+            bigger AS (SELECT x FROM big WHERE x > 2) \
+            SELECT x FROM big UNION ALL SELECT x FROM bigger ORDER BY x"),
+        vec![2.0, 3.0, 3.0]
+    );
+    let joined: Vec<(f64, f64)> = run_sql(
+        &db,
+        "SELECT a.x, t.n FROM a JOIN (SELECT x, x + 100 AS n FROM b) t ON t.x = a.x \
+         ORDER BY t.n",
+    )
+    .iter()
+    .map(|row| (row["a.x"].as_f64().unwrap(), row["t.n"].as_f64().unwrap()))
+    .collect();
+    assert_eq!(joined, vec![(1.0, 101.0), (1.0, 101.0), (2.0, 102.0)]);
+
+    // IN and EXISTS subqueries
+    assert_eq!(
+        xs("SELECT x FROM a WHERE x IN (SELECT x FROM b) ORDER BY x"),
+        vec![1.0, 1.0, 2.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a WHERE x NOT IN (SELECT x FROM b WHERE x > 1) ORDER BY x"),
+        vec![1.0, 1.0, 3.0]
+    );
+    assert_eq!(
+        xs("SELECT x FROM a WHERE EXISTS (SELECT * FROM b WHERE x = 4)").len(),
+        4
+    );
+    assert!(xs("SELECT x FROM a WHERE NOT EXISTS (SELECT * FROM b)").is_empty());
+
+    let arena = Bump::new();
+    let Statement::Select(plan) = parse(
+        "SELECT * FROM a WHERE x IN (SELECT x, x AS y FROM b)",
+        &arena,
+    )
+    .unwrap() else {
+        panic!("Expected Select");
+    };
+    assert_eq!(
+        execute_plan(plan, &db).err().unwrap(),
+        "Subquery must return exactly one column"
+    );
+}
+
+#[test]
+fn test_execute_distinct() {
+    let (mut db, _dir) = setup_db();
+    for (kind, n, tag) in [
+        ("b", json!(1), "x"),
+        ("a", json!(2), "y"),
+        ("b", json!(3), "z"),
+        ("a", json!(2.0), "w"),
+        ("c", json!(null), "v"),
+        ("a", json!(5), "u"),
+    ] {
+        db.insert(
+            "test",
+            serde_to_jsonb(json!({"info": {"kind": kind}, "n": n, "tag": tag})),
+        )
+        .unwrap();
+    }
+
+    let kinds = "SELECT DISTINCT info.kind AS kind FROM test ORDER BY info.kind";
+    let pairs = "SELECT DISTINCT info.kind AS kind, n FROM test ORDER BY info.kind, n";
+    let first = "SELECT DISTINCT ON (info.kind) info.kind AS kind, tag FROM test \
+                 ORDER BY info.kind, n DESC";
+    let expected_kinds = vec![
+        json!({"kind": "a"}),
+        json!({"kind": "b"}),
+        json!({"kind": "c"}),
+    ];
+    let expected_first = vec![
+        json!({"kind": "a", "tag": "u"}),
+        json!({"kind": "b", "tag": "z"}),
+        json!({"kind": "c", "tag": "v"}),
+    ];
+    assert_eq!(run_sql(&db, kinds), expected_kinds);
+    // 2 and 2.0 are the same value
+    assert_eq!(run_sql(&db, pairs).len(), 5);
+    assert_eq!(run_sql(&db, first), expected_first);
+    // Every row has its own ID
+    let by_id = "SELECT DISTINCT ON (_id) info.kind AS kind FROM test";
+    assert_eq!(run_sql(&db, by_id).len(), 6);
+
+    // Spilled rows come back deduplicated and in input order
+    db.set_distinct_spill_threshold(1);
+    assert_eq!(run_sql(&db, kinds), expected_kinds);
+    assert_eq!(run_sql(&db, pairs).len(), 5);
+    assert_eq!(run_sql(&db, first), expected_first);
+    assert_eq!(run_sql(&db, by_id).len(), 6);
+    db.set_distinct_spill_threshold(0);
+    assert_eq!(run_sql(&db, kinds), expected_kinds);
+}
+
+#[test]
+fn test_execute_window_functions() {
+    let (mut db, _dir) = setup_db();
+    for (user, ts, amount) in [
+        ("ann", 1, json!(10)),
+        ("bob", 2, json!(5)),
+        ("ann", 3, json!(20)),
+        ("ann", 3, json!(null)),
+        ("bob", 4, json!(2.5)),
+        ("ann", 5, json!(30)),
+    ] {
+        db.insert(
+            "test",
+            serde_to_jsonb(json!({"user": user, "ts": ts, "amount": amount})),
+        )
+        .unwrap();
+    }
+
+    // Ranking, with ts = 3 as a tie for ann
+    let rows = run_sql(
+        &db,
+        "SELECT user, ts, ROW_NUMBER() OVER (PARTITION BY user ORDER BY ts) AS rn, \
+         RANK() OVER (PARTITION BY user ORDER BY ts) AS r, \
+         DENSE_RANK() OVER (PARTITION BY user ORDER BY ts) AS dr \
+         FROM test ORDER BY user, rn",
+    );
+    let ranks: Vec<(i64, i64, i64)> = rows
+        .iter()
+        .map(|row| {
+            (
+                row["rn"].as_i64().unwrap(),
+                row["r"].as_i64().unwrap(),
+                row["dr"].as_i64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        ranks,
+        vec![
+            (1, 1, 1),
+            (2, 2, 2),
+            (3, 2, 2),
+            (4, 4, 3),
+            (1, 1, 1),
+            (2, 2, 2)
+        ]
+    );
+
+    // LAG and LEAD, with an offset and a default
+    let rows = run_sql(
+        &db,
+        "SELECT ts, LAG(ts) OVER (ORDER BY ts) AS prev, \
+         LEAD(ts, 2, 0) OVER (ORDER BY ts) AS next2 FROM test WHERE user = 'bob' OR ts != 3 \
+         ORDER BY ts",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            json!({"ts": 1, "prev": null, "next2": 4}),
+            json!({"ts": 2, "prev": 1, "next2": 5}),
+            json!({"ts": 4, "prev": 2, "next2": 0}),
+            json!({"ts": 5, "prev": 4, "next2": 0}),
+        ]
+    );
+
+    // Running totals: the default frame includes the current row's peers
+    let rows = run_sql(
+        &db,
+        "SELECT SUM(amount) OVER (PARTITION BY user ORDER BY ts) AS running, \
+         COUNT(*) OVER (PARTITION BY user ORDER BY ts, amount ROWS UNBOUNDED PRECEDING) AS n, \
+         AVG(amount) OVER (PARTITION BY user) AS avg, \
+         MAX(amount) OVER (PARTITION BY user ORDER BY ts, amount ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) AS max3 \
+         FROM test WHERE user = 'ann' ORDER BY ts, amount",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            json!({"running": 10, "n": 1, "avg": 20.0, "max3": 10}),
+            json!({"running": 30, "n": 2, "avg": 20.0, "max3": 20}),
+            json!({"running": 30, "n": 3, "avg": 20.0, "max3": 30}),
+            json!({"running": 60, "n": 4, "avg": 20.0, "max3": 30}),
+        ]
+    );
+
+    // Mixed integers and floats sum to a float
+    let rows = run_sql(
+        &db,
+        "SELECT SUM(amount) OVER (PARTITION BY user) AS total, \
+         FIRST_VALUE(ts) OVER (ORDER BY ts DESC) AS latest FROM test WHERE user = 'bob'",
+    );
+    assert_eq!(rows[0], json!({"total": 7.5, "latest": 4}));
+
+    // Unaliased results are named after the call, and those only sorted by
+    // are not output
+    let rows = run_sql(
+        &db,
+        "SELECT ts, ROW_NUMBER() OVER (ORDER BY ts DESC) FROM test WHERE user = 'bob' ORDER BY ts",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            json!({"ts": 2, "ROW_NUMBER() OVER (ORDER BY ts DESC)": 2}),
+            json!({"ts": 4, "ROW_NUMBER() OVER (ORDER BY ts DESC)": 1}),
+        ]
+    );
+    let rows = run_sql(
+        &db,
+        "SELECT * FROM test WHERE user = 'bob' ORDER BY ROW_NUMBER() OVER (ORDER BY ts DESC)",
+    );
+    let ts: Vec<_> = rows.iter().map(|row| row["ts"].clone()).collect();
+    assert_eq!(ts, vec![json!(4), json!(2)]);
+    for row in &rows {
+        let fields: Vec<&String> = row.as_object().unwrap().keys().collect();
+        assert_eq!(fields, vec!["amount", "ts", "user"]);
+    }
+
+    // A negative LAG offset is rejected rather than read as a large one
+    let lag = WindowCall {
+        func: WindowFunction::Lag,
+        args: vec![
+            Expression::FieldReference(vec!["ts"], "ts"),
+            Expression::Literal(serde_to_jsonb(json!(-1))),
+        ],
+        partition_by: Vec::new(),
+        order_by: Vec::new(),
+        frame: None,
+    };
+    let plan = LogicalPlan::Window {
+        input: Box::new(LogicalPlan::Scan {
+            collection: "test".to_string(),
+        }),
+        windows: vec![(lag, "prev")],
+    };
+    assert_eq!(
+        execute_plan(plan, &db).err(),
+        Some("LAG offset must be a non-negative integer".to_string())
+    );
+}
+
+#[test]
+fn test_execute_insert_select() {
+    let (mut db, _dir) = setup_db();
+    for i in 0..5 {
+        db.insert("test", serde_to_jsonb(json!({"n": i, "even": i % 2 == 0})))
+            .unwrap();
+    }
+    db.create_collection("evens").unwrap();
+
+    let arena = Bump::new();
+    let (collection, plan) =
+        match parse("INSERT INTO evens SELECT n FROM test WHERE even", &arena).unwrap() {
+            Statement::InsertSelect { collection, plan } => (collection, plan),
+            _ => panic!("Expected InsertSelect"),
+        };
+    assert_eq!(db.insert_plan(&collection, plan).unwrap(), 3);
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM evens ORDER BY n"),
+        vec![json!({"n": 0}), json!({"n": 2}), json!({"n": 4})]
+    );
+
+    // A query over the target collection does not see its own inserts
+    let (collection, plan) = match parse("INSERT INTO evens SELECT * FROM evens", &arena).unwrap() {
+        Statement::InsertSelect { collection, plan } => (collection, plan),
+        _ => panic!("Expected InsertSelect"),
+    };
+    assert_eq!(db.insert_plan(&collection, plan).unwrap(), 3);
+    assert_eq!(run_sql(&db, "SELECT n FROM evens").len(), 6);
+
+    let (collection, plan) = match parse(
+        "CREATE COLLECTION big AS SELECT n * 10 AS n FROM test WHERE n > 2",
+        &arena,
+    )
+    .unwrap()
+    {
+        Statement::CreateCollectionAs { collection, plan } => (collection, plan),
+        _ => panic!("Expected CreateCollectionAs"),
+    };
+    assert_eq!(db.create_collection_as(&collection, plan).unwrap(), 2);
+    assert_eq!(
+        run_sql(&db, "SELECT n FROM big ORDER BY n"),
+        vec![json!({"n": 30}), json!({"n": 40})]
+    );
+
+    let (collection, plan) =
+        match parse("CREATE COLLECTION missing AS SELECT * FROM missing", &arena).unwrap() {
+            Statement::CreateCollectionAs { collection, plan } => (collection, plan),
+            _ => panic!("Expected CreateCollectionAs"),
+        };
+    assert!(db.create_collection_as(&collection, plan).is_err());
+    assert!(db.insert("missing", serde_to_jsonb(json!({}))).is_err());
+}
+
+#[test]
+fn test_execute_prepared_statement() {
+    let (mut db, _dir) = setup_db();
+    for (name, age) in [("alice", 30), ("bob", 25), ("carol", 35)] {
+        db.insert("test", serde_to_jsonb(json!({"name": name, "age": age})))
+            .unwrap();
+    }
+
+    let arena = Bump::new();
+    let prepared = prepare(
+        "SELECT name FROM test WHERE age >= $1 AND name <> $2 ORDER BY name",
+        &arena,
+    )
+    .unwrap();
+    assert_eq!(prepared.parameter_count(), 2);
+    let run = |params: Vec<serde_json::Value>| -> Vec<serde_json::Value> {
+        let params: Vec<Value> = params.into_iter().map(serde_to_jsonb).collect();
+        let Statement::Select(plan) = prepared.bind(&params).unwrap() else {
+            panic!("Expected Select");
+        };
+        execute_plan(plan, &db)
+            .unwrap()
+            .map(|r| jsonb_to_serde(&r.get_value()))
+            .collect()
+    };
+    assert_eq!(
+        run(vec![json!(30), json!("bob")]),
+        vec![json!({"name": "alice"}), json!({"name": "carol"})]
+    );
+    assert_eq!(run(vec![json!(20), json!("alice")]).len(), 2);
+    // A bound value is never parsed as SQL
+    assert_eq!(run(vec![json!(0), json!("x' OR '1' = '1")]).len(), 3);
+
+    // Executing without binding fails
+    let Statement::Select(plan) = parse("SELECT * FROM test WHERE age = ?", &arena).unwrap() else {
+        panic!("Expected Select");
+    };
+    assert_eq!(
+        execute_plan(plan, &db).err().unwrap(),
+        "No value bound for parameter $1"
+    );
+}
+
+fn explain_sql(db: &DB, sql: &str) -> Vec<String> {
+    let arena = Bump::new();
+    match parse(sql, &arena).unwrap() {
+        Statement::Explain { plan, analyze } => explain(plan, db, analyze).unwrap(),
+        _ => panic!("Expected Explain"),
+    }
+}
+
+/// Returns the value of an `EXPLAIN ANALYZE` counter on a plan line.
+fn stat(line: &str, name: &str) -> u64 {
+    line.split([' ', '(', ')'])
+        .find_map(|word| word.strip_prefix(name)?.strip_prefix('=')?.parse().ok())
+        .unwrap_or_else(|| panic!("No {} in {}", name, line))
+}
+
+#[test]
+fn test_explain() {
+    let (db, _dir) = setup_db();
+
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE a > 5 LIMIT 2"),
+        vec![
+            "Flatten",
+            "-> BatchLimit 2",
+            "   -> BatchFilter a > 5",
+            "      -> BatchScan [batch size: 2]",
+            "         -> Scan test",
+        ]
+    );
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT name FROM test WHERE name = 'x'"),
+        vec!["Scan test [filter: name = 'x'] [project: name]"]
+    );
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE _id = 'abc'"),
+        vec!["IdLookup test [_id IN ('abc')]"]
+    );
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT name FROM test ORDER BY a DESC"),
+        vec![
+            "Project name",
+            "-> Sort a DESC",
+            "   -> Flatten",
+            "      -> BatchScan [batch size: 4096]",
+            "         -> Scan test [fields: a, name]",
+        ]
+    );
+}
+
+#[test]
+fn test_explain_analyze() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("test").unwrap();
+    for i in 0..5 {
+        db.insert("test", serde_to_jsonb(json!({ "a": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+
+    let lines = explain_sql(&db, "EXPLAIN ANALYZE SELECT * FROM test WHERE a > 1");
+    assert_eq!(lines.len(), 5);
+    assert!(lines[0].starts_with("Flatten (rows=3 "), "{}", lines[0]);
+    assert!(lines[1].starts_with("-> BatchFilter a > 1 (rows=3 "));
+    assert!(lines[3].starts_with("      -> Scan test (rows=5 "));
+    assert!(stat(&lines[3], "jstables") > 0);
+    assert!(stat(&lines[3], "bytes_read") > 0);
+    // Operators include the work of their inputs
+    assert_eq!(stat(&lines[0], "bytes_read"), stat(&lines[3], "bytes_read"));
+    assert!(lines[4].starts_with("Execution time: "));
+
+    // A missing id is ruled out by the JSTable filters
+    let lines = explain_sql(
+        &db,
+        "EXPLAIN ANALYZE SELECT * FROM test WHERE _id = 'missing'",
+    );
+    assert_eq!(stat(&lines[0], "rows"), 0);
+    assert!(stat(&lines[0], "filter_skips") > 0);
+
+    // Without ANALYZE nothing is read
+    let before = db.io_counts();
+    explain_sql(&db, "EXPLAIN SELECT * FROM test");
+    assert_eq!(db.io_counts(), before);
+}
+
+#[test]
+fn test_zone_map_pruning() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("test").unwrap();
+    // Four JSTables holding a in 0..2, 2..4, 4..6 and 6..8
+    let ids: Vec<String> = (0..10)
+        .map(|i| {
+            db.insert("test", serde_to_jsonb(json!({ "a": i })))
+                .unwrap()
+        })
+        .collect();
+    db.wait_for_flush("test").unwrap();
+
+    let sql = "SELECT a FROM test WHERE a < 2";
+    let lines = explain_sql(&db, &format!("EXPLAIN ANALYZE {}", sql));
+    let scan = &lines[lines.len() - 2];
+    assert!(scan.contains("-> Scan test [filter: a < 2]"), "{}", scan);
+    assert_eq!(stat(scan, "zone_skips"), 3);
+    assert_eq!(stat(scan, "jstables"), 1);
+    assert_eq!(run_sql(&db, sql), vec![json!({"a": 0}), json!({"a": 1})]);
+
+    // Newer tables holding updates or deletes are read, as they shadow
+    // the matching versions in the oldest table
+    db.update("test", &ids[0], serde_to_jsonb(json!({ "a": 50 })))
+        .unwrap();
+    db.insert("test", serde_to_jsonb(json!({ "a": 10 })))
+        .unwrap();
+    db.delete("test", &ids[1]).unwrap();
+    db.insert("test", serde_to_jsonb(json!({ "a": 11 })))
+        .unwrap();
+    db.insert("test", serde_to_jsonb(json!({ "a": 12 })))
+        .unwrap();
+    db.wait_for_flush("test").unwrap();
+    assert_eq!(
+        run_sql(&db, "SELECT a FROM test WHERE a < 2 OR a > 49"),
+        vec![json!({"a": 50})]
+    );
+    assert!(run_sql(&db, sql).is_empty());
+}
+
+#[test]
+fn test_schema_pruning() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("test").unwrap();
+    // One JSTable with string values of x and one without x
+    for doc in [
+        json!({"x": "a"}),
+        json!({"x": "b"}),
+        json!({"y": 1}),
+        json!({"y": 2}),
+        json!({"x": "c"}),
+    ] {
+        db.insert("test", serde_to_jsonb(doc)).unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+
+    let sql = "SELECT x FROM test WHERE x = 'a'";
+    let lines = explain_sql(&db, &format!("EXPLAIN ANALYZE {}", sql));
+    let scan = &lines[lines.len() - 2];
+    assert_eq!(stat(scan, "schema_skips"), 1);
+    assert_eq!(stat(scan, "jstables"), 1);
+    assert_eq!(run_sql(&db, sql), vec![json!({"x": "a"})]);
+
+    let sql = "SELECT x FROM test WHERE x > 5";
+    let lines = explain_sql(&db, &format!("EXPLAIN ANALYZE {}", sql));
+    let scan = &lines[lines.len() - 2];
+    assert_eq!(stat(scan, "schema_skips"), 2);
+    assert_eq!(stat(scan, "jstables"), 0);
+    assert!(run_sql(&db, sql).is_empty());
+}
+
+#[test]
+fn test_parallel_scan() {
+    let dir = tempdir().unwrap();
+    // Small sparse index blocks, so that scans split into several morsels
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        100,
+        JSTABLE_THRESHOLD,
+        16,
+        None,
+    );
+    db.create_collection("test").unwrap();
+    for i in 0..500 {
+        db.insert("test", serde_to_jsonb(json!({ "a": i, "b": i % 7 })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+    let queries = [
+        "SELECT a FROM test WHERE b = 3 AND a > 100",
+        "SELECT * FROM test WHERE a > 450 OR b = 0",
+        "SELECT a, b * 2 AS c FROM test ORDER BY a DESC LIMIT 5",
+        "SELECT a FROM test WHERE a % 50 = 0",
+    ];
+    let sorted = |mut rows: Vec<serde_json::Value>| {
+        rows.sort_by_key(|row| row.to_string());
+        rows
+    };
+
+    db.set_scan_parallelism(1);
+    assert!(!db.scans_in_parallel("test"));
+    let serial: Vec<_> = queries
+        .iter()
+        .map(|sql| sorted(run_sql(&db, sql)))
+        .collect();
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE a > 5"),
+        vec![
+            "Flatten",
+            "-> BatchFilter a > 5",
+            "   -> BatchScan [batch size: 4096]",
+            "      -> Scan test",
+        ]
+    );
+
+    // The workers evaluate the filter, and the results do not change
+    db.set_scan_parallelism(4);
+    assert!(db.scans_in_parallel("test"));
+    let parallel: Vec<_> = queries
+        .iter()
+        .map(|sql| sorted(run_sql(&db, sql)))
+        .collect();
+    assert_eq!(parallel, serial);
+    assert_eq!(parallel[1].len(), 114);
+    assert_eq!(
+        explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE a > 5"),
+        vec![
+            "Flatten",
+            "-> BatchScan [batch size: 4096]",
+            "   -> Scan test [filter: a > 5]",
+        ]
+    );
+
+    // Documents on disk come in ID order, as on a single thread
+    let ids: Vec<serde_json::Value> = run_sql(&db, "SELECT _id FROM test");
+    assert_eq!(ids.len(), 500);
+    assert!(
+        ids.windows(2)
+            .all(|w| w[0]["_id"].as_str() < w[1]["_id"].as_str())
+    );
+
+    // Tables ruled out by their schema are skipped once, not per morsel
+    db.set_scan_parallelism(1);
+    let lines = explain_sql(&db, "EXPLAIN ANALYZE SELECT a FROM test");
+    let tables = stat(&lines[lines.len() - 2], "jstables");
+    db.set_scan_parallelism(4);
+    for i in 0..200 {
+        db.insert("test", serde_to_jsonb(json!({ "c": i })))
+            .unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+    let lines = explain_sql(&db, "EXPLAIN ANALYZE SELECT c FROM test WHERE c >= 0");
+    let scan = &lines[lines.len() - 2];
+    assert!(scan.contains("-> Scan test [filter: c >= 0]"), "{}", scan);
+    assert_eq!(stat(scan, "schema_skips"), tables);
+}
+
+#[test]
+fn test_secondary_index() {
+    let dir = tempdir().unwrap();
+    let open = || {
+        DB::new(
+            dir.path().to_str().unwrap(),
+            2,
+            3,
+            INDEX_THRESHOLD,
+            Some(1024 * 1024),
+        )
+    };
+    let mut db = open();
+    db.create_collection("test").unwrap();
+    // Spread over compacted and flushed JSTables and the memtable
+    let ids: Vec<String> = (0..9)
+        .map(|i| {
+            db.insert("test", serde_to_jsonb(json!({ "a": i, "b": i % 2 })))
+                .unwrap()
+        })
+        .collect();
+    db.wait_for_flush("test").unwrap();
+    db.create_index("test", "idx_a", vec!["a".to_string()], IndexKind::Value)
+        .unwrap();
+    assert!(
+        db.create_index("test", "idx_a", vec!["b".to_string()], IndexKind::Value)
+            .is_err()
+    );
+    for i in 9..12 {
+        db.insert("test", serde_to_jsonb(json!({ "a": i, "b": i % 2 })))
+            .unwrap();
+    }
+
+    let plan = explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE a = 3").join("\n");
+    assert!(
+        plan.contains("IndexScan test [index: idx_a] [filter: a = 3]"),
+        "{}",
+        plan
+    );
+    assert_eq!(
+        run_sql(&db, "SELECT a FROM test WHERE a = 3"),
+        vec![json!({"a": 3})]
+    );
+    let sql = "SELECT a FROM test WHERE a >= 4 AND a < 11 AND b = 1 ORDER BY a";
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", sql)).join("\n");
+    assert!(plan.contains("IndexScan test [index: idx_a]"), "{}", plan);
+    assert_eq!(
+        run_sql(&db, sql),
+        vec![json!({"a": 5}), json!({"a": 7}), json!({"a": 9})]
+    );
+
+    // Stale entries for updated and deleted documents are not returned
+    db.update("test", &ids[3], serde_to_jsonb(json!({ "a": 30 })))
+        .unwrap();
+    db.delete("test", &ids[5]).unwrap();
+    assert!(run_sql(&db, "SELECT a FROM test WHERE a = 3").is_empty());
+    assert_eq!(
+        run_sql(&db, "SELECT a FROM test WHERE a > 4 AND a < 7"),
+        vec![json!({"a": 6})]
+    );
+    assert_eq!(
+        run_sql(&db, "SELECT a FROM test WHERE a = 30"),
+        vec![json!({"a": 30})]
+    );
+
+    // The index survives a restart
+    db.wait_for_flush("test").unwrap();
+    drop(db);
+    let mut db = open();
+    let plan = explain_sql(&db, "EXPLAIN SELECT a FROM test WHERE a BETWEEN 6 AND 8").join("\n");
+    assert!(plan.contains("IndexScan test [index: idx_a]"), "{}", plan);
+    assert_eq!(
+        run_sql(
+            &db,
+            "SELECT a FROM test WHERE a BETWEEN 6 AND 30 ORDER BY a"
+        ),
+        vec![
+            json!({"a": 6}),
+            json!({"a": 7}),
+            json!({"a": 8}),
+            json!({"a": 9}),
+            json!({"a": 10}),
+            json!({"a": 11}),
+            json!({"a": 30}),
+        ]
+    );
+
+    // Index tables lost in a crash are rebuilt from their JSTables
+    drop(db);
+    for entry in std::fs::read_dir(dir.path().join("test")).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().is_some_and(|ext| ext == "index") {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+    let mut db = open();
+    assert_eq!(
+        run_sql(&db, "SELECT a FROM test WHERE a BETWEEN 6 AND 8 ORDER BY a"),
+        vec![json!({"a": 6}), json!({"a": 7}), json!({"a": 8})]
+    );
+
+    db.drop_index("idx_a").unwrap();
+    let plan = explain_sql(&db, "EXPLAIN SELECT * FROM test WHERE a = 3").join("\n");
+    assert!(!plan.contains("IndexScan"), "{}", plan);
+    assert!(db.drop_index("idx_a").is_err());
+}
+
+#[test]
+fn test_full_text_search() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("docs").unwrap();
+    for (n, body) in [
+        (1, "The quick brown fox"),
+        (2, "Foxes jumping over foxes"),
+        (3, "A lazy dog sleeps"),
+        (4, "Quick thinking"),
+        (5, "Dogs and cats"),
+    ] {
+        db.insert("docs", serde_to_jsonb(json!({ "n": n, "body": body })))
+            .unwrap();
+    }
+    db.wait_for_flush("docs").unwrap();
+
+    // Without an index, MATCH filters a full scan
+    let sql = "SELECT n FROM docs WHERE MATCH(body, 'Fox') ORDER BY n";
+    assert_eq!(run_sql(&db, sql), vec![json!({"n": 1}), json!({"n": 2})]);
+
+    db.create_index(
+        "docs",
+        "idx_body",
+        vec!["body".to_string()],
+        IndexKind::FullText,
+    )
+    .unwrap();
+    db.insert(
+        "docs",
+        serde_to_jsonb(json!({ "n": 6, "body": "A fox, a dog" })),
+    )
+    .unwrap();
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", sql)).join("\n");
+    assert!(
+        plan.contains("IndexScan docs [index: idx_body] [filter: MATCH(body, 'Fox') > 0]"),
+        "{}",
+        plan
+    );
+    assert_eq!(
+        run_sql(&db, sql),
+        vec![json!({"n": 1}), json!({"n": 2}), json!({"n": 6})]
+    );
+
+    // Ranked by BM25: "quick" is rarer than "dog", and shorter texts rank higher
+    let sql = "SELECT n FROM docs WHERE MATCH(body, 'quick dogs') \
+               ORDER BY MATCH(body, 'quick dogs') DESC, n";
+    assert_eq!(
+        run_sql(&db, sql),
+        vec![
+            json!({"n": 4}),
+            json!({"n": 1}),
+            json!({"n": 5}),
+            json!({"n": 3}),
+            json!({"n": 6}),
+        ]
+    );
+}
+
+#[test]
+fn test_vector_search() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("points").unwrap();
+    // Points on a 4x4 grid
+    let mut ids = Vec::new();
+    for n in 0..16 {
+        let doc = json!({ "n": n, "embedding": [n % 4, n / 4] });
+        ids.push(db.insert("points", serde_to_jsonb(doc)).unwrap());
+    }
+    db.wait_for_flush("points").unwrap();
+
+    let sql = "SELECT n FROM points \
+               ORDER BY VECTOR_DISTANCE(embedding, ARRAY[2.1, 1.2], 'l2') NULLS LAST LIMIT 3";
+    let ns = |db: &DB, sql: &str| -> Vec<serde_json::Value> {
+        run_sql(db, sql)
+            .into_iter()
+            .map(|row| row["n"].clone())
+            .collect()
+    };
+    assert_eq!(ns(&db, sql), vec![json!(6), json!(10), json!(7)]);
+
+    db.create_index(
+        "points",
+        "idx_embedding",
+        vec!["embedding".to_string()],
+        IndexKind::Vector,
+    )
+    .unwrap();
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", sql)).join("\n");
+    assert!(
+        plan.contains("VectorScan points [index: idx_embedding] [metric: l2] [nearest: 3]"),
+        "{}",
+        plan
+    );
+    assert_eq!(ns(&db, sql), vec![json!(6), json!(10), json!(7)]);
+
+    // Documents without a vector sort first by default, so they are scanned
+    db.insert("points", serde_to_jsonb(json!({ "n": 20 })))
+        .unwrap();
+    let nulls_sql = "SELECT n FROM points \
+                     ORDER BY VECTOR_DISTANCE(embedding, ARRAY[2.1, 1.2], 'l2') LIMIT 3";
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", nulls_sql)).join("\n");
+    assert!(!plan.contains("VectorScan"), "{}", plan);
+    assert_eq!(ns(&db, nulls_sql), vec![json!(20), json!(6), json!(10)]);
+    assert_eq!(ns(&db, sql), vec![json!(6), json!(10), json!(7)]);
+
+    // The offset is searched for as well
+    let offset_sql = "SELECT n FROM points \
+                      ORDER BY VECTOR_DISTANCE(embedding, ARRAY[2.1, 1.2], 'l2') \
+                      NULLS LAST LIMIT 2 OFFSET 1";
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", offset_sql)).join("\n");
+    assert!(plan.contains("[nearest: 3]"), "{}", plan);
+    assert_eq!(ns(&db, offset_sql), vec![json!(10), json!(7)]);
+
+    // A filtered scan is not searched in the index
+    let filtered_sql = "SELECT n FROM points WHERE n > 6 \
+                        ORDER BY VECTOR_DISTANCE(embedding, ARRAY[2.1, 1.2], 'l2') NULLS LAST LIMIT 3";
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", filtered_sql)).join("\n");
+    assert!(!plan.contains("VectorScan"), "{}", plan);
+    assert_eq!(ns(&db, filtered_sql), vec![json!(10), json!(7), json!(11)]);
+
+    // Moved and deleted documents are ranked by their current vectors, also
+    // once flushed and compacted
+    db.update(
+        "points",
+        &ids[15],
+        serde_to_jsonb(json!({ "n": 15, "embedding": [2.0, 1.1] })),
+    )
+    .unwrap();
+    db.delete("points", &ids[6]).unwrap();
+    assert_eq!(ns(&db, sql), vec![json!(15), json!(10), json!(7)]);
+    db.delete("points", &ids[0]).unwrap();
+    let doc = json!({ "n": 16, "embedding": [3, 3] });
+    db.insert("points", serde_to_jsonb(doc)).unwrap();
+    db.wait_for_flush("points").unwrap();
+    assert_eq!(ns(&db, sql), vec![json!(15), json!(10), json!(7)]);
+
+    // Deleting the nearest flushed documents leaves enough others to find
+    for n in [15, 10, 7, 5, 11] {
+        db.delete("points", &ids[n]).unwrap();
+    }
+    db.wait_for_flush("points").unwrap();
+    let five_sql = "SELECT n FROM points \
+                    ORDER BY VECTOR_DISTANCE(embedding, ARRAY[2.1, 1.2], 'l2') NULLS LAST LIMIT 5";
+    assert_eq!(
+        ns(&db, five_sql),
+        vec![json!(2), json!(9), json!(3), json!(1), json!(14)]
+    );
+}
+
+#[test]
+fn test_spatial_search() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("places").unwrap();
+    let point = |lon: f64, lat: f64| json!({"type": "Point", "coordinates": [lon, lat]});
+    for (name, loc) in [
+        ("Paris", point(2.3522, 48.8566)),
+        ("London", point(-0.1276, 51.5072)),
+        ("Brussels", point(4.3517, 50.8503)),
+        ("Amsterdam", point(4.9041, 52.3676)),
+        ("Berlin", point(13.4050, 52.5200)),
+        ("Madrid", point(-3.7038, 40.4168)),
+        ("Lyon", point(4.8357, 45.7640)),
+        (
+            "Bois de Boulogne",
+            json!({"type": "Polygon", "coordinates": [[
+                [2.24, 48.85], [2.26, 48.85], [2.26, 48.87], [2.24, 48.87], [2.24, 48.85]
+            ]]}),
+        ),
+        ("Nowhere", json!("48.8566, 2.3522")),
+    ] {
+        db.insert(
+            "places",
+            serde_to_jsonb(json!({ "name": name, "loc": loc })),
+        )
+        .unwrap();
+    }
+    db.wait_for_flush("places").unwrap();
+
+    let names = |db: &DB, sql: &str| -> Vec<serde_json::Value> {
+        run_sql(db, sql)
+            .into_iter()
+            .map(|row| row["name"].clone())
+            .collect()
+    };
+    let radius_sql = "SELECT name FROM places \
+                      WHERE ST_DWITHIN(loc, ST_POINT(2.3522, 48.8566), 400000) \
+                      ORDER BY ST_DISTANCE(loc, ST_POINT(2.3522, 48.8566))";
+    let box_sql = "SELECT name FROM places WHERE ST_WITHIN_BBOX(loc, 2, 48, 3, 49.5) ORDER BY name";
+    let polygon_sql = r#"SELECT name FROM places WHERE ST_CONTAINS(PARSE_JSON('{"type": "Polygon",
+                         "coordinates": [[[-5, 42], [8, 42], [8, 51], [-5, 51], [-5, 42]]]}'), loc)
+                         ORDER BY name"#;
+    let check = |db: &DB| {
+        assert_eq!(
+            names(db, radius_sql),
+            vec![
+                json!("Paris"),
+                json!("Brussels"),
+                json!("London"),
+                json!("Lyon")
+            ]
+        );
+        assert_eq!(
+            names(db, box_sql),
+            vec![json!("Bois de Boulogne"), json!("Paris")]
+        );
+        assert_eq!(
+            names(db, polygon_sql),
+            vec![
+                json!("Bois de Boulogne"),
+                json!("Brussels"),
+                json!("Lyon"),
+                json!("Paris")
+            ]
+        );
+    };
+    check(&db);
+
+    db.create_index(
+        "places",
+        "idx_loc",
+        vec!["loc".to_string()],
+        IndexKind::Spatial,
+    )
+    .unwrap();
+    for sql in [radius_sql, box_sql, polygon_sql] {
+        let plan = explain_sql(&db, &format!("EXPLAIN {}", sql)).join("\n");
+        assert!(
+            plan.contains("IndexScan places [index: idx_loc]"),
+            "{}",
+            plan
+        );
+    }
+    check(&db);
+
+    // A region too large to cover with a few cells is scanned
+    let world_sql = "SELECT name FROM places WHERE ST_WITHIN_BBOX(loc, -180, -90, 180, 90)";
+    let plan = explain_sql(&db, &format!("EXPLAIN {}", world_sql)).join("\n");
+    assert!(!plan.contains("IndexScan"), "{}", plan);
+    assert_eq!(names(&db, world_sql).len(), 8);
+}
+
+#[test]
+fn test_optimized_derived_table() {
+    let (mut db, _dir) = setup_db();
+    db.insert("test", serde_to_jsonb(json!({"name": "a", "age": 10})))
+        .unwrap();
+    db.insert("test", serde_to_jsonb(json!({"name": "b", "age": 40})))
+        .unwrap();
+
+    let sql = "SELECT * FROM (SELECT name AS n, age FROM test) s WHERE n = 'b'";
+    assert_eq!(run_sql(&db, sql), vec![json!({"n": "b", "age": 40})]);
+    // The filter moves below the projection, and both into the scan
+    assert_eq!(
+        explain_sql(&db, &format!("EXPLAIN {}", sql)),
+        vec!["Scan test [filter: name = 'b'] [project: name AS n, age]"]
+    );
+
+    assert!(run_sql(&db, "SELECT * FROM test WHERE age > 1 AND 1 = 2").is_empty());
+}
+
+#[test]
+fn test_scan_fields_pushdown() {
+    let dir = tempdir().unwrap();
+    let mut db = DB::new(
+        dir.path().to_str().unwrap(),
+        2,
+        JSTABLE_THRESHOLD,
+        INDEX_THRESHOLD,
+        None,
+    );
+    db.create_collection("test").unwrap();
+    // Two documents on disk and one in the memtable
+    for (name, age) in [("a", 30), ("b", 10), ("c", 20)] {
+        let doc = json!({"name": name, "age": age, "address": {"city": name, "zip": age}});
+        db.insert("test", serde_to_jsonb(doc)).unwrap();
+    }
+    db.wait_for_flush("test").unwrap();
+
+    let sql = "SELECT name, address.city FROM test ORDER BY age";
+    assert_eq!(
+        explain_sql(&db, &format!("EXPLAIN {}", sql))[4],
+        "         -> Scan test [fields: address.city, age, name]"
+    );
+    assert_eq!(
+        run_sql(&db, sql),
+        vec![
+            json!({"name": "b", "address.city": "b"}),
+            json!({"name": "c", "address.city": "c"}),
+            json!({"name": "a", "address.city": "a"}),
+        ]
+    );
+
+    // A wildcard or JSON path reads whole documents
+    let lines = explain_sql(&db, "EXPLAIN SELECT * FROM test ORDER BY age");
+    assert!(lines.last().unwrap().ends_with("-> Scan test"));
+
+    // Join inputs read only the fields under their alias
+    let lines = explain_sql(
+        &db,
+        "EXPLAIN SELECT t.name FROM test t JOIN test u ON t.age = u.address.zip",
+    );
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.ends_with("Scan test [fields: age, name]"))
+    );
+    assert!(
+        lines
+            .iter()
+            .any(|l| l.ends_with("Scan test [fields: address.zip]"))
+    );
+    assert_eq!(
+        run_sql(
+            &db,
+            "SELECT t.name FROM test t JOIN test u ON t.age = u.address.zip ORDER BY t.name",
+        ),
+        vec![
+            json!({"t.name": "a"}),
+            json!({"t.name": "b"}),
+            json!({"t.name": "c"})
+        ]
+    );
+}
@@ -0,0 +1,120 @@
+use std::error::Error;
+use std::net::TcpListener;
+use std::process::{Child, Command};
+use std::time::Duration;
+use tempfile::tempdir;
+use tokio_postgres::types::{FromSql, Type};
+use tokio_postgres::{Client, NoTls};
+
+struct ServerGuard {
+    child: Child,
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A column read as the JSON text the server sends.
+#[derive(Debug, PartialEq)]
+struct Json(serde_json::Value);
+
+impl<'a> FromSql<'a> for Json {
+    fn from_sql(_ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(Json(serde_json::from_slice(raw)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::JSON
+    }
+}
+
+async fn connect(port: u16) -> Client {
+    let config = format!("host=127.0.0.1 port={} user=argus", port);
+    for _ in 0..100 {
+        if let Ok((client, connection)) = tokio_postgres::connect(&config, NoTls).await {
+            tokio::spawn(connection);
+            return client;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Could not connect to server on port {}", port);
+}
+
+#[tokio::test]
+async fn test_prepared_statements() {
+    let dir = tempdir().unwrap();
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let _server = ServerGuard {
+        child: Command::new(env!("CARGO_BIN_EXE_argusdb"))
+            .args(["--jstable-dir", dir.path().to_str().unwrap()])
+            .args(["--host", "127.0.0.1", "--port", &port.to_string()])
+            .arg("--no-log")
+            .spawn()
+            .unwrap(),
+    };
+    let client = connect(port).await;
+
+    client.simple_query("CREATE COLLECTION test").await.unwrap();
+    client
+        .simple_query(
+            "INSERT INTO test VALUES (`{\"name\": \"a\", \"age\": 25}`), \
+             (`{\"name\": \"b\", \"age\": 35}`), (`{\"name\": \"c\", \"age\": 45}`)",
+        )
+        .await
+        .unwrap();
+
+    // Typed parameters bind as values of their type
+    let stmt = client
+        .prepare_typed(
+            "SELECT name FROM test WHERE age > $1 ORDER BY age",
+            &[Type::INT8],
+        )
+        .await
+        .unwrap();
+    assert_eq!(stmt.params(), &[Type::INT8]);
+    assert_eq!(stmt.columns().len(), 1);
+    assert_eq!(stmt.columns()[0].name(), "name");
+    let names: Vec<Json> = client
+        .query(&stmt, &[&30i64])
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(
+        names,
+        vec![Json(serde_json::json!("b")), Json(serde_json::json!("c"))]
+    );
+
+    // The statement is executed again with new values
+    let rows = client.query(&stmt, &[&40i64]).await.unwrap();
+    assert_eq!(rows.len(), 1);
+
+    // Untyped parameters are text
+    let stmt = client
+        .prepare("SELECT * FROM test WHERE name = $1")
+        .await
+        .unwrap();
+    assert_eq!(stmt.params(), &[Type::TEXT]);
+    let rows = client.query(&stmt, &[&"a"]).await.unwrap();
+    assert_eq!(rows.len(), 1);
+    let Json(doc) = rows[0].get("document");
+    assert_eq!(doc["age"], serde_json::json!(25));
+
+    // Statements that return no rows run as well
+    let stmt = client
+        .prepare_typed(
+            "INSERT INTO test SELECT name FROM test WHERE age < $1",
+            &[Type::INT8],
+        )
+        .await
+        .unwrap();
+    assert_eq!(client.execute(&stmt, &[&30i64]).await.unwrap(), 1);
+}
@@ -63,7 +63,7 @@ fn test_tombstone_and_shadowing() {
 
     // 6. Verify scan doesn't return it
     let results: Vec<_> = db
-        .scan("test", None, None)
+        .scan("test", None, None, None)
         .unwrap()
         .filter(|r| r.id() == id)
         .collect();
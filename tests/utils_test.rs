@@ -38,7 +38,10 @@ fn test_create_collection_sanitization() {
     let doc = serde_to_jsonb(json!({ "foo": "bar" }));
     db.insert(problematic_name, doc.clone()).unwrap();
 
-    let results: Vec<_> = db.scan(problematic_name, None, None).unwrap().collect();
+    let results: Vec<_> = db
+        .scan(problematic_name, None, None, None)
+        .unwrap()
+        .collect();
     assert_eq!(results.len(), 1);
     assert_eq!(results[0].get_value(), doc);
 